@@ -13,8 +13,9 @@
 #![cfg_attr(not(debug_assertions), doc(test(attr(allow(dead_code)))))]
 #![cfg_attr(not(debug_assertions), doc(test(attr(allow(unused_variables)))))]
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::Arc;
 
 use wasmtime_wasi::I32Exit;
 
@@ -26,31 +27,60 @@ use {
     hyper::{client::Client, Body, Request},
     std::{
         env,
-        io::{self, Stderr, Stdout},
+        io::{self, Stderr, Stdout, Write},
         time::Duration,
     },
     tokio::time::timeout,
     tracing::{event, Level, Metadata},
     tracing_subscriber::{filter::EnvFilter, fmt::writer::MakeWriter, FmtSubscriber},
-    viceroy_lib::{config::FastlyConfig, BackendConnector, Error, ExecuteCtx, ViceroyService},
+    viceroy_lib::{
+        config::{FastlyConfig, ObjectStores},
+        kv_diff::{self, KvDiff},
+        kv_trace::{self, KvTraceRecorder},
+        BackendConnector, Error, ExecuteCtx, KvExport, ViceroyService,
+    },
 };
 
 /// Starts up a Viceroy server.
 ///
-/// Create a new server, bind it to an address, and serve responses until an error occurs.
+/// Create a new server, bind it to an address, and serve responses until an error occurs or
+/// Ctrl-C is received.
 pub async fn serve(serve_args: ServeArgs) -> Result<(), Error> {
     // Load the wasm module into an execution context
-    let ctx =
+    let mut ctx =
         create_execution_context(serve_args.shared(), true, serve_args.profile_guest()).await?;
+    if let Some(kv_namespaces) = serve_args.kv_namespaces() {
+        ctx = ctx.with_kv_namespaces(kv_namespaces);
+    }
+    ctx = ctx.with_kv_isolation(serve_args.kv_isolate_requests());
+    if let Some(kv_diagnostics) = serve_args.kv_diagnostics() {
+        ctx = ctx.with_kv_diagnostics(kv_diagnostics.to_path_buf());
+    }
+    let purge = serve_args
+        .kv_purge_interval()
+        .map(|period| ctx.object_stores().purge_expired_every(period))
+        .transpose()?;
 
     if let Some(guest_profile_path) = serve_args.profile_guest() {
         std::fs::create_dir_all(guest_profile_path)?;
     }
 
+    let kv_diff = KvDiffReport::start(serve_args.shared(), &ctx)?;
     let addr = serve_args.addr();
-    ViceroyService::new(ctx).serve(addr).await?;
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    ViceroyService::new(ctx)
+        .serve_with_graceful_shutdown(addr, ctrl_c, serve_args.shutdown_grace_period())
+        .await?;
+    if let Some(purge) = purge {
+        purge.stop().await;
+    }
 
-    unreachable!()
+    if let Some(kv_diff) = kv_diff {
+        kv_diff.finish()?;
+    }
+    Ok(())
 }
 
 #[tokio::main]
@@ -58,6 +88,7 @@ pub async fn main() -> ExitCode {
     // Parse the command-line options, exiting if there are any errors
     let opts = Opts::parse();
     let cmd = opts.command.unwrap_or(Commands::Serve(opts.serve));
+    viceroy_lib::session::install_kv_panic_hook();
     match cmd {
         Commands::Run(run_args) => {
             install_tracing_subscriber(run_args.shared().verbosity());
@@ -76,21 +107,12 @@ pub async fn main() -> ExitCode {
         }
         Commands::Serve(serve_args) => {
             install_tracing_subscriber(serve_args.shared().verbosity());
-            match {
-                tokio::select! {
-                    _ = tokio::signal::ctrl_c() => {
-                        Ok(())
-                    }
-                    res = serve(serve_args) => {
-                        if let Err(ref e) = res {
-                            event!(Level::ERROR, "{}", e);
-                        }
-                        res
-                    }
-                }
-            } {
+            match serve(serve_args).await {
                 Ok(_) => ExitCode::SUCCESS,
-                Err(_) => ExitCode::FAILURE,
+                Err(e) => {
+                    event!(Level::ERROR, "{}", e);
+                    ExitCode::FAILURE
+                }
             }
         }
         Commands::Adapt(adapt_args) => {
@@ -155,7 +177,67 @@ pub async fn main() -> ExitCode {
                 }
             }
         }
+        Commands::Kv(KvCommands::Export(export_args)) => {
+            install_tracing_subscriber(0);
+            match export_kv_stores(&export_args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    event!(Level::ERROR, "{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Commands::Kv(KvCommands::Replay(replay_args)) => {
+            install_tracing_subscriber(replay_args.verbosity());
+            match replay_kv_trace(&replay_args) {
+                Ok(true) => ExitCode::SUCCESS,
+                Ok(false) => ExitCode::FAILURE,
+                Err(e) => {
+                    event!(Level::ERROR, "{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
+
+/// Print the stores seeded by a configuration file as JSON.
+fn export_kv_stores(args: &KvExportArgs) -> Result<(), Error> {
+    let export = FastlyConfig::from_file(args.config_path())?
+        .object_stores()
+        .export(args.export_options())?;
+    io::stdout().write_all(&export.to_json())?;
+    Ok(())
+}
+
+/// Replay a recorded KV trace, printing any divergences. Returns whether the replay matched the
+/// recording.
+fn replay_kv_trace(args: &KvReplayArgs) -> Result<bool, Error> {
+    let trace = kv_trace::read_trace(args.trace())?;
+    let stores = match args.config_path() {
+        Some(config_path) => FastlyConfig::from_file(config_path)?
+            .object_stores()
+            .clone(),
+        None => ObjectStores::new(),
+    };
+
+    let report = kv_trace::replay(&stores, &trace);
+    for divergence in &report.divergences {
+        println!(
+            "operation {} diverged: {}\n  recorded: {}\n  replayed: {}",
+            divergence.index,
+            serde_json::to_string(&divergence.entry.op).unwrap_or_default(),
+            serde_json::to_string(&divergence.entry.result).unwrap_or_default(),
+            serde_json::to_string(&divergence.actual).unwrap_or_default(),
+        );
     }
+    println!(
+        "replayed {} KV operation(s), {} diverged",
+        report.operations,
+        report.divergences.len()
+    );
+
+    Ok(report.divergences.is_empty())
 }
 
 /// Execute a Wasm program in the Viceroy environment.
@@ -167,7 +249,45 @@ pub async fn run_wasm_main(run_args: RunArgs) -> Result<(), anyhow::Error> {
         Some(stem) => stem.to_string_lossy(),
         None => panic!("program cannot be a directory"),
     };
-    ctx.run_main(&program_name, run_args.wasm_args()).await
+    let kv_diff = KvDiffReport::start(run_args.shared(), &ctx)?;
+    let result = ctx.run_main(&program_name, run_args.wasm_args()).await;
+    if let Some(kv_diff) = kv_diff {
+        kv_diff.finish()?;
+    }
+    result
+}
+
+/// The KV stores as they were seeded, kept to report how they changed with `--kv-diff`.
+struct KvDiffReport {
+    seed: KvExport,
+    stores: ObjectStores,
+    /// The file to write the report to, or `None` for stderr.
+    path: Option<PathBuf>,
+}
+
+impl KvDiffReport {
+    fn start(args: &SharedArgs, ctx: &ExecuteCtx) -> Result<Option<Self>, Error> {
+        let Some(path) = args.kv_diff() else {
+            return Ok(None);
+        };
+        let stores = ctx.object_stores().share();
+        Ok(Some(KvDiffReport {
+            seed: kv_diff::snapshot(&stores)?,
+            stores,
+            path: path.map(Path::to_path_buf),
+        }))
+    }
+
+    /// Report the changes since the stores were seeded.
+    fn finish(self) -> Result<(), Error> {
+        let diff = KvDiff::since(&self.seed, &self.stores)?;
+        match self.path {
+            Some(path) => std::fs::write(path, diff.to_string())?,
+            None if diff.is_empty() => eprintln!("KV stores unchanged since they were seeded"),
+            None => eprint!("KV changes since the stores were seeded:\n{diff}"),
+        }
+        Ok(())
+    }
 }
 
 fn install_tracing_subscriber(verbosity: u8) {
@@ -308,10 +428,16 @@ async fn create_execution_context(
         args.adapt(),
     )?
     .with_log_stderr(args.log_stderr())
-    .with_log_stdout(args.log_stdout());
+    .with_log_stdout(args.log_stdout())
+    .with_deterministic_select(args.deterministic_select())
+    .with_local_extensions(args.local_extensions());
 
     if let Some(config_path) = args.config_path() {
-        let config = FastlyConfig::from_file(config_path)?;
+        let config = if args.skip_kv_validation() {
+            FastlyConfig::from_file_skipping_kv_validation(config_path)?
+        } else {
+            FastlyConfig::from_file(config_path)?
+        };
         let backends = config.backends();
         let device_detection = config.device_detection();
         let geolocation = config.geolocation();
@@ -378,5 +504,30 @@ async fn create_execution_context(
             "no configuration provided, invoke with `-C <TOML_FILE>` to provide a configuration"
         );
     }
+
+    if let Some(kv_strict) = args.kv_strict() {
+        ctx = ctx.with_kv_strict(kv_strict);
+    }
+
+    if let Some(seed) = args.kv_chaos() {
+        // Printed regardless of the log level, as it's needed to retry a failing run.
+        eprintln!("KV chaos mode enabled, with seed {seed}");
+        ctx = ctx.with_kv_chaos(seed);
+    }
+
+    if let Some(budget) = args.kv_memory_budget() {
+        ctx.object_stores().set_memory_budget(Some(budget));
+    }
+
+    if let Some(kv_trace) = args.kv_trace() {
+        event!(
+            Level::INFO,
+            "recording KV operations to {}",
+            kv_trace.display()
+        );
+        let recorder = KvTraceRecorder::create(kv_trace)?;
+        ctx.object_stores().add_observer(Arc::new(recorder));
+    }
+
     Ok(ctx)
 }
@@ -4,13 +4,18 @@ use viceroy_lib::config::UnknownImportBehavior;
 
 use {
     clap::{Args, Parser, Subcommand, ValueEnum},
+    hyper::header::HeaderName,
     std::net::{IpAddr, Ipv4Addr},
     std::{
         collections::HashSet,
         net::SocketAddr,
         path::{Path, PathBuf},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    viceroy_lib::{
+        config::ExperimentalModule, kv_strict::KvStrictConfig, BudgetPolicy, Error, ExportOptions,
+        KvNamespaceConfig, KvStoreError, MemoryBudget, ProfilingStrategy, Redaction,
     },
-    viceroy_lib::{config::ExperimentalModule, Error, ProfilingStrategy},
 };
 
 // Command-line arguments for the Viceroy CLI.
@@ -43,6 +48,20 @@ pub enum Commands {
 
     /// Adapt core wasm to a component.
     Adapt(AdaptArgs),
+
+    /// Work with KV store data outside of a running guest.
+    #[command(subcommand)]
+    Kv(KvCommands),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum KvCommands {
+    /// Replay a trace recorded with `--kv-trace` against freshly seeded stores, reporting any
+    /// operation whose result differs from the recording.
+    Replay(KvReplayArgs),
+
+    /// Print the KV stores seeded by a configuration file as JSON.
+    Export(KvExportArgs),
 }
 
 #[derive(Debug, Args, Clone)]
@@ -51,6 +70,43 @@ pub struct ServeArgs {
     #[arg(long = "addr")]
     socket_addr: Option<SocketAddr>,
 
+    /// How long to wait, in seconds, for in-flight requests and their KV operations to finish
+    /// after receiving Ctrl-C.
+    #[arg(long = "shutdown-grace-period", default_value = "5")]
+    shutdown_grace_period: u64,
+
+    /// Give requests carrying this header their own copy of every KV store, named by the
+    /// header's value and created from the configured seed data on first use.
+    #[arg(long = "kv-namespace-header", value_name = "HEADER")]
+    kv_namespace_header: Option<HeaderName>,
+
+    /// The maximum number of KV namespaces that may be live at once.
+    #[arg(long = "kv-max-namespaces", default_value = "64")]
+    kv_max_namespaces: usize,
+
+    /// How long, in seconds, a KV namespace may go unused before it is discarded.
+    #[arg(long = "kv-namespace-idle-timeout", default_value = "300")]
+    kv_namespace_idle_timeout: u64,
+
+    /// Give each request a copy-on-write view of the KV stores, so that its writes are discarded
+    /// when it ends rather than seen by later requests.
+    #[arg(long = "kv-isolate-requests")]
+    kv_isolate_requests: bool,
+
+    /// When a guest traps, write its trap, KV operations, and the KV keys it touched to a new
+    /// directory under this one.
+    #[arg(long = "kv-diagnostics", value_name = "DIR")]
+    kv_diagnostics: Option<PathBuf>,
+
+    /// Remove expired KV values every this many seconds, rather than only when they are looked
+    /// up again.
+    #[arg(
+        long = "kv-purge-interval",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    kv_purge_interval: Option<u64>,
+
     #[command(flatten)]
     shared: SharedArgs,
 }
@@ -115,6 +171,76 @@ pub struct SharedArgs {
     /// components before running them.
     #[arg(long = "adapt")]
     adapt: bool,
+    /// Record every KV operation, and its result, to the given JSON-lines file.
+    ///
+    /// The trace can be replayed later with `viceroy kv replay`.
+    #[arg(long = "kv-trace", value_name = "PATH")]
+    kv_trace: Option<PathBuf>,
+    /// Start even if the KV stores in the configuration file have problems.
+    ///
+    /// Each problem is logged as a warning, and the objects, settings, or stores it affects are
+    /// left out. Without this, every problem is reported at once, and Viceroy refuses to start.
+    #[arg(long = "skip-kv-validation")]
+    skip_kv_validation: bool,
+    /// Fail each request, or the run, in which the guest receives a KV error, listing the
+    /// operations that failed.
+    #[arg(long = "kv-strict")]
+    kv_strict: bool,
+    /// In strict KV mode, allow any error from this store. May be given more than once.
+    #[arg(
+        long = "kv-strict-allow-store",
+        value_name = "STORE",
+        requires = "kv_strict"
+    )]
+    kv_strict_allow_stores: Vec<String>,
+    /// In strict KV mode, allow this error from any store. May be given more than once.
+    #[arg(
+        long = "kv-strict-allow-error",
+        value_name = "ERROR",
+        value_enum,
+        requires = "kv_strict"
+    )]
+    kv_strict_allow_errors: Vec<KvErrorArg>,
+    /// Make `select` return the lowest-index ready handle, after waiting for any pending KV
+    /// operations among those given to complete, so that it picks the same winner on every run.
+    #[arg(long = "deterministic-select")]
+    deterministic_select: bool,
+    /// Let guests use the hostcalls Viceroy offers beyond production's, such as the one for
+    /// reading a KV store's limits. Guests that rely on these won't run in production.
+    #[arg(long = "local-extensions")]
+    local_extensions: bool,
+    /// Delay each KV operation by a random amount of up to a few milliseconds, so that
+    /// operations started together complete in a shuffled order. The seed is printed at startup.
+    #[arg(long = "kv-chaos", conflicts_with = "deterministic_select")]
+    kv_chaos: bool,
+    /// In KV chaos mode, draw the delays with this seed rather than a random one, to retry a run
+    /// with the same delays.
+    #[arg(long = "kv-chaos-seed", value_name = "SEED", requires = "kv_chaos")]
+    kv_chaos_seed: Option<u64>,
+    /// When the run ends or the server shuts down, report the keys added to, removed from, and
+    /// modified in each KV store since it was seeded. The report goes to stderr, or to a file if
+    /// given as `--kv-diff=PATH`.
+    #[arg(
+        long = "kv-diff",
+        value_name = "PATH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-"
+    )]
+    kv_diff: Option<PathBuf>,
+    /// Limit the bytes of values and metadata the KV stores may hold, so that a guest writing in
+    /// a loop can't use up all of memory. A warning is logged the first time the limit is hit.
+    #[arg(long = "kv-memory-budget", value_name = "BYTES")]
+    kv_memory_budget: Option<usize>,
+    /// What a KV write that would go over the memory budget does: fail as too large, or evict the
+    /// least recently written values to make room.
+    #[arg(
+        long = "kv-memory-budget-policy",
+        value_enum,
+        default_value_t = KvBudgetPolicyArg::Reject,
+        requires = "kv_memory_budget"
+    )]
+    kv_memory_budget_policy: KvBudgetPolicyArg,
 }
 
 #[derive(Debug, Clone)]
@@ -130,6 +256,36 @@ impl ServeArgs {
             .unwrap_or_else(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7676))
     }
 
+    /// How long to wait for in-flight requests to finish when shutting down.
+    pub fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_period)
+    }
+
+    /// How requests are mapped to KV store namespaces, if at all.
+    pub fn kv_namespaces(&self) -> Option<KvNamespaceConfig> {
+        let header = self.kv_namespace_header.clone()?;
+        Some(KvNamespaceConfig {
+            header,
+            max_namespaces: self.kv_max_namespaces,
+            idle_timeout: Duration::from_secs(self.kv_namespace_idle_timeout),
+        })
+    }
+
+    /// Whether each request is given its own view of the KV stores.
+    pub fn kv_isolate_requests(&self) -> bool {
+        self.kv_isolate_requests
+    }
+
+    /// The directory to write KV diagnostic bundles to, if any.
+    pub fn kv_diagnostics(&self) -> Option<&Path> {
+        self.kv_diagnostics.as_deref()
+    }
+
+    /// How often to remove expired KV values, if at all.
+    pub fn kv_purge_interval(&self) -> Option<Duration> {
+        self.kv_purge_interval.map(Duration::from_secs)
+    }
+
     /// The path to write guest profiles to
     pub fn profile_guest(&self) -> Option<PathBuf> {
         if let Some(Profile::Guest { path }) = &self.shared.profile {
@@ -221,6 +377,67 @@ impl SharedArgs {
     pub fn adapt(&self) -> bool {
         self.adapt
     }
+
+    /// The path to record KV operations to.
+    pub fn kv_trace(&self) -> Option<&Path> {
+        self.kv_trace.as_deref()
+    }
+
+    /// Whether to start despite problems with the configured KV stores.
+    pub fn skip_kv_validation(&self) -> bool {
+        self.skip_kv_validation
+    }
+
+    /// The KV errors tolerated in strict mode, if it is enabled.
+    pub fn kv_strict(&self) -> Option<KvStrictConfig> {
+        self.kv_strict.then(|| KvStrictConfig {
+            allowed_stores: self.kv_strict_allow_stores.iter().cloned().collect(),
+            allowed_errors: self
+                .kv_strict_allow_errors
+                .iter()
+                .map(|&e| e.into())
+                .collect(),
+        })
+    }
+
+    /// Where to report how the KV stores changed, if anywhere: the file to write the report to,
+    /// or `None` for stderr.
+    pub fn kv_diff(&self) -> Option<Option<&Path>> {
+        self.kv_diff
+            .as_deref()
+            .map(|path| (path != Path::new("-")).then_some(path))
+    }
+
+    /// Whether `select` picks its winner reproducibly.
+    pub fn deterministic_select(&self) -> bool {
+        self.deterministic_select
+    }
+
+    /// Whether guests may use the hostcalls Viceroy offers beyond production's.
+    pub fn local_extensions(&self) -> bool {
+        self.local_extensions
+    }
+
+    /// The seed to delay KV operations with, if chaos mode is enabled. Unless one was
+    /// given, this is drawn from the clock, so it differs between calls.
+    pub fn kv_chaos(&self) -> Option<u64> {
+        self.kv_chaos.then(|| {
+            self.kv_chaos_seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            })
+        })
+    }
+
+    /// The limit on the memory the KV stores may use, if any.
+    pub fn kv_memory_budget(&self) -> Option<MemoryBudget> {
+        self.kv_memory_budget.map(|max_bytes| MemoryBudget {
+            max_bytes,
+            policy: self.kv_memory_budget_policy.into(),
+        })
+    }
 }
 
 #[derive(Args, Debug, Clone)]
@@ -263,6 +480,125 @@ impl AdaptArgs {
     }
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct KvReplayArgs {
+    /// The path to the trace to replay.
+    trace: PathBuf,
+
+    /// The path to a TOML file whose `local_server` KV stores seed the replay.
+    #[arg(short = 'C', long = "config")]
+    config_path: Option<PathBuf>,
+
+    /// Verbosity of logs for Viceroy. `-v` sets the log level to INFO,
+    /// `-vv` to DEBUG, and `-vvv` to TRACE. This option will not take
+    /// effect if you set RUST_LOG to a value before starting Viceroy
+    #[arg(short = 'v', action = clap::ArgAction::Count)]
+    verbosity: u8,
+}
+
+impl KvReplayArgs {
+    /// The path to the trace to replay.
+    pub fn trace(&self) -> &Path {
+        &self.trace
+    }
+
+    /// The path to a `local_server` configuration file.
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
+    /// Verbosity of logs for Viceroy.
+    pub fn verbosity(&self) -> u8 {
+        self.verbosity
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct KvExportArgs {
+    /// The path to a TOML file whose `local_server` KV stores are exported.
+    #[arg(short = 'C', long = "config")]
+    config_path: PathBuf,
+
+    /// Replace values in stores marked `sensitive` with their length and a short hash.
+    #[arg(long = "redact")]
+    redact: bool,
+
+    /// Redact metadata in sensitive stores as well as values. Implies `--redact`.
+    #[arg(long = "redact-metadata")]
+    redact_metadata: bool,
+
+    /// Leave out generations and last-modified times, so that exports of the same data are
+    /// identical.
+    #[arg(long = "omit-volatile")]
+    omit_volatile: bool,
+}
+
+impl KvExportArgs {
+    /// The path to a `local_server` configuration file.
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// How to treat the contents of sensitive stores.
+    pub fn redaction(&self) -> Redaction {
+        match (self.redact, self.redact_metadata) {
+            (_, true) => Redaction::ValuesAndMetadata,
+            (true, false) => Redaction::Values,
+            (false, false) => Redaction::None,
+        }
+    }
+
+    /// What to include in the export.
+    pub fn export_options(&self) -> ExportOptions {
+        ExportOptions {
+            redaction: self.redaction(),
+            volatile: !self.omit_volatile,
+        }
+    }
+}
+
+/// The KV errors that strict mode can be told to allow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum KvErrorArg {
+    BadRequest,
+    NotFound,
+    PreconditionFailed,
+    PayloadTooLarge,
+    InternalError,
+    TooManyRequests,
+    TooManyKeys,
+}
+
+impl From<KvErrorArg> for KvStoreError {
+    fn from(arg: KvErrorArg) -> KvStoreError {
+        match arg {
+            KvErrorArg::BadRequest => KvStoreError::BadRequest,
+            KvErrorArg::NotFound => KvStoreError::NotFound,
+            KvErrorArg::PreconditionFailed => KvStoreError::PreconditionFailed,
+            KvErrorArg::PayloadTooLarge => KvStoreError::PayloadTooLarge,
+            KvErrorArg::InternalError => KvStoreError::InternalError,
+            KvErrorArg::TooManyRequests => KvStoreError::TooManyRequests,
+            KvErrorArg::TooManyKeys => KvStoreError::TooManyKeys,
+        }
+    }
+}
+
+/// What a KV write over the memory budget does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum KvBudgetPolicyArg {
+    Reject,
+    EvictOldest,
+}
+
+impl From<KvBudgetPolicyArg> for BudgetPolicy {
+    fn from(arg: KvBudgetPolicyArg) -> BudgetPolicy {
+        match arg {
+            KvBudgetPolicyArg::Reject => BudgetPolicy::Reject,
+            KvBudgetPolicyArg::EvictOldest => BudgetPolicy::EvictOldest,
+        }
+    }
+}
+
 /// Enum of available (experimental) wasi modules
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Hash)]
 pub enum ExperimentalModuleArg {
@@ -345,6 +681,8 @@ mod opts_tests {
         clap::{error::ErrorKind, Parser},
         std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
         std::path::PathBuf,
+        std::time::Duration,
+        viceroy_lib::{BudgetPolicy, KvStoreError, MemoryBudget},
     };
 
     fn test_file(name: &str) -> String {
@@ -605,4 +943,239 @@ mod opts_tests {
         }
         Ok(())
     }
+
+    /// Test that strict KV mode's allowlists are read, and need `--kv-strict`.
+    #[test]
+    fn kv_strict_allowlists_are_read() -> TestResult {
+        let args = &[
+            "dummy-program-name",
+            "--kv-strict",
+            "--kv-strict-allow-error",
+            "not-found",
+            "--kv-strict-allow-error",
+            "precondition-failed",
+            "--kv-strict-allow-store",
+            "cache",
+            &test_file("minimal.wat"),
+        ];
+        let opts = Opts::try_parse_from(args)?;
+        let config = opts.serve.shared().kv_strict().expect("strict mode is on");
+        assert_eq!(
+            config.allowed_errors.into_iter().collect::<Vec<_>>(),
+            [KvStoreError::NotFound, KvStoreError::PreconditionFailed]
+        );
+        assert_eq!(
+            config.allowed_stores.into_iter().collect::<Vec<_>>(),
+            ["cache"]
+        );
+
+        let args = &["dummy-program-name", &test_file("minimal.wat")];
+        assert!(Opts::try_parse_from(args)?
+            .serve
+            .shared()
+            .kv_strict()
+            .is_none());
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-strict-allow-store",
+            "cache",
+            &test_file("minimal.wat"),
+        ];
+        match Opts::try_parse_from(args) {
+            Err(err) if err.kind() == ErrorKind::MissingRequiredArgument => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        Ok(())
+    }
+
+    /// Test that the KV purge interval is off by default, and can't be zero.
+    #[test]
+    fn kv_purge_interval_is_read() -> TestResult {
+        let args = &["dummy-program-name", &test_file("minimal.wat")];
+        assert_eq!(Opts::try_parse_from(args)?.serve.kv_purge_interval(), None);
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-purge-interval",
+            "30",
+            &test_file("minimal.wat"),
+        ];
+        assert_eq!(
+            Opts::try_parse_from(args)?.serve.kv_purge_interval(),
+            Some(Duration::from_secs(30))
+        );
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-purge-interval",
+            "0",
+            &test_file("minimal.wat"),
+        ];
+        match Opts::try_parse_from(args) {
+            Err(err) if err.kind() == ErrorKind::ValueValidation => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        Ok(())
+    }
+
+    /// Test that local extensions are off unless asked for.
+    #[test]
+    fn local_extensions_are_read() -> TestResult {
+        let args = &["dummy-program-name", &test_file("minimal.wat")];
+        assert!(!Opts::try_parse_from(args)?
+            .serve
+            .shared()
+            .local_extensions());
+
+        let args = &[
+            "dummy-program-name",
+            "--local-extensions",
+            &test_file("minimal.wat"),
+        ];
+        assert!(Opts::try_parse_from(args)?
+            .serve
+            .shared()
+            .local_extensions());
+        Ok(())
+    }
+
+    /// Test that KV chaos mode takes a seed, and can't be combined with deterministic `select`.
+    #[test]
+    fn kv_chaos_is_read() -> TestResult {
+        let args = &["dummy-program-name", &test_file("minimal.wat")];
+        assert_eq!(Opts::try_parse_from(args)?.serve.shared().kv_chaos(), None);
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-chaos",
+            "--kv-chaos-seed",
+            "42",
+            &test_file("minimal.wat"),
+        ];
+        assert_eq!(
+            Opts::try_parse_from(args)?.serve.shared().kv_chaos(),
+            Some(42)
+        );
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-chaos",
+            &test_file("minimal.wat"),
+        ];
+        assert!(Opts::try_parse_from(args)?
+            .serve
+            .shared()
+            .kv_chaos()
+            .is_some());
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-chaos-seed",
+            "42",
+            &test_file("minimal.wat"),
+        ];
+        match Opts::try_parse_from(args) {
+            Err(err) if err.kind() == ErrorKind::MissingRequiredArgument => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-chaos",
+            "--deterministic-select",
+            &test_file("minimal.wat"),
+        ];
+        match Opts::try_parse_from(args) {
+            Err(err) if err.kind() == ErrorKind::ArgumentConflict => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        Ok(())
+    }
+
+    /// Test that the KV diff goes to stderr unless given a path, which must follow an `=` so the
+    /// module isn't taken for one.
+    #[test]
+    fn kv_diff_is_read() -> TestResult {
+        let args = &["dummy-program-name", &test_file("minimal.wat")];
+        assert_eq!(Opts::try_parse_from(args)?.serve.shared().kv_diff(), None);
+
+        let args = &["dummy-program-name", "--kv-diff", &test_file("minimal.wat")];
+        let opts = Opts::try_parse_from(args)?;
+        assert_eq!(opts.serve.shared().kv_diff(), Some(None));
+        assert!(opts.serve.shared().input().ends_with("minimal.wat"));
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-diff=kv-diff.txt",
+            &test_file("minimal.wat"),
+        ];
+        assert_eq!(
+            Opts::try_parse_from(args)?.serve.shared().kv_diff(),
+            Some(Some(PathBuf::from("kv-diff.txt").as_path()))
+        );
+        Ok(())
+    }
+
+    /// Test that the KV memory budget rejects writes unless told to evict, and that a policy
+    /// needs a budget.
+    #[test]
+    fn kv_memory_budget_is_read() -> TestResult {
+        let args = &["dummy-program-name", &test_file("minimal.wat")];
+        assert_eq!(
+            Opts::try_parse_from(args)?
+                .serve
+                .shared()
+                .kv_memory_budget(),
+            None
+        );
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-memory-budget",
+            "1048576",
+            &test_file("minimal.wat"),
+        ];
+        assert_eq!(
+            Opts::try_parse_from(args)?
+                .serve
+                .shared()
+                .kv_memory_budget(),
+            Some(MemoryBudget {
+                max_bytes: 1048576,
+                policy: BudgetPolicy::Reject,
+            })
+        );
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-memory-budget",
+            "1048576",
+            "--kv-memory-budget-policy",
+            "evict-oldest",
+            &test_file("minimal.wat"),
+        ];
+        assert_eq!(
+            Opts::try_parse_from(args)?
+                .serve
+                .shared()
+                .kv_memory_budget(),
+            Some(MemoryBudget {
+                max_bytes: 1048576,
+                policy: BudgetPolicy::EvictOldest,
+            })
+        );
+
+        let args = &[
+            "dummy-program-name",
+            "--kv-memory-budget-policy",
+            "evict-oldest",
+            &test_file("minimal.wat"),
+        ];
+        match Opts::try_parse_from(args) {
+            Err(err) if err.kind() == ErrorKind::MissingRequiredArgument => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        Ok(())
+    }
 }
@@ -10,6 +10,7 @@ use std::{
     net::{Ipv4Addr, SocketAddr},
     path::PathBuf,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use tracing_subscriber::filter::EnvFilter;
 use viceroy_lib::config::UnknownImportBehavior;
@@ -89,6 +90,7 @@ pub struct Test {
     via_hyper: bool,
     unknown_import_behavior: UnknownImportBehavior,
     adapt_component: bool,
+    kv_chaos: Option<u64>,
 }
 
 impl Test {
@@ -111,6 +113,7 @@ impl Test {
             via_hyper: false,
             unknown_import_behavior: Default::default(),
             adapt_component: false,
+            kv_chaos: None,
         }
     }
 
@@ -133,6 +136,7 @@ impl Test {
             via_hyper: false,
             unknown_import_behavior: Default::default(),
             adapt_component: false,
+            kv_chaos: None,
         }
     }
 
@@ -278,6 +282,12 @@ impl Test {
         self
     }
 
+    /// Run with KV chaos mode enabled, with the given seed.
+    pub fn using_kv_chaos(mut self, seed: u64) -> Self {
+        self.kv_chaos = Some(seed);
+        self
+    }
+
     /// Pass the given requests through this test, returning the associated responses.
     ///
     /// A `Test` can be used repeatedly against different requests, either individually (as with
@@ -320,26 +330,10 @@ impl Test {
             self.backends.start_servers().await;
         }
 
-        let ctx = ExecuteCtx::new(
-            &self.module_path,
-            ProfilingStrategy::None,
-            HashSet::new(),
-            None,
-            self.unknown_import_behavior,
-            self.adapt_component,
-        )?
-        .with_backends(self.backends.backend_configs().await)
-        .with_dictionaries(self.dictionaries.clone())
-        .with_device_detection(self.device_detection.clone())
-        .with_geolocation(self.geolocation.clone())
-        .with_object_stores(self.object_stores.clone())
-        .with_secret_stores(self.secret_stores.clone())
-        .with_capture_logs(self.capture_logs.clone())
-        .with_log_stderr(self.log_stderr)
-        .with_log_stdout(self.log_stdout);
+        let ctx = self.execute_ctx().await?;
 
         if self.via_hyper {
-            let svc = ViceroyService::new(ctx);
+            let svc = ViceroyService::new(ctx.clone());
             // We use the "graceful shutdown" capability of Hyper, with a oneshot channel signaling
             // completion:
             let (tx, rx) = tokio::sync::oneshot::channel();
@@ -423,9 +417,46 @@ impl Test {
             }
         }
 
+        // Guests can keep running after sending their response. Once they have finished, a
+        // well-behaved guest has waited on every KV operation it started.
+        ctx.drain_in_flight_requests(Duration::from_secs(5)).await;
+        assert_eq!(
+            ctx.leaked_kv_handles(),
+            0,
+            "guest left KV operations pending"
+        );
+
         Ok(responses)
     }
 
+    /// Build the Viceroy execution context defined by this test.
+    ///
+    /// This is the context that `against_many()` passes requests through; it is exposed for tests
+    /// that need to drive the context directly.
+    pub async fn execute_ctx(&self) -> Result<ExecuteCtx, Error> {
+        let ctx = ExecuteCtx::new(
+            &self.module_path,
+            ProfilingStrategy::None,
+            HashSet::new(),
+            None,
+            self.unknown_import_behavior,
+            self.adapt_component,
+        )?
+        .with_backends(self.backends.backend_configs().await)
+        .with_dictionaries(self.dictionaries.clone())
+        .with_device_detection(self.device_detection.clone())
+        .with_geolocation(self.geolocation.clone())
+        .with_object_stores(self.object_stores.clone())
+        .with_secret_stores(self.secret_stores.clone())
+        .with_capture_logs(self.capture_logs.clone())
+        .with_log_stderr(self.log_stderr)
+        .with_log_stdout(self.log_stdout);
+        Ok(match self.kv_chaos {
+            Some(seed) => ctx.with_kv_chaos(seed),
+            None => ctx,
+        })
+    }
+
     /// Pass the given request to a Viceroy execution context defined by this test.
     ///
     /// Only the path, query, and fragment of the request URI will be used; the host and port will
@@ -0,0 +1,64 @@
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{Body, Request};
+use std::{net::Ipv4Addr, time::Duration};
+use viceroy_lib::DrainSummary;
+
+// `sleep.wasm` sleeps for 100 milliseconds, which is long enough for the drain to start while the
+// request is still executing.
+viceroy_test!(drain_waits_for_in_flight_requests, |is_component| {
+    let ctx = Test::using_fixture("sleep.wasm")
+        .adapt_component(is_component)
+        .execute_ctx()
+        .await?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let request = tokio::spawn(ctx.clone().handle_request(req, local, remote));
+    while ctx.in_flight_requests() == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    let summary = ctx.drain_in_flight_requests(Duration::from_secs(5)).await;
+    assert_eq!(
+        summary,
+        DrainSummary {
+            completed: 1,
+            abandoned: 0
+        }
+    );
+    assert_eq!(ctx.in_flight_requests(), 0);
+    request.await??;
+
+    Ok(())
+});
+
+viceroy_test!(drain_abandons_requests_after_grace_period, |is_component| {
+    let ctx = Test::using_fixture("sleep.wasm")
+        .adapt_component(is_component)
+        .execute_ctx()
+        .await?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let request = tokio::spawn(ctx.clone().handle_request(req, local, remote));
+    while ctx.in_flight_requests() == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    let summary = ctx.drain_in_flight_requests(Duration::ZERO).await;
+    assert_eq!(
+        summary,
+        DrainSummary {
+            completed: 0,
+            abandoned: 1
+        }
+    );
+    request.await??;
+
+    Ok(())
+});
@@ -0,0 +1,69 @@
+//! Tests for KV hostcalls made by a core module that has been adapted to a component, which is
+//! the path a guest built against the `fastly_kv_store` imports takes when Viceroy runs it.
+
+use crate::common::{Test, TestResult, RUST_FIXTURE_PATH};
+use hyper::{body::to_bytes, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{adapt, KvStoreError, ObjectKey, ObjectStoreKey};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-adapter-test"
+    description = "kv adapter test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seed", data = "s" },
+        { key = "gone", data = "g" },
+    ]
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_hostcalls_through_the_adapter() -> TestResult {
+    let module = std::fs::read(format!("{RUST_FIXTURE_PATH}kv_adapter.wasm"))?;
+    assert!(!adapt::is_component(&module));
+    let component = adapt::adapt_bytes(&module)?;
+    assert!(adapt::is_component(&component));
+
+    let path = std::env::temp_dir().join(format!("viceroy-kv-adapter-{}.wasm", std::process::id()));
+    std::fs::write(&path, component)?;
+    // An absolute path replaces the fixture directory, and the component is run as it is.
+    let ctx = Test::using_fixture(path.to_str().unwrap())
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await;
+    std::fs::remove_file(&path)?;
+    let ctx = ctx?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = to_bytes(resp.into_body()).await?;
+    assert_eq!(
+        std::str::from_utf8(&body)?,
+        "lookup seed: s\n\
+         delete missing: not found\n\
+         lookup gone: not found\n\
+         insert unknown mode: invalid argument\n\
+         list: {\"data\":[\"new\",\"seed\"],\"meta\":{\"limit\":1000}}\n"
+    );
+
+    let stores = ctx.object_stores();
+    let store = || ObjectStoreKey::new("store").unwrap();
+    assert_eq!(
+        stores.lookup(store(), ObjectKey::new("seed")?)?.body(),
+        &b"s+"[..]
+    );
+    assert_eq!(
+        stores.lookup(store(), ObjectKey::new("new")?)?.body(),
+        &b"n"[..]
+    );
+    assert!(matches!(
+        stores.lookup(store(), ObjectKey::new("gone")?),
+        Err(KvStoreError::NotFound)
+    ));
+
+    Ok(())
+}
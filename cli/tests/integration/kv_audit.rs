@@ -0,0 +1,94 @@
+//! Tests that KV writes record the request that made them.
+
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{Body, Request, StatusCode};
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+use viceroy_lib::{KvEvent, KvObserver, KvOp, ObjectKey, ObjectStoreKey, ValueOrigin};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-audit-test"
+    description = "kv audit test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seeded", data = "s" },
+    ]
+"#;
+
+/// Records the request each insert was made for.
+#[derive(Default)]
+struct Inserts(Mutex<Vec<String>>);
+
+impl KvObserver for Inserts {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        if let KvOp::Insert { key, .. } = event.op {
+            let request = event.request.expect("guest writes are made for a request");
+            self.0.lock().unwrap().push(format!("{key}: {request}"));
+        }
+    }
+}
+
+viceroy_test!(kv_writes_record_their_request, |is_component| {
+    let ctx = Test::using_fixture("kv_audit.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    let inserts = Arc::new(Inserts::default());
+    ctx.object_stores().add_observer(inserts.clone());
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    for (method, uri) in [
+        ("POST", "http://localhost/cart"),
+        ("PUT", "http://localhost/wishlist?item=1"),
+    ] {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())?;
+        let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+        assert!(err.is_none(), "{err:?}");
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    assert_eq!(
+        *inserts.0.lock().unwrap(),
+        [
+            "cart: request 0 (POST /cart)",
+            "wishlist: request 1 (PUT /wishlist)",
+        ]
+    );
+
+    let stores = ctx.object_stores();
+    let lookup = |key| {
+        stores
+            .lookup(
+                ObjectStoreKey::new("store").unwrap(),
+                ObjectKey::new(key).unwrap(),
+            )
+            .unwrap()
+    };
+    let cart = lookup("cart");
+    assert_eq!(cart.body(), &b"POST"[..]);
+    assert_eq!(
+        cart.written_by().map(|r| r.to_string()).as_deref(),
+        Some("request 0 (POST /cart)")
+    );
+    assert_eq!(cart.origin(), ValueOrigin::Runtime { req_id: Some(0) });
+    let wishlist = lookup("wishlist");
+    assert_eq!(
+        wishlist.written_by().map(|r| r.to_string()).as_deref(),
+        Some("request 1 (PUT /wishlist)")
+    );
+    assert_eq!(wishlist.origin(), ValueOrigin::Runtime { req_id: Some(1) });
+    assert!(lookup("seeded").written_by().is_none());
+
+    Ok(())
+});
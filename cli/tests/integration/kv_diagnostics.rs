@@ -0,0 +1,111 @@
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use base64::prelude::*;
+use hyper::{Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::kv_trace::{TraceEntry, TraceOp};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-diagnostics-test"
+    description = "kv diagnostics test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [{key = "untouched", data = "left alone"}]
+"#;
+
+viceroy_test!(kv_diagnostics_are_written_on_trap, |is_component| {
+    let dir = std::env::temp_dir().join(format!(
+        "viceroy-kv-diagnostics-{}-{}",
+        std::process::id(),
+        is_component
+    ));
+
+    let ctx = Test::using_fixture("kv_trap.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_kv_diagnostics(dir.clone());
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/trap").body(Body::empty())?;
+    let (resp, err) = ctx.handle_request(req, local, remote).await?;
+    assert!(err.is_some());
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // One bundle, for the one request.
+    let bundles = std::fs::read_dir(&dir)?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(bundles.len(), 1);
+    let bundle = bundles[0].path();
+
+    let trap = std::fs::read_to_string(bundle.join("trap.txt"))?;
+    assert!(trap.starts_with("GET http://localhost/trap\n"), "{trap}");
+
+    let ops = std::fs::read_to_string(bundle.join("kv_events.jsonl"))?
+        .lines()
+        .map(|line| {
+            let entry: TraceEntry = serde_json::from_str(line)?;
+            Ok(match entry.op {
+                TraceOp::Lookup { key } => format!("lookup {}/{key}", entry.store),
+                TraceOp::Head { key } => format!("head {}/{key}", entry.store),
+                TraceOp::Insert { key, .. } => format!("insert {}/{key}", entry.store),
+                TraceOp::Delete { key } => format!("delete {}/{key}", entry.store),
+                TraceOp::List { .. } => format!("list {}", entry.store),
+            })
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+    assert_eq!(
+        ops,
+        [
+            "insert store/kept",
+            "insert store/dropped",
+            "delete store/dropped",
+            "lookup store/kept",
+        ]
+    );
+
+    // Only the keys the session touched are exported, and deleted ones are gone.
+    let export: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(bundle.join("kv_export.json"))?)?;
+    let items = export["stores"]["store"]["items"]
+        .as_object()
+        .expect("store is exported");
+    assert_eq!(items.keys().collect::<Vec<_>>(), ["kept"]);
+    assert_eq!(
+        items["kept"]["body"],
+        BASE64_STANDARD.encode("kept value").as_str()
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+});
+
+viceroy_test!(kv_diagnostics_need_a_trap, |is_component| {
+    let dir = std::env::temp_dir().join(format!(
+        "viceroy-kv-diagnostics-ok-{}-{}",
+        std::process::id(),
+        is_component
+    ));
+
+    let ctx = Test::using_fixture("kv_namespace.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_kv_diagnostics(dir.clone());
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/")
+        .header("x-value", "value")
+        .body(Body::empty())?;
+    let (resp, err) = ctx.handle_request(req, local, remote).await?;
+    assert!(err.is_none());
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!dir.exists());
+
+    Ok(())
+});
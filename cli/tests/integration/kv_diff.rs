@@ -0,0 +1,46 @@
+//! Tests for reporting how a run changed the KV stores, as `--kv-diff` does.
+
+use crate::common::{Test, TestResult};
+use hyper::{Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::kv_diff::{self, KvDiff};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-diff-test"
+    description = "kv diff test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seed", data = "s" },
+        { key = "gone", data = "g" },
+    ]
+    kv_stores.untouched = [{ key = "same", data = "same" }]
+"#;
+
+// `kv_adapter.wasm` inserts `new`, appends to `seed`, and deletes `gone`, among operations that
+// don't change anything. The fixture is run as the core module it is.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_diff_reports_added_removed_and_modified_keys() -> TestResult {
+    let ctx = Test::using_fixture("kv_adapter.wasm")
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    let seed = kv_diff::snapshot(ctx.object_stores())?;
+    assert!(KvDiff::since(&seed, ctx.object_stores())?.is_empty());
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    assert_eq!(
+        KvDiff::since(&seed, ctx.object_stores())?.to_string(),
+        "`store`: removed `gone` (1 byte)\n\
+         `store`: added `new` (1 byte)\n\
+         `store`: modified `seed` (1 -> 2 bytes, +1)\n"
+    );
+
+    Ok(())
+}
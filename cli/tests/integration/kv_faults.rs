@@ -0,0 +1,81 @@
+//! Tests for faults injected into KV operations.
+
+use crate::common::{Test, TestResult};
+use hyper::{body::to_bytes, Body, Request, StatusCode};
+use std::{net::Ipv4Addr, sync::Arc};
+use viceroy_lib::{
+    kv_trace::{self, KvTraceRecorder, TraceOp, TraceResult},
+    KvStoreError, LostWriteRule, ObjectKey, ObjectStoreKey,
+};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-faults-test"
+    description = "kv faults test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seed", data = "s" },
+        { key = "gone", data = "g" },
+    ]
+"#;
+
+// `kv_adapter.wasm` asserts that its inserts of `new` and `seed` succeed, and then lists the
+// store. The fixture is run as the core module it is.
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_writes_succeed_but_are_not_written() -> TestResult {
+    let trace_path =
+        std::env::temp_dir().join(format!("viceroy-kv-faults-{}.jsonl", std::process::id()));
+
+    let ctx = Test::using_fixture("kv_adapter.wasm")
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    let stores = ctx.object_stores();
+    stores.add_observer(Arc::new(KvTraceRecorder::create(&trace_path)?));
+    stores.add_lost_write_rule(LostWriteRule {
+        store: Some("store".to_string()),
+        key_prefix: Some("new".to_string()),
+        probability: 1.0,
+        seed: 0,
+    });
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = to_bytes(resp.into_body()).await?;
+    let body = std::str::from_utf8(&body)?;
+    assert!(
+        body.ends_with("list: {\"data\":[\"seed\"],\"meta\":{\"limit\":1000}}\n"),
+        "{body}"
+    );
+
+    let store = || ObjectStoreKey::new("store").unwrap();
+    assert!(matches!(
+        stores.lookup(store(), ObjectKey::new("new")?),
+        Err(KvStoreError::NotFound)
+    ));
+    assert_eq!(
+        stores.lookup(store(), ObjectKey::new("seed")?)?.body(),
+        &b"s+"[..]
+    );
+
+    // the trace records which insert was lost
+    let trace = kv_trace::read_trace(&trace_path)?;
+    std::fs::remove_file(&trace_path)?;
+    let inserts = trace
+        .iter()
+        .filter_map(|entry| match &entry.op {
+            TraceOp::Insert { key, .. } => Some((key.as_str(), &entry.result)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        inserts,
+        [("new", &TraceResult::Lost), ("seed", &TraceResult::Ok)]
+    );
+
+    Ok(())
+}
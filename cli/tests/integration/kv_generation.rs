@@ -0,0 +1,37 @@
+//! Tests for generations past what the KV hostcalls can give guests.
+
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body, StatusCode};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-generation-test"
+    description = "kv generation test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seeded", data = "s", generation = 5000000000 },
+    ]
+"#;
+
+// A value seeded from a production export can have a generation past `u32::MAX`, and so can every
+// value written after it. Guests are given `u32::MAX` for those, and can compare-and-swap with it.
+viceroy_test!(kv_generations_past_u32_are_saturated, |is_component| {
+    let resp = Test::using_fixture("kv_generation_saturation.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        String::from_utf8(body::to_bytes(resp.into_body()).await?.to_vec())?,
+        "lookup seeded: s, generation 4294967295\n\
+         insert seeded if generation 4294967295: ok\n\
+         insert seeded if generation 7: precondition failed\n\
+         lookup seeded: swapped, generation 4294967295\n"
+    );
+
+    Ok(())
+});
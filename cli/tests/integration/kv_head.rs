@@ -0,0 +1,32 @@
+//! Tests for the `fastly_kv_store` `head` hostcalls.
+
+use crate::common::{Test, TestResult};
+use hyper::{body, StatusCode};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-head-test"
+    description = "kv head test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seeded", data = "hello", metadata = "meta", generation = 7 },
+    ]
+"#;
+
+// The adapter has no `head` to map these hostcalls onto, so the fixture is only run as a core
+// module.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_head_finds_everything_but_the_body() -> TestResult {
+    let resp = Test::using_fixture("kv_head.wasm")
+        .using_fastly_toml(FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        String::from_utf8(body::to_bytes(resp.into_body()).await?.to_vec())?,
+        "head seeded: metadata meta, generation 7, length 5\n\
+         head missing: not found\n"
+    );
+
+    Ok(())
+}
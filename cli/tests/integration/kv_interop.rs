@@ -0,0 +1,56 @@
+//! Tests that the legacy `fastly_object_store` hostcalls and the `fastly_kv_store` hostcalls are
+//! views of the same stores.
+
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{ObjectKey, ObjectStoreKey};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-interop-test"
+    description = "kv interop test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "seeded", data = "s", metadata = "m", generation = 7 },
+    ]
+"#;
+
+// Legacy writes are overwrites with no metadata, so they are seen through the current interface
+// with empty metadata and the next generation, and they clear any metadata the key had.
+viceroy_test!(kv_legacy_hostcalls_share_stores, |is_component| {
+    let ctx = Test::using_fixture("kv_interop.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        String::from_utf8(body::to_bytes(resp.into_body()).await?.to_vec())?,
+        "lookup seeded: s, metadata m, generation 7\n\
+         legacy lookup seeded: s\n\
+         lookup legacy: l, metadata none, generation 8\n\
+         legacy lookup current: c\n\
+         lookup seeded: o, metadata none, generation 10\n\
+         list: {\"data\":[\"current\",\"legacy\",\"seeded\"],\"meta\":{\"limit\":1000}}\n"
+    );
+
+    let stores = ctx.object_stores();
+    let store = ObjectStoreKey::new("store")?;
+    let lookup = |key| stores.lookup(store.clone(), ObjectKey::new(key).unwrap());
+    let legacy = lookup("legacy")?;
+    assert!(legacy.metadata().is_empty());
+    assert_eq!(legacy.metadata_len(), 0);
+    assert_eq!(lookup("current")?.metadata(), b"meta");
+
+    Ok(())
+});
@@ -0,0 +1,67 @@
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body::to_bytes, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{ExecuteCtx, KvInsertMode, ObjectKey, ObjectStoreKey};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-isolation-test"
+    description = "kv isolation test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = []
+"#;
+
+/// Send a request through `ctx`, returning the value the guest read back from the store.
+async fn stored_value(ctx: &ExecuteCtx, value: &str) -> Result<String, anyhow::Error> {
+    let req = Request::get("http://localhost/")
+        .header("x-value", value)
+        .body(Body::empty())?;
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let resp = ctx
+        .clone()
+        .handle_request_with_runtime_error(req, local, remote)
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    Ok(String::from_utf8(
+        to_bytes(resp.into_body()).await?.to_vec(),
+    )?)
+}
+
+viceroy_test!(
+    kv_isolated_requests_do_not_see_each_others_writes,
+    |is_component| {
+        let ctx = Test::using_fixture("kv_namespace.wasm")
+            .adapt_component(is_component)
+            .using_fastly_toml(FASTLY_TOML)?
+            .execute_ctx()
+            .await?
+            .with_kv_isolation(true);
+
+        // Each request finds the store empty, and writes its own value.
+        assert_eq!(stored_value(&ctx, "a").await?, "a");
+        assert_eq!(stored_value(&ctx, "b").await?, "b");
+        let store = ObjectStoreKey::new("store").unwrap();
+        assert!(ctx
+            .object_stores()
+            .lookup(store.clone(), ObjectKey::new("key")?)
+            .is_err());
+
+        // Values in the shared stores are read through.
+        ctx.object_stores().insert(
+            store,
+            ObjectKey::new("key")?,
+            b"shared".to_vec(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        )?;
+        assert_eq!(stored_value(&ctx, "c").await?, "shared");
+
+        Ok(())
+    }
+);
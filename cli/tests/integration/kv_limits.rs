@@ -0,0 +1,58 @@
+//! Tests for the `fastly_kv_store` `limits` hostcall, a local extension.
+
+use crate::{
+    common::{Error, Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-limits-test"
+    description = "kv limits test"
+    language = "rust"
+    [local_server]
+    kv_stores.small = { max_value_size = 16, max_metadata_size = 4, items = [] }
+    kv_stores.prefixed = { key_prefix = "app/", items = [] }
+    kv_stores.plain = []
+"#;
+
+/// Run `kv_limits.wasm`, with or without local extensions, and return its response body.
+async fn run(is_component: bool, local_extensions: bool) -> Result<String, Error> {
+    let ctx = Test::using_fixture("kv_limits.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_local_extensions(local_extensions);
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    Ok(String::from_utf8(
+        body::to_bytes(resp.into_body()).await?.to_vec(),
+    )?)
+}
+
+viceroy_test!(kv_limits_are_read, |is_component| {
+    assert_eq!(
+        run(is_component, true).await?,
+        "small: value 16, metadata 4, key 1024\n\
+         prefixed: value 26214400, metadata 2000, key 1020\n\
+         plain: value 26214400, metadata 2000, key 1024\n"
+    );
+    Ok(())
+});
+
+viceroy_test!(kv_limits_are_unsupported_by_default, |is_component| {
+    assert_eq!(
+        run(is_component, false).await?,
+        "small: unsupported\n\
+         prefixed: unsupported\n\
+         plain: unsupported\n"
+    );
+    Ok(())
+});
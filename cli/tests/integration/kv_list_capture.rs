@@ -0,0 +1,95 @@
+use crate::{
+    common::{Error, Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body, Body, Request, StatusCode};
+use std::{net::Ipv4Addr, sync::Arc};
+use viceroy_lib::{kv_list_capture::KvListCapture, ExecuteCtx};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-list-capture-test"
+    description = "kv list capture test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "a", data = "1" },
+        { key = "b", data = "2" },
+        { key = "c", data = "3" },
+        { key = "d", data = "4" },
+        { key = "e", data = "5" },
+    ]
+"#;
+
+/// Run `kv_list_pages.wasm`, and return the pages it reports receiving.
+async fn list_pages(ctx: &ExecuteCtx) -> Result<Vec<Vec<u8>>, Error> {
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = body::to_bytes(resp.into_body()).await?;
+    Ok(body
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect())
+}
+
+viceroy_test!(kv_list_capture_matches_guest_pages, |is_component| {
+    let capture = Arc::new(KvListCapture::new(64 * 1024));
+    let ctx = Test::using_fixture("kv_list_pages.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_kv_list_capture(capture.clone());
+
+    let guest_pages = list_pages(&ctx).await?;
+    // five keys, two to a page
+    assert_eq!(guest_pages.len(), 3);
+
+    let captured = capture.take();
+    assert_eq!(captured.dropped, 0);
+    let captured_bodies = captured
+        .pages
+        .iter()
+        .map(|page| page.body.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(captured_bodies, guest_pages);
+    assert!(captured.pages.iter().all(|page| page.store == "store"));
+    assert!(captured.pages.iter().all(|page| page.limit == 2));
+    assert_eq!(captured.pages[0].cursor, None);
+    assert!(captured.pages[1..].iter().all(|page| page.cursor.is_some()));
+
+    // each request's pages are captured separately, and tagged with its ID
+    list_pages(&ctx).await?;
+    let second = capture.take();
+    assert_eq!(second.pages.len(), 3);
+    assert!(second
+        .pages
+        .iter()
+        .all(|page| page.req_id != captured.pages[0].req_id));
+    Ok(())
+});
+
+viceroy_test!(kv_list_capture_is_bounded, |is_component| {
+    let ctx = Test::using_fixture("kv_list_pages.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    assert!(ctx.kv_list_capture().is_none());
+
+    // room for the first page, and not the rest
+    let guest_pages = list_pages(&ctx).await?;
+    let capture = Arc::new(KvListCapture::new(guest_pages[0].len()));
+    let ctx = ctx.with_kv_list_capture(capture.clone());
+
+    assert_eq!(list_pages(&ctx).await?, guest_pages);
+    let captured = capture.take();
+    assert_eq!(captured.pages.len(), 1);
+    assert_eq!(captured.pages[0].body, guest_pages[0]);
+    assert_eq!(captured.dropped, 2);
+    Ok(())
+});
@@ -0,0 +1,90 @@
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body::to_bytes, header::HeaderName, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{ExecuteCtx, KvNamespaceConfig};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-namespace-test"
+    description = "kv namespace test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = []
+"#;
+
+/// Send a request through `ctx`, returning the value the guest read back from the store.
+async fn stored_value(
+    ctx: &ExecuteCtx,
+    namespace: Option<&str>,
+    value: &str,
+) -> Result<String, anyhow::Error> {
+    let mut req = Request::get("http://localhost/").header("x-value", value);
+    if let Some(namespace) = namespace {
+        req = req.header("x-test-namespace", namespace);
+    }
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let resp = ctx
+        .clone()
+        .handle_request_with_runtime_error(req.body(Body::empty())?, local, remote)
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    Ok(String::from_utf8(
+        to_bytes(resp.into_body()).await?.to_vec(),
+    )?)
+}
+
+viceroy_test!(kv_namespaces_are_isolated, |is_component| {
+    let ctx = Test::using_fixture("kv_namespace.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_kv_namespaces(KvNamespaceConfig::new(HeaderName::from_static(
+            "x-test-namespace",
+        )));
+
+    // Each namespace keeps the first value written to it.
+    assert_eq!(stored_value(&ctx, Some("A"), "a").await?, "a");
+    assert_eq!(stored_value(&ctx, Some("B"), "b").await?, "b");
+    assert_eq!(stored_value(&ctx, Some("A"), "x").await?, "a");
+    assert_eq!(stored_value(&ctx, Some("B"), "x").await?, "b");
+
+    // Requests without the header use the shared stores, which neither namespace wrote to.
+    assert_eq!(stored_value(&ctx, None, "shared").await?, "shared");
+    assert_eq!(ctx.object_stores().namespace_count(), 2);
+
+    Ok(())
+});
+
+viceroy_test!(kv_namespace_limit_is_enforced, |is_component| {
+    let mut config = KvNamespaceConfig::new(HeaderName::from_static("x-test-namespace"));
+    config.max_namespaces = 1;
+    let ctx = Test::using_fixture("kv_namespace.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_kv_namespaces(config);
+
+    assert_eq!(stored_value(&ctx, Some("A"), "a").await?, "a");
+
+    // Opening a store in a second namespace fails, so the guest panics.
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/")
+        .header("x-test-namespace", "B")
+        .header("x-value", "b")
+        .body(Body::empty())?;
+    let resp = ctx
+        .clone()
+        .handle_request_with_runtime_error(req, local, remote)
+        .await?;
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(ctx.object_stores().namespace_count(), 1);
+
+    Ok(())
+});
@@ -0,0 +1,67 @@
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{Body, Request, StatusCode};
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use viceroy_lib::{KvEvent, KvObserver, KvOp};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-panic-test"
+    description = "kv panic test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = []
+"#;
+
+/// Injects a panic into every KV operation on the key `boom`.
+#[derive(Default)]
+struct PanicOnBoom(AtomicUsize);
+
+impl KvObserver for PanicOnBoom {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        let key = match &event.op {
+            KvOp::Lookup { key, .. }
+            | KvOp::Head { key, .. }
+            | KvOp::Insert { key, .. }
+            | KvOp::Delete { key, .. } => key,
+            KvOp::List { .. } => return,
+        };
+        if *key == "boom" {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            panic!("injected KV panic");
+        }
+    }
+}
+
+viceroy_test!(kv_hostcall_panics_are_contained, |is_component| {
+    // as the CLI does, so the contained panics are logged with their backtraces
+    viceroy_lib::session::install_kv_panic_hook();
+    let ctx = Test::using_fixture("kv_panic.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    let injected = Arc::new(PanicOnBoom::default());
+    ctx.object_stores().add_observer(injected.clone());
+
+    // The guest sees each panicking operation fail with an internal error, and carries on; the
+    // server goes on serving requests after it.
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    for _ in 0..3 {
+        let req = Request::get("http://localhost/").body(Body::empty())?;
+        let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+        assert!(err.is_none(), "{err:?}");
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    assert_eq!(injected.0.load(Ordering::Relaxed), 6);
+
+    Ok(())
+});
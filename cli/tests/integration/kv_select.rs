@@ -0,0 +1,80 @@
+use crate::{
+    common::{Error, Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::ExecuteCtx;
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-select-test"
+    description = "kv select test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = [
+        { key = "k0", data = "v0" },
+        { key = "k1", data = "v1" },
+        { key = "k2", data = "v2" },
+        { key = "k3", data = "v3" },
+    ]
+"#;
+
+async fn select_ctx(is_component: bool, deterministic: bool) -> Result<ExecuteCtx, Error> {
+    Ok(Test::using_fixture("kv_select.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_deterministic_select(deterministic))
+}
+
+/// Run `kv_select.wasm`, which races KV lookups with `select`, and return the winners it reports.
+async fn race(ctx: &ExecuteCtx) -> Result<String, Error> {
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    // The guest checks that every handle can still be waited on after a race.
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    Ok(String::from_utf8(
+        body::to_bytes(resp.into_body()).await?.to_vec(),
+    )?)
+}
+
+viceroy_test!(kv_select_is_deterministic, |is_component| {
+    let ctx = select_ctx(is_component, true).await?;
+    for _ in 0..5 {
+        assert_eq!(race(&ctx).await?, "first: k0\norder: k0 k1 k2 k3\n");
+    }
+    Ok(())
+});
+
+/// Check that the races `kv_select.wasm` reports were won by one of its lookups, and then by
+/// each of them in some order.
+fn assert_all_won(winners: &str) {
+    let (first, order) = winners
+        .strip_prefix("first: ")
+        .and_then(|w| w.trim_end().split_once("\norder: "))
+        .expect("winners are reported");
+    assert!(["k0", "k1", "k2", "k3"].contains(&first), "{winners}");
+    let mut order = order.split(' ').collect::<Vec<_>>();
+    order.sort();
+    assert_eq!(order, ["k0", "k1", "k2", "k3"], "{winners}");
+}
+
+viceroy_test!(kv_select_leaves_losers_pending, |is_component| {
+    let ctx = select_ctx(is_component, false).await?;
+    for _ in 0..5 {
+        assert_all_won(&race(&ctx).await?);
+    }
+    Ok(())
+});
+
+viceroy_test!(kv_select_survives_chaos, |is_component| {
+    let ctx = select_ctx(is_component, false).await?.with_kv_chaos(42);
+    for _ in 0..5 {
+        assert_all_won(&race(&ctx).await?);
+    }
+    Ok(())
+});
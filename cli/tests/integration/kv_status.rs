@@ -0,0 +1,78 @@
+//! Tests that the KV statuses guests see match the table in
+//! `test-fixtures/data/kv-status-matrix.txt`, through both ABI layers.
+
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{body::to_bytes, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{ObjectKey, ObjectStoreKey};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-status-test"
+    description = "kv status test"
+    language = "rust"
+    [local_server.kv_stores.store]
+    max_value_size = 8
+    max_keys = 2
+    items = [{ key = "seed", data = "seed", generation = 7 }]
+    [local_server.kv_stores.reference]
+    read_only = true
+    items = [{ key = "ref", data = "fixed" }]
+"#;
+
+/// The table, with its comments.
+const MATRIX: &str = include_str!("../../../test-fixtures/data/kv-status-matrix.txt");
+
+// `kv_status_matrix.wasm` brings about each condition in the table, in order, and reports the
+// status it sees for each as a row. Every row that differs from the table is reported, so that a
+// change to the statuses can be seen all at once. The read-only store's seeded value is checked to
+// have survived the guest's attempts to overwrite and delete it.
+viceroy_test!(kv_statuses_match_the_matrix, |is_component| {
+    let ctx = Test::using_fixture("kv_status_matrix.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = to_bytes(resp.into_body()).await?;
+    let seen = std::str::from_utf8(&body)?.lines().collect::<Vec<_>>();
+
+    let expected = MATRIX
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>();
+    let differences = expected
+        .iter()
+        .zip(&seen)
+        .filter(|(expected, seen)| expected != seen)
+        .map(|(expected, seen)| format!("  expected `{expected}`, saw `{seen}`"))
+        .collect::<Vec<_>>();
+    assert!(
+        differences.is_empty(),
+        "statuses differ from the matrix:\n{}",
+        differences.join("\n")
+    );
+    assert_eq!(
+        seen.len(),
+        expected.len(),
+        "the fixture reported {} rows, for a matrix of {}",
+        seen.len(),
+        expected.len()
+    );
+
+    let reference = ctx.object_stores().lookup(
+        ObjectStoreKey::new("reference").unwrap(),
+        ObjectKey::new("ref")?,
+    )?;
+    assert_eq!(reference.body(), &b"fixed"[..]);
+
+    Ok(())
+});
@@ -2,7 +2,11 @@ use crate::{
     common::{Test, TestResult},
     viceroy_test,
 };
-use hyper::{body::to_bytes, StatusCode};
+use hyper::{body::to_bytes, Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{
+    wiggle_abi::types::KvInsertMode, KvStoreError, ObjectKey, ObjectStoreKey, ValueOrigin,
+};
 
 viceroy_test!(kv_store, |is_component| {
     const FASTLY_TOML: &str = r#"
@@ -382,7 +386,7 @@ viceroy_test!(kv_store_bad_key_values, |is_component| {
     "#;
     match Test::using_fixture("kv_store.wasm").adapt_component(is_component).using_fastly_toml(BAD_3_FASTLY_TOML) {
         Err(e) => assert_eq!(
-            "invalid configuration for 'store_one': Invalid `key` value used: Keys for objects cannot start with `.well-known/acme-challenge`.",
+            "invalid configuration for 'store_one': Invalid `key` value used: Keys for objects cannot start with `.well-known/acme-challenge/`.",
             &e.to_string()
         ),
         _ => panic!(),
@@ -423,7 +427,7 @@ viceroy_test!(kv_store_bad_key_values, |is_component| {
         kv_stores.store_one = [{key = "carriage\rreturn", data = "This is some data"}]
     "#;
     match Test::using_fixture("kv_store.wasm").adapt_component(is_component).using_fastly_toml(BAD_6_FASTLY_TOML) {
-        Err(e) => assert_eq!("invalid configuration for 'store_one': Invalid `key` value used: Keys for objects cannot contain a `\r`.", &e.to_string()),
+        Err(e) => assert_eq!("invalid configuration for 'store_one': Invalid `key` value used: Keys for objects cannot contain control characters, such as '\\r'.", &e.to_string()),
         _ => panic!(),
     }
 
@@ -436,7 +440,7 @@ viceroy_test!(kv_store_bad_key_values, |is_component| {
         kv_stores.store_one = [{key = "newlines\nin\nthis\neconomy?", data = "This is some data"}]
     "#;
     match Test::using_fixture("kv_store.wasm").adapt_component(is_component).using_fastly_toml(BAD_7_FASTLY_TOML) {
-        Err(e) => assert_eq!("invalid configuration for 'store_one': Invalid `key` value used: Keys for objects cannot contain a `\n`.", &e.to_string()),
+        Err(e) => assert_eq!("invalid configuration for 'store_one': Invalid `key` value used: Keys for objects cannot contain control characters, such as '\\n'.", &e.to_string()),
         _ => panic!(),
     }
 
@@ -507,3 +511,476 @@ viceroy_test!(kv_store_bad_key_values, |is_component| {
 
     Ok(())
 });
+
+/// A [`MakeWriter`][tracing_subscriber::fmt::MakeWriter] that collects log output into a buffer.
+#[derive(Clone, Default)]
+struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// The subscriber installed here is thread-local, so this runs on a current-thread runtime to keep
+// the guest's log events on the same thread.
+#[tokio::test]
+async fn kv_summary_is_logged_on_request_completion() -> TestResult {
+    const FASTLY_TOML: &str = r#"
+        name = "kv-store-test"
+        description = "kv store test"
+        authors = ["Jill Bryson <jbryson@fastly.com>", "Rose McDowall <rmcdowall@fastly.com>"]
+        language = "rust"
+        [local_server]
+        kv_stores.empty_store = []
+        kv_stores.store_one = [{key = "first", data = "This is some data"},{key = "second", file = "../test-fixtures/data/kv-store.txt"}]
+    "#;
+
+    for is_component in [false, true] {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let resp = Test::using_fixture("kv_store.wasm")
+            .adapt_component(is_component)
+            .using_fastly_toml(FASTLY_TOML)?
+            .against_empty()
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+        let summary = logs
+            .lines()
+            .find(|line| line.contains("request completed in"))
+            .expect("request completion is logged");
+        // The fixture looks up "first", "second", and "bar" (a miss), then inserts "foo" under
+        // "bar" and looks it up again.
+        for field in [
+            "kv_lookups=4",
+            "kv_hits=3",
+            "kv_misses=1",
+            "kv_expired_misses=0",
+            "kv_inserts=1",
+            "kv_deletes=0",
+            "kv_lists=0",
+            "kv_bytes_written=3",
+            "kv_errors=0",
+            "kv_time=",
+        ] {
+            assert!(summary.contains(field), "{field} missing from: {summary}");
+        }
+    }
+
+    Ok(())
+}
+
+// With one operation allowed to run against the store at a time, and one more to wait its turn, a
+// guest that starts four lookups together has the second wait for the first, and the last two
+// shed. Chaos mode has the first lookup hold its permit through its delay, which seed 15 makes the
+// longest there is, and the current-thread runtime keeps it from finishing before the guest has
+// started the rest.
+#[tokio::test]
+async fn kv_concurrency_limits_queue_and_shed_operations() -> TestResult {
+    const FASTLY_TOML: &str = r#"
+        name = "kv-store-test"
+        description = "kv store test"
+        language = "rust"
+        [local_server.kv_stores.store]
+        max_concurrent_operations = 1
+        max_queued_operations = 1
+        items = [
+            { key = "k0", data = "v0" },
+            { key = "k1", data = "v1" },
+            { key = "k2", data = "v2" },
+            { key = "k3", data = "v3" },
+        ]
+    "#;
+
+    for is_component in [false, true] {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let resp = Test::using_fixture("kv_concurrency_limit.wasm")
+            .adapt_component(is_component)
+            .using_fastly_toml(FASTLY_TOML)?
+            .using_kv_chaos(15)
+            .against_empty()
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            String::from_utf8(to_bytes(resp.into_body()).await?.to_vec())?,
+            "k0: v0\n\
+             k1: v1\n\
+             k2: too many requests\n\
+             k3: too many requests\n"
+        );
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+        let summary = logs
+            .lines()
+            .find(|line| line.contains("request completed in"))
+            .expect("request completion is logged");
+        for field in ["kv_lookups=4", "kv_hits=2", "kv_errors=2", "kv_queued=1"] {
+            assert!(summary.contains(field), "{field} missing from: {summary}");
+        }
+    }
+
+    Ok(())
+}
+
+// A guest appending where it meant to overwrite can grow a value without bound, so stores can be
+// given a size past which growing values are warned about.
+#[tokio::test]
+async fn kv_values_growing_past_the_warning_size_are_logged() -> TestResult {
+    const FASTLY_TOML: &str = r#"
+        name = "kv-store-test"
+        description = "kv store test"
+        language = "rust"
+        [local_server.kv_stores.store]
+        warn_value_size = 16
+        items = []
+    "#;
+
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let ctx = Test::using_fixture("kv_store.wasm")
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    let stores = ctx.object_stores();
+    for _ in 0..10 {
+        stores.insert(
+            ObjectStoreKey::new("store")?,
+            ObjectKey::new("log")?,
+            b"line\n".to_vec(),
+            KvInsertMode::Append,
+            None,
+            None,
+            None,
+        )?;
+    }
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+    let warnings = logs
+        .lines()
+        .filter(|line| line.contains("past the `warn_value_size` of 16"))
+        .collect::<Vec<_>>();
+    // warned once, on the fourth append, rather than on every append after it
+    assert_eq!(warnings.len(), 1, "{logs}");
+    assert!(warnings[0].contains("WARN"), "{}", warnings[0]);
+    assert!(warnings[0].contains("grown to 20 bytes"), "{}", warnings[0]);
+
+    let stats = stores.insert_stats();
+    assert_eq!(stats["store"].appends, 10);
+    assert_eq!(stats["store"].largest_value, 50);
+
+    Ok(())
+}
+
+const KV_HANDLES_FASTLY_TOML: &str = r#"
+    name = "kv-handles-test"
+    description = "kv handles test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = []
+"#;
+
+// `kv_handles.wasm` asserts that opening the same store twice yields the same handle. The test
+// harness then checks that it left no KV operations pending.
+viceroy_test!(kv_store_handles_are_reused, |is_component| {
+    let resp = Test::using_fixture("kv_handles.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+});
+
+viceroy_test!(pending_kv_handles_are_counted_as_leaked, |is_component| {
+    let ctx = Test::using_fixture("kv_handles.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+
+    let local = (std::net::Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (std::net::Ipv4Addr::LOCALHOST, 0).into();
+    let req = hyper::Request::get("http://localhost/")
+        .header("x-leak", "1")
+        .body(hyper::Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none());
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    ctx.drain_in_flight_requests(std::time::Duration::from_secs(5))
+        .await;
+    assert_eq!(ctx.leaked_kv_handles(), 1);
+
+    Ok(())
+});
+
+// `kv_metadata_retry.wasm` looks up a value with a metadata buffer that is too small, then retries
+// on the same handle with the size it was told it needs. This only runs as core wasm: the
+// component interface returns a lookup result whose metadata can be read with any buffer size, so
+// the retry is up to the adapter rather than the host.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_lookup_metadata_can_be_retried_with_a_larger_buffer() -> TestResult {
+    let resp = Test::using_fixture("kv_metadata_retry.wasm")
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+// The metadata `kv_metadata_retry.wasm` writes can be checked from the host, through a snapshot
+// of the store taken after the request.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_snapshot_shows_metadata_written_by_the_guest() -> TestResult {
+    let ctx = Test::using_fixture("kv_metadata_retry.wasm")
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none());
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let store = ObjectStoreKey::new("store")?;
+    let snapshot = ctx.object_stores().snapshot(&store, None)?;
+    assert_eq!(snapshot.len(), 1);
+    let (key, value) = &snapshot[0];
+    assert_eq!(key, "key");
+    assert_eq!(value.metadata(), b"some metadata that needs a big buffer");
+    assert!(matches!(
+        value.origin(),
+        ValueOrigin::Runtime { req_id: Some(_) }
+    ));
+
+    Ok(())
+}
+
+// `kv_ttl_zero.wasm` inserts with a TTL of zero through the insert config, which is rejected. As a
+// component, the same guest exercises the component insert config through the adapter.
+viceroy_test!(kv_insert_with_zero_ttl_is_rejected, |is_component| {
+    let resp = Test::using_fixture("kv_ttl_zero.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+});
+
+// `kv_bad_pointers.wasm` passes bad out-pointers to each KV hostcall, and checks that the calls
+// fail without consuming any handles before retrying them with good pointers. This only runs as
+// core wasm, since with a component the adapter owns the out-pointers.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_bad_out_pointers_leave_the_store_alone() -> TestResult {
+    let ctx = Test::using_fixture("kv_bad_pointers.wasm")
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Only the retried calls took effect: each value was inserted once, and only one was deleted.
+    let stores = ctx.object_stores();
+    let store = || ObjectStoreKey::new("store").unwrap();
+    let kept = stores.lookup(store(), ObjectKey::new("kept")?)?;
+    assert_eq!(kept.body(), &b"value"[..]);
+    assert!(matches!(
+        stores.lookup(store(), ObjectKey::new("deleted")?),
+        Err(KvStoreError::NotFound)
+    ));
+
+    Ok(())
+}
+
+// After `kv_store.wasm` runs, the seeded values are still marked as seed data, and the value the
+// guest wrote is marked with the request that wrote it.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_values_record_their_origin() -> TestResult {
+    const FASTLY_TOML: &str = r#"
+        name = "kv-store-test"
+        description = "kv store test"
+        authors = ["Jill Bryson <jbryson@fastly.com>", "Rose McDowall <rmcdowall@fastly.com>"]
+        language = "rust"
+        [local_server]
+        kv_stores.empty_store = []
+        kv_stores.store_one = [{key = "first", data = "This is some data"},{key = "second", file = "../test-fixtures/data/kv-store.txt"}]
+    "#;
+
+    let ctx = Test::using_fixture("kv_store.wasm")
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let stores = ctx.object_stores();
+    let seeded = stores.lookup(ObjectStoreKey::new("store_one")?, ObjectKey::new("first")?)?;
+    assert_eq!(seeded.origin(), ValueOrigin::Seed);
+    let written = stores.lookup(ObjectStoreKey::new("empty_store")?, ObjectKey::new("bar")?)?;
+    assert!(matches!(
+        written.origin(),
+        ValueOrigin::Runtime { req_id: Some(_) }
+    ));
+
+    Ok(())
+}
+
+// `kv_long_key.wasm` checks that a 1025-byte key is a buffer length error for lookup, insert, and
+// delete, while a 1024-byte key works. With a component, the host reports the 1024-byte limit in
+// the `buffer-len` error, which the adapter turns into the same status.
+viceroy_test!(
+    kv_over_long_keys_are_a_buffer_length_error,
+    |is_component| {
+        let resp = Test::using_fixture("kv_long_key.wasm")
+            .adapt_component(is_component)
+            .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+            .against_empty()
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
+);
+
+// `kv_bad_key.wasm` checks that inserting, looking up, or deleting a key with a line break, a key
+// named `..`, or a key with a `#` starts the operation, and that waiting on it reports a bad
+// request, as production does, rather than the hostcall failing.
+viceroy_test!(kv_invalid_keys_are_a_bad_request, |is_component| {
+    let resp = Test::using_fixture("kv_bad_key.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+});
+
+// `kv_non_utf8_key.wasm` checks that the key `[0xff, 0xfe]` is a bad request for insert, lookup,
+// and delete, and that opening a store by that name is an invalid argument. The component
+// interface takes keys and names as bytes, so only a component can be given ones that aren't
+// UTF-8; the module's hostcalls take them as strings.
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_non_utf8_keys_are_a_bad_request_component() -> TestResult {
+    let resp = Test::using_fixture("kv_non_utf8_key.wasm")
+        .adapt_component(true)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+// `kv_long_list_options.wasm` checks that a list prefix as long as the longest key works, while a
+// longer prefix, or a ten megabyte prefix or cursor, is an invalid argument. The module's hostcall
+// refuses them before copying them out of guest memory.
+viceroy_test!(kv_over_long_list_options_are_refused, |is_component| {
+    let resp = Test::using_fixture("kv_long_list_options.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+});
+
+// `kv_double_wait.wasm` waits twice on the same pending insert, lookup, and delete. The second
+// wait is a bad handle, and the body returned by the first lookup wait can still be read.
+viceroy_test!(kv_waiting_twice_is_a_bad_handle, |is_component| {
+    let resp = Test::using_fixture("kv_double_wait.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+});
+
+// `kv_bad_handles.wasm` passes store handles it never opened, pending handles of the wrong kind,
+// and `u32::MAX` to the KV hostcalls. Each is a bad handle rather than a host panic, and the
+// operations that were actually started can still be waited on.
+viceroy_test!(kv_bad_handles_are_rejected, |is_component| {
+    let resp = Test::using_fixture("kv_bad_handles.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(KV_HANDLES_FASTLY_TOML)?
+        .against_empty()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+});
+
+// Every problem with the configured stores is reported at once, grouped by store, before any
+// guest runs.
+#[test]
+fn kv_store_config_problems_are_reported_together() {
+    const FASTLY_TOML: &str = r#"
+        name = "kv-store-test"
+        description = "kv store test"
+        language = "rust"
+        [local_server]
+        kv_stores.store_one = [
+            {key = "first", data = "This is some data"},
+            {key = "first", data = "This is more data"},
+            {key = "second", data = 3},
+        ]
+        kv_stores.store_two = [{key = "", data = "This is some data"}]
+    "#;
+    match Test::using_fixture("kv_store.wasm").using_fastly_toml(FASTLY_TOML) {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "invalid configuration for 2 object store(s), with 3 problem(s):\n  \
+             'store_one':\n    \
+             The key `first` is used by more than one object.\n    \
+             The `data` value for the object `second` is not a string.\n  \
+             'store_two':\n    \
+             Invalid `key` value used: Keys for objects cannot be empty."
+        ),
+        _ => panic!(),
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{
+    common::{Error, Test, TestResult},
+    viceroy_test,
+};
+use hyper::{Body, Request, StatusCode};
+use std::net::Ipv4Addr;
+use viceroy_lib::{kv_strict::KvStrictConfig, ExecuteCtx, KvStoreError};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-strict-test"
+    description = "kv strict test"
+    language = "rust"
+    [local_server]
+    kv_stores.store = []
+"#;
+
+/// Run `kv_strict.wasm`, which looks up a missing key and then adds a key that already exists, in
+/// strict mode with `config`.
+async fn run_strict(
+    is_component: bool,
+    config: KvStrictConfig,
+) -> Result<(ExecuteCtx, StatusCode, Option<anyhow::Error>), Error> {
+    let ctx = Test::using_fixture("kv_strict.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?
+        .with_kv_strict(config);
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+    Ok((ctx, resp.status(), err))
+}
+
+viceroy_test!(kv_strict_mode_fails_the_request, |is_component| {
+    let (ctx, status, err) = run_strict(is_component, KvStrictConfig::default()).await?;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(ctx.kv_strict_failures(), 1);
+
+    // The failure lists each operation the guest saw fail.
+    let err = err.expect("request failed").to_string();
+    assert_eq!(
+        err,
+        "guest received 2 KV error(s) not allowed in strict mode:\n  \
+         lookup of `missing` in `store` failed with NotFound\n  \
+         insert of `key` in `store` failed with PreconditionFailed"
+    );
+
+    Ok(())
+});
+
+viceroy_test!(kv_strict_mode_allows_listed_errors, |is_component| {
+    let config = KvStrictConfig {
+        allowed_errors: [KvStoreError::NotFound, KvStoreError::PreconditionFailed].into(),
+        ..Default::default()
+    };
+    let (ctx, status, err) = run_strict(is_component, config).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ctx.kv_strict_failures(), 0);
+
+    // Allowing only some of them still fails the request.
+    let config = KvStrictConfig {
+        allowed_errors: [KvStoreError::NotFound].into(),
+        ..Default::default()
+    };
+    let (ctx, status, err) = run_strict(is_component, config).await?;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(err.unwrap().to_string().contains("PreconditionFailed"));
+    assert_eq!(ctx.kv_strict_failures(), 1);
+
+    Ok(())
+});
+
+viceroy_test!(kv_strict_mode_allows_listed_stores, |is_component| {
+    let config = KvStrictConfig {
+        allowed_stores: ["store".to_string()].into(),
+        ..Default::default()
+    };
+    let (ctx, status, err) = run_strict(is_component, config).await?;
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ctx.kv_strict_failures(), 0);
+
+    Ok(())
+});
@@ -0,0 +1,97 @@
+use crate::{
+    common::{Test, TestResult},
+    viceroy_test,
+};
+use hyper::{Body, Request, StatusCode};
+use std::{net::Ipv4Addr, str::FromStr, sync::Arc};
+use viceroy_lib::{
+    config::FastlyConfig,
+    kv_trace::{self, KvTraceRecorder, TraceOp, TraceResult},
+};
+
+const FASTLY_TOML: &str = r#"
+    name = "kv-trace-test"
+    description = "kv trace test"
+    authors = ["Jill Bryson <jbryson@fastly.com>", "Rose McDowall <rmcdowall@fastly.com>"]
+    language = "rust"
+    [local_server]
+    kv_stores.empty_store = []
+    kv_stores.store_one = [{key = "first", data = "This is some data"},{key = "second", file = "../test-fixtures/data/kv-store.txt"}]
+"#;
+
+viceroy_test!(kv_trace_records_and_replays, |is_component| {
+    let trace_path = std::env::temp_dir().join(format!(
+        "viceroy-kv-trace-{}-{}.jsonl",
+        std::process::id(),
+        is_component
+    ));
+
+    let ctx = Test::using_fixture("kv_store.wasm")
+        .adapt_component(is_component)
+        .using_fastly_toml(FASTLY_TOML)?
+        .execute_ctx()
+        .await?;
+    ctx.object_stores()
+        .add_observer(Arc::new(KvTraceRecorder::create(&trace_path)?));
+
+    let local = (Ipv4Addr::LOCALHOST, 80).into();
+    let remote = (Ipv4Addr::LOCALHOST, 0).into();
+    let req = Request::get("http://localhost/").body(Body::empty())?;
+    let (resp, err) = ctx.handle_request(req, local, remote).await?;
+    assert!(err.is_none());
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let trace = kv_trace::read_trace(&trace_path)?;
+    std::fs::remove_file(&trace_path)?;
+
+    // The fixture looks up "first" and "second", misses on "bar", then inserts "bar" and looks it
+    // up again.
+    let ops = trace
+        .iter()
+        .map(|entry| match &entry.op {
+            TraceOp::Lookup { key } => format!("lookup {}/{key}", entry.store),
+            TraceOp::Insert { key, .. } => format!("insert {}/{key}", entry.store),
+            other => panic!("unexpected operation: {other:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ops,
+        [
+            "lookup store_one/first",
+            "lookup store_one/second",
+            "lookup empty_store/bar",
+            "insert empty_store/bar",
+            "lookup empty_store/bar",
+        ]
+    );
+    assert_eq!(
+        trace[2].result,
+        TraceResult::Error {
+            error: "NotFound".to_string()
+        }
+    );
+
+    // Against freshly seeded stores, every operation should reproduce its recorded result.
+    let seeded = FastlyConfig::from_str(FASTLY_TOML)?.object_stores().clone();
+    let report = kv_trace::replay(&seeded, &trace);
+    assert_eq!(report.operations, 5);
+    assert!(report.divergences.is_empty(), "{:?}", report.divergences);
+
+    // With the same stores but none of the seed data, the first two lookups miss.
+    let unseeded = FastlyConfig::from_str(
+        r#"
+        [local_server]
+        kv_stores.empty_store = []
+        kv_stores.store_one = []
+    "#,
+    )?;
+    let report = kv_trace::replay(unseeded.object_stores(), &trace);
+    let diverged = report
+        .divergences
+        .iter()
+        .map(|d| d.index)
+        .collect::<Vec<_>>();
+    assert_eq!(diverged, [0, 1]);
+
+    Ok(())
+});
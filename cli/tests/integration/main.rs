@@ -10,10 +10,28 @@ mod downstream_req;
 mod edge_rate_limiting;
 mod env_vars;
 mod geolocation_lookup;
+mod graceful_shutdown;
 mod grpc;
 mod http_semantics;
 mod inspect;
+mod kv_adapter;
+mod kv_audit;
+mod kv_diagnostics;
+mod kv_diff;
+mod kv_faults;
+mod kv_generation;
+mod kv_head;
+mod kv_interop;
+mod kv_isolation;
+mod kv_limits;
+mod kv_list_capture;
+mod kv_namespace;
+mod kv_panic;
+mod kv_select;
+mod kv_status;
 mod kv_store;
+mod kv_strict;
+mod kv_trace;
 mod logging;
 mod memory;
 mod request;
@@ -2508,9 +2508,27 @@ pub mod fastly_kv_store {
         }
     }
 
+    /// Fails for a mode this version of the ABI doesn't define, such as one from a newer SDK.
+    impl TryFrom<u32> for InsertMode {
+        type Error = ();
+
+        fn try_from(value: u32) -> Result<Self, Self::Error> {
+            [
+                InsertMode::Overwrite,
+                InsertMode::Add,
+                InsertMode::Append,
+                InsertMode::Prepend,
+            ]
+            .into_iter()
+            .find(|mode| *mode as u32 == value)
+            .ok_or(())
+        }
+    }
+
     #[repr(C)]
     pub struct InsertConfig {
-        pub mode: InsertMode,
+        /// An [`InsertMode`], read as the raw value the guest wrote, since it may not be one.
+        pub mode: u32,
         pub if_generation_match: u32,
         pub metadata: *const u8,
         pub metadata_len: u32,
@@ -2520,7 +2538,7 @@ pub mod fastly_kv_store {
     impl Default for InsertConfig {
         fn default() -> Self {
             InsertConfig {
-                mode: InsertMode::Overwrite,
+                mode: InsertMode::Overwrite as u32,
                 if_generation_match: 0,
                 metadata: std::ptr::null(),
                 metadata_len: 0,
@@ -2815,9 +2833,12 @@ pub mod fastly_kv_store {
         let key = unsafe { slice::from_raw_parts(key_ptr, key_len) };
 
         let insert_config_mask = insert_config_mask.into();
+        let Ok(mode) = InsertMode::try_from(unsafe { (*insert_config).mode }) else {
+            return FastlyStatus::INVALID_ARGUMENT;
+        };
         let insert_config = unsafe {
             kv_store::InsertConfig {
-                mode: (*insert_config).mode.into(),
+                mode: mode.into(),
                 if_generation_match: (*insert_config).if_generation_match,
                 metadata: {
                     let len = usize::try_from((*insert_config).metadata_len).trapping_unwrap();
@@ -2999,6 +3020,33 @@ pub mod fastly_kv_store {
             }
         }
     }
+
+    /// The limits that writes to a store are held to, in bytes.
+    #[repr(C)]
+    pub struct KvStoreLimits {
+        pub max_value_size: u64,
+        pub max_metadata_size: u64,
+        pub max_key_len: u64,
+    }
+
+    #[export_name = "fastly_kv_store#limits"]
+    pub fn limits(kv_store_handle: KVStoreHandle, limits_out: *mut KvStoreLimits) -> FastlyStatus {
+        match kv_store::limits(kv_store_handle) {
+            Ok(res) => {
+                unsafe {
+                    *limits_out = KvStoreLimits {
+                        max_value_size: res.max_value_size,
+                        max_metadata_size: res.max_metadata_size,
+                        max_key_len: res.max_key_len,
+                    };
+                }
+
+                FastlyStatus::OK
+            }
+
+            Err(e) => e.into(),
+        }
+    }
 }
 
 pub mod fastly_secret_store {
@@ -0,0 +1,61 @@
+#ifndef VICEROY_KV_SEED_H
+#define VICEROY_KV_SEED_H
+
+/* Generated by cbindgen from viceroy-lib's `ffi` module. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ A set of stores being seeded, created by [`viceroy_kv_seed_new`] and freed by
+ [`viceroy_kv_seed_free`].
+ */
+typedef struct ViceroyKvSeed ViceroyKvSeed;
+
+/*
+ Create an empty seed. Never returns null.
+ */
+ViceroyKvSeed *viceroy_kv_seed_new(void);
+
+/*
+ Free a seed. Does nothing if `seed` is null.
+ */
+void viceroy_kv_seed_free(ViceroyKvSeed *seed);
+
+/*
+ Add an empty store named `store`, a NUL-terminated UTF-8 string, if there isn't one already.
+ */
+int viceroy_kv_seed_add_store(ViceroyKvSeed *seed, const char *store);
+
+/*
+ Set `key` in `store` to the `value_len` bytes at `value`, with the `metadata_len` bytes at
+ `metadata` as its metadata, creating the store if needed. `store` and `key` are NUL-terminated
+ UTF-8 strings; `value` and `metadata` may be null if their lengths are zero.
+ */
+int viceroy_kv_seed_insert(ViceroyKvSeed *seed,
+                           const char *store,
+                           const char *key,
+                           const uint8_t *value,
+                           size_t value_len,
+                           const uint8_t *metadata,
+                           size_t metadata_len);
+
+/*
+ Write the seed to the file at `path`, a NUL-terminated UTF-8 string, replacing it if it exists.
+
+ The file is an export without generations or last-modified times, so the same seed always
+ writes the same file. The seed can still be added to, and written again, afterwards.
+ */
+int viceroy_kv_seed_write(ViceroyKvSeed *seed, const char *path);
+
+/*
+ The message for the last call on `seed` that failed, as a NUL-terminated UTF-8 string, or null
+ if the last call succeeded. The message belongs to the seed, and is only valid until the next
+ call on it.
+ */
+const char *viceroy_kv_seed_last_error(const ViceroyKvSeed *seed);
+
+#endif /* VICEROY_KV_SEED_H */
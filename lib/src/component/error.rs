@@ -3,7 +3,7 @@ use {
     crate::{
         config::ClientCertError,
         error::{self, HandleError},
-        object_store::{KeyValidationError, KvStoreError, ObjectStoreError},
+        object_store::{KeyValidationError, KvStoreError, ObjectStoreError, MAX_KEY_BYTES},
         wiggle_abi::{DictionaryError, SecretStoreError},
     },
     http::{
@@ -131,8 +131,10 @@ impl From<ObjectStoreError> for types::Error {
         use ObjectStoreError::*;
         match err {
             MissingObject => types::Error::OptionalNone,
-            PoisonedLock => panic!("{}", err),
+            PoisonedLock => types::Error::GenericError,
             UnknownObjectStore(_) => types::Error::InvalidArgument,
+            InvalidStoreName(_) => types::Error::InvalidArgument,
+            UnsupportedExportVersion(_) | InvalidExport(_) => types::Error::InvalidArgument,
         }
     }
 }
@@ -141,14 +143,15 @@ impl From<KvStoreError> for types::Error {
     fn from(err: KvStoreError) -> Self {
         use KvStoreError::*;
         match err {
-            Uninitialized => panic!("{}", err),
-            Ok => panic!("{err} should never be converted to an error"),
+            // neither should ever be converted to an error
+            Uninitialized | Ok => types::Error::GenericError,
             BadRequest => types::Error::InvalidArgument,
             NotFound => types::Error::OptionalNone,
             PreconditionFailed => types::Error::InvalidArgument,
             PayloadTooLarge => types::Error::InvalidArgument,
             InternalError => types::Error::InvalidArgument,
             TooManyRequests => types::Error::InvalidArgument,
+            TooManyKeys => types::Error::LimitExceeded,
         }
     }
 }
@@ -156,7 +159,12 @@ impl From<KvStoreError> for types::Error {
 impl From<ResourceTableError> for types::Error {
     fn from(err: ResourceTableError) -> Self {
         match err {
-            _ => panic!("{}", err),
+            ResourceTableError::NotPresent | ResourceTableError::WrongType => {
+                types::Error::BadHandle
+            }
+            ResourceTableError::Full | ResourceTableError::HasChildren => {
+                types::Error::GenericError
+            }
         }
     }
 }
@@ -165,7 +173,8 @@ impl From<KvStoreError> for KvStatus {
     fn from(err: KvStoreError) -> Self {
         use KvStoreError::*;
         match err {
-            Uninitialized => panic!("{}", err),
+            // never set by the stores
+            Uninitialized => KvStatus::InternalError,
             Ok => KvStatus::Ok,
             BadRequest => KvStatus::BadRequest,
             NotFound => KvStatus::NotFound,
@@ -173,13 +182,20 @@ impl From<KvStoreError> for KvStatus {
             PayloadTooLarge => KvStatus::PayloadTooLarge,
             InternalError => KvStatus::InternalError,
             TooManyRequests => KvStatus::TooManyRequests,
+            // never reported this way: `insert-wait` fails with `LimitExceeded` instead
+            TooManyKeys => KvStatus::InternalError,
         }
     }
 }
 
 impl From<KeyValidationError> for types::Error {
-    fn from(_: KeyValidationError) -> Self {
-        types::Error::GenericError
+    fn from(err: KeyValidationError) -> Self {
+        match err {
+            // Report the longest key allowed, as the length a buffer error carries. Keys given to
+            // a store with a `key_prefix` are reported as `KvKeyTooLong`, with the shorter limit.
+            KeyValidationError::Over1024Bytes => types::Error::BufferLen(MAX_KEY_BYTES as u64),
+            _ => types::Error::GenericError,
+        }
     }
 }
 
@@ -226,6 +242,8 @@ impl From<error::Error> for types::Error {
             Error::ObjectStoreError(e) => e.into(),
             Error::KvStoreError(e) => e.into(),
             Error::SecretStoreError(e) => e.into(),
+            Error::ObjectStoreKeyValidationError(e) => e.into(),
+            Error::KvKeyTooLong { max_len } => types::Error::BufferLen(max_len as u64),
             // All other hostcall errors map to a generic `ERROR` value.
             Error::AbiVersionMismatch
             | Error::BackendUrl(_)
@@ -251,13 +269,13 @@ impl From<error::Error> for types::Error {
             | Error::BackendNameRegistryError(_)
             | Error::HttpError(_)
             | Error::UnknownObjectStore(_)
-            | Error::ObjectStoreKeyValidationError(_)
             | Error::UnfinishedStreamingBody
             | Error::ValueAbsent
             | Error::ToStr(_)
             | Error::InvalidAlpnRepsonse { .. }
             | Error::DeviceDetectionError(_)
             | Error::Again
+            | Error::InvalidKvTrace { .. }
             | Error::SharedMemory => types::Error::GenericError,
         }
     }
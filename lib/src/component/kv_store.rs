@@ -9,16 +9,43 @@ use {
     },
     crate::{
         linking::ComponentCtx,
-        object_store::{ObjectKey, ObjectStoreError},
+        object_store::{
+            max_key_len, KeyValidationError, KeyValidationProfile, KvStoreError, ObjectKey,
+            ObjectStoreError, ObjectStoreKey, MAX_KEY_BYTES, MAX_LIST_CURSOR_LEN,
+        },
         session::{
             PeekableTask, PendingKvDeleteTask, PendingKvInsertTask, PendingKvListTask,
-            PendingKvLookupTask,
+            PendingKvLookupTask, Session,
         },
         wiggle_abi::types::KvInsertMode,
     },
+    futures::future::{self, FutureExt},
     wasmtime_wasi::WasiView,
 };
 
+/// Make a key for `store` out of a guest's, as the `fastly_kv_store` hostcalls do: a key that is
+/// too long is a buffer length error for the call, while any other invalid key, including one
+/// that isn't UTF-8, is the `BadRequest` the operation results in, reported when the guest waits
+/// on it. The buffer length error carries the longest key the store takes, which its `key_prefix`
+/// makes shorter.
+fn op_key(
+    session: &Session,
+    store: &ObjectStoreKey,
+    key: Vec<u8>,
+) -> Result<Result<ObjectKey, KvStoreError>, types::Error> {
+    let Ok(key) = String::from_utf8(key) else {
+        return Ok(Err(KvStoreError::BadRequest));
+    };
+    match session.kv_key(store, key, KeyValidationProfile::KvStore) {
+        Ok(key) => Ok(Ok(key)),
+        Err(KeyValidationError::Over1024Bytes) => {
+            let max_len = max_key_len(&session.kv_key_prefix(store));
+            Err(types::Error::BufferLen(max_len as u64))
+        }
+        Err(_) => Ok(Err(KvStoreError::BadRequest)),
+    }
+}
+
 pub struct LookupResult {
     body: http_body::BodyHandle,
     metadata: Option<Vec<u8>>,
@@ -70,13 +97,12 @@ impl kv_store::HostLookupResult for ComponentCtx {
 #[async_trait::async_trait]
 impl kv_store::Host for ComponentCtx {
     async fn open(&mut self, name: Vec<u8>) -> Result<Option<kv_store::Handle>, types::Error> {
-        let name = String::from_utf8(name)?;
-        if self.session.kv_store.store_exists(&name)? {
+        // a name that isn't UTF-8 can't be a valid store name
+        let name = String::from_utf8(name).map_err(|_| KvStoreError::BadRequest)?;
+        match self.session.kv_store_open(&name)? {
             // todo (byoung), handle optional/none/error case
-            let h = self.session.kv_store_handle(&name)?;
-            Ok(Some(h.into()))
-        } else {
-            Err(ObjectStoreError::UnknownObjectStore(name.to_owned()).into())
+            Some(h) => Ok(Some(h.into())),
+            None => Err(ObjectStoreError::UnknownObjectStore(name.to_owned()).into()),
         }
     }
 
@@ -85,11 +111,13 @@ impl kv_store::Host for ComponentCtx {
         store: kv_store::Handle,
         key: Vec<u8>,
     ) -> Result<kv_store::LookupHandle, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap();
-        let key = String::from_utf8(key)?;
-        // just create a future that's already ready
-        let fut = futures::future::ok(self.session.obj_lookup(store.clone(), ObjectKey::new(key)?));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.session.get_kv_store_key(store.into())?;
+        let key = op_key(&self.session, store, key)?;
+        let fut = match key {
+            Ok(key) => self.session.kv_lookup(store.clone(), key).left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         let lh = self
             .session
             .insert_pending_kv_lookup(PendingKvLookupTask::new(task));
@@ -115,13 +143,15 @@ impl kv_store::Host for ComponentCtx {
 
         match resp {
             Ok(value) => {
+                let generation = value.abi_generation();
+                let metadata_len = value.metadata_len();
                 let lr = kv_store::LookupResult {
                     body: self.session.insert_body(value.body.into()).into(),
-                    metadata: match value.metadata_len {
+                    metadata: match metadata_len {
                         0 => None,
                         _ => Some(value.metadata),
                     },
-                    generation: value.generation,
+                    generation,
                 };
 
                 let res = self.table().push(lr)?;
@@ -145,8 +175,7 @@ impl kv_store::Host for ComponentCtx {
             .take_body(body_handle.into())?
             .read_into_vec()
             .await?;
-        let store = self.session.get_kv_store_key(store.into()).unwrap();
-        let key = String::from_utf8(key)?;
+        let store = self.session.get_kv_store_key(store.into())?;
 
         let mode = match config.mode {
             InsertMode::Overwrite => KvInsertMode::Overwrite,
@@ -155,14 +184,18 @@ impl kv_store::Host for ComponentCtx {
             InsertMode::Prepend => KvInsertMode::Prepend,
         };
 
+        // As for the `fastly_kv_store` hostcall, metadata that is flagged must not be empty.
         let meta = if mask.contains(kv_store::InsertConfigOptions::METADATA) {
+            if config.metadata.is_empty() {
+                return Err(types::Error::InvalidArgument);
+            }
             Some(config.metadata)
         } else {
             None
         };
 
         let igm = if mask.contains(kv_store::InsertConfigOptions::IF_GENERATION_MATCH) {
-            Some(config.if_generation_match)
+            Some(u64::from(config.if_generation_match))
         } else {
             None
         };
@@ -175,16 +208,15 @@ impl kv_store::Host for ComponentCtx {
             None
         };
 
-        let fut = futures::future::ok(self.session.kv_insert(
-            store.clone(),
-            ObjectKey::new(key)?,
-            body,
-            Some(mode),
-            igm,
-            meta,
-            ttl,
-        ));
-        let task = PeekableTask::spawn(fut).await;
+        let key = op_key(&self.session, store, key)?;
+        let fut = match key {
+            Ok(key) => self
+                .session
+                .kv_insert(store.clone(), key, body, Some(mode), igm, meta, ttl)
+                .left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         let handle = self
             .session
             .insert_pending_kv_insert(PendingKvInsertTask::new(task));
@@ -203,7 +235,9 @@ impl kv_store::Host for ComponentCtx {
             .await?;
 
         match resp {
-            Ok(()) => Ok(kv_store::KvStatus::Ok),
+            Ok(_) => Ok(kv_store::KvStatus::Ok),
+            // there's no `KvStatus` for a full store, so the hostcall fails instead
+            Err(e @ KvStoreError::TooManyKeys) => Err(e.into()),
             Err(e) => Ok(e.into()),
         }
     }
@@ -213,11 +247,16 @@ impl kv_store::Host for ComponentCtx {
         store: kv_store::Handle,
         key: Vec<u8>,
     ) -> Result<kv_store::DeleteHandle, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap();
-        let key = String::from_utf8(key)?;
-        // just create a future that's already ready
-        let fut = futures::future::ok(self.session.kv_delete(store.clone(), ObjectKey::new(key)?));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.session.get_kv_store_key(store.into())?;
+        let key = op_key(&self.session, store, key)?;
+        let fut = match key {
+            Ok(key) => self
+                .session
+                .kv_delete(store.clone(), key, None)
+                .left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         let lh = self
             .session
             .insert_pending_kv_delete(PendingKvDeleteTask::new(task));
@@ -247,9 +286,28 @@ impl kv_store::Host for ComponentCtx {
         mask: kv_store::ListConfigOptions,
         options: kv_store::ListConfig,
     ) -> Result<kv_store::ListHandle, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap();
+        let store = self.session.get_kv_store_key(store.into())?;
 
+        // As for the `fastly_kv_store` hostcall, options longer than any useful one are refused.
+        // They have already been copied out of guest memory, but go no further.
+        let too_long = |flag, len: usize, max| mask.contains(flag) && len > max;
+        if too_long(
+            kv_store::ListConfigOptions::CURSOR,
+            options.cursor.len(),
+            MAX_LIST_CURSOR_LEN,
+        ) || too_long(
+            kv_store::ListConfigOptions::PREFIX,
+            options.prefix.len(),
+            MAX_KEY_BYTES,
+        ) {
+            return Err(KvStoreError::BadRequest.into());
+        }
+
+        // A flagged cursor must not be empty, while an empty prefix is the same as no prefix.
         let cursor = if mask.contains(kv_store::ListConfigOptions::CURSOR) {
+            if options.cursor.is_empty() {
+                return Err(types::Error::InvalidArgument);
+            }
             Some(String::from_utf8(options.cursor)?)
         } else {
             None
@@ -267,8 +325,8 @@ impl kv_store::Host for ComponentCtx {
             None
         };
 
-        let fut = futures::future::ok(self.session.kv_list(store.clone(), cursor, prefix, limit));
-        let task = PeekableTask::spawn(fut).await;
+        let fut = self.session.kv_list(store.clone(), cursor, prefix, limit);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         let handle = self
             .session
             .insert_pending_kv_list(PendingKvListTask::new(task));
@@ -294,4 +352,91 @@ impl kv_store::Host for ComponentCtx {
             Err(e) => Ok((None, e.into())),
         }
     }
+
+    async fn limits(
+        &mut self,
+        store: kv_store::Handle,
+    ) -> Result<kv_store::StoreLimits, types::Error> {
+        let limits = self.session.kv_store_limits(store.into())?;
+        Ok(kv_store::StoreLimits {
+            max_value_size: limits.max_value_size as u64,
+            max_metadata_size: limits.max_metadata_size as u64,
+            max_key_len: limits.max_key_len as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::kv_store,
+        crate::{
+            session::AsyncItemHandle,
+            wiggle_abi::types::{
+                KvStoreDeleteHandle, KvStoreHandle, KvStoreInsertHandle, KvStoreListHandle,
+                KvStoreLookupHandle, ObjectStoreHandle, PendingKvDeleteHandle,
+                PendingKvInsertHandle, PendingKvListHandle, PendingKvLookupHandle,
+            },
+        },
+    };
+
+    const RAW: [u32; 4] = [0, 1, 42, u32::MAX];
+
+    #[test]
+    fn store_handles_round_trip() {
+        for raw in RAW {
+            let handle: kv_store::Handle = raw;
+            let store = KvStoreHandle::from(handle);
+            assert_eq!(u32::from(store), raw);
+            // the legacy object store interface shares its store handles with the KV store
+            assert_eq!(u32::from(ObjectStoreHandle::from(store)), raw);
+            assert_eq!(KvStoreHandle::from(ObjectStoreHandle::from(store)), store);
+        }
+    }
+
+    /// Check that a pending handle of the component interface refers to the same slot in the
+    /// session's async items through every view the hostcalls take of it.
+    macro_rules! assert_pending_round_trip {
+        ($raw:expr, $component:ty, $pending:ty, $witx:ty) => {{
+            let handle: $component = $raw;
+            let pending = <$pending>::from(handle);
+            assert_eq!(u32::from(pending), $raw);
+            assert_eq!(AsyncItemHandle::from(pending).as_u32(), $raw);
+            assert_eq!(<$pending>::from(AsyncItemHandle::from(pending)), pending);
+            let witx = <$witx>::from(pending);
+            assert_eq!(u32::from(witx), $raw);
+            assert_eq!(AsyncItemHandle::from(witx).as_u32(), $raw);
+            assert_eq!(<$pending>::from(witx), pending);
+        }};
+    }
+
+    #[test]
+    fn pending_handles_round_trip() {
+        for raw in RAW {
+            assert_pending_round_trip!(
+                raw,
+                kv_store::LookupHandle,
+                PendingKvLookupHandle,
+                KvStoreLookupHandle
+            );
+            assert_pending_round_trip!(
+                raw,
+                kv_store::InsertHandle,
+                PendingKvInsertHandle,
+                KvStoreInsertHandle
+            );
+            assert_pending_round_trip!(
+                raw,
+                kv_store::DeleteHandle,
+                PendingKvDeleteHandle,
+                KvStoreDeleteHandle
+            );
+            assert_pending_round_trip!(
+                raw,
+                kv_store::ListHandle,
+                PendingKvListHandle,
+                KvStoreListHandle
+            );
+        }
+    }
 }
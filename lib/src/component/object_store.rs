@@ -3,20 +3,17 @@ use {
     crate::{
         body::Body,
         linking::ComponentCtx,
-        object_store::{KvStoreError, ObjectKey},
+        object_store::{KeyValidationProfile, KvStoreError},
         session::{PeekableTask, PendingKvDeleteTask, PendingKvInsertTask, PendingKvLookupTask},
     },
+    futures::FutureExt,
 };
 
 #[async_trait::async_trait]
 impl object_store::Host for ComponentCtx {
     async fn open(&mut self, name: String) -> Result<Option<object_store::Handle>, types::Error> {
-        if self.session.kv_store.store_exists(&name)? {
-            let handle = self.session.kv_store_handle(&name)?;
-            Ok(Some(handle.into()))
-        } else {
-            Ok(None)
-        }
+        let handle = self.session.kv_store_open(&name)?;
+        Ok(handle.map(Into::into))
     }
 
     async fn lookup(
@@ -24,9 +21,11 @@ impl object_store::Host for ComponentCtx {
         store: object_store::Handle,
         key: String,
     ) -> Result<Option<object_store::BodyHandle>, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap();
-        let key = ObjectKey::new(&key)?;
-        match self.session.obj_lookup(store.clone(), key) {
+        let store = self.session.get_kv_store_key(store.into())?;
+        let key = self
+            .session
+            .kv_key(store, key, KeyValidationProfile::ObjectStore)?;
+        match self.session.obj_lookup(store.clone(), key).await {
             Ok(obj) => {
                 let new_handle = self.session.insert_body(Body::from(obj.body));
                 Ok(Some(new_handle.into()))
@@ -44,11 +43,12 @@ impl object_store::Host for ComponentCtx {
         store: object_store::Handle,
         key: String,
     ) -> Result<object_store::PendingLookupHandle, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap();
-        let key = ObjectKey::new(key)?;
-        // just create a future that's already ready
-        let fut = futures::future::ok(self.session.obj_lookup(store.clone(), key));
-        let task = PendingKvLookupTask::new(PeekableTask::spawn(fut).await);
+        let store = self.session.get_kv_store_key(store.into())?;
+        let key = self
+            .session
+            .kv_key(store, key, KeyValidationProfile::ObjectStore)?;
+        let fut = self.session.obj_lookup(store.clone(), key);
+        let task = PendingKvLookupTask::new(PeekableTask::spawn(fut.map(Ok)).await);
         Ok(self.session.insert_pending_kv_lookup(task).into())
     }
 
@@ -76,15 +76,18 @@ impl object_store::Host for ComponentCtx {
         key: String,
         body_handle: http_types::BodyHandle,
     ) -> Result<(), types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap().clone();
-        let key = ObjectKey::new(&key)?;
+        let store = self.session.get_kv_store_key(store.into())?.clone();
+        let key = self
+            .session
+            .kv_key(&store, key, KeyValidationProfile::ObjectStore)?;
         let bytes = self
             .session
             .take_body(body_handle.into())?
             .read_into_vec()
             .await?;
         self.session
-            .kv_insert(store, key, bytes, None, None, None, None)?;
+            .kv_insert(store, key, bytes, None, None, None, None)
+            .await?;
 
         Ok(())
     }
@@ -95,18 +98,19 @@ impl object_store::Host for ComponentCtx {
         key: String,
         body_handle: http_types::BodyHandle,
     ) -> Result<object_store::PendingInsertHandle, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap().clone();
-        let key = ObjectKey::new(&key)?;
+        let store = self.session.get_kv_store_key(store.into())?.clone();
+        let key = self
+            .session
+            .kv_key(&store, key, KeyValidationProfile::ObjectStore)?;
         let bytes = self
             .session
             .take_body(body_handle.into())?
             .read_into_vec()
             .await?;
-        let fut = futures::future::ok(
-            self.session
-                .kv_insert(store, key, bytes, None, None, None, None),
-        );
-        let task = PeekableTask::spawn(fut).await;
+        let fut = self
+            .session
+            .kv_insert(store, key, bytes, None, None, None, None);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
 
         Ok(self
             .session
@@ -118,12 +122,12 @@ impl object_store::Host for ComponentCtx {
         &mut self,
         handle: object_store::PendingInsertHandle,
     ) -> Result<(), types::Error> {
-        Ok((self
-            .session
+        self.session
             .take_pending_kv_insert(handle.into())?
             .task()
             .recv()
-            .await?)?)
+            .await??;
+        Ok(())
     }
 
     async fn delete_async(
@@ -131,10 +135,12 @@ impl object_store::Host for ComponentCtx {
         store: object_store::Handle,
         key: String,
     ) -> Result<object_store::PendingDeleteHandle, types::Error> {
-        let store = self.session.get_kv_store_key(store.into()).unwrap().clone();
-        let key = ObjectKey::new(&key)?;
-        let fut = futures::future::ok(self.session.kv_delete(store, key));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.session.get_kv_store_key(store.into())?.clone();
+        let key = self
+            .session
+            .kv_key(&store, key, KeyValidationProfile::ObjectStore)?;
+        let fut = self.session.kv_delete(store, key, None);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
 
         Ok(self
             .session
@@ -16,7 +16,7 @@ use {
 mod unit_tests;
 
 /// Fastly limits
-mod limits;
+pub(crate) mod limits;
 
 /// Types and deserializers for dictionaries configuration settings.
 mod dictionaries;
@@ -115,20 +115,52 @@ impl FastlyConfig {
     }
 
     /// Parse a `fastly.toml` file into a `FastlyConfig`.
+    ///
+    /// Every object store and object is validated before any of them are used, and if any are
+    /// invalid, all of their problems are reported together.
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self, FastlyConfigError> {
+        Self::read_file(path, true)
+    }
+
+    /// Parse a `fastly.toml` file into a `FastlyConfig`, without refusing problems with its
+    /// object stores.
+    ///
+    /// Each problem is logged as a warning instead, and whatever it affects is left out: an
+    /// object, a setting, or a whole store. The rest of the file is validated as usual.
+    pub fn from_file_skipping_kv_validation(
+        path: impl AsRef<Path>,
+    ) -> Result<Self, FastlyConfigError> {
+        Self::read_file(path, false)
+    }
+
+    fn read_file(path: impl AsRef<Path>, validate_kv: bool) -> Result<Self, FastlyConfigError> {
         fs::read_to_string(path.as_ref())
             .map_err(|err| FastlyConfigError::IoError {
                 path: path.as_ref().display().to_string(),
                 err,
             })
-            .and_then(Self::from_str)
+            .and_then(|toml| Self::read_str(toml, validate_kv))
     }
 
     /// Parse a string containing TOML data into a `FastlyConfig`.
     fn from_str(toml: impl AsRef<str>) -> Result<Self, FastlyConfigError> {
-        toml::from_str::<'_, TomlFastlyConfig>(toml.as_ref())
-            .map_err(Into::into)
-            .and_then(TryInto::try_into)
+        Self::read_str(toml, true)
+    }
+
+    fn read_str(toml: impl AsRef<str>, validate_kv: bool) -> Result<Self, FastlyConfigError> {
+        let mut toml = toml::from_str::<'_, TomlFastlyConfig>(toml.as_ref())?;
+        let unvalidated_kv = match validate_kv {
+            true => None,
+            false => toml
+                .local_server
+                .as_mut()
+                .and_then(|local_server| local_server.object_stores.take()),
+        };
+        let mut config: FastlyConfig = toml.try_into()?;
+        if let Some(object_stores) = unvalidated_kv {
+            config.local_server.object_stores = ObjectStoreConfig::read_lenient(object_stores);
+        }
+        Ok(config)
     }
 }
 
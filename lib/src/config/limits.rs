@@ -1,3 +1,7 @@
 // From https://docs.fastly.com/en/guides/resource-limits#vcl-and-configuration-limits
 pub const DICTIONARY_ITEM_KEY_MAX_LEN: usize = 256;
 pub const DICTIONARY_ITEM_VALUE_MAX_LEN: usize = 8000;
+
+// From https://docs.fastly.com/en/guides/resource-limits#kv-store-limits
+pub const KV_STORE_VALUE_MAX_LEN: usize = 25 * 1024 * 1024;
+pub const KV_STORE_METADATA_MAX_LEN: usize = 2000;
@@ -1,14 +1,21 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use toml::{toml, Value};
 use {
     crate::{
-        error::{FastlyConfigError, ObjectStoreConfigError},
-        object_store::{ObjectKey, ObjectStoreKey, ObjectStores},
-        wiggle_abi::types::KvInsertMode,
+        config::limits::{KV_STORE_METADATA_MAX_LEN, KV_STORE_VALUE_MAX_LEN},
+        error::{FastlyConfigError, ObjectStoreConfigError, ObjectStoreConfigProblems},
+        object_store::{
+            is_valid_prefix, Compression, KeyValidationProfile, MetadataFormat, ObjectKey,
+            ObjectStoreKey, ObjectStores, ObjectValue, StoreConfig, ValueOrigin,
+        },
     },
+    base64::prelude::*,
     std::fs,
     toml::value::Table,
+    tracing::{debug, info, warn},
 };
 
 #[derive(Clone, Debug, Default)]
@@ -17,168 +24,636 @@ pub struct ObjectStoreConfig(pub(crate) ObjectStores);
 impl TryFrom<Table> for ObjectStoreConfig {
     type Error = FastlyConfigError;
     fn try_from(toml: Table) -> Result<Self, Self::Error> {
+        let (config, problems) = Self::read(toml);
+        match problems.len() {
+            0 => Ok(config),
+            // A single problem is reported on its own, as it always has been.
+            1 => {
+                let (name, err) = problems.into_iter().next().expect("there is one problem");
+                Err(FastlyConfigError::InvalidObjectStoreDefinition { name, err })
+            }
+            _ => Err(FastlyConfigError::InvalidObjectStoreDefinitions(problems)),
+        }
+    }
+}
+
+impl ObjectStoreConfig {
+    /// Read the stores without refusing configuration problems. Each problem is logged as a
+    /// warning, and whatever it affects is left out: an object, a setting, or a whole store.
+    pub(crate) fn read_lenient(toml: Table) -> Self {
+        let (config, problems) = Self::read(toml);
+        for (name, err) in problems {
+            warn!("skipping invalid configuration for '{name}': {err}");
+        }
+        config
+    }
+
+    /// Read every store and object, seeding the ones that are valid, and collecting a problem for
+    /// each one that isn't, so that they can all be reported at once.
+    fn read(toml: Table) -> (Self, ObjectStoreConfigProblems) {
+        let workers = std::thread::available_parallelism().map_or(1, usize::from);
+        Self::read_with_workers(toml, workers)
+    }
+
+    /// Read the stores as [`read`][Self::read] does, with up to `workers` threads reading and
+    /// checking their definitions at once.
+    ///
+    /// Only the reading is spread across threads. The stores are seeded one at a time, in the
+    /// order they're configured, so that the generations given to their values are the same from
+    /// one start to the next.
+    pub(crate) fn read_with_workers(
+        toml: Table,
+        workers: usize,
+    ) -> (Self, ObjectStoreConfigProblems) {
+        let start = Instant::now();
         let obj_store = ObjectStores::new();
-        for (store, items) in toml.iter() {
-            // Either the items here is from a top-level file with "file" and "format" keys
-            // or it's an inline array.
-            // We try to parse either one of them to the same Vec<toml::Value>
-            // to allow them to run through the same validation path further down
-            let file_path = items
-                .as_table()
-                .and_then(|table| table.get("file"))
-                .and_then(|file| file.as_str());
-            let file_format = items
-                .as_table()
-                .and_then(|table| table.get("format"))
-                .and_then(|format| format.as_str());
-
-            let items: Vec<toml::Value> = match (file_path, file_format) {
-                (Some(file_path), Some(file_type)) => {
-                    if file_type != "json" {
-                        return Err(FastlyConfigError::InvalidObjectStoreDefinition {
-                            name: store.to_string(),
-                            err: ObjectStoreConfigError::InvalidFileFormat(file_type.to_string()),
-                        });
-                    }
+        let defs = toml.iter().collect::<Vec<_>>();
+        let workers = workers.clamp(1, defs.len().max(1));
+        let mut parsed = std::thread::scope(|s| {
+            let threads = (0..workers)
+                .map(|first| {
+                    let (defs, obj_store) = (&defs, &obj_store);
+                    s.spawn(move || {
+                        defs.iter()
+                            .enumerate()
+                            .skip(first)
+                            .step_by(workers)
+                            .map(|(i, (name, items))| (i, parse_store(obj_store, name, items)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().expect("store reading thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        parsed.sort_unstable_by_key(|(i, _)| *i);
 
-                    let path = PathBuf::from(&file_path);
+        let mut problems = ObjectStoreConfigProblems::default();
+        let mut timings = SeedTimings::default();
+        for ((name, _), (_, parsed)) in defs.iter().zip(parsed) {
+            for err in parsed.problems {
+                problems.push(name, err);
+            }
+            let Some((store, values)) = parsed.seed else {
+                continue;
+            };
+            let count = values.len();
+            let write_start = Instant::now();
+            // The store exists even if it has no items to insert, or none of them are valid.
+            obj_store
+                .insert_many(store, values)
+                .expect("Lock was not poisoned");
+            let written = write_start.elapsed();
+            debug!(
+                "seeded KV store '{name}' with {count} values from {}, read in {:?} and written in {written:?}",
+                parsed.source.as_str(),
+                parsed.elapsed,
+            );
+            timings.add(name, parsed.source, count, parsed.elapsed, written);
+        }
+        timings.log(start.elapsed());
+        (ObjectStoreConfig(obj_store), problems)
+    }
+}
 
-                    let json = read_json_contents(&path).map_err(|err| {
-                        FastlyConfigError::InvalidObjectStoreDefinition {
-                            name: store.to_string(),
-                            err,
-                        }
-                    })?;
-
-                    let toml: Vec<Value> = json
-                        .into_iter()
-                        .map(|(key, value)| {
-                            toml! {
-                                key = key
-                                data = value
-                            }
-                        })
-                        .collect();
-
-                    toml
-                }
-                (None, None) => {
-                    // No file or format specified, parse the TOML as an array
-                    items
-                        .as_array()
-                        .ok_or_else(|| FastlyConfigError::InvalidObjectStoreDefinition {
-                            name: store.to_string(),
-                            err: ObjectStoreConfigError::NotAnArray,
-                        })?
-                        .clone()
-                }
-                // This means that *either* `format` or `file` is set, which isn't allowed
-                // we need both or neither.
-                (_, _) => {
-                    return Err(FastlyConfigError::InvalidObjectStoreDefinition {
-                        name: store.to_string(),
-                        err: ObjectStoreConfigError::OnlyOneFormatOrFileSet,
-                    });
+/// Where a store's items are read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SeedSource {
+    /// An array of items in the manifest.
+    Inline,
+    /// A JSON file of keys and values, given by `file` and `format`.
+    JsonFile,
+}
+
+impl SeedSource {
+    fn of(items: &Value) -> Self {
+        match items.as_table() {
+            Some(table) if table.contains_key("file") => SeedSource::JsonFile,
+            _ => SeedSource::Inline,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SeedSource::Inline => "inline items",
+            SeedSource::JsonFile => "a JSON file",
+        }
+    }
+}
+
+/// One store's definition, read and checked, but not yet seeded.
+struct ParsedStore {
+    problems: Vec<ObjectStoreConfigError>,
+    /// The store and the values to seed it with, unless its definition couldn't be read at all.
+    seed: Option<(ObjectStoreKey, Vec<(ObjectKey, ObjectValue)>)>,
+    source: SeedSource,
+    /// How long reading and checking the definition took.
+    elapsed: Duration,
+}
+
+/// Read and check one store's definition, collecting its problems.
+fn parse_store(obj_store: &ObjectStores, name: &str, items: &Value) -> ParsedStore {
+    let start = Instant::now();
+    let mut problems = Vec::new();
+    let seed = match ObjectStoreKey::new(name) {
+        Ok(store) => read_store(obj_store, &store, items, &mut |err| problems.push(err))
+            .map(|values| (store, values)),
+        Err(err) => {
+            problems.push(err.into());
+            None
+        }
+    };
+    ParsedStore {
+        problems,
+        seed,
+        source: SeedSource::of(items),
+        elapsed: start.elapsed(),
+    }
+}
+
+/// How long seeding the stores took, summarized once they're all seeded.
+#[derive(Debug, Default)]
+struct SeedTimings {
+    stores: usize,
+    values: usize,
+    /// The time spent reading each source, summed across the threads reading them.
+    inline: Duration,
+    json_files: Duration,
+    writing: Duration,
+    /// The store that took longest to read and write, and how long that took.
+    slowest: Option<(String, Duration)>,
+}
+
+impl SeedTimings {
+    fn add(
+        &mut self,
+        name: &str,
+        source: SeedSource,
+        count: usize,
+        read: Duration,
+        written: Duration,
+    ) {
+        self.stores += 1;
+        self.values += count;
+        match source {
+            SeedSource::Inline => self.inline += read,
+            SeedSource::JsonFile => self.json_files += read,
+        }
+        self.writing += written;
+        let took = read + written;
+        if self
+            .slowest
+            .as_ref()
+            .map_or(true, |(_, slowest)| took > *slowest)
+        {
+            self.slowest = Some((name.to_string(), took));
+        }
+    }
+
+    fn log(&self, elapsed: Duration) {
+        let Some((slowest, took)) = &self.slowest else {
+            return;
+        };
+        info!(
+            "seeded {} KV store(s) with {} values in {elapsed:?}: {:?} reading inline items, {:?} reading JSON files, and {:?} writing; the slowest was '{slowest}', at {took:?}",
+            self.stores, self.values, self.inline, self.json_files, self.writing,
+        );
+    }
+}
+
+/// Read the values to seed one store with, reporting each problem with its definition to
+/// `problem`, or `None` if the store can't be created at all.
+fn read_store(
+    obj_store: &ObjectStores,
+    store: &ObjectStoreKey,
+    items: &Value,
+    problem: &mut impl FnMut(ObjectStoreConfigError),
+) -> Option<Vec<(ObjectKey, ObjectValue)>> {
+    // Either the items here is from a top-level file with "file" and "format" keys
+    // or it's an inline array.
+    // We try to parse either one of them to the same Vec<toml::Value>
+    // to allow them to run through the same validation path further down
+    let file_path = items
+        .as_table()
+        .and_then(|table| table.get("file"))
+        .and_then(|file| file.as_str());
+    let file_format = items
+        .as_table()
+        .and_then(|table| table.get("format"))
+        .and_then(|format| format.as_str());
+    // Stores given as a table may also carry settings: `sensitive` stores have their contents
+    // redacted from exports, and `default_ttl` is the time-to-live in seconds for inserts that
+    // don't specify one. `max_concurrent_operations` limits how many guest operations run against
+    // the store at once, and `max_queued_operations` how many may wait for their turn before the
+    // rest are rejected. `max_value_size` lowers or raises the largest value in bytes that may be
+    // written, from production's limit, `max_metadata_size` does the same for metadata, and
+    // `max_keys` limits how many keys the store may hold. A
+    // warning is logged when a value grows past `warn_value_size` bytes, to catch a guest appending
+    // where it meant to overwrite. `key_filter` stores keep a filter of their keys, so that most
+    // lookups of missing keys fail without waiting on the store, for guests that mostly look up
+    // keys that aren't there. `compression` stores hold their values compressed with `"gzip"` or
+    // `"deflate"`, decompressing them before guests see them; sizes are always counted
+    // uncompressed. `key_validation` checks keys against the rules of `"kv_store"`,
+    // `"object_store"`, or `"permissive"` through every API, for stores that mirror existing data
+    // with keys one API would refuse. `read_only` stores refuse guests' inserts and deletes, for
+    // reference data that the app should never write. `key_prefix` is put before every key a
+    // guest gives, and taken off the keys it lists, so that services sharing stores keep to their
+    // own keys; seeded items are under it too. `metadata_format = "json"` stores refuse metadata
+    // that isn't JSON, or, with `object_only`, that isn't a JSON object, for apps whose readers
+    // would fail on it. Inline items can be given settings by placing them under an `items` key.
+    let setting = |name| items.as_table().and_then(|table| table.get(name));
+    let sensitive = match setting("sensitive") {
+        None => false,
+        Some(sensitive) => sensitive.as_bool().unwrap_or_else(|| {
+            problem(ObjectStoreConfigError::SensitiveNotABool);
+            false
+        }),
+    };
+    let default_ttl = setting("default_ttl").and_then(|ttl| {
+        let ttl = ttl
+            .as_integer()
+            .and_then(|ttl| u64::try_from(ttl).ok())
+            .filter(|ttl| *ttl > 0)
+            .map(Duration::from_secs);
+        if ttl.is_none() {
+            problem(ObjectStoreConfigError::InvalidDefaultTtl);
+        }
+        ttl
+    });
+    let mut count = |name, min, err: fn() -> ObjectStoreConfigError| {
+        setting(name).and_then(|count| {
+            let count = count
+                .as_integer()
+                .and_then(|count| usize::try_from(count).ok())
+                .filter(|count| *count >= min);
+            if count.is_none() {
+                problem(err());
+            }
+            count
+        })
+    };
+    let max_concurrent_operations = count("max_concurrent_operations", 1, || {
+        ObjectStoreConfigError::InvalidMaxConcurrentOperations
+    });
+    let max_queued_operations = count("max_queued_operations", 0, || {
+        ObjectStoreConfigError::InvalidMaxQueuedOperations
+    });
+    let max_value_size = count("max_value_size", 1, || {
+        ObjectStoreConfigError::InvalidMaxValueSize
+    });
+    let max_metadata_size = count("max_metadata_size", 1, || {
+        ObjectStoreConfigError::InvalidMaxMetadataSize
+    });
+    let max_keys = count("max_keys", 1, || ObjectStoreConfigError::InvalidMaxKeys);
+    let warn_value_size = count("warn_value_size", 1, || {
+        ObjectStoreConfigError::InvalidWarnValueSize
+    });
+    let key_filter = match setting("key_filter") {
+        None => false,
+        Some(key_filter) => key_filter.as_bool().unwrap_or_else(|| {
+            problem(ObjectStoreConfigError::KeyFilterNotABool);
+            false
+        }),
+    };
+    let compression = setting("compression").and_then(|compression| {
+        let parsed = compression.as_str().and_then(Compression::from_name);
+        if parsed.is_none() {
+            problem(ObjectStoreConfigError::InvalidCompression(
+                compression.to_string(),
+            ));
+        }
+        parsed
+    });
+    let key_validation = setting("key_validation").and_then(|profile| {
+        let parsed = profile.as_str().and_then(KeyValidationProfile::from_name);
+        if parsed.is_none() {
+            problem(ObjectStoreConfigError::InvalidKeyValidation(
+                profile.to_string(),
+            ));
+        }
+        parsed
+    });
+    let read_only = match setting("read_only") {
+        None => false,
+        Some(read_only) => read_only.as_bool().unwrap_or_else(|| {
+            problem(ObjectStoreConfigError::ReadOnlyNotABool);
+            false
+        }),
+    };
+    let key_prefix = setting("key_prefix").and_then(|prefix| {
+        let parsed = prefix.as_str().filter(|prefix| {
+            !prefix.is_empty()
+                && is_valid_prefix(prefix, key_validation.unwrap_or_default()).is_ok()
+        });
+        if parsed.is_none() {
+            problem(ObjectStoreConfigError::InvalidKeyPrefix(prefix.to_string()));
+        }
+        parsed.map(str::to_string)
+    });
+    let object_only = match setting("object_only") {
+        None => false,
+        Some(object_only) => object_only.as_bool().unwrap_or_else(|| {
+            problem(ObjectStoreConfigError::ObjectOnlyNotABool);
+            false
+        }),
+    };
+    let metadata_format = setting("metadata_format").and_then(|format| {
+        let parsed = format
+            .as_str()
+            .and_then(|name| MetadataFormat::from_name(name, object_only));
+        if parsed.is_none() {
+            problem(ObjectStoreConfigError::InvalidMetadataFormat(
+                format.to_string(),
+            ));
+        }
+        parsed
+    });
+    if object_only && setting("metadata_format").is_none() {
+        problem(ObjectStoreConfigError::ObjectOnlyWithoutMetadataFormat);
+    }
+    let settings = StoreConfig {
+        sensitive,
+        default_ttl,
+        max_concurrent_operations,
+        max_queued_operations,
+        key_filter,
+        compression,
+        max_value_size,
+        max_metadata_size,
+        max_keys,
+        warn_value_size,
+        key_validation,
+        read_only,
+        key_prefix: key_prefix.clone(),
+        metadata_format,
+    };
+    if settings != StoreConfig::default() {
+        if let Err(err) = obj_store.configure_store(store.clone(), settings) {
+            problem(err.into());
+        }
+    }
+    let max_value_size = max_value_size.unwrap_or(KV_STORE_VALUE_MAX_LEN);
+    let max_metadata_size = max_metadata_size.unwrap_or(KV_STORE_METADATA_MAX_LEN);
+    let items = items
+        .as_table()
+        .and_then(|table| table.get("items"))
+        .unwrap_or(items);
+
+    // Inline items are checked where they are, rather than copied.
+    let items: Cow<'_, [Value]> = match (file_path, file_format) {
+        (Some(file_path), Some(file_type)) => {
+            if file_type != "json" {
+                problem(ObjectStoreConfigError::InvalidFileFormat(
+                    file_type.to_string(),
+                ));
+                return None;
+            }
+
+            let path = PathBuf::from(&file_path);
+
+            let json = match read_json_contents(&path) {
+                Ok(json) => json,
+                Err(err) => {
+                    problem(err);
+                    return None;
                 }
             };
 
-            // Handle the case where there are no items to insert, but the store
-            // exists and needs to be in the ObjectStore
-            if items.is_empty() {
-                obj_store
-                    .insert_empty_store(ObjectStoreKey::new(store))
-                    .map_err(|err| FastlyConfigError::InvalidObjectStoreDefinition {
-                        name: store.to_string(),
-                        err: err.into(),
-                    })?;
+            let toml: Vec<Value> = json
+                .into_iter()
+                .map(|(key, value)| {
+                    toml! {
+                        key = key
+                        data = value
+                    }
+                })
+                .collect();
+
+            Cow::Owned(toml)
+        }
+        (None, None) => {
+            // No file or format specified, parse the TOML as an array
+            match items.as_array() {
+                Some(items) => Cow::Borrowed(items.as_slice()),
+                None => {
+                    problem(ObjectStoreConfigError::NotAnArray);
+                    return None;
+                }
+            }
+        }
+        // This means that *either* `format` or `file` is set, which isn't allowed
+        // we need both or neither.
+        (_, _) => {
+            problem(ObjectStoreConfigError::OnlyOneFormatOrFileSet);
+            return None;
+        }
+    };
+
+    // The items are read before any of them are written, so that the store is published fully
+    // seeded, and no request sees it part way through.
+    let mut values = Vec::with_capacity(items.len());
+    let mut keys = HashSet::new();
+    let mut generations = HashMap::new();
+    for item in items.iter() {
+        let Some(item) = item.as_table() else {
+            problem(ObjectStoreConfigError::NotATable);
+            continue;
+        };
+
+        let key = match item.get("key").map(Value::as_str) {
+            None => {
+                problem(ObjectStoreConfigError::NoKey);
                 continue;
             }
-            for item in items.iter() {
-                let item = item.as_table().ok_or_else(|| {
-                    FastlyConfigError::InvalidObjectStoreDefinition {
-                        name: store.to_string(),
-                        err: ObjectStoreConfigError::NotATable,
-                    }
-                })?;
-
-                let key = item
-                    .get("key")
-                    .ok_or_else(|| FastlyConfigError::InvalidObjectStoreDefinition {
-                        name: store.to_string(),
-                        err: ObjectStoreConfigError::NoKey,
-                    })?
-                    .as_str()
-                    .ok_or_else(|| FastlyConfigError::InvalidObjectStoreDefinition {
-                        name: store.to_string(),
-                        err: ObjectStoreConfigError::KeyNotAString,
-                    })?;
-
-                // Previously the "file" key was named "path".  We want
-                // to continue supporting the old name.
-                let file = match (item.get("file"), item.get("path")) {
-                    (None, None) => None,
-                    (Some(file), _) => Some(file),
-                    (None, Some(path)) => Some(path),
-                };
+            Some(None) => {
+                problem(ObjectStoreConfigError::KeyNotAString);
+                continue;
+            }
+            Some(Some(key)) => key,
+        };
+        if !keys.insert(key) {
+            problem(ObjectStoreConfigError::DuplicateKey(key.to_string()));
+            continue;
+        }
 
-                let bytes = match (file, item.get("data")) {
-                    (None, None) => {
-                        return Err(FastlyConfigError::InvalidObjectStoreDefinition {
-                            name: store.to_string(),
-                            err: ObjectStoreConfigError::NoFileOrData(key.to_string()),
-                        })
-                    }
-                    (Some(_), Some(_)) => {
-                        return Err(FastlyConfigError::InvalidObjectStoreDefinition {
-                            name: store.to_string(),
-                            err: ObjectStoreConfigError::FileAndData(key.to_string()),
-                        })
-                    }
-                    (Some(path), None) => {
-                        let path = path.as_str().ok_or_else(|| {
-                            FastlyConfigError::InvalidObjectStoreDefinition {
-                                name: store.to_string(),
-                                err: ObjectStoreConfigError::FileNotAString(key.to_string()),
-                            }
-                        })?;
-                        fs::read(path).map_err(|e| {
-                            FastlyConfigError::InvalidObjectStoreDefinition {
-                                name: store.to_string(),
-                                err: ObjectStoreConfigError::IoError(e),
-                            }
-                        })?
-                    }
-                    (None, Some(data)) => data
-                        .as_str()
-                        .ok_or_else(|| FastlyConfigError::InvalidObjectStoreDefinition {
-                            name: store.to_string(),
-                            err: ObjectStoreConfigError::DataNotAString(key.to_string()),
-                        })?
-                        .as_bytes()
-                        .to_vec(),
+        // Previously the "file" key was named "path".  We want
+        // to continue supporting the old name.
+        let file = match (item.get("file"), item.get("path")) {
+            (None, None) => None,
+            (Some(file), _) => Some(file),
+            (None, Some(path)) => Some(path),
+        };
+
+        let bytes = match (file, item.get("data")) {
+            (None, None) => {
+                problem(ObjectStoreConfigError::NoFileOrData(key.to_string()));
+                continue;
+            }
+            (Some(_), Some(_)) => {
+                problem(ObjectStoreConfigError::FileAndData(key.to_string()));
+                continue;
+            }
+            (Some(path), None) => {
+                let Some(path) = path.as_str() else {
+                    problem(ObjectStoreConfigError::FileNotAString(key.to_string()));
+                    continue;
                 };
+                match fs::read(path) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        problem(ObjectStoreConfigError::IoError(e));
+                        continue;
+                    }
+                }
+            }
+            (None, Some(data)) => match data.as_str() {
+                Some(data) => data.as_bytes().to_vec(),
+                None => {
+                    problem(ObjectStoreConfigError::DataNotAString(key.to_string()));
+                    continue;
+                }
+            },
+        };
+        if bytes.len() > max_value_size {
+            problem(ObjectStoreConfigError::ValueTooLarge {
+                key: key.to_string(),
+                len: bytes.len(),
+                max: max_value_size,
+            });
+            continue;
+        }
+
+        let key = match ObjectKey::new_prefixed(
+            key_prefix.as_deref().unwrap_or_default(),
+            key,
+            key_validation.unwrap_or_default(),
+        ) {
+            Ok(key) => key,
+            Err(err) => {
+                problem(err.into());
+                continue;
+            }
+        };
+        // Seeded generations are kept exactly, and runtime writes are given greater ones.
+        let generation = match item.get("generation") {
+            None => None,
+            Some(generation) => match generation.as_integer().filter(|g| *g > 0) {
+                Some(generation) => Some(generation as u64),
+                None => {
+                    problem(ObjectStoreConfigError::InvalidGeneration(
+                        key.as_str().to_string(),
+                    ));
+                    continue;
+                }
+            },
+        };
+        if let Some(generation) = generation {
+            if let Some(first) = generations.insert(generation, key.as_str().to_string()) {
+                problem(ObjectStoreConfigError::DuplicateGeneration {
+                    generation,
+                    first,
+                    second: key.as_str().to_string(),
+                });
+                continue;
+            }
+        }
 
-                obj_store
-                    .insert(
-                        ObjectStoreKey::new(store),
-                        ObjectKey::new(key).map_err(|err| {
-                            FastlyConfigError::InvalidObjectStoreDefinition {
-                                name: store.to_string(),
-                                err: err.into(),
-                            }
-                        })?,
-                        bytes,
-                        KvInsertMode::Overwrite,
-                        None,
-                        None,
-                        None,
-                    )
-                    .expect("Lock was not poisoned");
+        // Metadata is text, unless `metadata_encoding = "base64"`, for metadata that isn't UTF-8.
+        let metadata = match item.get("metadata") {
+            None => None,
+            Some(metadata) => {
+                let Some(metadata) = metadata.as_str() else {
+                    problem(ObjectStoreConfigError::MetadataNotAString(
+                        key.as_str().to_string(),
+                    ));
+                    continue;
+                };
+                match item.get("metadata_encoding").map(Value::as_str) {
+                    None | Some(Some("utf8")) => Some(metadata.as_bytes().to_vec()),
+                    Some(Some("base64")) => match BASE64_STANDARD.decode(metadata) {
+                        Ok(metadata) => Some(metadata),
+                        Err(_) => {
+                            problem(ObjectStoreConfigError::InvalidBase64Metadata(
+                                key.as_str().to_string(),
+                            ));
+                            continue;
+                        }
+                    },
+                    Some(_) => {
+                        problem(ObjectStoreConfigError::InvalidMetadataEncoding(
+                            key.as_str().to_string(),
+                        ));
+                        continue;
+                    }
+                }
             }
+        };
+        if let Some(len) = metadata
+            .as_ref()
+            .map(Vec::len)
+            .filter(|len| *len > max_metadata_size)
+        {
+            problem(ObjectStoreConfigError::MetadataTooLarge {
+                key: key.as_str().to_string(),
+                len,
+                max: max_metadata_size,
+            });
+            continue;
+        }
+
+        // A value written before it was seeded, such as one from an export, may give when it was
+        // created and last modified, in milliseconds since the Unix epoch.
+        let seed_time = |field: &'static str| match item.get(field) {
+            None => Ok(None),
+            Some(ms) => match ms.as_integer().and_then(|ms| u64::try_from(ms).ok()) {
+                Some(ms) => Ok(Some(SystemTime::UNIX_EPOCH + Duration::from_millis(ms))),
+                None => Err(ObjectStoreConfigError::InvalidSeedTime {
+                    key: key.as_str().to_string(),
+                    field,
+                }),
+            },
+        };
+        let (created_at, updated_at) =
+            match (seed_time("created_ms"), seed_time("last_modified_ms")) {
+                (Ok(created_at), Ok(updated_at)) => (created_at, updated_at),
+                (Err(err), _) | (_, Err(err)) => {
+                    problem(err);
+                    continue;
+                }
+            };
+        if matches!((created_at, updated_at), (Some(created), Some(updated)) if created > updated) {
+            problem(ObjectStoreConfigError::CreatedAfterLastModified(
+                key.as_str().to_string(),
+            ));
+            continue;
         }
 
-        Ok(ObjectStoreConfig(obj_store))
+        // A value given only one of its times was written once, at that time.
+        let mut value = ObjectValue::new(bytes)
+            .metadata(metadata.unwrap_or_default())
+            .generation(generation.unwrap_or(0))
+            .origin(ValueOrigin::Seed);
+        if let Some(at) = created_at.or(updated_at) {
+            value = value.created_at(at);
+        }
+        if let Some(at) = updated_at.or(created_at) {
+            value = value.updated_at(at);
+        }
+        let value = value.build(obj_store.now());
+        values.push((key, value));
     }
+
+    if let Some(max) = max_keys.filter(|max| values.len() > *max) {
+        problem(ObjectStoreConfigError::TooManyKeys {
+            count: values.len(),
+            max,
+        });
+        values.truncate(max);
+    }
+
+    Some(values)
 }
 
 fn read_json_contents(file: &Path) -> Result<HashMap<String, String>, ObjectStoreConfigError> {
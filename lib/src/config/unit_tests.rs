@@ -1216,3 +1216,980 @@ Qq7tJAMLnPnAdAUousI0RDcLpB8adGkhZH66lL4oV9U+aQ0dA0oiqSKZtMoHeWbr
         assert_eq!(3, shark_backend.ca_certs.len());
     }
 }
+
+mod object_store_config_tests {
+    use {
+        super::read_local_server_config,
+        crate::{
+            config::{
+                limits::{KV_STORE_METADATA_MAX_LEN, KV_STORE_VALUE_MAX_LEN},
+                object_store::ObjectStoreConfig,
+                FastlyConfig,
+            },
+            error::{
+                FastlyConfigError::{InvalidObjectStoreDefinition, InvalidObjectStoreDefinitions},
+                ObjectStoreConfigError,
+            },
+            object_store::{
+                Compression, KeyValidationProfile, KvExport, KvStoreError, MetadataFormat,
+                ObjectKey, ObjectStoreKey, ObjectStores, Redaction, StoreNameValidationError,
+                ValueOrigin,
+            },
+            wiggle_abi::types::KvInsertMode,
+        },
+        std::time::{Duration, SystemTime},
+        tempfile::tempdir,
+    };
+
+    const PROBLEMS: &str = r#"
+        [object_stores."my store"]
+        items = []
+
+        [object_stores.one]
+        default_ttl = 0
+        items = [
+            { key = "", data = "empty" },
+            { key = "dup", data = "first" },
+            { key = "dup", data = "second" },
+            { key = "fine", data = "fine" },
+            { key = "missing", file = "/path/does/not/exist" },
+        ]
+
+        [object_stores.two]
+        items = [{ data = "no key" }]
+    "#;
+
+    /// Check that a store's `default_ttl` is read, and must be a positive number of seconds.
+    #[test]
+    fn object_store_default_ttls_can_be_set() {
+        let config = r#"
+            [object_stores.cache]
+            default_ttl = 60
+            items = [{ key = "a", data = "b" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read default_ttl");
+        assert_eq!(
+            config.object_stores.0.default_ttl("cache"),
+            Some(std::time::Duration::from_secs(60))
+        );
+
+        for ttl in ["0", "-1", "\"60s\""] {
+            let config = format!(
+                r#"
+                [object_stores.cache]
+                default_ttl = {ttl}
+                items = []
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition {
+                    err: ObjectStoreConfigError::InvalidDefaultTtl,
+                    ..
+                }) => {}
+                res => panic!("unexpected result for {ttl}: {:?}", res),
+            }
+        }
+    }
+
+    /// Check that values read from configuration are marked as seed data.
+    #[test]
+    fn object_store_items_are_seed_data() {
+        let config = r#"
+            [object_stores.store]
+            items = [{ key = "a", data = "b" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read items");
+        let value = config
+            .object_stores
+            .0
+            .lookup(
+                ObjectStoreKey::new("store").unwrap(),
+                ObjectKey::new("a").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(value.origin, ValueOrigin::Seed);
+    }
+
+    #[test]
+    fn object_store_concurrency_limits_can_be_set() {
+        let config = r#"
+            [object_stores.slow]
+            max_concurrent_operations = 2
+            max_queued_operations = 0
+            items = []
+        "#;
+        let config = read_local_server_config(config).expect("can read concurrency limits");
+        let settings = config.object_stores.0.store_config("slow").unwrap();
+        assert_eq!(settings.max_concurrent_operations, Some(2));
+        assert_eq!(settings.max_queued_operations, Some(0));
+
+        for (setting, value) in [
+            ("max_concurrent_operations", "0"),
+            ("max_concurrent_operations", "-1"),
+            ("max_queued_operations", "-1"),
+            ("max_queued_operations", "\"none\""),
+        ] {
+            let config = format!(
+                r#"
+                [object_stores.slow]
+                {setting} = {value}
+                items = []
+            "#
+            );
+            match (setting, read_local_server_config(&config)) {
+                (
+                    "max_concurrent_operations",
+                    Err(InvalidObjectStoreDefinition {
+                        err: ObjectStoreConfigError::InvalidMaxConcurrentOperations,
+                        ..
+                    }),
+                )
+                | (
+                    "max_queued_operations",
+                    Err(InvalidObjectStoreDefinition {
+                        err: ObjectStoreConfigError::InvalidMaxQueuedOperations,
+                        ..
+                    }),
+                ) => {}
+                (_, res) => panic!("unexpected result for {setting} = {value}: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn object_store_key_filters_can_be_set() {
+        let config = r#"
+            [object_stores.sparse]
+            key_filter = true
+            items = [{ key = "a", data = "b" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read key_filter");
+        let stores = &config.object_stores.0;
+        assert!(stores.store_config("sparse").unwrap().key_filter);
+        assert!(stores
+            .lookup(
+                ObjectStoreKey::new("sparse").unwrap(),
+                ObjectKey::new("a").unwrap()
+            )
+            .is_ok());
+        assert_eq!(
+            stores
+                .lookup(
+                    ObjectStoreKey::new("sparse").unwrap(),
+                    ObjectKey::new("b").unwrap()
+                )
+                .err(),
+            Some(KvStoreError::NotFound)
+        );
+
+        let config = r#"
+            [object_stores.sparse]
+            key_filter = "yes"
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::KeyFilterNotABool,
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn object_store_compression_can_be_set() {
+        let config = r#"
+            [object_stores.catalog]
+            compression = "gzip"
+            items = [{ key = "a", data = "{\"sku\": 1}" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read compression");
+        let stores = &config.object_stores.0;
+        assert_eq!(
+            stores.store_config("catalog").unwrap().compression,
+            Some(Compression::Gzip)
+        );
+        // seeded values are compressed too, and read back as they were given
+        assert_eq!(
+            stores
+                .lookup(
+                    ObjectStoreKey::new("catalog").unwrap(),
+                    ObjectKey::new("a").unwrap()
+                )
+                .unwrap()
+                .body,
+            &b"{\"sku\": 1}"[..]
+        );
+
+        let config = r#"
+            [object_stores.catalog]
+            compression = "zstd"
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::InvalidCompression(value),
+                ..
+            }) if value == "\"zstd\"" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn object_store_key_validation_can_be_set() {
+        // `#` is reserved by the KV store, so this key can only be seeded with an override
+        let config = r#"
+            [object_stores.legacy]
+            key_validation = "object_store"
+            items = [{ key = "page#anchor", data = "a" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read key_validation");
+        let stores = &config.object_stores.0;
+        assert_eq!(
+            stores.key_validation("legacy"),
+            Some(KeyValidationProfile::ObjectStore)
+        );
+        let key = ObjectKey::new_with_profile("page#anchor", KeyValidationProfile::ObjectStore);
+        assert_eq!(
+            stores
+                .lookup(ObjectStoreKey::new("legacy").unwrap(), key.unwrap())
+                .unwrap()
+                .body,
+            &b"a"[..]
+        );
+
+        let config = r#"
+            [object_stores.legacy]
+            items = [{ key = "page#anchor", data = "a" }]
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::KeyValidationError(_),
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let config = r#"
+            [object_stores.legacy]
+            key_validation = "loose"
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::InvalidKeyValidation(value),
+                ..
+            }) if value == "\"loose\"" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    /// Check that a store can be made `read_only`, and that the setting must be a boolean.
+    #[test]
+    fn object_store_read_only_can_be_set() {
+        let config = r#"
+            [object_stores.reference]
+            read_only = true
+            items = [{ key = "a", data = "b" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read read_only");
+        let stores = &config.object_stores.0;
+        assert!(stores.is_read_only("reference"));
+        let store = ObjectStoreKey::new("reference").unwrap();
+        let key = ObjectKey::new("a").unwrap();
+        assert_eq!(
+            stores.insert(
+                store.clone(),
+                key.clone(),
+                b"c".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            ),
+            Err(KvStoreError::BadRequest)
+        );
+        assert_eq!(stores.lookup(store, key).unwrap().body, &b"b"[..]);
+
+        let config = r#"
+            [object_stores.reference]
+            read_only = "yes"
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::ReadOnlyNotABool,
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    /// Check that a store's `key_prefix` is read, that seeded items are stored under it, and that
+    /// it must be a non-empty string keys can start with.
+    #[test]
+    fn object_store_key_prefix_can_be_set() {
+        let config = r#"
+            [object_stores.shared]
+            key_prefix = "checkout/"
+            items = [{ key = "a", data = "b" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read key_prefix");
+        let stores = &config.object_stores.0;
+        assert_eq!(stores.key_prefix("shared"), Some("checkout/".to_string()));
+        let store = ObjectStoreKey::new("shared").unwrap();
+        let key = ObjectKey::new("checkout/a").unwrap();
+        assert_eq!(stores.lookup(store, key).unwrap().body, &b"b"[..]);
+
+        for prefix in ["\"\"", "\"a#\"", "true"] {
+            let config = format!(
+                r#"
+                [object_stores.shared]
+                key_prefix = {prefix}
+                items = []
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition {
+                    err: ObjectStoreConfigError::InvalidKeyPrefix(value),
+                    ..
+                }) if value == prefix => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    /// Check that a store's `metadata_format` and `object_only` settings are read, and that
+    /// `object_only` is only allowed along with a format.
+    #[test]
+    fn object_store_metadata_format_can_be_set() {
+        let config = r#"
+            [object_stores.plain]
+            metadata_format = "json"
+            items = []
+
+            [object_stores.objects]
+            metadata_format = "json"
+            object_only = true
+            items = []
+        "#;
+        let config = read_local_server_config(config).expect("can read metadata_format");
+        let stores = &config.object_stores.0;
+        assert_eq!(
+            stores.metadata_format("plain"),
+            Some(MetadataFormat::Json { object_only: false })
+        );
+        assert_eq!(
+            stores.metadata_format("objects"),
+            Some(MetadataFormat::Json { object_only: true })
+        );
+
+        let config = r#"
+            [object_stores.plain]
+            metadata_format = "yaml"
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::InvalidMetadataFormat(value),
+                ..
+            }) if value == "\"yaml\"" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let config = r#"
+            [object_stores.plain]
+            object_only = true
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::ObjectOnlyWithoutMetadataFormat,
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    /// Check that store names are held to the same rules as production.
+    #[test]
+    fn object_store_names_must_be_valid() {
+        let config = r#"
+            [object_stores."my store"]
+            items = []
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                name,
+                err:
+                    ObjectStoreConfigError::StoreNameValidationError(
+                        StoreNameValidationError::InvalidCharacter(' '),
+                    ),
+            }) if name == "my store" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    /// Check that the contents of stores marked `sensitive` never appear in a redacted export,
+    /// while other stores are exported as-is.
+    #[test]
+    fn sensitive_stores_are_redacted_from_exports() {
+        let config = r#"
+            [object_stores.public]
+            items = [{ key = "greeting", data = "hello, world" }]
+
+            [object_stores.secrets]
+            sensitive = true
+            items = [{ key = "token", data = "hunter2-hunter2" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read sensitive stores");
+        let stores = &config.object_stores.0;
+        assert!(stores.is_sensitive("secrets"));
+        assert!(!stores.is_sensitive("public"));
+
+        let export = |redaction| serde_json::to_string(&stores.export(redaction).unwrap()).unwrap();
+        let secret_b64 = "aHVudGVyMi1odW50ZXIy";
+        let public_b64 = "aGVsbG8sIHdvcmxk";
+
+        for redaction in [Redaction::Values, Redaction::ValuesAndMetadata] {
+            let export = export(redaction);
+            assert!(!export.contains("hunter2"));
+            assert!(!export.contains(secret_b64));
+            assert!(export.contains(public_b64));
+            assert!(export.contains(r#""token":{"body":{"len":15,"hash":"#));
+        }
+        assert!(export(Redaction::None).contains(secret_b64));
+
+        // debug output summarizes values rather than printing them
+        let token = stores
+            .lookup(
+                ObjectStoreKey::new("secrets").unwrap(),
+                ObjectKey::new("token").unwrap(),
+            )
+            .unwrap();
+        let debug = format!("{token:?} {stores:?}");
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("104, 117, 110"));
+    }
+
+    /// Check that every problem with the stores is found, and that they are reported together,
+    /// grouped by store.
+    #[test]
+    fn object_store_problems_are_reported_together() {
+        let problems = match read_local_server_config(PROBLEMS) {
+            Err(InvalidObjectStoreDefinitions(problems)) => problems,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        assert_eq!(problems.len(), 6);
+
+        let report = problems.to_string();
+        let mut lines = report.lines();
+        assert_eq!(
+            lines.next(),
+            Some("invalid configuration for 3 object store(s), with 6 problem(s):")
+        );
+        assert_eq!(lines.next(), Some("  'my store':"));
+        assert_eq!(
+            lines.next(),
+            Some("    Invalid store name: Store names can only contain ASCII letters, digits, `-`, `_`, and `.`, not ' '.")
+        );
+        assert_eq!(lines.next(), Some("  'one':"));
+        assert_eq!(
+            lines.next(),
+            Some("    The `default_ttl` value for the store is not a positive number of seconds.")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("    Invalid `key` value used: Keys for objects cannot be empty.")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("    The key `dup` is used by more than one object.")
+        );
+        // the I/O error's wording depends on the platform
+        assert!(lines.next().is_some());
+        assert_eq!(lines.next(), Some("  'two':"));
+        assert_eq!(
+            lines.next(),
+            Some("    The `key` key for an object is not set. It must be used.")
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    /// Check that seeded values are held to the production size limit.
+    #[test]
+    fn object_store_values_must_fit_the_size_limit() {
+        let config = format!(
+            r#"
+            [object_stores.big]
+            items = [{{ key = "big", data = "{}" }}]
+        "#,
+            "x".repeat(KV_STORE_VALUE_MAX_LEN + 1)
+        );
+        match read_local_server_config(&config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::ValueTooLarge { key, len, max },
+                ..
+            }) if key == "big"
+                && len == KV_STORE_VALUE_MAX_LEN + 1
+                && max == KV_STORE_VALUE_MAX_LEN => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    /// Check that a store's `max_value_size` replaces the production size limit, for seeded values
+    /// as well as writes.
+    #[test]
+    fn object_store_max_value_size_can_be_set() {
+        let config = r#"
+            [object_stores.small]
+            max_value_size = 4
+            items = [{ key = "fits", data = "1234" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read max_value_size");
+        let stores = &config.object_stores.0;
+        assert_eq!(stores.max_value_size("small"), 4);
+        assert_eq!(stores.max_value_size("other"), KV_STORE_VALUE_MAX_LEN);
+
+        let config = r#"
+            [object_stores.small]
+            max_value_size = 4
+            items = [{ key = "big", data = "12345" }]
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err:
+                    ObjectStoreConfigError::ValueTooLarge {
+                        key,
+                        len: 5,
+                        max: 4,
+                    },
+                ..
+            }) if key == "big" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        for value in ["0", "-1", "\"4\""] {
+            let config = format!(
+                r#"
+                [object_stores.small]
+                max_value_size = {value}
+                items = []
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition {
+                    err: ObjectStoreConfigError::InvalidMaxValueSize,
+                    ..
+                }) => {}
+                res => panic!("unexpected result for {value}: {:?}", res),
+            }
+        }
+    }
+
+    /// Check that a store's `max_metadata_size` replaces the production metadata limit, and that
+    /// seeded metadata is held to it.
+    #[test]
+    fn object_store_max_metadata_size_can_be_set() {
+        let config = r#"
+            [object_stores.small]
+            max_metadata_size = 4
+            items = [{ key = "fits", data = "v", metadata = "1234" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read max_metadata_size");
+        let stores = &config.object_stores.0;
+        assert_eq!(stores.max_metadata_size("small"), 4);
+        assert_eq!(stores.max_metadata_size("other"), KV_STORE_METADATA_MAX_LEN);
+
+        let config = r#"
+            [object_stores.small]
+            max_metadata_size = 4
+            items = [{ key = "big", data = "v", metadata = "12345" }]
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err:
+                    ObjectStoreConfigError::MetadataTooLarge {
+                        key,
+                        len: 5,
+                        max: 4,
+                    },
+                ..
+            }) if key == "big" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        for value in ["0", "-1", "\"4\""] {
+            let config = format!(
+                r#"
+                [object_stores.small]
+                max_metadata_size = {value}
+                items = []
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition {
+                    err: ObjectStoreConfigError::InvalidMaxMetadataSize,
+                    ..
+                }) => {}
+                res => panic!("unexpected result for {value}: {:?}", res),
+            }
+        }
+    }
+
+    /// Check that a store's `max_keys` is read, and that seeding more items than it allows is a
+    /// problem.
+    #[test]
+    fn object_store_max_keys_can_be_set() {
+        let config = r#"
+            [object_stores.small]
+            max_keys = 2
+            items = [{ key = "a", data = "1" }, { key = "b", data = "2" }]
+        "#;
+        let config = read_local_server_config(config).expect("can read max_keys");
+        let stores = &config.object_stores.0;
+        assert_eq!(stores.max_keys("small"), Some(2));
+        assert_eq!(stores.max_keys("other"), None);
+
+        let config = r#"
+            [object_stores.small]
+            max_keys = 1
+            items = [{ key = "a", data = "1" }, { key = "b", data = "2" }]
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err: ObjectStoreConfigError::TooManyKeys { count: 2, max: 1 },
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        for value in ["0", "-1", "\"4\""] {
+            let config = format!(
+                r#"
+                [object_stores.small]
+                max_keys = {value}
+                items = []
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition {
+                    err: ObjectStoreConfigError::InvalidMaxKeys,
+                    ..
+                }) => {}
+                res => panic!("unexpected result for {value}: {:?}", res),
+            }
+        }
+    }
+
+    /// Check that seeded generations are kept, that runtime writes are given greater ones, and
+    /// that a store's seeded generations must be unique positive integers.
+    #[test]
+    fn object_store_generations_can_be_seeded() {
+        let config = r#"
+            [object_stores]
+            store = [
+                { key = "pinned", data = "seed", generation = 5000 },
+                { key = "unpinned", data = "seed" },
+            ]
+        "#;
+        let config = read_local_server_config(config).expect("can read seeded generations");
+        let stores = &config.object_stores.0;
+        let store = ObjectStoreKey::new("store").unwrap();
+        let key = ObjectKey::new("pinned").unwrap();
+        assert_eq!(
+            stores
+                .lookup(store.clone(), key.clone())
+                .unwrap()
+                .generation,
+            5000
+        );
+        stores
+            .insert(
+                store.clone(),
+                key.clone(),
+                b"runtime".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(stores.lookup(store, key).unwrap().generation > 5000);
+
+        let config = r#"
+            [object_stores]
+            store = [
+                { key = "a", data = "1", generation = 7 },
+                { key = "b", data = "2", generation = 7 },
+            ]
+        "#;
+        match read_local_server_config(config) {
+            Err(InvalidObjectStoreDefinition {
+                err:
+                    ObjectStoreConfigError::DuplicateGeneration {
+                        generation: 7,
+                        first,
+                        second,
+                    },
+                ..
+            }) if first == "a" && second == "b" => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        for value in ["0", "-1", "\"7\""] {
+            let config = format!(
+                r#"
+                [object_stores]
+                store = [{{ key = "a", data = "1", generation = {value} }}]
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition {
+                    err: ObjectStoreConfigError::InvalidGeneration(key),
+                    ..
+                }) if key == "a" => {}
+                res => panic!("unexpected result for {value}: {:?}", res),
+            }
+        }
+    }
+
+    /// Check that metadata can be seeded as text or, when it isn't UTF-8, as base64, and that
+    /// binary metadata survives an export and import unchanged.
+    #[test]
+    fn object_store_binary_metadata_can_be_seeded() {
+        let config = r#"
+            [object_stores]
+            store = [
+                { key = "text", data = "a", metadata = "plain" },
+                { key = "binary", data = "b", metadata = "/wCAwQ==", metadata_encoding = "base64" },
+                { key = "none", data = "c" },
+            ]
+        "#;
+        let config = read_local_server_config(config).expect("can read seeded metadata");
+        let stores = &config.object_stores.0;
+        let store = ObjectStoreKey::new("store").unwrap();
+        let metadata = |stores: &ObjectStores, key| {
+            stores
+                .lookup(store.clone(), ObjectKey::new(key).unwrap())
+                .unwrap()
+                .metadata
+        };
+        // not UTF-8, as `0xff` never is, so it can only have been seeded as base64
+        let binary = [0xff, 0x00, 0x80, 0xc1];
+        assert_eq!(metadata(stores, "text"), b"plain");
+        assert_eq!(metadata(stores, "binary"), binary);
+        assert_eq!(metadata(stores, "none"), b"");
+
+        let json = stores.export(Redaction::None).unwrap().to_json();
+        let imported = ObjectStores::default();
+        imported
+            .import(&KvExport::from_json(&json).unwrap())
+            .unwrap();
+        for key in ["text", "binary", "none"] {
+            assert_eq!(metadata(&imported, key), metadata(stores, key), "{key}");
+        }
+
+        let err = |item: &str| {
+            let config = format!(
+                r#"
+                [object_stores]
+                store = [{{ key = "a", data = "1", {item} }}]
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition { err, .. }) => err,
+                res => panic!("unexpected result for {item}: {:?}", res),
+            }
+        };
+        assert!(matches!(
+            err(r#"metadata = 7"#),
+            ObjectStoreConfigError::MetadataNotAString(key) if key == "a"
+        ));
+        assert!(matches!(
+            err(r#"metadata = "?", metadata_encoding = "base64""#),
+            ObjectStoreConfigError::InvalidBase64Metadata(key) if key == "a"
+        ));
+        assert!(matches!(
+            err(r#"metadata = "a", metadata_encoding = "hex""#),
+            ObjectStoreConfigError::InvalidMetadataEncoding(key) if key == "a"
+        ));
+    }
+
+    /// Check that values can be seeded with when they were created and last modified, and that
+    /// times that are negative, or out of order, are rejected.
+    #[test]
+    fn object_store_times_can_be_seeded() {
+        let config = r#"
+            [object_stores]
+            store = [
+                { key = "both", data = "a", created_ms = 1000, last_modified_ms = 2500 },
+                { key = "modified", data = "b", last_modified_ms = 3000 },
+            ]
+        "#;
+        let config = read_local_server_config(config).expect("can read seeded times");
+        let stores = &config.object_stores.0;
+        let times = |key| {
+            let val = stores
+                .lookup(
+                    ObjectStoreKey::new("store").unwrap(),
+                    ObjectKey::new(key).unwrap(),
+                )
+                .unwrap();
+            (val.created_at, val.updated_at)
+        };
+        let at = |ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+        assert_eq!(times("both"), (at(1000), at(2500)));
+        assert_eq!(times("modified"), (at(3000), at(3000)));
+
+        let err = |item: &str| {
+            let config = format!(
+                r#"
+                [object_stores]
+                store = [{{ key = "a", data = "1", {item} }}]
+            "#
+            );
+            match read_local_server_config(&config) {
+                Err(InvalidObjectStoreDefinition { err, .. }) => err,
+                res => panic!("unexpected result for {item}: {:?}", res),
+            }
+        };
+        assert!(matches!(
+            err(r#"created_ms = -1"#),
+            ObjectStoreConfigError::InvalidSeedTime { key, field: "created_ms" } if key == "a"
+        ));
+        assert!(matches!(
+            err(r#"last_modified_ms = "now""#),
+            ObjectStoreConfigError::InvalidSeedTime { key, field: "last_modified_ms" }
+                if key == "a"
+        ));
+        assert!(matches!(
+            err(r#"created_ms = 2, last_modified_ms = 1"#),
+            ObjectStoreConfigError::CreatedAfterLastModified(key) if key == "a"
+        ));
+    }
+
+    /// Check that when validation is skipped, only the parts with problems are left out.
+    #[test]
+    fn object_store_validation_can_be_skipped() {
+        let manifest = PROBLEMS.replace("[object_stores", "[local_server.object_stores");
+        let config = FastlyConfig::read_str(&manifest, false).expect("problems are skipped");
+        let stores = config.object_stores();
+        let lookup = |store, key| {
+            stores
+                .lookup(
+                    ObjectStoreKey::new(store).unwrap(),
+                    ObjectKey::new(key).unwrap(),
+                )
+                .map(|value| value.body.to_vec())
+        };
+
+        assert!(stores.store_key("my store").is_err());
+        assert_eq!(stores.default_ttl("one"), None);
+        assert_eq!(lookup("one", "fine").unwrap(), b"fine");
+        assert_eq!(lookup("one", "dup").unwrap(), b"first");
+        assert!(matches!(
+            lookup("one", "missing"),
+            Err(KvStoreError::NotFound)
+        ));
+        assert!(stores.store_key("two").unwrap().is_some());
+
+        // without skipping, the same file is refused
+        assert!(matches!(
+            FastlyConfig::read_str(&manifest, true),
+            Err(InvalidObjectStoreDefinitions(_))
+        ));
+    }
+    /// A table of `stores` inline stores, each with `keys` items.
+    fn inline_stores(stores: usize, keys: usize) -> toml::value::Table {
+        (0..stores)
+            .map(|s| {
+                let items = (0..keys)
+                    .map(|k| {
+                        let mut item = toml::value::Table::new();
+                        item.insert("key".into(), format!("key{k}").into());
+                        item.insert("data".into(), format!("value{s}-{k}").into());
+                        toml::Value::Table(item)
+                    })
+                    .collect::<Vec<_>>();
+                (format!("store{s}"), toml::Value::Array(items))
+            })
+            .collect()
+    }
+
+    /// Check that stores read by several threads at once are seeded just as they would be by one,
+    /// with the same generations and the same problems.
+    #[test]
+    fn object_store_seeding_does_not_depend_on_threads() {
+        let mut toml = inline_stores(16, 10);
+        toml.insert("bad name".into(), toml::Value::Array(vec![]));
+        toml.insert("not_an_array".into(), toml::Value::Integer(1));
+
+        let read = |workers| ObjectStoreConfig::read_with_workers(toml.clone(), workers);
+        let (one, one_problems) = read(1);
+        let (many, many_problems) = read(8);
+        assert_eq!(one_problems.to_string(), many_problems.to_string());
+        assert_eq!(many_problems.len(), 2);
+        for s in 0..16 {
+            for k in 0..10 {
+                let lookup = |config: &ObjectStoreConfig| {
+                    config
+                        .0
+                        .lookup(
+                            ObjectStoreKey::new(format!("store{s}")).unwrap(),
+                            ObjectKey::new(format!("key{k}")).unwrap(),
+                        )
+                        .unwrap()
+                };
+                let (one, many) = (lookup(&one), lookup(&many));
+                assert_eq!(one.body, many.body);
+                assert_eq!(one.generation, many.generation);
+            }
+        }
+        assert!(many.0.store_key("not_an_array").unwrap().is_none());
+    }
+
+    /// Compare seeding many stores, and one 50,000 entry store read from a JSON file, on one
+    /// thread and on as many as there are cores.
+    ///
+    /// Run with `cargo test -p viceroy-lib -- --ignored bench_object_store_seeding --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_object_store_seeding() {
+        use std::time::Instant;
+
+        const STORES: usize = 32;
+        const KEYS: usize = 5_000;
+        const JSON_KEYS: usize = 50_000;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("big.json");
+        let json = (0..JSON_KEYS)
+            .map(|k| {
+                (
+                    format!("key{k}"),
+                    serde_json::Value::from(format!("value{k}")),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+
+        let mut toml = inline_stores(STORES, KEYS);
+        let mut big = toml::value::Table::new();
+        big.insert("file".into(), path.to_str().unwrap().into());
+        big.insert("format".into(), "json".into());
+        toml.insert("big".into(), toml::Value::Table(big));
+
+        let workers = std::thread::available_parallelism().map_or(1, usize::from);
+        for workers in [1, workers] {
+            let start = Instant::now();
+            let (_, problems) = ObjectStoreConfig::read_with_workers(toml.clone(), workers);
+            assert!(problems.is_empty());
+            println!(
+                "{workers} thread(s): seeded {} values in {:?}",
+                STORES * KEYS + JSON_KEYS,
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Running guests against KV stores from Rust.
+//!
+//! Embedders own the [`ObjectStores`] a guest sees. They can seed the stores before a request,
+//! attach them to an [`ExecuteCtx`], and inspect them once the request completes. Clones of an
+//! `ObjectStores` share their contents, so the handle used to seed the stores sees the guest's
+//! writes, as does the one returned by [`ExecuteCtx::object_stores`].
+//!
+//! # Example
+//!
+//! This runs the `kv_store` test fixture, which reads two seeded values and writes a third. The
+//! test fixtures must be built first, with `make fix-build`.
+//!
+//! ```
+//! use {
+//!     std::collections::HashSet,
+//!     viceroy_lib::{
+//!         config::{ObjectStores, UnknownImportBehavior},
+//!         ExecuteCtx, KvInsertMode, ObjectKey, ObjectStoreKey, ProfilingStrategy,
+//!     },
+//! };
+//!
+//! # #[tokio::main(flavor = "multi_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // Seed the stores the guest expects.
+//! let stores = ObjectStores::new();
+//! for (key, value) in [("first", "This is some data"), ("second", "More data")] {
+//!     stores.insert(
+//!         ObjectStoreKey::new("store_one")?,
+//!         ObjectKey::new(key)?,
+//!         value.into(),
+//!         KvInsertMode::Overwrite,
+//!         None,
+//!         None,
+//!         None,
+//!     )?;
+//! }
+//! stores.insert_empty_store(ObjectStoreKey::new("empty_store")?)?;
+//!
+//! // Attach them to an execution context, and run a request.
+//! let module = concat!(
+//!     env!("CARGO_MANIFEST_DIR"),
+//!     "/../test-fixtures/target/wasm32-wasi/debug/kv_store.wasm"
+//! );
+//! let ctx = ExecuteCtx::new(
+//!     module,
+//!     ProfilingStrategy::None,
+//!     HashSet::new(),
+//!     None,
+//!     UnknownImportBehavior::LinkError,
+//!     false,
+//! )?
+//! .with_object_stores(stores.share());
+//!
+//! let req = hyper::Request::get("http://localhost/").body(hyper::Body::empty())?;
+//! let local = "127.0.0.1:80".parse()?;
+//! let remote = "127.0.0.1:0".parse()?;
+//! let (resp, err) = ctx.clone().handle_request(req, local, remote).await?;
+//! assert!(err.is_none());
+//! assert_eq!(resp.status(), 200);
+//!
+//! // The guest's write is visible through the context, and through the original handle.
+//! for stores in [ctx.object_stores(), &stores] {
+//!     let value = stores.lookup(ObjectStoreKey::new("empty_store")?, ObjectKey::new("bar")?)?;
+//!     assert_eq!(value.body(), &b"foo"[..]);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ObjectStores`]: crate::config::ObjectStores
+//! [`ExecuteCtx`]: crate::ExecuteCtx
+//! [`ExecuteCtx::object_stores`]: crate::ExecuteCtx::object_stores
@@ -1,5 +1,6 @@
 //! Error types.
 
+use std::collections::BTreeMap;
 use std::error::Error as StdError;
 use std::io;
 use {crate::wiggle_abi::types::FastlyStatus, url::Url, wiggle::GuestError};
@@ -128,6 +129,10 @@ pub enum Error {
     #[error("Invalid Object Store `key` value used: {0}.")]
     ObjectStoreKeyValidationError(#[from] crate::object_store::KeyValidationError),
 
+    /// A KV key that, with its store's `key_prefix`, is longer than the store allows.
+    #[error("KV key is longer than the {max_len} bytes its store allows")]
+    KvKeyTooLong { max_len: usize },
+
     #[error("Unfinished streaming body")]
     UnfinishedStreamingBody,
 
@@ -148,6 +153,9 @@ pub enum Error {
 
     #[error("Resource temporarily unavailable")]
     Again,
+
+    #[error("Invalid KV trace entry on line {line}: {err}")]
+    InvalidKvTrace { line: usize, err: serde_json::Error },
 }
 
 impl Error {
@@ -187,6 +195,10 @@ impl Error {
             Error::ObjectStoreError(e) => e.into(),
             Error::KvStoreError(e) => e.into(),
             Error::SecretStoreError(e) => e.into(),
+            Error::ObjectStoreKeyValidationError(
+                crate::object_store::KeyValidationError::Over1024Bytes,
+            ) => FastlyStatus::Buflen,
+            Error::KvKeyTooLong { .. } => FastlyStatus::Buflen,
             Error::Again => FastlyStatus::Again,
             // All other hostcall errors map to a generic `ERROR` value.
             Error::AbiVersionMismatch
@@ -214,7 +226,8 @@ impl Error {
             | Error::UnfinishedStreamingBody
             | Error::SharedMemory
             | Error::ToStr(_)
-            | Error::InvalidAlpnRepsonse(_, _) => FastlyStatus::Error,
+            | Error::InvalidAlpnRepsonse(_, _)
+            | Error::InvalidKvTrace { .. } => FastlyStatus::Error,
         }
     }
 
@@ -280,6 +293,10 @@ pub enum HandleError {
     #[error("Invalid pending KV list handle: {0}")]
     InvalidPendingKvListHandle(crate::wiggle_abi::types::PendingKvListHandle),
 
+    /// A head handle was not valid.
+    #[error("Invalid pending KV head handle: {0}")]
+    InvalidPendingKvHeadHandle(crate::wiggle_abi::types::KvStoreHeadHandle),
+
     /// A dictionary handle was not valid.
     #[error("Invalid dictionary handle: {0}")]
     InvalidDictionaryHandle(crate::wiggle_abi::types::DictionaryHandle),
@@ -288,6 +305,10 @@ pub enum HandleError {
     #[error("Invalid object-store handle: {0}")]
     InvalidObjectStoreHandle(crate::wiggle_abi::types::ObjectStoreHandle),
 
+    /// A KV store handle was not valid.
+    #[error("Invalid KV store handle: {0}")]
+    InvalidKvStoreHandle(crate::wiggle_abi::types::KvStoreHandle),
+
     /// A secret store handle was not valid.
     #[error("Invalid secret store handle: {0}")]
     InvalidSecretStoreHandle(crate::wiggle_abi::types::SecretStoreHandle),
@@ -329,6 +350,10 @@ pub(crate) enum ExecutionError {
     /// Errors thrown when trying to instantiate a guest module.
     #[error("Error instantiating WebAssembly: {0}")]
     Instantiation(anyhow::Error),
+
+    /// The guest received KV errors that strict mode doesn't allow.
+    #[error(transparent)]
+    KvStrict(crate::kv_strict::KvStrictFailure),
 }
 
 /// Errors that can occur while parsing a `fastly.toml` file.
@@ -377,6 +402,10 @@ pub enum FastlyConfigError {
         err: ObjectStoreConfigError,
     },
 
+    /// More than one problem was found with the object store definitions.
+    #[error(transparent)]
+    InvalidObjectStoreDefinitions(ObjectStoreConfigProblems),
+
     #[error("invalid configuration for '{name}': {err}")]
     InvalidSecretStoreDefinition {
         name: String,
@@ -651,12 +680,46 @@ pub enum ObjectStoreConfigError {
     NotAnArray,
     #[error("There is an object in the given store that is not a table of keys.")]
     NotATable,
+    #[error("The `sensitive` value for the store is not a boolean.")]
+    SensitiveNotABool,
+    #[error("The `default_ttl` value for the store is not a positive number of seconds.")]
+    InvalidDefaultTtl,
+    #[error("The `max_concurrent_operations` value for the store is not a positive integer.")]
+    InvalidMaxConcurrentOperations,
+    #[error("The `max_queued_operations` value for the store is not a non-negative integer.")]
+    InvalidMaxQueuedOperations,
+    #[error("The `max_value_size` value for the store is not a positive integer.")]
+    InvalidMaxValueSize,
+    #[error("The `max_metadata_size` value for the store is not a positive integer.")]
+    InvalidMaxMetadataSize,
+    #[error("The `max_keys` value for the store is not a positive integer.")]
+    InvalidMaxKeys,
+    #[error("The `warn_value_size` value for the store is not a positive integer.")]
+    InvalidWarnValueSize,
+    #[error("The `key_filter` value for the store is not a boolean.")]
+    KeyFilterNotABool,
+    #[error("The `compression` value for the store is {0}, not one of \"gzip\" or \"deflate\".")]
+    InvalidCompression(String),
+    #[error("The `key_validation` value for the store is {0}, not one of \"kv_store\", \"object_store\", or \"permissive\".")]
+    InvalidKeyValidation(String),
+    #[error("The `read_only` value for the store is not a boolean.")]
+    ReadOnlyNotABool,
+    #[error("The `key_prefix` value for the store is {0}, not a non-empty string that keys can start with.")]
+    InvalidKeyPrefix(String),
+    #[error("The `metadata_format` value for the store is {0}, not \"json\".")]
+    InvalidMetadataFormat(String),
+    #[error("The `object_only` value for the store is not a boolean.")]
+    ObjectOnlyNotABool,
+    #[error("The `object_only` setting for the store needs `metadata_format` to be set.")]
+    ObjectOnlyWithoutMetadataFormat,
     #[error("There was an error when manipulating the ObjectStore: {0}.")]
     ObjectStoreError(#[from] crate::object_store::ObjectStoreError),
     #[error("There was an error when manipulating the KvStore: {0}.")]
     KvStoreError(#[from] crate::object_store::KvStoreError),
     #[error("Invalid `key` value used: {0}.")]
     KeyValidationError(#[from] crate::object_store::KeyValidationError),
+    #[error("Invalid store name: {0}.")]
+    StoreNameValidationError(#[from] crate::object_store::StoreNameValidationError),
     #[error("'{0}' is not a valid format for the config store. Supported format(s) are: 'json'.")]
     InvalidFileFormat(String),
     #[error("When using a top-level 'file' to load data, both 'file' and 'format' must be set.")]
@@ -667,8 +730,91 @@ pub enum ObjectStoreConfigError {
     FileWrongFormat,
     #[error("Item value under key named '{key}' is of the wrong format. The value is expected to be a JSON String.")]
     FileValueWrongFormat { key: String },
+    #[error("The key `{0}` is used by more than one object.")]
+    DuplicateKey(String),
+    #[error("The `generation` value for the object `{0}` is not a positive integer.")]
+    InvalidGeneration(String),
+    #[error("The objects `{first}` and `{second}` are both seeded with generation {generation}.")]
+    DuplicateGeneration {
+        generation: u64,
+        first: String,
+        second: String,
+    },
+    #[error("The `metadata` value for the object `{0}` is not a string.")]
+    MetadataNotAString(String),
+    #[error("The `metadata_encoding` value for the object `{0}` is not \"utf8\" or \"base64\".")]
+    InvalidMetadataEncoding(String),
+    #[error("The `metadata` value for the object `{0}` is not valid base64.")]
+    InvalidBase64Metadata(String),
+    #[error("The `{field}` value for the object `{key}` is not a non-negative integer.")]
+    InvalidSeedTime { key: String, field: &'static str },
+    #[error("The object `{0}` is seeded with a `created_ms` later than its `last_modified_ms`.")]
+    CreatedAfterLastModified(String),
+    #[error(
+        "The value for the object `{key}` is {len} bytes, which is over the limit of {max} bytes."
+    )]
+    ValueTooLarge { key: String, len: usize, max: usize },
+    #[error(
+        "The metadata for the object `{key}` is {len} bytes, which is over the limit of {max} bytes."
+    )]
+    MetadataTooLarge { key: String, len: usize, max: usize },
+    #[error("The store has {count} objects, which is over its `max_keys` of {max}.")]
+    TooManyKeys { count: usize, max: usize },
 }
 
+/// Every problem found with the object store definitions, grouped by store.
+#[derive(Debug, Default)]
+pub struct ObjectStoreConfigProblems(BTreeMap<String, Vec<ObjectStoreConfigError>>);
+
+impl ObjectStoreConfigProblems {
+    pub(crate) fn push(&mut self, store: &str, err: ObjectStoreConfigError) {
+        self.0.entry(store.to_string()).or_default().push(err);
+    }
+
+    /// The number of problems found, across all stores.
+    pub fn len(&self) -> usize {
+        self.0.values().map(Vec::len).sum()
+    }
+
+    /// Whether no problems were found.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for ObjectStoreConfigProblems {
+    type Item = (String, ObjectStoreConfigError);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(
+            self.0
+                .into_iter()
+                .flat_map(|(store, errs)| errs.into_iter().map(move |err| (store.clone(), err))),
+        )
+    }
+}
+
+impl std::fmt::Display for ObjectStoreConfigProblems {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid configuration for {} object store(s), with {} problem(s):",
+            self.0.len(),
+            self.len()
+        )?;
+        for (store, errs) in &self.0 {
+            write!(f, "\n  '{store}':")?;
+            for err in errs {
+                write!(f, "\n    {err}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ObjectStoreConfigProblems {}
+
 /// Errors that may occur while validating secret store configurations.
 #[derive(Debug, thiserror::Error)]
 pub enum SecretStoreConfigError {
@@ -11,10 +11,13 @@ use {
         },
         downstream::prepare_request,
         error::ExecutionError,
+        kv_diagnostics::SessionKvLog,
+        kv_list_capture::{KvListCapture, SessionKvLists},
+        kv_strict::{KvStrictConfig, SessionKvStrict},
         linking::{create_store, link_host_functions, ComponentCtx, WasmCtx},
-        object_store::ObjectStores,
+        object_store::{KvNamespaceConfig, ObjectStores},
         secret_store::SecretStores,
-        session::Session,
+        session::{KvSummary, Session},
         upstream::TlsConfig,
         Error,
     },
@@ -36,8 +39,11 @@ use {
         thread::{self, JoinHandle},
         time::{Duration, Instant, SystemTime},
     },
-    tokio::sync::oneshot::{self, Sender},
-    tracing::{event, info, info_span, warn, Instrument, Level},
+    tokio::sync::{
+        oneshot::{self, Sender},
+        Notify,
+    },
+    tracing::{error, event, info, info_span, warn, Instrument, Level},
     wasmtime::{
         component::{self, Component},
         Engine, GuestProfiler, InstancePre, Linker, Module, ProfilingStrategy,
@@ -92,8 +98,21 @@ pub struct ExecuteCtx {
     log_stderr: bool,
     /// The ID to assign the next incoming request
     next_req_id: Arc<AtomicU64>,
+    /// Requests whose guest code is still executing, along with any KV operations they have
+    /// pending.
+    in_flight: Arc<InFlightRequests>,
+    /// The total number of KV handles that guests left pending when their request completed.
+    leaked_kv_handles: Arc<AtomicU64>,
     /// The ObjectStore associated with this instance of Viceroy
     object_store: ObjectStores,
+    /// How requests are mapped to KV store namespaces, if at all.
+    kv_namespaces: Option<KvNamespaceConfig>,
+    /// Whether each request sees an isolated view of the KV stores, rather than the shared ones.
+    kv_isolation: bool,
+    /// Whether `select` picks its winner reproducibly, rather than in completion order.
+    deterministic_select: bool,
+    /// The seed for delaying pending KV operations by random amounts, if chaos mode is enabled.
+    kv_chaos: Option<u64>,
     /// The secret stores for this execution.
     secret_stores: Arc<SecretStores>,
     // `Arc` for the two fields below because this struct must be `Clone`.
@@ -103,6 +122,16 @@ pub struct ExecuteCtx {
     /// this must refer to a directory, while in run mode it names
     /// a file.
     guest_profile_path: Arc<Option<PathBuf>>,
+    /// Directory to write a KV diagnostic bundle to when a guest traps, if any.
+    kv_diagnostics_path: Arc<Option<PathBuf>>,
+    /// The KV errors tolerated in strict mode, if it is enabled.
+    kv_strict: Option<Arc<KvStrictConfig>>,
+    /// The number of requests failed by strict mode.
+    kv_strict_failures: Arc<AtomicU64>,
+    /// Where to capture the list responses handed to guests, if anywhere.
+    kv_list_capture: Option<Arc<KvListCapture>>,
+    /// Whether guests may use the hostcalls Viceroy offers beyond production's.
+    local_extensions: bool,
 }
 
 impl ExecuteCtx {
@@ -218,11 +247,22 @@ impl ExecuteCtx {
             log_stdout: false,
             log_stderr: false,
             next_req_id: Arc::new(AtomicU64::new(0)),
+            in_flight: Arc::new(InFlightRequests::default()),
+            leaked_kv_handles: Arc::new(AtomicU64::new(0)),
             object_store: ObjectStores::new(),
+            kv_namespaces: None,
+            kv_isolation: false,
+            deterministic_select: false,
+            kv_chaos: None,
             secret_stores: Arc::new(SecretStores::new()),
             epoch_increment_thread,
             epoch_increment_stop,
             guest_profile_path: Arc::new(guest_profile_path),
+            kv_diagnostics_path: Arc::new(None),
+            kv_strict: None,
+            kv_strict_failures: Arc::new(AtomicU64::new(0)),
+            kv_list_capture: None,
+            local_extensions: false,
         })
     }
 
@@ -281,6 +321,142 @@ impl ExecuteCtx {
         self
     }
 
+    /// Get the object stores for this execution context.
+    pub fn object_stores(&self) -> &ObjectStores {
+        &self.object_store
+    }
+
+    /// Give each request naming a namespace in the configured header its own copy of the KV
+    /// stores.
+    pub fn with_kv_namespaces(mut self, kv_namespaces: KvNamespaceConfig) -> Self {
+        self.kv_namespaces = Some(kv_namespaces);
+        self
+    }
+
+    /// How requests are mapped to KV store namespaces, if at all.
+    pub fn kv_namespaces(&self) -> Option<&KvNamespaceConfig> {
+        self.kv_namespaces.as_ref()
+    }
+
+    /// Give each request an [isolated][ObjectStores::isolated] view of the KV stores, so that its
+    /// writes are seen by no other request and are discarded when it ends.
+    ///
+    /// Requests still read whatever the shared stores hold for the keys they haven't written,
+    /// including seed data and values written through [`object_stores`][Self::object_stores].
+    /// Off by default, in which case every request writes to the shared stores.
+    pub fn with_kv_isolation(mut self, enabled: bool) -> Self {
+        self.kv_isolation = enabled;
+        self
+    }
+
+    /// Whether each request is given an isolated view of the KV stores.
+    pub fn kv_isolation(&self) -> bool {
+        self.kv_isolation
+    }
+
+    /// Make `select` choose the same winner every time it is given the same handles, rather than
+    /// whichever completes first.
+    ///
+    /// In deterministic mode, `select` first waits for every pending KV operation it is given to
+    /// complete, and then returns the lowest-index handle that is ready. As KV operations always
+    /// complete, a `select` over KV handles alone always returns the same winner. Other handles,
+    /// such as pending requests, are still ready whenever they happen to be, so a `select` that
+    /// includes them is only reproducible as far as their timing is.
+    ///
+    /// In either mode, the handles `select` doesn't return are left pending, and can still be
+    /// waited on or selected again.
+    ///
+    /// Deterministic mode can't be combined with [chaos mode][Self::with_kv_chaos], so enabling
+    /// it disables chaos mode.
+    pub fn with_deterministic_select(mut self, deterministic_select: bool) -> Self {
+        self.deterministic_select = deterministic_select;
+        if deterministic_select {
+            self.kv_chaos = None;
+        }
+        self
+    }
+
+    /// Whether `select` picks its winner reproducibly. See
+    /// [`with_deterministic_select`][Self::with_deterministic_select].
+    pub fn deterministic_select(&self) -> bool {
+        self.deterministic_select
+    }
+
+    /// Delay each KV operation by a random amount of up to a few milliseconds, so that
+    /// operations started together complete in a shuffled order.
+    ///
+    /// A delayed operation holds its turn under its store's `max_concurrent_operations` limit
+    /// until it completes, as a slow operation would, so chaos mode exercises those limits too.
+    ///
+    /// This is for flushing out guests that depend on the order KV operations complete in, which
+    /// the store doesn't guarantee. The delays are drawn from a generator seeded with `seed` and
+    /// the request's ID, so a failure can be retried with the same delays; the order operations
+    /// complete in still depends on how long they take to run, though, so a retry isn't
+    /// guaranteed to reproduce it.
+    ///
+    /// Chaos mode can't be combined with [deterministic `select`][Self::with_deterministic_select],
+    /// so enabling it disables deterministic `select`.
+    pub fn with_kv_chaos(mut self, seed: u64) -> Self {
+        self.kv_chaos = Some(seed);
+        self.deterministic_select = false;
+        self
+    }
+
+    /// The seed pending KV operations' delays are drawn with, if chaos mode is enabled. See
+    /// [`with_kv_chaos`][Self::with_kv_chaos].
+    pub fn kv_chaos(&self) -> Option<u64> {
+        self.kv_chaos
+    }
+
+    /// Record each request's KV operations, and when a guest traps, write a diagnostic bundle
+    /// for its request to a new directory under `path`.
+    ///
+    /// See [`kv_diagnostics`](crate::kv_diagnostics) for the contents of the bundle.
+    pub fn with_kv_diagnostics(mut self, path: PathBuf) -> Self {
+        self.kv_diagnostics_path = Arc::new(Some(path));
+        self
+    }
+
+    /// Fail any request whose guest receives a KV error that `config` doesn't allow, once the
+    /// guest finishes.
+    ///
+    /// See [`kv_strict`](crate::kv_strict) for details.
+    pub fn with_kv_strict(mut self, config: KvStrictConfig) -> Self {
+        self.kv_strict = Some(Arc::new(config));
+        self
+    }
+
+    /// Capture the body of every KV list response handed to a guest into `capture`, where the
+    /// embedder can take them from once its requests complete.
+    ///
+    /// See [`kv_list_capture`](crate::kv_list_capture) for details.
+    pub fn with_kv_list_capture(mut self, capture: Arc<KvListCapture>) -> Self {
+        self.kv_list_capture = Some(capture);
+        self
+    }
+
+    /// Where the list responses handed to guests are captured, if anywhere. See
+    /// [`with_kv_list_capture`][Self::with_kv_list_capture].
+    pub fn kv_list_capture(&self) -> Option<&Arc<KvListCapture>> {
+        self.kv_list_capture.as_ref()
+    }
+
+    /// Let guests use the hostcalls Viceroy offers beyond production's, such as the one for
+    /// reading a KV store's limits.
+    ///
+    /// These are off by default, so that a guest relying on one fails locally as it would in
+    /// production; a guest that calls one while they are off gets an `Unsupported` error.
+    pub fn with_local_extensions(mut self, local_extensions: bool) -> Self {
+        self.local_extensions = local_extensions;
+        self
+    }
+
+    /// Whether guests may use the hostcalls Viceroy offers beyond production's. See
+    /// [`with_local_extensions`][Self::with_local_extensions].
+    pub fn local_extensions(&self) -> bool {
+        self.local_extensions
+    }
+
     /// Set the secret stores for this execution context.
     pub fn with_secret_stores(mut self, secret_stores: SecretStores) -> Self {
         self.secret_stores = Arc::new(secret_stores);
@@ -332,6 +508,48 @@ impl ExecuteCtx {
         &self.tls_config
     }
 
+    /// The total number of KV lookups, inserts, deletes, and lists that guests started but never
+    /// waited on before their request completed.
+    pub fn leaked_kv_handles(&self) -> u64 {
+        self.leaked_kv_handles.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests failed because their guest received KV errors not allowed in
+    /// [strict mode](Self::with_kv_strict).
+    pub fn kv_strict_failures(&self) -> u64 {
+        self.kv_strict_failures.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests whose guest code is still executing.
+    pub fn in_flight_requests(&self) -> u64 {
+        self.in_flight.count.load(Ordering::SeqCst)
+    }
+
+    /// Wait for in-flight requests to finish, giving up once `grace_period` has elapsed.
+    ///
+    /// Guests apply their KV inserts and deletes before the corresponding `*_wait` hostcall
+    /// returns, so once a request has finished executing, every KV operation it issued has
+    /// landed in the [`ObjectStores`]. Requests still running when the grace period expires are
+    /// abandoned, along with any operations they had yet to issue.
+    pub async fn drain_in_flight_requests(&self, grace_period: Duration) -> DrainSummary {
+        let finished_before = self.in_flight.finished.load(Ordering::SeqCst);
+        let _ = tokio::time::timeout(grace_period, async {
+            loop {
+                let notified = self.in_flight.notify.notified();
+                if self.in_flight_requests() == 0 {
+                    break;
+                }
+                notified.await;
+            }
+        })
+        .await;
+
+        DrainSummary {
+            completed: self.in_flight.finished.load(Ordering::SeqCst) - finished_before,
+            abandoned: self.in_flight_requests(),
+        }
+    }
+
     /// Asynchronously handle a request.
     ///
     /// This method fully instantiates the wasm module housed within the `ExecuteCtx`,
@@ -412,6 +630,13 @@ impl ExecuteCtx {
                         .unwrap();
                     (response, Some(_e))
                 }
+                Err(ExecutionError::KvStrict(failure)) => {
+                    let response = Response::builder()
+                        .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::empty())
+                        .unwrap();
+                    (response, Some(failure.into()))
+                }
                 Err(e) => panic!("failed to run guest: {}", e),
             },
         };
@@ -442,6 +667,69 @@ impl ExecuteCtx {
         Ok(resp)
     }
 
+    /// Emit the end-of-request log event, and note any KV handles the guest left pending.
+    fn log_request_completion(&self, kv: KvSummary, request_duration: Duration) {
+        self.leaked_kv_handles
+            .fetch_add(kv.pending_handles as u64, Ordering::Relaxed);
+        info!(
+            kv_lookups = kv.lookups,
+            kv_hits = kv.hits,
+            kv_misses = kv.misses,
+            kv_expired_misses = kv.expired_misses,
+            kv_inserts = kv.inserts,
+            kv_appends = kv.appends,
+            kv_prepends = kv.prepends,
+            kv_deletes = kv.deletes,
+            kv_lists = kv.lists,
+            kv_bytes_written = kv.bytes_written,
+            kv_errors = kv.errors,
+            kv_queued = kv.queued,
+            kv_time = ?kv.time,
+            kv_store_handles = kv.store_handles,
+            kv_pending_handles = kv.pending_handles,
+            "request completed in {:.0?}",
+            request_duration
+        );
+    }
+
+    /// Write a KV diagnostic bundle for a request whose guest trapped.
+    fn write_kv_diagnostics(
+        &self,
+        req_id: u64,
+        request: &str,
+        trap: &anyhow::Error,
+        log: &SessionKvLog,
+        stores: &ObjectStores,
+    ) {
+        let Some(path) = self.kv_diagnostics_path.as_deref() else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let dir = path.join(format!("{}-{}", now, req_id));
+        match log.write_bundle(&dir, request, trap, stores) {
+            Ok(()) => info!("wrote KV diagnostics to {}", dir.display()),
+            Err(e) => warn!("failed to write KV diagnostics to {}: {}", dir.display(), e),
+        }
+    }
+
+    /// Fail a request whose guest received KV errors that strict mode doesn't allow, unless it
+    /// already failed for another reason.
+    fn check_kv_strict(
+        &self,
+        strict: Option<&SessionKvStrict>,
+        outcome: Result<(), ExecutionError>,
+    ) -> Result<(), ExecutionError> {
+        let Some(failure) = strict.and_then(SessionKvStrict::failure) else {
+            return outcome;
+        };
+        self.kv_strict_failures.fetch_add(1, Ordering::Relaxed);
+        error!("{failure}");
+        outcome.and(Err(ExecutionError::KvStrict(failure)))
+    }
+
     async fn run_guest(
         self,
         req: Request<Body>,
@@ -452,7 +740,31 @@ impl ExecuteCtx {
         active_cpu_time_us: Arc<AtomicU64>,
     ) -> Result<(), ExecutionError> {
         info!("handling request {} {}", req.method(), req.uri());
+        let _in_flight = InFlightGuard::enter(&self.in_flight);
         let start_timestamp = Instant::now();
+        let request_line = format!("{} {}", req.method(), req.uri());
+        let kv_log = self
+            .kv_diagnostics_path
+            .is_some()
+            .then(|| Arc::new(SessionKvLog::default()));
+        let kv_strict = self
+            .kv_strict
+            .as_ref()
+            .map(|config| Arc::new(SessionKvStrict::new(config.clone())));
+        let mut kv_store = self.object_store.clone();
+        if let Some(log) = &kv_log {
+            kv_store = kv_store.with_scoped_observer(log.clone());
+        }
+        if let Some(strict) = &kv_strict {
+            kv_store = kv_store.with_scoped_observer(strict.clone());
+        }
+        if let Some(capture) = &self.kv_list_capture {
+            let lists = SessionKvLists::new(req_id, capture.clone());
+            kv_store = kv_store.with_scoped_observer(Arc::new(lists));
+        }
+        if self.kv_isolation {
+            kv_store = kv_store.isolated();
+        }
         let session = Session::new(
             req_id,
             req,
@@ -467,7 +779,7 @@ impl ExecuteCtx {
             self.tls_config.clone(),
             self.dictionaries.clone(),
             self.config_path.clone(),
-            self.object_store.clone(),
+            kv_store,
             self.secret_stores.clone(),
         );
 
@@ -528,9 +840,15 @@ impl ExecuteCtx {
                     bytesize::ByteSize::b(store.data().limiter().memory_allocated as u64),
                 );
 
-                info!("request completed in {:.0?}", request_duration);
+                let kv = store.data_mut().session().kv_summary();
+                self.log_request_completion(kv, request_duration);
+
+                if let (Err(ExecutionError::WasmTrap(e)), Some(log)) = (&outcome, &kv_log) {
+                    let stores = &store.data_mut().session().kv_store;
+                    self.write_kv_diagnostics(req_id, &request_line, e, log, stores);
+                }
 
-                outcome
+                self.check_kv_strict(kv_strict.as_deref(), outcome)
             }
 
             Instance::Module(module, instance_pre) => {
@@ -595,9 +913,15 @@ impl ExecuteCtx {
                     bytesize::ByteSize::b(store.data().limiter().memory_allocated as u64)
                 );
 
-                info!("request completed in {:.0?}", request_duration);
+                let kv = store.data_mut().session().kv_summary();
+                self.log_request_completion(kv, request_duration);
 
-                outcome
+                if let (Err(ExecutionError::WasmTrap(e)), Some(log)) = (&outcome, &kv_log) {
+                    let stores = &store.data_mut().session().kv_store;
+                    self.write_kv_diagnostics(req_id, &request_line, e, log, stores);
+                }
+
+                self.check_kv_strict(kv_strict.as_deref(), outcome)
             }
         }
     }
@@ -610,6 +934,18 @@ impl ExecuteCtx {
         let local = (Ipv4Addr::LOCALHOST, 80).into();
         let remote = (Ipv4Addr::LOCALHOST, 0).into();
         let active_cpu_time_us = Arc::new(AtomicU64::new(0));
+        let kv_strict = self
+            .kv_strict
+            .as_ref()
+            .map(|config| Arc::new(SessionKvStrict::new(config.clone())));
+        let mut kv_store = self.object_store.clone();
+        if let Some(strict) = &kv_strict {
+            kv_store = kv_store.with_scoped_observer(strict.clone());
+        }
+        if let Some(capture) = &self.kv_list_capture {
+            let lists = SessionKvLists::new(req_id, capture.clone());
+            kv_store = kv_store.with_scoped_observer(Arc::new(lists));
+        }
 
         let session = Session::new(
             req_id,
@@ -625,7 +961,7 @@ impl ExecuteCtx {
             self.tls_config.clone(),
             self.dictionaries.clone(),
             self.config_path.clone(),
-            self.object_store.clone(),
+            kv_store,
             self.secret_stores.clone(),
         );
 
@@ -677,10 +1013,52 @@ impl ExecuteCtx {
         // finished.
         drop(receiver);
 
+        if let Some(failure) = kv_strict.as_deref().and_then(SessionKvStrict::failure) {
+            self.kv_strict_failures.fetch_add(1, Ordering::Relaxed);
+            match result {
+                Ok(()) => return Err(failure.into()),
+                Err(_) => error!("{failure}"),
+            }
+        }
+
         result
     }
 }
 
+/// The outcome of [`ExecuteCtx::drain_in_flight_requests`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrainSummary {
+    /// Requests that finished executing within the grace period.
+    pub completed: u64,
+    /// Requests that were still executing when the grace period expired.
+    pub abandoned: u64,
+}
+
+#[derive(Default)]
+struct InFlightRequests {
+    count: AtomicU64,
+    finished: AtomicU64,
+    notify: Notify,
+}
+
+/// Marks a request as in flight for as long as it is alive.
+struct InFlightGuard(Arc<InFlightRequests>);
+
+impl InFlightGuard {
+    fn enter(in_flight: &Arc<InFlightRequests>) -> Self {
+        in_flight.count.fetch_add(1, Ordering::SeqCst);
+        Self(in_flight.clone())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.finished.fetch_add(1, Ordering::SeqCst);
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+}
+
 fn write_profile(store: &mut wasmtime::Store<WasmCtx>, guest_profile_path: Option<&PathBuf>) {
     if let (Some(profile), Some(path)) =
         (store.data_mut().take_guest_profiler(), guest_profile_path)
@@ -0,0 +1,329 @@
+//! A C-callable interface for preparing KV store seed files, for tooling not written in Rust.
+//!
+//! Seeds are built up with the same validation Viceroy applies to its own stores, and written out
+//! as a [`KvExport`], which [`ObjectStores::import`] reads back in. The C declarations are in
+//! `include/viceroy_kv_seed.h`, generated with:
+//!
+//! ```text
+//! cbindgen --config cbindgen.toml --crate viceroy-lib --output include/viceroy_kv_seed.h
+//! ```
+//!
+//! Every function that can fail returns `0` on success and `-1` on failure, after which
+//! [`viceroy_kv_seed_last_error`] describes what went wrong. Passing a null seed is always a
+//! failure, with no message, as there is nowhere to keep one.
+//!
+//! Only available with the `ffi` feature.
+//!
+//! [`KvExport`]: crate::object_store::KvExport
+//! [`ObjectStores::import`]: crate::object_store::ObjectStores::import
+
+#![allow(clippy::missing_safety_doc)]
+
+use {
+    crate::{
+        config::limits::KV_STORE_VALUE_MAX_LEN,
+        object_store::{
+            ExportOptions, ObjectKey, ObjectStoreKey, ObjectStores, Redaction, ValueOrigin,
+        },
+        wiggle_abi::types::KvInsertMode,
+    },
+    std::{
+        ffi::{c_char, c_int, CStr, CString},
+        fmt, fs, slice,
+    },
+};
+
+/// A set of stores being seeded, created by [`viceroy_kv_seed_new`] and freed by
+/// [`viceroy_kv_seed_free`].
+pub struct ViceroyKvSeed {
+    stores: ObjectStores,
+    last_error: Option<CString>,
+}
+
+impl ViceroyKvSeed {
+    /// Record the outcome of a call, keeping the error message if it failed.
+    fn finish<E: fmt::Display>(&mut self, res: Result<(), E>) -> c_int {
+        match res {
+            Ok(()) => {
+                self.last_error = None;
+                0
+            }
+            Err(e) => {
+                // messages never contain a NUL, but one is dropped rather than losing the rest
+                let message = e.to_string().replace('\0', "");
+                self.last_error = CString::new(message).ok();
+                -1
+            }
+        }
+    }
+}
+
+/// Borrow a NUL-terminated UTF-8 string, naming `what` it is if it isn't one.
+unsafe fn str_arg<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, String> {
+    if ptr.is_null() {
+        return Err(format!("the {what} is null"));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| format!("the {what} is not valid UTF-8"))
+}
+
+/// Borrow `len` bytes at `ptr`, which may only be null if `len` is zero.
+unsafe fn bytes_arg<'a>(ptr: *const u8, len: usize, what: &str) -> Result<&'a [u8], String> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(format!("the {what} is null")),
+        (false, _) => Ok(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Create an empty seed. Never returns null.
+#[no_mangle]
+pub extern "C" fn viceroy_kv_seed_new() -> *mut ViceroyKvSeed {
+    Box::into_raw(Box::new(ViceroyKvSeed {
+        stores: ObjectStores::new().with_origin(ValueOrigin::Seed),
+        last_error: None,
+    }))
+}
+
+/// Free a seed. Does nothing if `seed` is null.
+#[no_mangle]
+pub unsafe extern "C" fn viceroy_kv_seed_free(seed: *mut ViceroyKvSeed) {
+    if !seed.is_null() {
+        drop(Box::from_raw(seed));
+    }
+}
+
+/// Add an empty store named `store`, a NUL-terminated UTF-8 string, if there isn't one already.
+#[no_mangle]
+pub unsafe extern "C" fn viceroy_kv_seed_add_store(
+    seed: *mut ViceroyKvSeed,
+    store: *const c_char,
+) -> c_int {
+    let Some(seed) = seed.as_mut() else {
+        return -1;
+    };
+    let res = str_arg(store, "store name").and_then(|store| {
+        let store = ObjectStoreKey::new(store).map_err(|e| e.to_string())?;
+        seed.stores
+            .insert_empty_store(store)
+            .map_err(|e| e.to_string())
+    });
+    seed.finish(res)
+}
+
+/// Set `key` in `store` to the `value_len` bytes at `value`, with the `metadata_len` bytes at
+/// `metadata` as its metadata, creating the store if needed. `store` and `key` are NUL-terminated
+/// UTF-8 strings; `value` and `metadata` may be null if their lengths are zero.
+#[no_mangle]
+pub unsafe extern "C" fn viceroy_kv_seed_insert(
+    seed: *mut ViceroyKvSeed,
+    store: *const c_char,
+    key: *const c_char,
+    value: *const u8,
+    value_len: usize,
+    metadata: *const u8,
+    metadata_len: usize,
+) -> c_int {
+    let Some(seed) = seed.as_mut() else {
+        return -1;
+    };
+    let res = (|| {
+        let store = str_arg(store, "store name")?;
+        let key = str_arg(key, "key")?;
+        let value = bytes_arg(value, value_len, "value")?;
+        let metadata = bytes_arg(metadata, metadata_len, "metadata")?;
+        if value.len() > KV_STORE_VALUE_MAX_LEN {
+            let (len, max) = (value.len(), KV_STORE_VALUE_MAX_LEN);
+            return Err(format!(
+                "the value for {key:?} is {len} bytes, over the limit of {max}"
+            ));
+        }
+        let store = ObjectStoreKey::new(store).map_err(|e| e.to_string())?;
+        let key = ObjectKey::new(key).map_err(|e| e.to_string())?;
+        seed.stores
+            .insert_empty_store(store.clone())
+            .map_err(|e| e.to_string())?;
+        seed.stores
+            .insert(
+                store,
+                key,
+                value.to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                (!metadata.is_empty()).then(|| metadata.to_vec()),
+                None,
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })();
+    seed.finish(res)
+}
+
+/// Write the seed to the file at `path`, a NUL-terminated UTF-8 string, replacing it if it exists.
+///
+/// The file is an export without generations or last-modified times, so the same seed always
+/// writes the same file. The seed can still be added to, and written again, afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn viceroy_kv_seed_write(
+    seed: *mut ViceroyKvSeed,
+    path: *const c_char,
+) -> c_int {
+    let Some(seed) = seed.as_mut() else {
+        return -1;
+    };
+    let res = str_arg(path, "path").and_then(|path| {
+        let options = ExportOptions {
+            redaction: Redaction::None,
+            volatile: false,
+        };
+        let export = seed.stores.export(options).map_err(|e| e.to_string())?;
+        fs::write(path, export.to_json()).map_err(|e| format!("cannot write {path:?}: {e}"))
+    });
+    seed.finish(res)
+}
+
+/// The message for the last call on `seed` that failed, as a NUL-terminated UTF-8 string, or null
+/// if the last call succeeded. The message belongs to the seed, and is only valid until the next
+/// call on it.
+#[no_mangle]
+pub unsafe extern "C" fn viceroy_kv_seed_last_error(seed: *const ViceroyKvSeed) -> *const c_char {
+    match seed.as_ref().and_then(|seed| seed.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::object_store::{KvExport, ObjectKey, ObjectStoreKey, ObjectStores},
+        std::ffi::{c_char, c_int, CStr, CString},
+    };
+
+    /// The seed as C sees it: an opaque type only ever handled through a pointer.
+    #[repr(C)]
+    struct ViceroyKvSeed {
+        _private: [u8; 0],
+    }
+
+    // Declared afresh, as a C caller would see them, so that the tests go through the C ABI.
+    extern "C" {
+        fn viceroy_kv_seed_new() -> *mut ViceroyKvSeed;
+        fn viceroy_kv_seed_free(seed: *mut ViceroyKvSeed);
+        fn viceroy_kv_seed_add_store(seed: *mut ViceroyKvSeed, store: *const c_char) -> c_int;
+        fn viceroy_kv_seed_insert(
+            seed: *mut ViceroyKvSeed,
+            store: *const c_char,
+            key: *const c_char,
+            value: *const u8,
+            value_len: usize,
+            metadata: *const u8,
+            metadata_len: usize,
+        ) -> c_int;
+        fn viceroy_kv_seed_write(seed: *mut ViceroyKvSeed, path: *const c_char) -> c_int;
+        fn viceroy_kv_seed_last_error(seed: *const ViceroyKvSeed) -> *const c_char;
+    }
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    unsafe fn last_error(seed: *const ViceroyKvSeed) -> Option<String> {
+        let message = viceroy_kv_seed_last_error(seed);
+        (!message.is_null()).then(|| CStr::from_ptr(message).to_str().unwrap().to_string())
+    }
+
+    unsafe fn insert(seed: *mut ViceroyKvSeed, store: &str, key: &str, value: &[u8]) -> c_int {
+        let (store, key) = (c(store), c(key));
+        viceroy_kv_seed_insert(
+            seed,
+            store.as_ptr(),
+            key.as_ptr(),
+            value.as_ptr(),
+            value.len(),
+            b"meta".as_ptr(),
+            4,
+        )
+    }
+
+    #[test]
+    fn seeds_are_written_as_importable_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = c(dir.path().join("seed.json").to_str().unwrap());
+        let (empty, store, empty_value) = (c("empty"), c("store"), c("empty-value"));
+        unsafe {
+            let seed = viceroy_kv_seed_new();
+            assert_eq!(viceroy_kv_seed_add_store(seed, empty.as_ptr()), 0);
+            assert_eq!(insert(seed, "store", "key", b"value"), 0);
+            let null = std::ptr::null();
+            assert_eq!(
+                viceroy_kv_seed_insert(
+                    seed,
+                    store.as_ptr(),
+                    empty_value.as_ptr(),
+                    null,
+                    0,
+                    null,
+                    0
+                ),
+                0
+            );
+            assert_eq!(last_error(seed), None);
+            assert_eq!(viceroy_kv_seed_write(seed, path.as_ptr()), 0);
+            viceroy_kv_seed_free(seed);
+        }
+
+        let json = std::fs::read(path.to_str().unwrap()).unwrap();
+        let stores = ObjectStores::new();
+        assert_eq!(stores.import(&KvExport::from_json(&json).unwrap()), Ok(2));
+        let store = ObjectStoreKey::new("store").unwrap();
+        let value = stores
+            .lookup(store.clone(), ObjectKey::new("key").unwrap())
+            .unwrap();
+        assert_eq!(
+            (value.body.to_vec(), value.metadata),
+            (b"value".to_vec(), b"meta".to_vec())
+        );
+        let value = stores
+            .lookup(store, ObjectKey::new("empty-value").unwrap())
+            .unwrap();
+        assert!(value.body.is_empty() && value.metadata.is_empty());
+        assert!(stores.store_key("empty").unwrap().is_some());
+    }
+
+    #[test]
+    fn invalid_seeds_are_rejected_with_a_message() {
+        let (empty, store, key) = (c(""), c("store"), c("key"));
+        let path = c("/nonexistent/dir/seed.json");
+        let null = std::ptr::null();
+        unsafe {
+            let seed = viceroy_kv_seed_new();
+            assert_eq!(insert(seed, "store", "bad#key", b"value"), -1);
+            assert!(last_error(seed).unwrap().contains('#'));
+            assert_eq!(viceroy_kv_seed_add_store(seed, empty.as_ptr()), -1);
+            assert!(last_error(seed).is_some());
+            assert_eq!(viceroy_kv_seed_add_store(seed, std::ptr::null()), -1);
+            assert_eq!(last_error(seed).unwrap(), "the store name is null");
+            // a value can't be missing unless it is empty
+            assert_eq!(
+                viceroy_kv_seed_insert(seed, store.as_ptr(), key.as_ptr(), null, 1, null, 0),
+                -1
+            );
+            assert_eq!(last_error(seed).unwrap(), "the value is null");
+
+            // a successful call clears the last error
+            assert_eq!(insert(seed, "store", "key", b"value"), 0);
+            assert_eq!(last_error(seed), None);
+
+            assert_eq!(viceroy_kv_seed_write(seed, path.as_ptr()), -1);
+            assert!(last_error(seed).unwrap().starts_with("cannot write"));
+            viceroy_kv_seed_free(seed);
+
+            // null seeds fail without a message
+            assert_eq!(insert(std::ptr::null_mut(), "store", "key", b""), -1);
+            assert!(viceroy_kv_seed_last_error(std::ptr::null()).is_null());
+            viceroy_kv_seed_free(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Capturing the KV state of requests whose guest traps.
+//!
+//! When [`ExecuteCtx::with_kv_diagnostics`] is set, each request's KV operations are recorded as
+//! they happen, through a [scoped observer][ObjectStores::with_scoped_observer] on the session's
+//! stores. If the guest then traps, a bundle directory is written, containing:
+//!
+//! * `trap.txt`: the request line and the trap, with its backtrace.
+//! * `kv_events.jsonl`: every KV operation the session performed, one [`TraceEntry`] per line.
+//! * `kv_export.json`: a [`KvExport`] of just the keys the session touched, as they stood when the
+//!   guest trapped.
+//!
+//! Like a KV trace, the bundle holds the values the session saw in full, including those in
+//! stores marked `sensitive`.
+//!
+//! [`ExecuteCtx::with_kv_diagnostics`]: crate::ExecuteCtx::with_kv_diagnostics
+//! [`KvExport`]: crate::KvExport
+
+use {
+    crate::{
+        error::Error,
+        kv_trace::TraceEntry,
+        object_store::{KvEvent, KvObserver, KvOp, ObjectStores, Redaction},
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fs::{self, File},
+        io::{BufWriter, Write},
+        path::Path,
+        sync::Mutex,
+    },
+};
+
+/// The KV operations performed by a single session.
+#[derive(Debug, Default)]
+pub(crate) struct SessionKvLog(Mutex<SessionKvLogInner>);
+
+#[derive(Debug, Default)]
+struct SessionKvLogInner {
+    entries: Vec<TraceEntry>,
+    /// The keys looked up, inserted, or deleted, by store.
+    touched: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl KvObserver for SessionKvLog {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        let mut log = self.0.lock().expect("KV log lock poisoned");
+        let key = match &event.op {
+            KvOp::Lookup { key, .. }
+            | KvOp::Head { key, .. }
+            | KvOp::Insert { key, .. }
+            | KvOp::Delete { key, .. } => Some(key),
+            KvOp::List { .. } => None,
+        };
+        if let Some(key) = key {
+            log.touched
+                .entry(event.store.to_string())
+                .or_default()
+                .insert(key.to_string());
+        }
+        log.entries.push(TraceEntry::from(event));
+    }
+}
+
+impl SessionKvLog {
+    /// Write a bundle describing a trap to the directory `dir`, which is created if needed.
+    pub(crate) fn write_bundle(
+        &self,
+        dir: &Path,
+        request: &str,
+        trap: &anyhow::Error,
+        stores: &ObjectStores,
+    ) -> Result<(), Error> {
+        let log = self.0.lock().expect("KV log lock poisoned");
+        fs::create_dir_all(dir)?;
+
+        fs::write(dir.join("trap.txt"), format!("{request}\n\n{trap:?}\n"))?;
+
+        let mut events = BufWriter::new(File::create(dir.join("kv_events.jsonl"))?);
+        for entry in &log.entries {
+            serde_json::to_writer(&mut events, entry).map_err(std::io::Error::from)?;
+            events.write_all(b"\n")?;
+        }
+        events.flush()?;
+
+        let export = stores.export_keys(Redaction::None, &log.touched)?;
+        fs::write(dir.join("kv_export.json"), export.to_json())?;
+
+        Ok(())
+    }
+}
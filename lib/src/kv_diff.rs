@@ -0,0 +1,159 @@
+//! Reporting how a run changed the KV stores.
+//!
+//! A [snapshot] of the stores is taken before a run, usually right after they are seeded, and
+//! compared with their contents afterwards by [`KvDiff::since`]. The result lists the keys added,
+//! removed, and modified in each store, sorted by store and then by key, so that the same run
+//! always reports the same diff.
+//!
+//! Only what guests can see is compared: bodies and metadata. A value that was rewritten with the
+//! same contents is not reported, and nor are values that merely expired.
+
+use {
+    crate::object_store::{
+        ExportOptions, ExportedStore, KvExport, ObjectStoreError, ObjectStores, Redaction,
+    },
+    std::{collections::BTreeMap, fmt},
+};
+
+/// The options snapshots are exported with: nothing redacted, since the diff needs every value's
+/// contents, and nothing volatile, since only contents are compared.
+const SNAPSHOT_OPTIONS: ExportOptions = ExportOptions {
+    redaction: Redaction::None,
+    volatile: false,
+};
+
+/// Take a snapshot of `stores`, for comparing with their contents later with [`KvDiff::since`].
+pub fn snapshot(stores: &ObjectStores) -> Result<KvExport, ObjectStoreError> {
+    stores.export(SNAPSHOT_OPTIONS)
+}
+
+/// The changes between two snapshots of a set of stores.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KvDiff {
+    /// Sorted by store, and then by key.
+    pub changes: Vec<KvChange>,
+}
+
+/// A key whose value differs between two snapshots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KvChange {
+    pub store: String,
+    pub key: String,
+    pub kind: KvChangeKind,
+}
+
+/// How a key changed, with the lengths of its bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KvChangeKind {
+    Added {
+        len: usize,
+    },
+    Removed {
+        len: usize,
+    },
+    /// The body, the metadata, or both changed.
+    Modified {
+        before: usize,
+        after: usize,
+    },
+}
+
+impl KvDiff {
+    /// The changes to `stores` since `before` was taken of them with [`snapshot`].
+    pub fn since(before: &KvExport, stores: &ObjectStores) -> Result<KvDiff, ObjectStoreError> {
+        Ok(KvDiff::between(before, &snapshot(stores)?))
+    }
+
+    /// The changes from `before` to `after`. A store missing from either is treated as empty.
+    pub fn between(before: &KvExport, after: &KvExport) -> KvDiff {
+        let empty = ExportedStore::default();
+        let mut names = before.stores.keys().collect::<Vec<_>>();
+        names.extend(after.stores.keys());
+        names.sort();
+        names.dedup();
+
+        let mut changes = Vec::new();
+        for name in names {
+            let before = &before.stores.get(name).unwrap_or(&empty).items;
+            let after = &after.stores.get(name).unwrap_or(&empty).items;
+            let mut keys = BTreeMap::new();
+            for (key, val) in before {
+                keys.insert(key, (Some(val), None));
+            }
+            for (key, val) in after {
+                keys.entry(key).or_insert((None, None)).1 = Some(val);
+            }
+            for (key, vals) in keys {
+                let kind = match vals {
+                    (None, Some(after)) => KvChangeKind::Added {
+                        len: after.body.len(),
+                    },
+                    (Some(before), None) => KvChangeKind::Removed {
+                        len: before.body.len(),
+                    },
+                    (Some(before), Some(after))
+                        if before.body != after.body || before.metadata != after.metadata =>
+                    {
+                        KvChangeKind::Modified {
+                            before: before.body.len(),
+                            after: after.body.len(),
+                        }
+                    }
+                    _ => continue,
+                };
+                changes.push(KvChange {
+                    store: name.clone(),
+                    key: key.clone(),
+                    kind,
+                });
+            }
+        }
+        KvDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// One line per change.
+impl fmt::Display for KvDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for KvChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (store, key) = (&self.store, &self.key);
+        match self.kind {
+            KvChangeKind::Added { len } => {
+                write!(f, "`{store}`: added `{key}` ({})", ByteCount(len))
+            }
+            KvChangeKind::Removed { len } => {
+                write!(f, "`{store}`: removed `{key}` ({})", ByteCount(len))
+            }
+            KvChangeKind::Modified { before, after } => write!(
+                f,
+                "`{store}`: modified `{key}` ({before} -> {}, {:+})",
+                ByteCount(after),
+                after as i64 - before as i64
+            ),
+        }
+    }
+}
+
+/// A number of bytes, spelled out.
+struct ByteCount(usize);
+
+impl fmt::Display for ByteCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => write!(f, "1 byte"),
+            len => write!(f, "{len} bytes"),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Capturing the KV list responses handed to guests.
+//!
+//! When [`ExecuteCtx::with_kv_list_capture`] is set, each request's KV lists are watched through a
+//! [scoped observer][ObjectStores::with_scoped_observer] on the session's stores, and the body of
+//! every list that succeeds is copied into the [`KvListCapture`], byte for byte as the guest
+//! receives it. An embedder can then [`take`][KvListCapture::take] the captured pages once its
+//! requests complete, to assert on exactly what its guests were shown.
+//!
+//! A capture holds at most the number of bytes it was created with. Pages that would go over are
+//! dropped whole, and counted, until the capture is taken.
+//!
+//! [`ExecuteCtx::with_kv_list_capture`]: crate::ExecuteCtx::with_kv_list_capture
+//! [ObjectStores::with_scoped_observer]: crate::config::ObjectStores::with_scoped_observer
+
+use {
+    crate::object_store::{KvEvent, KvObserver, KvOp},
+    std::sync::{Arc, Mutex},
+};
+
+/// The list responses captured from guests, up to a size bound.
+#[derive(Debug)]
+pub struct KvListCapture {
+    max_bytes: usize,
+    inner: Mutex<CaptureInner>,
+}
+
+#[derive(Debug, Default)]
+struct CaptureInner {
+    pages: Vec<CapturedPage>,
+    bytes: usize,
+    dropped: u64,
+}
+
+/// A list response handed to a guest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPage {
+    /// The ID of the request whose guest listed the page.
+    pub req_id: u64,
+    pub store: String,
+    pub cursor: Option<String>,
+    pub prefix: Option<String>,
+    pub limit: u32,
+    /// The JSON body of the response, exactly as the guest received it.
+    pub body: Vec<u8>,
+}
+
+/// The pages captured since the capture was last taken.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CapturedLists {
+    /// The captured pages, in the order they were listed.
+    pub pages: Vec<CapturedPage>,
+    /// The number of pages that weren't captured because they would have gone over the bound.
+    pub dropped: u64,
+}
+
+impl KvListCapture {
+    /// A capture that holds at most `max_bytes` of list bodies at a time.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Take the pages captured so far, leaving the capture empty.
+    pub fn take(&self) -> CapturedLists {
+        let inner = std::mem::take(&mut *self.inner.lock().expect("KV list capture lock poisoned"));
+        CapturedLists {
+            pages: inner.pages,
+            dropped: inner.dropped,
+        }
+    }
+
+    fn record(&self, page: CapturedPage) {
+        let mut inner = self.inner.lock().expect("KV list capture lock poisoned");
+        if inner.bytes + page.body.len() > self.max_bytes {
+            inner.dropped += 1;
+            return;
+        }
+        inner.bytes += page.body.len();
+        inner.pages.push(page);
+    }
+}
+
+/// The observer capturing a single session's lists.
+#[derive(Debug)]
+pub(crate) struct SessionKvLists {
+    req_id: u64,
+    capture: Arc<KvListCapture>,
+}
+
+impl SessionKvLists {
+    pub(crate) fn new(req_id: u64, capture: Arc<KvListCapture>) -> Self {
+        Self { req_id, capture }
+    }
+}
+
+impl KvObserver for SessionKvLists {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        let KvOp::List {
+            cursor,
+            prefix,
+            limit,
+            result: Ok(body),
+        } = &event.op
+        else {
+            return;
+        };
+        self.capture.record(CapturedPage {
+            req_id: self.req_id,
+            store: event.store.to_string(),
+            cursor: cursor.map(str::to_string),
+            prefix: prefix.map(str::to_string),
+            limit: *limit,
+            body: body.to_vec(),
+        });
+    }
+}
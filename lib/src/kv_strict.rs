@@ -0,0 +1,128 @@
+//! Failing requests whose guests see KV errors.
+//!
+//! When [`ExecuteCtx::with_kv_strict`] is set, each request's KV operations are watched through a
+//! [scoped observer][ObjectStores::with_scoped_observer] on the session's stores, and every one
+//! that fails is recorded, unless its store or error is allowed by the [`KvStrictConfig`]. Once the
+//! guest finishes, a request with any such failures is failed itself, with a [`KvStrictFailure`]
+//! listing them. This is meant for CI runs of guests that are expected to be KV-clean.
+//!
+//! [`ExecuteCtx::with_kv_strict`]: crate::ExecuteCtx::with_kv_strict
+//! [ObjectStores::with_scoped_observer]: crate::config::ObjectStores::with_scoped_observer
+
+use {
+    crate::object_store::{KvEvent, KvObserver, KvOp, KvStoreError},
+    std::{
+        collections::BTreeSet,
+        fmt,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Which KV errors a strict run tolerates.
+#[derive(Clone, Debug, Default)]
+pub struct KvStrictConfig {
+    /// Stores whose errors are all allowed.
+    pub allowed_stores: BTreeSet<String>,
+    /// Errors allowed from any store, such as [`KvStoreError::NotFound`] for guests that look up
+    /// keys that may be missing, or [`KvStoreError::PreconditionFailed`] for those that retry
+    /// conditional writes.
+    pub allowed_errors: BTreeSet<KvStoreError>,
+}
+
+impl KvStrictConfig {
+    fn allows(&self, store: &str, error: &KvStoreError) -> bool {
+        self.allowed_stores.contains(store) || self.allowed_errors.contains(error)
+    }
+}
+
+/// A KV operation that failed with an error strict mode doesn't allow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KvViolation {
+    pub store: String,
+    /// The operation: `lookup`, `insert`, `delete`, or `list`.
+    pub op: &'static str,
+    /// The key operated on, for everything but lists.
+    pub key: Option<String>,
+    pub error: KvStoreError,
+}
+
+impl fmt::Display for KvViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{} of `{key}` in `{}`", self.op, self.store)?,
+            None => write!(f, "{} of `{}`", self.op, self.store)?,
+        }
+        write!(f, " failed with {:?}", self.error)
+    }
+}
+
+/// The KV errors that failed a request in strict mode.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub struct KvStrictFailure {
+    pub violations: Vec<KvViolation>,
+}
+
+impl fmt::Display for KvStrictFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "guest received {} KV error(s) not allowed in strict mode:",
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            write!(f, "\n  {violation}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The disallowed KV errors seen by a single session.
+#[derive(Debug)]
+pub(crate) struct SessionKvStrict {
+    config: Arc<KvStrictConfig>,
+    violations: Mutex<Vec<KvViolation>>,
+}
+
+impl SessionKvStrict {
+    pub(crate) fn new(config: Arc<KvStrictConfig>) -> Self {
+        Self {
+            config,
+            violations: Mutex::default(),
+        }
+    }
+
+    /// The failure to report for this session, if it saw any disallowed errors.
+    pub(crate) fn failure(&self) -> Option<KvStrictFailure> {
+        let violations = self.violations.lock().expect("KV strict lock poisoned");
+        (!violations.is_empty()).then(|| KvStrictFailure {
+            violations: violations.clone(),
+        })
+    }
+}
+
+impl KvObserver for SessionKvStrict {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        let (op, key, error) = match &event.op {
+            KvOp::Lookup { key, result, .. } => ("lookup", Some(key), result.err()),
+            KvOp::Head { key, result, .. } => ("head", Some(key), result.err()),
+            KvOp::Insert { key, result, .. } => ("insert", Some(key), result.err()),
+            KvOp::Delete { key, result } => ("delete", Some(key), result.err()),
+            KvOp::List { result, .. } => ("list", None, result.err()),
+        };
+        let Some(error) = error else {
+            return;
+        };
+        if self.config.allows(event.store, error) {
+            return;
+        }
+        self.violations
+            .lock()
+            .expect("KV strict lock poisoned")
+            .push(KvViolation {
+                store: event.store.to_string(),
+                op,
+                key: key.map(|k| k.to_string()),
+                error: error.clone(),
+            });
+    }
+}
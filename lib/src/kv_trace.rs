@@ -0,0 +1,479 @@
+//! Recording and replaying traces of KV operations.
+//!
+//! A [`KvTraceRecorder`] writes every operation performed against an [`ObjectStores`] to a
+//! JSON-lines file, one [`TraceEntry`] per line. The trace can later be [replayed][replay] against
+//! a freshly seeded set of stores, without running the guest that produced it.
+
+use {
+    crate::{
+        error::Error,
+        object_store::{
+            KeyValidationProfile, KvEvent, KvObserver, KvOp, KvRequest, KvStoreError, ObjectKey,
+            ObjectStoreKey, ObjectStores,
+        },
+        wiggle_abi::types::KvInsertMode,
+    },
+    base64::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::{BufRead, BufReader, LineWriter, Write},
+        path::Path,
+        sync::Mutex,
+        time::Duration,
+    },
+    tracing::warn,
+};
+
+/// A single recorded KV operation and its outcome.
+///
+/// Bodies and metadata are base64-encoded; list responses are kept as the JSON text the guest saw.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub store: String,
+    /// The request the operation was made for, if a guest made it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<KvRequest>,
+    #[serde(flatten)]
+    pub op: TraceOp,
+    pub result: TraceResult,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TraceOp {
+    Lookup {
+        key: String,
+    },
+    Head {
+        key: String,
+    },
+    Insert {
+        key: String,
+        body: String,
+        mode: TraceInsertMode,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        generation: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl_ms: Option<u64>,
+    },
+    Delete {
+        key: String,
+    },
+    List {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prefix: Option<String>,
+        limit: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceInsertMode {
+    Overwrite,
+    Add,
+    Append,
+    Prepend,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceResult {
+    Ok,
+    Value {
+        body: String,
+        metadata: String,
+        generation: u64,
+    },
+    /// What a head found: a value's metadata, generation, and body length.
+    Head {
+        metadata: String,
+        generation: u64,
+        length: usize,
+    },
+    List {
+        body: String,
+    },
+    /// An insert that the guest saw succeed, but that a [`LostWriteRule`] dropped rather than
+    /// wrote. A replay drops it too.
+    ///
+    /// [`LostWriteRule`]: crate::object_store::LostWriteRule
+    Lost,
+    /// A lookup or head that missed because the key's value had expired. The guest saw `NotFound`, as
+    /// for a key that was never written, and a replay treats the two alike.
+    Expired,
+    Error {
+        error: String,
+    },
+}
+
+impl From<KvInsertMode> for TraceInsertMode {
+    fn from(mode: KvInsertMode) -> Self {
+        match mode {
+            KvInsertMode::Overwrite => TraceInsertMode::Overwrite,
+            KvInsertMode::Add => TraceInsertMode::Add,
+            KvInsertMode::Append => TraceInsertMode::Append,
+            KvInsertMode::Prepend => TraceInsertMode::Prepend,
+        }
+    }
+}
+
+impl From<TraceInsertMode> for KvInsertMode {
+    fn from(mode: TraceInsertMode) -> Self {
+        match mode {
+            TraceInsertMode::Overwrite => KvInsertMode::Overwrite,
+            TraceInsertMode::Add => KvInsertMode::Add,
+            TraceInsertMode::Append => KvInsertMode::Append,
+            TraceInsertMode::Prepend => KvInsertMode::Prepend,
+        }
+    }
+}
+
+impl From<&KvEvent<'_>> for TraceEntry {
+    fn from(event: &KvEvent<'_>) -> Self {
+        fn error(e: &KvStoreError) -> TraceResult {
+            TraceResult::Error {
+                error: format!("{e:?}"),
+            }
+        }
+
+        let (op, result) = match &event.op {
+            KvOp::Lookup {
+                key,
+                result,
+                expired,
+            } => (
+                TraceOp::Lookup {
+                    key: key.to_string(),
+                },
+                match result {
+                    Ok(v) => TraceResult::Value {
+                        body: BASE64_STANDARD.encode(&v.body),
+                        metadata: BASE64_STANDARD.encode(&v.metadata),
+                        generation: v.generation,
+                    },
+                    Err(KvStoreError::NotFound) if *expired => TraceResult::Expired,
+                    Err(e) => error(e),
+                },
+            ),
+            KvOp::Head {
+                key,
+                result,
+                expired,
+            } => (
+                TraceOp::Head {
+                    key: key.to_string(),
+                },
+                match result {
+                    Ok(head) => TraceResult::Head {
+                        metadata: BASE64_STANDARD.encode(&head.metadata),
+                        generation: head.generation,
+                        length: head.body_len,
+                    },
+                    Err(KvStoreError::NotFound) if *expired => TraceResult::Expired,
+                    Err(e) => error(e),
+                },
+            ),
+            KvOp::Insert {
+                key,
+                body,
+                mode,
+                generation,
+                metadata,
+                ttl,
+                result,
+                lost,
+            } => (
+                TraceOp::Insert {
+                    key: key.to_string(),
+                    body: BASE64_STANDARD.encode(body),
+                    mode: (*mode).into(),
+                    generation: *generation,
+                    metadata: metadata.map(|m| BASE64_STANDARD.encode(m)),
+                    ttl_ms: ttl.map(|t| t.as_millis() as u64),
+                },
+                match result {
+                    Ok(()) if *lost => TraceResult::Lost,
+                    Ok(()) => TraceResult::Ok,
+                    Err(e) => error(e),
+                },
+            ),
+            KvOp::Delete { key, result } => (
+                TraceOp::Delete {
+                    key: key.to_string(),
+                },
+                result.map_or_else(error, |()| TraceResult::Ok),
+            ),
+            KvOp::List {
+                cursor,
+                prefix,
+                limit,
+                result,
+            } => (
+                TraceOp::List {
+                    cursor: cursor.map(str::to_string),
+                    prefix: prefix.map(str::to_string),
+                    limit: *limit,
+                },
+                match result {
+                    Ok(body) => TraceResult::List {
+                        body: String::from_utf8_lossy(body).into_owned(),
+                    },
+                    Err(e) => error(e),
+                },
+            ),
+        };
+
+        TraceEntry {
+            store: event.store.to_string(),
+            request: event.request.cloned(),
+            op,
+            result,
+        }
+    }
+}
+
+/// A [`KvObserver`] that appends each operation to a JSON-lines trace file.
+pub struct KvTraceRecorder {
+    out: Mutex<LineWriter<File>>,
+}
+
+impl KvTraceRecorder {
+    /// Create a recorder writing to `path`, truncating any existing file.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self {
+            out: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+}
+
+impl KvObserver for KvTraceRecorder {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        let entry = TraceEntry::from(event);
+        let mut out = self.out.lock().expect("trace writer lock poisoned");
+        if let Err(e) = serde_json::to_writer(&mut *out, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|()| out.write_all(b"\n"))
+        {
+            warn!("failed to record KV trace entry: {e}");
+        }
+    }
+}
+
+/// Read a trace previously written by a [`KvTraceRecorder`].
+pub fn read_trace(path: impl AsRef<Path>) -> Result<Vec<TraceEntry>, Error> {
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|err| Error::InvalidKvTrace { line: i + 1, err })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The outcome of [replaying][replay] a trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// The number of operations replayed.
+    pub operations: usize,
+    /// The operations whose result differed from the recorded one.
+    pub divergences: Vec<Divergence>,
+}
+
+/// A replayed operation whose result differed from the recorded one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The position of the operation in the trace, starting from zero.
+    pub index: usize,
+    pub entry: TraceEntry,
+    pub actual: TraceResult,
+}
+
+/// Replay `trace` against `stores`, reporting any operation whose result differs from the
+/// recorded one.
+///
+/// Generations are assigned when a value is written, so they cannot match between the recording
+/// and the replay. Instead, the first time a recorded generation is seen in a lookup or head it is
+/// paired with the replayed one, and later lookups, heads, and generation-match inserts are
+/// translated through that pairing.
+pub fn replay(stores: &ObjectStores, trace: &[TraceEntry]) -> ReplayReport {
+    let mut generations: HashMap<u64, u64> = HashMap::new();
+    let mut report = ReplayReport::default();
+
+    for (index, entry) in trace.iter().enumerate() {
+        let actual = match ObjectStoreKey::new(&entry.store) {
+            Err(_) => invalid("store"),
+            Ok(store) => match &entry.op {
+                TraceOp::Lookup { key } => replay_key(key, |key| match stores.lookup(store, key) {
+                    Ok(v) => TraceResult::Value {
+                        body: BASE64_STANDARD.encode(&v.body),
+                        metadata: BASE64_STANDARD.encode(&v.metadata),
+                        generation: v.generation,
+                    },
+                    Err(e) => TraceResult::Error {
+                        error: format!("{e:?}"),
+                    },
+                }),
+                TraceOp::Head { key } => replay_key(key, |key| match stores.head(store, key) {
+                    Ok(head) => TraceResult::Head {
+                        metadata: BASE64_STANDARD.encode(&head.metadata),
+                        generation: head.generation,
+                        length: head.body_len,
+                    },
+                    Err(e) => TraceResult::Error {
+                        error: format!("{e:?}"),
+                    },
+                }),
+                TraceOp::Insert {
+                    key,
+                    body,
+                    mode,
+                    generation,
+                    metadata,
+                    ttl_ms,
+                } => replay_key(key, |key| {
+                    if entry.result == TraceResult::Lost {
+                        return TraceResult::Lost;
+                    }
+                    let body = match BASE64_STANDARD.decode(body) {
+                        Ok(body) => body,
+                        Err(_) => return invalid("body"),
+                    };
+                    let metadata = match metadata
+                        .as_ref()
+                        .map(|m| BASE64_STANDARD.decode(m))
+                        .transpose()
+                    {
+                        Ok(metadata) => metadata,
+                        Err(_) => return invalid("metadata"),
+                    };
+                    let generation = generation.map(|g| *generations.get(&g).unwrap_or(&g));
+                    stores
+                        .insert(
+                            store,
+                            key,
+                            body,
+                            (*mode).into(),
+                            generation,
+                            metadata,
+                            ttl_ms.map(Duration::from_millis),
+                        )
+                        .map_or_else(
+                            |e| TraceResult::Error {
+                                error: format!("{e:?}"),
+                            },
+                            |_| TraceResult::Ok,
+                        )
+                }),
+                TraceOp::Delete { key } => replay_key(key, |key| {
+                    stores.delete(store, key, None).map_or_else(
+                        |e| TraceResult::Error {
+                            error: format!("{e:?}"),
+                        },
+                        |()| TraceResult::Ok,
+                    )
+                }),
+                TraceOp::List {
+                    cursor,
+                    prefix,
+                    limit,
+                } => match stores.list_under(
+                    store.clone(),
+                    // lists were recorded as the guest saw them, without the store's prefix
+                    &stores.key_prefix(store.as_str()).unwrap_or_default(),
+                    cursor.clone(),
+                    prefix.clone(),
+                    *limit,
+                ) {
+                    Ok(body) => TraceResult::List {
+                        body: String::from_utf8_lossy(&body).into_owned(),
+                    },
+                    Err(e) => TraceResult::Error {
+                        error: format!("{e:?}"),
+                    },
+                },
+            },
+        };
+
+        let matches = match (&entry.result, &actual) {
+            (
+                TraceResult::Value {
+                    body: expected_body,
+                    metadata: expected_metadata,
+                    generation: expected_generation,
+                },
+                TraceResult::Value {
+                    body,
+                    metadata,
+                    generation,
+                },
+            ) => {
+                let paired = *generations
+                    .entry(*expected_generation)
+                    .or_insert(*generation);
+                expected_body == body && expected_metadata == metadata && paired == *generation
+            }
+            (
+                TraceResult::Head {
+                    metadata: expected_metadata,
+                    generation: expected_generation,
+                    length: expected_length,
+                },
+                TraceResult::Head {
+                    metadata,
+                    generation,
+                    length,
+                },
+            ) => {
+                let paired = *generations
+                    .entry(*expected_generation)
+                    .or_insert(*generation);
+                expected_metadata == metadata && expected_length == length && paired == *generation
+            }
+            // whether a value has expired depends on when the trace is replayed
+            (TraceResult::Expired, actual) => *actual == not_found(),
+            (expected, actual) => expected == actual,
+        };
+
+        if !matches {
+            report.divergences.push(Divergence {
+                index,
+                entry: entry.clone(),
+                actual,
+            });
+        }
+        report.operations += 1;
+    }
+
+    report
+}
+
+/// Replay an operation on `key`. Only the length of the key is checked, as the key was accepted
+/// by whichever API's rules it was recorded under.
+fn replay_key(key: &str, op: impl FnOnce(ObjectKey) -> TraceResult) -> TraceResult {
+    match ObjectKey::new_with_profile(key, KeyValidationProfile::Permissive) {
+        Ok(key) => op(key),
+        Err(_) => invalid("key"),
+    }
+}
+
+fn not_found() -> TraceResult {
+    TraceResult::Error {
+        error: format!("{:?}", KvStoreError::NotFound),
+    }
+}
+
+fn invalid(field: &str) -> TraceResult {
+    TraceResult::Error {
+        error: format!("invalid {field} in trace entry"),
+    }
+}
@@ -17,7 +17,15 @@ pub mod adapt;
 pub mod body;
 pub mod cache;
 pub mod config;
+pub mod embedding;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod kv_diagnostics;
+pub mod kv_diff;
+pub mod kv_list_capture;
+pub mod kv_strict;
+pub mod kv_trace;
 pub mod logging;
 pub mod session;
 
@@ -35,6 +43,20 @@ mod upstream;
 pub mod wiggle_abi;
 
 pub use {
-    error::Error, execute::ExecuteCtx, service::ViceroyService, upstream::BackendConnector,
+    error::Error,
+    execute::{DrainSummary, ExecuteCtx},
+    object_store::{
+        AwaitKeyError, BudgetPolicy, Clock, Compression, CountingGenerations, ExportOptions,
+        ExportedBytes, ExportedStore, ExportedValue, GenerationSource, InsertStats,
+        KeyValidationError, KeyValidationProfile, KvEvent, KvExport, KvNamespaceConfig, KvObserver,
+        KvOp, KvOpKind, KvRequest, KvScope, KvStoreError, KvTransaction, LatencySnapshot, ListMeta,
+        ListOrder, ListResponse, LostWriteRule, MemoryBudget, MetadataFormat, MockClock,
+        ObjectHead, ObjectKey, ObjectStoreError, ObjectStoreKey, ObjectValue, ObjectValueBuilder,
+        PurgeTask, Redaction, ScopeStats, SeedOptions, StoreConfig, StoreNameValidationError,
+        StoreStats, SystemClock, ValueOrigin, KV_EXPORT_FORMAT_VERSION,
+    },
+    service::ViceroyService,
+    upstream::BackendConnector,
     wasmtime::ProfilingStrategy,
+    wiggle_abi::types::KvInsertMode,
 };
@@ -1,948 +1,8340 @@
+mod budget;
+mod clock;
+mod compression;
+mod export;
+mod faults;
+mod filter;
+mod generation;
+mod insert_stats;
+mod latency;
+mod limit;
+mod metadata;
+mod namespace;
+mod observer;
+mod overlay;
+mod saturation;
+mod scope;
+mod transaction;
+mod values;
+mod waiter;
+
+pub use budget::{BudgetPolicy, MemoryBudget};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use export::{
+    ExportOptions, ExportedBytes, ExportedStore, ExportedValue, KvExport, Redaction,
+    KV_EXPORT_FORMAT_VERSION,
+};
+pub use faults::LostWriteRule;
+pub use generation::{CountingGenerations, GenerationSource};
+pub use insert_stats::InsertStats;
+pub use latency::{KvOpKind, LatencySnapshot};
+pub(crate) use limit::Turn;
+pub use metadata::MetadataFormat;
+pub use namespace::KvNamespaceConfig;
+pub use observer::{KvEvent, KvObserver, KvOp};
+pub use scope::{KvScope, ScopeStats};
+pub use transaction::KvTransaction;
+pub use values::StoreStats;
+
+pub use compression::Compression;
+
 use {
-    crate::wiggle_abi::types::{FastlyStatus, KvError, KvInsertMode},
+    self::{
+        budget::Budget,
+        export::RedactedBytes,
+        faults::Faults,
+        filter::KeyFilter,
+        insert_stats::InsertStatsByStore,
+        latency::Latencies,
+        limit::StoreLimiter,
+        namespace::Namespaces,
+        observer::Observers,
+        overlay::Overlay,
+        saturation::SaturatedKeys,
+        scope::{InScope, ScopeStatsByName},
+        values::{StoreValues, Written},
+        waiter::{KeyWaiter, Registered},
+    },
+    crate::{
+        config::limits::{KV_STORE_METADATA_MAX_LEN, KV_STORE_VALUE_MAX_LEN},
+        wiggle_abi::types::{FastlyStatus, KvError, KvInsertMode},
+    },
     base64::prelude::*,
-    serde::Serialize,
+    bytes::Bytes,
+    serde::{Deserialize, Serialize},
     std::{
-        collections::BTreeMap,
-        sync::{Arc, RwLock},
-        time::SystemTime,
+        borrow::Borrow,
+        collections::{BTreeMap, BTreeSet},
+        fmt,
+        sync::{Arc, PoisonError, RwLock},
+        time::{Duration, SystemTime},
     },
+    tracing::{debug, warn},
 };
 
-#[derive(Debug, Clone)]
+/// A value in a store.
+///
+/// Values are made with [`ObjectValue::new`], which keeps their fields consistent with one
+/// another, and read through the accessors here.
+#[derive(Clone)]
 pub struct ObjectValue {
-    pub body: Vec<u8>,
-    pub metadata: Vec<u8>,
-    pub metadata_len: usize,
-    pub generation: u32,
-    pub expiration: Option<SystemTime>,
+    /// Shared with the stored value, so a lookup hands out the body without copying it, unless the
+    /// store is compressed. Writes that extend a value build a new body rather than changing this
+    /// one.
+    pub(crate) body: Bytes,
+    pub(crate) metadata: Vec<u8>,
+    /// Assigned on every write, from a counter that only ever increases, so each write to a key
+    /// gets a greater generation than the last. Guests only see 32 bits of it; see
+    /// [`abi_generation`][Self::abi_generation].
+    pub(crate) generation: u64,
+    pub(crate) expiration: Option<SystemTime>,
+    /// When the key was first written. Overwriting, appending to, or prepending to a live value
+    /// keeps this, while writing to a key that is missing, deleted, or expired sets it afresh.
+    pub(crate) created_at: SystemTime,
+    /// When the value was last written, by any write including an append or prepend.
+    pub(crate) updated_at: SystemTime,
+    /// Where the value came from.
+    pub(crate) origin: ValueOrigin,
+    /// The request that last wrote the value, if a guest wrote it through a handle made with
+    /// [`ObjectStores::with_request`].
+    pub(crate) written_by: Option<Arc<KvRequest>>,
+    /// How `body` is compressed, if it is. Only ever set on values at rest in a store; the values
+    /// handed out by lookups are always decompressed.
+    pub(crate) compression: Option<Compression>,
+    /// The length of the body once decompressed, so a [head][ObjectStores::head] needn't
+    /// decompress it to find out.
+    pub(crate) body_len: usize,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct ObjectStores {
-    #[allow(clippy::type_complexity)]
-    stores: Arc<RwLock<BTreeMap<ObjectStoreKey, BTreeMap<ObjectKey, ObjectValue>>>>,
+/// A value being put together, from [`ObjectValue::new`].
+///
+/// Each setting left alone is as for a value written once, by [`build`][Self::build], with no
+/// metadata or expiry.
+#[derive(Clone)]
+#[must_use]
+pub struct ObjectValueBuilder {
+    body: Bytes,
+    metadata: Vec<u8>,
+    generation: u64,
+    ttl: Option<Duration>,
+    created_at: Option<SystemTime>,
+    updated_at: Option<SystemTime>,
+    origin: ValueOrigin,
+    written_by: Option<Arc<KvRequest>>,
 }
 
-impl ObjectStores {
-    pub fn new() -> Self {
-        Self {
-            stores: Arc::new(RwLock::new(BTreeMap::new())),
-        }
+impl ObjectValueBuilder {
+    pub fn metadata(mut self, metadata: impl Into<Vec<u8>>) -> Self {
+        self.metadata = metadata.into();
+        self
     }
 
-    pub(crate) fn store_exists(&self, obj_store_key: &str) -> Result<bool, ObjectStoreError> {
-        Ok(self
-            .stores
-            .read()
-            .map_err(|_| ObjectStoreError::PoisonedLock)?
-            .get(&ObjectStoreKey::new(obj_store_key))
-            .is_some())
+    /// The generation to give the value. Zero, the default, is never a stored value's, so
+    /// [`ObjectStores::insert_many`] gives such a value the next one instead.
+    pub fn generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
     }
 
-    pub fn lookup(
-        &self,
-        obj_store_key: ObjectStoreKey,
-        obj_key: ObjectKey,
-    ) -> Result<ObjectValue, KvStoreError> {
-        let mut res = Err(KvStoreError::Uninitialized);
-
-        self.stores
-            .write()
-            .map_err(|_| KvStoreError::InternalError)?
-            .entry(obj_store_key)
-            .and_modify(|store| match store.get(&obj_key) {
-                Some(val) => {
-                    res = Ok(val.clone());
-                    // manages ttl
-                    if let Some(exp) = val.expiration {
-                        if SystemTime::now() >= exp {
-                            store.remove(&obj_key);
-                            res = Err(KvStoreError::NotFound);
-                        }
-                    }
-                }
-                None => {
-                    res = Err(KvStoreError::NotFound);
-                }
-            });
+    /// How long the value lives, from when it is built.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
 
-        res
+    /// When the key was first written, rather than when the value is built.
+    pub fn created_at(mut self, at: SystemTime) -> Self {
+        self.created_at = Some(at);
+        self
     }
 
-    pub(crate) fn insert_empty_store(
-        &self,
-        obj_store_key: ObjectStoreKey,
-    ) -> Result<(), ObjectStoreError> {
-        self.stores
-            .write()
-            .map_err(|_| ObjectStoreError::PoisonedLock)?
-            .entry(obj_store_key)
-            .and_modify(|_| {})
-            .or_insert_with(BTreeMap::new);
+    /// When the value was last written, rather than when it is built.
+    pub fn updated_at(mut self, at: SystemTime) -> Self {
+        self.updated_at = Some(at);
+        self
+    }
 
-        Ok(())
+    pub fn origin(mut self, origin: ValueOrigin) -> Self {
+        self.origin = origin;
+        self
     }
 
-    pub fn insert(
-        &self,
-        obj_store_key: ObjectStoreKey,
-        obj_key: ObjectKey,
-        obj: Vec<u8>,
-        mode: KvInsertMode,
-        generation: Option<u32>,
-        metadata: Option<Vec<u8>>,
-        ttl: Option<std::time::Duration>,
-    ) -> Result<(), KvStoreError> {
-        // manages ttl
-        let existing = self.lookup(obj_store_key.clone(), obj_key.clone());
+    pub(crate) fn written_by(mut self, request: Option<Arc<KvRequest>>) -> Self {
+        self.written_by = request;
+        self
+    }
 
-        if let Some(g) = generation {
-            if let Ok(val) = &existing {
-                if val.generation != g {
-                    return Err(KvStoreError::PreconditionFailed);
-                }
-            }
+    /// The value, as if written at `now`, which is usually [`ObjectStores::now`].
+    pub fn build(self, now: SystemTime) -> ObjectValue {
+        ObjectValue {
+            body_len: self.body.len(),
+            body: self.body,
+            metadata: self.metadata,
+            generation: self.generation,
+            expiration: self.ttl.map(|ttl| now + ttl),
+            created_at: self.created_at.unwrap_or(now),
+            updated_at: self.updated_at.unwrap_or(now),
+            origin: self.origin,
+            written_by: self.written_by,
+            compression: None,
         }
+    }
+}
 
-        let out_obj = match mode {
-            KvInsertMode::Overwrite => obj,
-            KvInsertMode::Add => {
-                if existing.is_ok() {
-                    // key exists, add fails
-                    return Err(KvStoreError::PreconditionFailed);
-                }
-                obj
-            }
-            KvInsertMode::Append => {
-                let mut out_obj;
-                match existing {
-                    Err(KvStoreError::NotFound) => {
-                        out_obj = obj;
-                    }
-                    Err(_) => return Err(KvStoreError::InternalError),
-                    Ok(v) => {
-                        out_obj = v.body;
-                        out_obj.append(&mut obj.clone());
-                    }
-                }
-                out_obj
-            }
-            KvInsertMode::Prepend => {
-                let mut out_obj;
-                match existing {
-                    Err(KvStoreError::NotFound) => {
-                        out_obj = obj;
-                    }
-                    Err(_) => return Err(KvStoreError::InternalError),
-                    Ok(mut v) => {
-                        out_obj = obj;
-                        out_obj.append(&mut v.body);
-                    }
-                }
-                out_obj
-            }
-        };
-
-        let exp = ttl.map(|t| SystemTime::now() + t);
-
-        let mut obj_val = ObjectValue {
-            body: out_obj,
+impl ObjectValue {
+    /// Start on a value holding `body`, for installing with [`ObjectStores::insert_many`].
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(body: impl Into<Bytes>) -> ObjectValueBuilder {
+        ObjectValueBuilder {
+            body: body.into(),
             metadata: vec![],
-            metadata_len: 0,
-            generation: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos() as u32,
-            expiration: exp,
-        };
-
-        // magic number hack to ensure a case for integration tests
-        if obj_val.generation == 1337 {
-            obj_val.generation = 1338;
+            generation: 0,
+            ttl: None,
+            created_at: None,
+            updated_at: None,
+            origin: ValueOrigin::default(),
+            written_by: None,
         }
+    }
 
-        if let Some(m) = metadata {
-            obj_val.metadata_len = m.len();
-            obj_val.metadata = m;
-        }
+    /// The body, decompressed.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
 
-        self.stores
-            .write()
-            .map_err(|_| KvStoreError::InternalError)?
-            .entry(obj_store_key)
-            .and_modify(|store| {
-                store.insert(obj_key.clone(), obj_val.clone());
-            })
-            .or_insert_with(|| {
-                let mut store = BTreeMap::new();
-                store.insert(obj_key, obj_val);
-                store
-            });
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata
+    }
 
-        Ok(())
+    pub fn metadata_len(&self) -> usize {
+        self.metadata.len()
     }
 
-    pub fn delete(
-        &self,
-        obj_store_key: ObjectStoreKey,
-        obj_key: ObjectKey,
-    ) -> Result<(), KvStoreError> {
-        let mut res = Ok(());
+    /// The generation of the write that left the value; see [`ObjectValue::abi_generation`] for
+    /// the part guests see.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 
-        self.stores
-            .write()
-            .map_err(|_| KvStoreError::InternalError)?
-            .entry(obj_store_key)
-            .and_modify(|store| match store.get(&obj_key) {
-                // 404 if the key doesn't exist, otherwise delete
-                Some(val) => {
-                    // manages ttl
-                    if let Some(exp) = val.expiration {
-                        if SystemTime::now() >= exp {
-                            res = Err(KvStoreError::NotFound);
-                        }
-                    }
-                    store.remove(&obj_key);
-                }
-                None => {
-                    res = Err(KvStoreError::NotFound);
-                }
-            });
+    /// When the value expires, if it has a TTL.
+    pub fn expiration(&self) -> Option<SystemTime> {
+        self.expiration
+    }
 
-        res
+    /// When the key was first written. Overwriting, appending to, or prepending to a live value
+    /// keeps this, while writing to a key that is missing, deleted, or expired sets it afresh.
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
     }
 
-    pub fn list(
-        &self,
-        obj_store_key: ObjectStoreKey,
-        cursor: Option<String>,
-        prefix: Option<String>,
-        limit: u32,
-    ) -> Result<Vec<u8>, KvStoreError> {
-        let mut res = Err(KvStoreError::InternalError);
+    /// When the value was last written, by any write including an append or prepend.
+    pub fn updated_at(&self) -> SystemTime {
+        self.updated_at
+    }
 
-        let cursor = match cursor {
-            Some(c) => {
-                let cursor_bytes = BASE64_STANDARD
-                    .decode(c)
-                    .map_err(|_| KvStoreError::BadRequest)?;
-                let decoded =
-                    String::from_utf8(cursor_bytes).map_err(|_| KvStoreError::BadRequest)?;
-                Some(decoded)
-            }
-            None => None,
-        };
+    pub fn origin(&self) -> ValueOrigin {
+        self.origin
+    }
 
-        self.stores
-            .write()
-            .map_err(|_| KvStoreError::InternalError)?
-            .entry(obj_store_key.clone())
-            .and_modify(|store| {
-                // manages ttl
-                // a bit wasteful to run this loop twice, but we need mutable access to store,
-                // and it's already claimed in the filters below
-                let ttl_list = store.iter_mut().map(|(k, _)| k.clone()).collect::<Vec<_>>();
-                ttl_list.into_iter().for_each(|k| {
-                    let val = store.get(&k);
-                    if let Some(v) = val {
-                        if let Some(exp) = v.expiration {
-                            if SystemTime::now() >= exp {
-                                store.remove(&k);
-                            }
-                        }
-                    }
-                });
+    /// The request that last wrote the value, if a guest wrote it through a handle made with
+    /// [`ObjectStores::with_request`].
+    pub fn written_by(&self) -> Option<&KvRequest> {
+        self.written_by.as_deref()
+    }
 
-                let mut list = store
-                    .iter_mut()
-                    .filter(|(k, _)| {
-                        if let Some(c) = &cursor {
-                            &k.0 > c
-                        } else {
-                            true
-                        }
-                    })
-                    .filter(|(k, _)| {
-                        if let Some(p) = &prefix {
-                            k.0.starts_with(p)
-                        } else {
-                            true
-                        }
-                    })
-                    .map(|(k, _)| String::from_utf8(k.0.as_bytes().to_vec()).unwrap())
-                    .collect::<Vec<_>>();
+    /// The generation as the guest ABIs expose it, saturated at `u32::MAX`.
+    pub fn abi_generation(&self) -> u32 {
+        abi_generation(self.generation)
+    }
 
-                // limit
-                let old_len = list.len();
-                list.truncate(limit as usize);
-                let new_len = list.len();
+    /// Whether the value matches the `generation` an insert or delete was made with: its whole
+    /// generation, or, for [`with_abi_generations`][ObjectStores::with_abi_generations] handles,
+    /// the saturated one guests are given.
+    fn matches_generation(&self, generation: u64, abi_generations: bool) -> bool {
+        match abi_generations {
+            true => u64::from(self.abi_generation()) == generation,
+            false => self.generation == generation,
+        }
+    }
 
-                let next_cursor = match old_len != new_len {
-                    true => Some(BASE64_STANDARD.encode(&list[new_len - 1])),
-                    false => None,
-                };
+    /// The bytes of body and metadata the value takes up, as held.
+    fn held_bytes(&self) -> usize {
+        self.body.len() + self.metadata.len()
+    }
 
-                #[derive(Serialize)]
-                struct Metadata {
-                    limit: u32,
-                    #[serde(skip_serializing_if = "Option::is_none")]
-                    prefix: Option<String>,
-                    #[serde(skip_serializing_if = "Option::is_none")]
-                    next_cursor: Option<String>,
-                }
-                #[derive(Serialize)]
-                struct JsonOutput {
-                    data: Vec<String>,
-                    meta: Metadata,
-                }
+    /// The value with its body compressed with `compression`, for storing.
+    fn compressed(mut self, compression: Option<Compression>) -> ObjectValue {
+        if let Some(compression) = compression {
+            self.body = compression.compress(&self.body).into();
+            self.compression = Some(compression);
+        }
+        self
+    }
 
-                let body = JsonOutput {
-                    data: list,
-                    meta: Metadata {
-                        limit,
-                        prefix,
-                        next_cursor,
-                    },
-                };
+    /// The value with its body decompressed, as guests see it.
+    fn decompressed(mut self) -> Result<ObjectValue, KvStoreError> {
+        if let Some(compression) = self.compression.take() {
+            self.body = compression
+                .decompress(&self.body)
+                .map_err(|e| {
+                    warn!("failed to decompress KV value: {e}");
+                    KvStoreError::InternalError
+                })?
+                .into();
+        }
+        Ok(self)
+    }
 
-                match serde_json::to_string(&body).map_err(|_| KvStoreError::InternalError) {
-                    Ok(s) => res = Ok(s.as_bytes().to_vec()),
-                    Err(e) => res = Err(e),
-                };
-            });
-        res
+    /// Whether the value's TTL has elapsed as of `now`.
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expiration.is_some_and(|exp| now >= exp)
     }
 }
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Default)]
-pub struct ObjectStoreKey(String);
+/// Where a value came from: seed data, a write while running, or both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValueOrigin {
+    /// Seeded from configuration, or by an embedder through a handle marked as seeding.
+    Seed,
+    /// Written while running, by the request with `req_id` if a guest wrote it.
+    Runtime {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        req_id: Option<u64>,
+    },
+    /// Seed data that was later appended or prepended to. `req_id` is that of the request that
+    /// last extended it, if a guest did.
+    Mixed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        req_id: Option<u64>,
+    },
+}
 
-impl ObjectStoreKey {
-    pub fn new(key: impl ToString) -> Self {
-        Self(key.to_string())
+impl Default for ValueOrigin {
+    fn default() -> Self {
+        ValueOrigin::Runtime { req_id: None }
     }
 }
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Default)]
-pub struct ObjectKey(String);
+impl ValueOrigin {
+    /// The origin of a value after a write from `writer` appends or prepends to it.
+    fn extended_by(self, writer: ValueOrigin) -> ValueOrigin {
+        use ValueOrigin::*;
+        match (self, writer) {
+            (Seed, Seed) => Seed,
+            (Runtime { .. }, Runtime { req_id }) => Runtime { req_id },
+            (_, Runtime { req_id } | Mixed { req_id }) => Mixed { req_id },
+            (Runtime { req_id } | Mixed { req_id }, Seed) => Mixed { req_id },
+        }
+    }
+}
 
-impl ObjectKey {
-    pub fn new(key: impl ToString) -> Result<Self, KeyValidationError> {
-        let key = key.to_string();
-        is_valid_key(&key)?;
-        Ok(Self(key))
+/// Stores may hold credentials or personal data, so bodies and metadata are summarized rather than
+/// printed.
+impl fmt::Debug for ObjectValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectValue")
+            .field("body", &RedactedBytes(&self.body))
+            .field("metadata", &RedactedBytes(&self.metadata))
+            .field("generation", &self.generation)
+            .field("expiration", &self.expiration)
+            .field("created_at", &self.created_at)
+            .field("updated_at", &self.updated_at)
+            .field("origin", &self.origin)
+            .field("written_by", &self.written_by)
+            .field("compression", &self.compression)
+            .field("body_len", &self.body_len)
+            .finish()
     }
 }
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, thiserror::Error)]
-pub enum ObjectStoreError {
-    #[error("The object was not in the store")]
-    MissingObject,
-    #[error("Viceroy's ObjectStore lock was poisoned")]
-    PoisonedLock,
-    /// An Object Store with the given name was not found.
-    #[error("Unknown object-store: {0}")]
-    UnknownObjectStore(String),
+/// The downstream request a KV operation was made for, for telling which of several requests
+/// sharing a store wrote a value.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvRequest {
+    pub req_id: u64,
+    pub method: String,
+    /// The path of the request's URL, without its query.
+    pub path: String,
 }
 
-impl From<&ObjectStoreError> for FastlyStatus {
-    fn from(e: &ObjectStoreError) -> Self {
-        use ObjectStoreError::*;
-        match e {
-            MissingObject => FastlyStatus::None,
-            PoisonedLock => panic!("{}", e),
-            UnknownObjectStore(_) => FastlyStatus::Inval,
-        }
+impl fmt::Display for KvRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request {} ({} {})", self.req_id, self.method, self.path)
     }
 }
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, thiserror::Error)]
-pub enum KvStoreError {
-    #[error("The error was not set")]
-    Uninitialized,
-    #[error("There was no error")]
-    Ok,
-    #[error("KV store cannot or will not process the request due to something that is perceived to be a client error")]
-    BadRequest,
-    #[error("KV store cannot find the requested resource")]
-    NotFound,
-    #[error("KV store cannot fulfill the request, as definied by the client's prerequisites (ie. if-generation-match)")]
-    PreconditionFailed,
-    #[error("The size limit for a KV store key was exceeded")]
-    PayloadTooLarge,
-    #[error("The system encountered an unexpected internal error")]
-    InternalError,
-    #[error("Too many requests have been made to the KV store")]
-    TooManyRequests,
+/// What [`ObjectStores::head`] finds for a key: everything a lookup would, but the body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectHead {
+    pub metadata: Vec<u8>,
+    pub generation: u64,
+    /// The length of the body a lookup would return.
+    pub body_len: usize,
 }
 
-impl From<&KvError> for KvStoreError {
-    fn from(e: &KvError) -> Self {
-        match e {
-            KvError::Uninitialized => KvStoreError::Uninitialized,
-            KvError::Ok => KvStoreError::Ok,
-            KvError::BadRequest => KvStoreError::BadRequest,
-            KvError::NotFound => KvStoreError::NotFound,
-            KvError::PreconditionFailed => KvStoreError::PreconditionFailed,
-            KvError::PayloadTooLarge => KvStoreError::PayloadTooLarge,
-            KvError::InternalError => KvStoreError::InternalError,
-            KvError::TooManyRequests => KvStoreError::TooManyRequests,
-        }
+impl ObjectHead {
+    /// The generation as the guest ABIs expose it, saturated at `u32::MAX`.
+    pub fn abi_generation(&self) -> u32 {
+        abi_generation(self.generation)
     }
 }
 
-impl From<&KvStoreError> for KvError {
-    fn from(e: &KvStoreError) -> Self {
-        match e {
-            KvStoreError::Uninitialized => KvError::Uninitialized,
-            KvStoreError::Ok => KvError::Ok,
-            KvStoreError::BadRequest => KvError::BadRequest,
-            KvStoreError::NotFound => KvError::NotFound,
-            KvStoreError::PreconditionFailed => KvError::PreconditionFailed,
-            KvStoreError::PayloadTooLarge => KvError::PayloadTooLarge,
-            KvStoreError::InternalError => KvError::InternalError,
-            KvStoreError::TooManyRequests => KvError::TooManyRequests,
+impl From<&ObjectValue> for ObjectHead {
+    fn from(val: &ObjectValue) -> Self {
+        ObjectHead {
+            metadata: val.metadata.clone(),
+            generation: val.generation,
+            body_len: val.body_len,
         }
     }
 }
 
-impl From<&KvStoreError> for ObjectStoreError {
-    fn from(e: &KvStoreError) -> Self {
-        match e {
-            // the only real one
-            KvStoreError::NotFound => ObjectStoreError::MissingObject,
-            _ => ObjectStoreError::UnknownObjectStore("".to_string()),
-        }
-    }
+/// How [`ObjectStores::create_store`] seeds a value, beyond its key and body.
+///
+/// Each setting left as `None` is as for a value written at runtime when the store is created.
+#[derive(Clone, Default)]
+pub struct SeedOptions {
+    pub metadata: Option<Vec<u8>>,
+    /// The generation to give the value, rather than the next one.
+    pub generation: Option<u64>,
+    /// When the key was first written, rather than when it was seeded.
+    pub created_at: Option<SystemTime>,
+    /// When the value was last written, rather than when it was seeded.
+    pub updated_at: Option<SystemTime>,
 }
 
-impl From<&KvStoreError> for FastlyStatus {
-    fn from(e: &KvStoreError) -> Self {
-        match e {
-            KvStoreError::Uninitialized => panic!("{}", e),
-            KvStoreError::Ok => FastlyStatus::Ok,
-            KvStoreError::BadRequest => FastlyStatus::Inval,
-            KvStoreError::NotFound => FastlyStatus::None,
-            KvStoreError::PreconditionFailed => FastlyStatus::Inval,
-            KvStoreError::PayloadTooLarge => FastlyStatus::Inval,
-            KvStoreError::InternalError => FastlyStatus::Inval,
-            KvStoreError::TooManyRequests => FastlyStatus::Inval,
+/// The order in which [`ObjectStores::list_ordered`] returns keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListOrder {
+    /// Ascending by key, as production does.
+    #[default]
+    Lexicographic,
+    /// Most recently written first, with ties broken by ascending key.
+    LastModified,
+}
+
+/// The limits a store holds writes to, as guests can ask for them when [local
+/// extensions](crate::ExecuteCtx::with_local_extensions) are enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreLimits {
+    /// The largest value, in bytes, that may be written.
+    pub max_value_size: usize,
+    /// The largest metadata, in bytes, that may be written.
+    pub max_metadata_size: usize,
+    /// The longest key, in bytes, that may be given.
+    pub max_key_len: usize,
+}
+
+impl Default for StoreLimits {
+    /// Production's limits.
+    fn default() -> Self {
+        Self {
+            max_value_size: KV_STORE_VALUE_MAX_LEN,
+            max_metadata_size: KV_STORE_METADATA_MAX_LEN,
+            max_key_len: MAX_KEY_BYTES,
         }
     }
 }
 
-/// Keys in the Object Store must follow the following rules:
+/// A page of keys, as [`ObjectStores::list`] writes it as JSON, and as production sends it.
 ///
-///   * Keys can contain any sequence of valid Unicode characters, of length 1-1024 bytes when
-///     UTF-8 encoded.
-///   * Keys cannot contain Carriage Return or Line Feed characters.
-///   * Keys cannot start with `.well-known/acme-challenge/`.
-///   * Keys cannot be named `.` or `..`.
-fn is_valid_key(key: &str) -> Result<(), KeyValidationError> {
-    let len = key.as_bytes().len();
-    if len < 1 {
-        return Err(KeyValidationError::EmptyKey);
-    } else if len > 1024 {
-        return Err(KeyValidationError::Over1024Bytes);
-    }
+/// Lists are written from this type, and [`ObjectStores::parse_list_response`] reads them back
+/// into it, so the two can't disagree about the format.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListResponse {
+    /// The keys on the page, in the order they were listed.
+    pub data: Vec<String>,
+    pub meta: ListMeta,
+}
 
-    if key.starts_with(".well-known/acme-challenge") {
-        return Err(KeyValidationError::StartsWithWellKnown);
-    }
+/// What a [`ListResponse`] says about the list it is a page of.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListMeta {
+    /// The most keys the page could have held.
+    pub limit: u32,
+    /// The prefix every key listed starts with, if the list was given one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// The cursor for the next page, if there are more keys to list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
 
-    if key.eq("..") {
-        return Err(KeyValidationError::ContainsDotDot);
-    } else if key.eq(".") {
-        return Err(KeyValidationError::ContainsDot);
-    } else if key.contains('\r') {
-        return Err(KeyValidationError::Contains("\r".to_owned()));
-    } else if key.contains('\n') {
-        return Err(KeyValidationError::Contains("\n".to_owned()));
-    } else if key.contains('[') {
-        return Err(KeyValidationError::Contains("[".to_owned()));
-    } else if key.contains(']') {
-        return Err(KeyValidationError::Contains("]".to_owned()));
-    } else if key.contains('*') {
-        return Err(KeyValidationError::Contains("*".to_owned()));
-    } else if key.contains('?') {
-        return Err(KeyValidationError::Contains("?".to_owned()));
-    } else if key.contains('#') {
-        return Err(KeyValidationError::Contains("#".to_owned()));
-    }
+/// The contents of every store, by store and then by key.
+type StoreMap = BTreeMap<ObjectStoreKey, StoreValues>;
 
-    Ok(())
+/// A set of KV stores, and a handle to them.
+///
+/// **Cloning an `ObjectStores` does not copy the stores.** A clone is another handle to the same
+/// stores, settings, observers, and namespaces, so a write through either is visible through both;
+/// it is equivalent to [`share`][Self::share], which says so at the call site. Use
+/// [`deep_clone`][Self::deep_clone] for an independent copy.
+#[derive(Clone, Debug)]
+pub struct ObjectStores {
+    stores: Arc<RwLock<StoreMap>>,
+    observers: Observers,
+    namespaces: Namespaces,
+    clock: Arc<dyn Clock>,
+    generations: Arc<dyn GenerationSource>,
+    /// Per-store settings from configuration.
+    settings: Arc<RwLock<BTreeMap<ObjectStoreKey, StoreConfig>>>,
+    /// Concurrency limits for the stores configured with one.
+    #[allow(clippy::type_complexity)]
+    limiters: Arc<RwLock<BTreeMap<ObjectStoreKey, Arc<StoreLimiter>>>>,
+    /// Key filters for the stores configured with one. Unlike settings and limiters, these belong
+    /// to the stores they filter, so namespaces have their own.
+    #[allow(clippy::type_complexity)]
+    filters: Arc<RwLock<BTreeMap<ObjectStoreKey, Arc<KeyFilter>>>>,
+    /// The origin recorded for writes made through this handle.
+    origin: ValueOrigin,
+    /// The request that operations made through this handle are for, if any.
+    request: Option<Arc<KvRequest>>,
+    /// Latency histograms for each store and kind of operation, shared with namespaces.
+    latencies: Arc<Latencies>,
+    /// Counts of inserts by mode for each store, shared with namespaces.
+    insert_stats: Arc<InsertStatsByStore>,
+    /// The faults injected into operations, shared with namespaces.
+    faults: Arc<Faults>,
+    /// The counters for each stats scope, by name, shared with namespaces.
+    scope_stats: Arc<ScopeStatsByName>,
+    /// The stores these are an [isolated][Self::isolated] view of, if they are one.
+    overlay: Option<Arc<Overlay>>,
+    /// The limit on the memory values may take up, shared with namespaces.
+    budget: Arc<Budget>,
+    /// Whether `generation` checks are made against the generations guests are given; see
+    /// [`with_abi_generations`][Self::with_abi_generations].
+    abi_generations: bool,
+    /// The keys whose generations guests have been given saturated, shared with namespaces.
+    saturated: Arc<SaturatedKeys>,
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum KeyValidationError {
-    #[error("Keys for objects cannot be empty")]
-    EmptyKey,
-    #[error("Keys for objects cannot be over 1024 bytes in size")]
-    Over1024Bytes,
-    #[error("Keys for objects cannot start with `.well-known/acme-challenge`")]
-    StartsWithWellKnown,
-    #[error("Keys for objects cannot be named `.`")]
-    ContainsDot,
-    #[error("Keys for objects cannot be named `..`")]
-    ContainsDotDot,
-    #[error("Keys for objects cannot contain a `{0}`")]
-    Contains(String),
+/// The limits and behaviors of a single store, from configuration or
+/// [`configure_store`][ObjectStores::configure_store].
+///
+/// The default is a store that behaves as production's do, with production's limits.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreConfig {
+    /// Whether the store's contents are redacted from exports.
+    pub sensitive: bool,
+    /// The time-to-live for inserts that don't specify one.
+    pub default_ttl: Option<Duration>,
+    /// The number of guest operations that may run against the store at once. Unlimited if unset.
+    pub max_concurrent_operations: Option<usize>,
+    /// The number of guest operations that may wait for their turn before further ones are
+    /// rejected. Unlimited if unset.
+    pub max_queued_operations: Option<usize>,
+    /// Whether lookups consult a filter of the store's keys before the store, so that most lookups
+    /// of missing keys fail without taking the store lock.
+    pub key_filter: bool,
+    /// How values are compressed at rest. Uncompressed if unset.
+    pub compression: Option<Compression>,
+    /// The largest value, in bytes, that may be written. Production's limit if unset.
+    pub max_value_size: Option<usize>,
+    /// The largest metadata, in bytes, that may be written. Production's limit if unset.
+    pub max_metadata_size: Option<usize>,
+    /// The number of keys the store may hold. Unlimited if unset.
+    pub max_keys: Option<usize>,
+    /// The size, in bytes, past which a value growing is logged as a warning. Never if unset.
+    pub warn_value_size: Option<usize>,
+    /// The rules keys are checked against, whichever API they come through. Each API's own if
+    /// unset.
+    pub key_validation: Option<KeyValidationProfile>,
+    /// Whether inserts and deletes are refused, for stores of reference data.
+    pub read_only: bool,
+    /// What the keys guests give are prefixed with, and the keys they list are stripped of, so
+    /// that the services sharing a store each see only their own keys. The APIs here take and
+    /// return the stored keys, prefix and all.
+    pub key_prefix: Option<String>,
+    /// The form metadata written to the store must take. Opaque bytes if unset.
+    pub metadata_format: Option<MetadataFormat>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    const STORE_NAME: &'static str = "test_store";
+impl Default for ObjectStores {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    #[test]
-    fn test_kv_store_exists() {
-        let stores = ObjectStores::default();
-        stores
-            .insert_empty_store(ObjectStoreKey(STORE_NAME.to_string()))
-            .unwrap();
+impl ObjectStores {
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
 
-        let res = stores.store_exists(STORE_NAME);
-        match res {
-            Ok(true) => {}
-            _ => panic!("should have been OK(true)"),
-        }
+    /// Create an empty set of stores that reads the time from `clock`.
+    ///
+    /// Every time-dependent behavior goes through the clock: when values expire, the
+    /// last-modified times they record, and the listing order that depends on those. Namespaces
+    /// share the clock of the stores they were created from.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self::with_sources(clock, Arc::new(CountingGenerations::default()))
     }
 
-    #[test]
-    fn test_kv_store_basics() {
-        let stores = ObjectStores::default();
-        stores
-            .insert_empty_store(ObjectStoreKey(STORE_NAME.to_string()))
-            .unwrap();
+    /// Create an empty set of stores that assigns generations from `generations`, for tests that
+    /// need to know them ahead of time. Namespaces share the generation source of the stores they
+    /// were created from.
+    pub fn with_generation_source(generations: Arc<dyn GenerationSource>) -> Self {
+        Self::with_sources(Arc::new(SystemClock), generations)
+    }
 
-        let key = "insert_key".to_string();
-        let val1 = "val1".to_string();
+    /// The current time, as the stores' clock has it.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
 
-        // insert
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            None,
-            None,
-        );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
+    /// Create an empty set of stores that reads the time from `clock` and assigns generations from
+    /// `generations`.
+    pub fn with_sources(clock: Arc<dyn Clock>, generations: Arc<dyn GenerationSource>) -> Self {
+        Self {
+            stores: Arc::new(RwLock::new(BTreeMap::new())),
+            observers: Observers::default(),
+            namespaces: Namespaces::default(),
+            clock,
+            generations,
+            settings: Arc::default(),
+            limiters: Arc::default(),
+            filters: Arc::default(),
+            origin: ValueOrigin::default(),
+            request: None,
+            latencies: Arc::default(),
+            insert_stats: Arc::default(),
+            faults: Arc::default(),
+            scope_stats: Arc::default(),
+            overlay: None,
+            budget: Arc::default(),
+            abi_generations: false,
+            saturated: Arc::default(),
         }
+    }
 
-        // lookup
-        let res = stores.lookup(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-        );
-        match res {
-            Ok(ov) => {
-                assert_eq!(ov.body, val1.as_bytes().to_vec())
-            }
-            Err(_) => panic!("should have been OK"),
-        }
+    /// Another handle to these stores, sharing everything with this one.
+    ///
+    /// This is what [`Clone`] does, under a name that makes it clear that writes through the
+    /// returned handle are visible through this one, and the other way around.
+    pub fn share(&self) -> ObjectStores {
+        self.clone()
+    }
 
-        // list
-        let limit = 1000;
-        let res = stores.list(ObjectStoreKey(STORE_NAME.to_string()), None, None, limit);
-        match res {
-            Ok(ov) => {
-                let val = format!(r#"{{"data":["{key}"],"meta":{{"limit":{limit}}}}}"#);
-                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
-            }
-            Err(_) => panic!("should have been OK"),
-        }
+    /// An independent copy of these stores: writes to either are not visible to the other.
+    ///
+    /// The copy starts out with the same contents and per-store settings, but with no observers,
+    /// no namespaces, no [lost write rules][Self::add_lost_write_rule], no
+    /// [stats scopes][Self::add_stats_scope], and its own latencies and insert counts. It has the
+    /// same [memory budget][Self::set_memory_budget], but is held to it by itself. Values' bodies
+    /// are shared between the two rather than copied, as they're never modified in place. The
+    /// clock and the generation source are shared too, so new writes to either can't reuse a
+    /// generation. This handle's [origin][Self::with_origin] and [request][Self::with_request] are
+    /// carried over.
+    ///
+    /// The copy of an [isolated][Self::isolated] view holds everything the view can see, and is
+    /// not a view itself.
+    pub fn deep_clone(&self) -> Result<ObjectStores, ObjectStoreError> {
+        self.take_over_all()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        let stores = self
+            .stores
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .clone();
+        let settings = self
+            .settings
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .clone();
+        let limiters = settings
+            .iter()
+            .filter_map(|(key, settings)| {
+                let max = settings.max_concurrent_operations?;
+                let limiter = StoreLimiter::new(max, settings.max_queued_operations);
+                Some((key.clone(), Arc::new(limiter)))
+            })
+            .collect();
+        let filters = self
+            .filters
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .keys()
+            .map(|key| (key.clone(), Arc::new(KeyFilter::new(stores.get(key)))))
+            .collect();
+        Ok(ObjectStores {
+            stores: Arc::new(RwLock::new(stores)),
+            observers: Observers::default(),
+            namespaces: Namespaces::default(),
+            clock: self.clock.clone(),
+            generations: self.generations.clone(),
+            settings: Arc::new(RwLock::new(settings)),
+            limiters: Arc::new(RwLock::new(limiters)),
+            filters: Arc::new(RwLock::new(filters)),
+            origin: self.origin,
+            request: self.request.clone(),
+            latencies: Arc::default(),
+            insert_stats: Arc::default(),
+            faults: Arc::default(),
+            scope_stats: Arc::default(),
+            overlay: None,
+            budget: Arc::new(Budget::new(self.budget.get())),
+            abi_generations: self.abi_generations,
+            saturated: Arc::default(),
+        })
+    }
 
-        // delete
-        let res = stores.delete(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-        );
-        match res {
-            Ok(_) => {}
-            Err(_) => panic!("should have been OK"),
+    /// Get the stores for namespace `name`.
+    ///
+    /// The first time a namespace is used, its stores are copied from the current contents of
+    /// these stores; after that, writes to either are not visible to the other. Observers are
+    /// shared with the namespace, and this handle's [scoped observers][Self::with_scoped_observer]
+    /// and [origin][Self::with_origin], [request][Self::with_request], and
+    /// [generation checks][Self::with_abi_generations] are carried over to the handle returned.
+    ///
+    /// The namespace of an [isolated][Self::isolated] view is the same namespace of the stores it
+    /// was made from, isolated in turn.
+    pub fn namespace(
+        &self,
+        name: &str,
+        config: &KvNamespaceConfig,
+    ) -> Result<ObjectStores, KvStoreError> {
+        if let Some(overlay) = &self.overlay {
+            let mut namespace = overlay.base.namespace(name, config)?.isolated();
+            namespace.observers.rescope(&self.observers);
+            namespace.origin = self.origin;
+            namespace.request = self.request.clone();
+            namespace.abi_generations = self.abi_generations;
+            return Ok(namespace);
         }
+        let mut namespace = self.namespaces.get_or_create(name, config, || {
+            let seed = self
+                .stores
+                .read()
+                .map_err(|_| KvStoreError::InternalError)?
+                .clone();
+            let filters = self
+                .filters
+                .read()
+                .map_err(|_| KvStoreError::InternalError)?
+                .keys()
+                .map(|key| (key.clone(), Arc::new(KeyFilter::new(seed.get(key)))))
+                .collect();
+            Ok(ObjectStores {
+                stores: Arc::new(RwLock::new(seed)),
+                observers: self.observers.unscoped(),
+                namespaces: Namespaces::default(),
+                clock: self.clock.clone(),
+                // shared, so new writes can't reuse one of the seed's generations
+                generations: self.generations.clone(),
+                settings: self.settings.clone(),
+                limiters: self.limiters.clone(),
+                filters: Arc::new(RwLock::new(filters)),
+                origin: self.origin,
+                request: self.request.clone(),
+                latencies: self.latencies.clone(),
+                insert_stats: self.insert_stats.clone(),
+                faults: self.faults.clone(),
+                scope_stats: self.scope_stats.clone(),
+                overlay: None,
+                budget: self.budget.clone(),
+                abi_generations: self.abi_generations,
+                saturated: self.saturated.clone(),
+            })
+        })?;
+        namespace.observers.rescope(&self.observers);
+        namespace.origin = self.origin;
+        namespace.request = self.request.clone();
+        namespace.abi_generations = self.abi_generations;
+        Ok(namespace)
     }
 
-    #[test]
-    fn test_kv_store_item_404s() {
-        let stores = ObjectStores::default();
+    /// The latencies of the lookups, inserts, deletes, and lists run against each store, by store
+    /// name and kind of operation, including those run through namespaces.
+    ///
+    /// Latencies are measured from the start of each operation to its end, including any time
+    /// spent waiting on other operations for the stores' lock, but not time spent notifying
+    /// observers.
+    pub fn latencies(&self) -> BTreeMap<String, BTreeMap<KvOpKind, LatencySnapshot>> {
+        self.latencies.snapshot()
+    }
+
+    /// The inserts made to each store, by store name, including those made through namespaces.
+    pub fn insert_stats(&self) -> BTreeMap<String, InsertStats> {
+        self.insert_stats.snapshot()
+    }
+
+    /// The number of keys each store holds, and the bytes their values and metadata take up, in
+    /// order of store name.
+    ///
+    /// The totals are kept up to date as values are written and removed, so this only takes the
+    /// read lock long enough to copy them, however many values the stores hold. Namespaces are
+    /// not included, and an [isolated][Self::isolated] view counts only what it has taken over.
+    pub fn stats(&self) -> Vec<StoreStats> {
+        let Ok(stores) = self.stores.read() else {
+            return Vec::new();
+        };
         stores
-            .insert_empty_store(ObjectStoreKey(STORE_NAME.to_string()))
-            .unwrap();
+            .iter()
+            .map(|(name, store)| store.stats(name.as_str()))
+            .collect()
+    }
 
-        let res = stores.lookup(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey("bad_key".to_string()),
-        );
-        match res {
-            Ok(_) => panic!("should not have been OK"),
-            Err(e) => assert_eq!(e, KvStoreError::NotFound),
-        }
+    /// The [stats][Self::stats] of a single store, if it exists.
+    pub fn stats_for(&self, obj_store_key: &str) -> Option<StoreStats> {
+        let stores = self.stores.read().ok()?;
+        Some(stores.get(obj_store_key)?.stats(obj_store_key))
+    }
 
-        let res = stores.delete(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey("bad_key".to_string()),
-        );
-        match res {
-            Ok(_) => panic!("should not have been OK"),
-            Err(e) => assert_eq!(e, KvStoreError::NotFound),
-        }
+    /// The bytes of bodies and metadata held across every store, which is what the
+    /// [memory budget][Self::set_memory_budget] is held to: the sum of the
+    /// [stats'][Self::stats] `value_bytes` and `metadata_bytes`.
+    pub fn memory_usage(&self) -> usize {
+        self.stores.read().map_or(0, |stores| held_bytes(&stores))
     }
 
-    #[test]
-    fn test_kv_store_item_insert_modes() {
-        let stores = ObjectStores::default();
-        stores
-            .insert_empty_store(ObjectStoreKey(STORE_NAME.to_string()))
-            .unwrap();
+    /// Limit the memory the values in these stores may take up, or lift the limit with `None`.
+    ///
+    /// A write that would take the stores over the budget is refused or makes room by evicting
+    /// older values, as the budget's [policy][BudgetPolicy] says, and the first write to do so
+    /// is logged as a warning. Values already held are left alone until then, even if they are
+    /// over the budget. Inserts, appends and prepends, [`insert_many`][Self::insert_many],
+    /// [transactions][Self::transaction], and [imports][Self::import] are all held to it, and
+    /// deletes and expired values being evicted free up room.
+    ///
+    /// The budget is shared with namespaces and [isolated][Self::isolated] views, but each holds
+    /// its own values, and is held to the budget by itself.
+    pub fn set_memory_budget(&self, budget: Option<MemoryBudget>) {
+        self.budget.set(budget);
+    }
 
-        let key = "insert_key".to_string();
-        let val1 = "val1".to_string();
-        let val2 = "val2".to_string();
-        let val3 = "val3".to_string();
+    /// The [memory budget][Self::set_memory_budget], if there is one.
+    pub fn memory_budget(&self) -> Option<MemoryBudget> {
+        self.budget.get()
+    }
 
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
-            KvInsertMode::Add,
-            None,
-            None,
-            None,
-        );
-        assert!(res.is_ok());
-        // fail on Add, because key already exists
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
-            KvInsertMode::Add,
-            None,
-            None,
-            None,
-        );
-        match res {
-            Ok(_) => panic!("should not have been OK"),
-            Err(e) => assert_eq!(e, KvStoreError::PreconditionFailed),
-        }
-        // prepend val2
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val2.clone().into(),
-            KvInsertMode::Prepend,
-            None,
-            None,
-            None,
-        );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
+    /// Count the operations within `scope` performed against these stores, including by clones of
+    /// this handle and through namespaces, and report them under `name` in
+    /// [`scope_stats`][Self::scope_stats].
+    ///
+    /// Returns `false`, and changes nothing, if a scope named `name` is already counted.
+    pub fn add_stats_scope(&self, name: impl Into<String>, scope: KvScope) -> bool {
+        let Some(counters) = self.scope_stats.add(name.into()) else {
+            return false;
+        };
+        self.add_observer_in(scope, counters);
+        true
+    }
+
+    /// The operations counted for each scope added with
+    /// [`add_stats_scope`][Self::add_stats_scope], by scope name.
+    pub fn scope_stats(&self) -> BTreeMap<String, ScopeStats> {
+        self.scope_stats.snapshot()
+    }
+
+    /// Turn latency tracking on or off, for these stores and their namespaces. It is on by
+    /// default; when it is off, operations pay for a single atomic load.
+    pub fn set_latency_tracking(&self, enabled: bool) {
+        self.latencies.set_enabled(enabled);
+    }
+
+    /// Lose some of the inserts made through these stores, their clones, and their namespaces, as
+    /// `rule` says. See [`LostWriteRule`].
+    ///
+    /// Only inserts made one at a time are lost: those made in a
+    /// [transaction][Self::transaction], or with [`insert_many`][Self::insert_many], never are.
+    pub fn add_lost_write_rule(&self, rule: LostWriteRule) {
+        self.faults.add(rule);
+    }
+
+    /// Remove every rule added with [`add_lost_write_rule`][Self::add_lost_write_rule].
+    pub fn clear_lost_write_rules(&self) {
+        self.faults.clear();
+    }
+
+    /// The number of namespaces currently live.
+    pub fn namespace_count(&self) -> usize {
+        self.namespaces.len()
+    }
+
+    /// Register an observer to be notified of every lookup, insert, delete, and list performed
+    /// against these stores, including by clones of this handle.
+    pub fn add_observer(&self, observer: Arc<dyn KvObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// As [`add_observer`][Self::add_observer], but only notifying `observer` of the operations
+    /// within `scope`. The scope is only checked for operations made while there are observers,
+    /// so stores without any pay nothing for it.
+    pub fn add_observer_in(&self, scope: KvScope, observer: Arc<dyn KvObserver>) {
+        self.observers.push(Arc::new(InScope::new(scope, observer)));
+    }
+
+    /// A handle to these stores that also notifies `observer` of the operations performed through
+    /// it, and through its clones.
+    ///
+    /// Unlike [`add_observer`][Self::add_observer], this leaves other handles to the same stores
+    /// alone, so it can be used to watch a single session.
+    pub fn with_scoped_observer(&self, observer: Arc<dyn KvObserver>) -> ObjectStores {
+        ObjectStores {
+            observers: self.observers.scoped(observer),
+            ..self.clone()
         }
-        // append val3
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val3.clone().into(),
-            KvInsertMode::Append,
-            None,
-            None,
-            None,
-        );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
+    }
+
+    /// A handle to these stores whose writes, and those of its clones, are recorded as coming
+    /// from `origin`.
+    ///
+    /// Configuration seeds stores through a [`ValueOrigin::Seed`] handle, and each session writes
+    /// through a [`ValueOrigin::Runtime`] handle for its request, made with
+    /// [`with_request`][Self::with_request]. Writes through any other handle are runtime writes
+    /// with no request.
+    pub fn with_origin(&self, origin: ValueOrigin) -> ObjectStores {
+        ObjectStores {
+            origin,
+            ..self.clone()
         }
-        let res = stores.lookup(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-        );
-        match res {
-            Ok(ov) => {
-                let val = format!("{val2}{val1}{val3}");
-                assert_eq!(ov.body, val.as_bytes().to_vec())
-            }
-            Err(_) => panic!("should have been OK"),
+    }
+
+    /// A handle to these stores whose operations, and those of its clones, are made for the
+    /// downstream request `request`.
+    ///
+    /// Writes through the handle have a [`ValueOrigin::Runtime`] origin with the request's ID,
+    /// and the values they leave record the request as their
+    /// [`written_by`][ObjectValue::written_by]. Every [`KvEvent`] for an operation made through
+    /// the handle names the request too.
+    pub fn with_request(&self, request: KvRequest) -> ObjectStores {
+        ObjectStores {
+            origin: ValueOrigin::Runtime {
+                req_id: Some(request.req_id),
+            },
+            request: Some(Arc::new(request)),
+            ..self.clone()
         }
+    }
 
-        // overwrite val3
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val3.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            Some(val2.as_bytes().to_vec()),
-            None,
-        );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
+    /// A handle to these stores whose `generation` checks, and those of its clones, are made as
+    /// the guest ABIs need them: against the [saturated][ObjectValue::abi_generation] generations
+    /// guests are given, rather than the whole generation. A `generation` of `u32::MAX` matches
+    /// any value whose generation is at least that, and smaller ones match as before.
+    pub fn with_abi_generations(&self) -> ObjectStores {
+        ObjectStores {
+            abi_generations: true,
+            ..self.clone()
         }
+    }
 
-        // test overwrite
-        let res = stores.lookup(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-        );
-        match res {
-            Ok(ov) => {
-                assert_eq!(ov.body, val3.as_bytes().to_vec());
-                assert_eq!(ov.metadata, val2.as_bytes().to_vec());
-            }
-            Err(_) => panic!("should have been OK"),
+    /// Note that a guest is being given the generation of `key` in `store`, warning, once per key,
+    /// if it is past what the guest ABIs can give and so is given saturated.
+    pub(crate) fn note_abi_generation(
+        &self,
+        store: &ObjectStoreKey,
+        key: &ObjectKey,
+        generation: u64,
+    ) {
+        self.saturated.note(store, key, generation);
+    }
+
+    /// A copy-on-write view of these stores, whose writes no other handle sees.
+    ///
+    /// Reads through the view fall through to these stores, and its writes land in the view
+    /// alone, to be dropped along with it. A key is copied into the view the first time it is
+    /// used there, and a whole store the first time it is listed, counted, or deleted from by
+    /// prefix; from then on, the view sees only its own writes to them. Deletes in the view mask
+    /// the values these stores hold, and `generation` checks are made against the values copied
+    /// from them.
+    ///
+    /// Settings, observers, statistics, and concurrency limits are shared with these stores. Key
+    /// filters are not used by the view, as they can't see its writes.
+    pub fn isolated(&self) -> ObjectStores {
+        ObjectStores {
+            stores: Arc::default(),
+            filters: Arc::default(),
+            overlay: Some(Arc::new(Overlay::new(self.clone()))),
+            ..self.clone()
         }
     }
 
-    #[test]
-    fn test_kv_store_item_insert_generation() {
-        let stores = ObjectStores::default();
-        stores
-            .insert_empty_store(ObjectStoreKey(STORE_NAME.to_string()))
-            .unwrap();
+    /// Whether these stores are an [isolated][Self::isolated] view of others.
+    pub fn is_isolated(&self) -> bool {
+        self.overlay.is_some()
+    }
 
-        let key = "insert_key".to_string();
-        let val1 = "val1".to_string();
+    /// Take over `key` of a store from the stores this is a view of, or every key of it if there
+    /// is none. Does nothing unless this is an [isolated][Self::isolated] view.
+    fn take_over(
+        &self,
+        obj_store_key: &ObjectStoreKey,
+        key: Option<&ObjectKey>,
+    ) -> Result<(), KvStoreError> {
+        let Some(overlay) = &self.overlay else {
+            return Ok(());
+        };
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        overlay.take_over(&mut stores, obj_store_key, key)
+    }
 
-        // insert val1
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            None,
-            None,
-        );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
+    /// As [`take_over`][Self::take_over], against stores the caller holds the write lock for.
+    fn take_over_locked(
+        &self,
+        stores: &mut StoreMap,
+        obj_store_key: &ObjectStoreKey,
+        key: Option<&ObjectKey>,
+    ) -> Result<(), KvStoreError> {
+        match &self.overlay {
+            Some(overlay) => overlay.take_over(stores, obj_store_key, key),
+            None => Ok(()),
         }
+    }
 
-        // test overwrite, get gen
-        let generation;
+    /// Take over every store from the stores this is a view of, if it is one.
+    fn take_over_all(&self) -> Result<(), KvStoreError> {
+        let Some(overlay) = &self.overlay else {
+            return Ok(());
+        };
+        let store_keys = overlay.base_store_keys()?;
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        for obj_store_key in &store_keys {
+            overlay.take_over(&mut stores, obj_store_key, None)?;
+        }
+        Ok(())
+    }
+
+    /// Configure a store, replacing whatever configuration it had. The store's contents are left
+    /// alone, and it is not created if it doesn't exist yet; its configuration applies once it
+    /// is.
+    pub fn configure_store(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        config: StoreConfig,
+    ) -> Result<(), ObjectStoreError> {
+        let mut limiters = self
+            .limiters
+            .write()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        match config.max_concurrent_operations {
+            Some(max) => limiters.insert(
+                obj_store_key.clone(),
+                Arc::new(StoreLimiter::new(max, config.max_queued_operations)),
+            ),
+            None => limiters.remove(&obj_store_key),
+        };
+        // the stores are locked first, as writes do, and the filter built and installed under that
+        // lock, so no write can slip in between
+        let stores = self
+            .stores
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        let mut filters = self
+            .filters
+            .write()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        if config.key_filter {
+            let filter = KeyFilter::new(stores.get(&obj_store_key));
+            filters.insert(obj_store_key.clone(), Arc::new(filter));
+        } else {
+            filters.remove(&obj_store_key);
+        }
+        drop((filters, stores));
+        self.settings
+            .write()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .insert(obj_store_key, config);
+        Ok(())
+    }
+
+    /// Take an operation's turn to run against a store, under its `max_concurrent_operations`
+    /// limit if it has one.
+    ///
+    /// The operation may run now if the store has a free permit, or must wait for one otherwise.
+    /// If the store's queue is already full, it is not to run at all, and
+    /// [`KvStoreError::TooManyRequests`] is returned instead.
+    pub(crate) fn take_turn(&self, obj_store_key: &ObjectStoreKey) -> Result<Turn, KvStoreError> {
+        let limiter = self
+            .limiters
+            .read()
+            .map_err(|_| KvStoreError::InternalError)?
+            .get(obj_store_key)
+            .cloned();
+        match limiter {
+            Some(limiter) => limiter.try_acquire(),
+            None => Ok(Turn::Now(None)),
+        }
+    }
+
+    /// The number of guest operations currently waiting for their turn to run against a store.
+    pub fn queued_operations(&self, obj_store_key: &str) -> usize {
+        self.limiters
+            .read()
+            .ok()
+            .and_then(|limiters| limiters.get(obj_store_key).map(|limiter| limiter.queued()))
+            .unwrap_or(0)
+    }
+
+    /// The key filter for a store, if it is configured with one and these aren't an
+    /// [isolated][Self::isolated] view.
+    fn filter(&self, obj_store_key: &ObjectStoreKey) -> Option<Arc<KeyFilter>> {
+        if self.overlay.is_some() {
+            return None;
+        }
+        self.filters.read().ok()?.get(obj_store_key).cloned()
+    }
+
+    /// A store's configuration, if it has been configured.
+    pub fn store_config(&self, obj_store_key: &str) -> Option<StoreConfig> {
+        self.setting(obj_store_key, StoreConfig::clone)
+    }
+
+    /// Read from a store's configuration under the settings lock, without copying the rest of it.
+    ///
+    /// Settings are only ever written a field at a time, so a poisoned lock still guards a
+    /// consistent configuration, and is recovered rather than taken for a missing one, which would
+    /// make a read-only store writable.
+    fn setting<T>(&self, obj_store_key: &str, read: impl FnOnce(&StoreConfig) -> T) -> Option<T> {
+        self.settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(obj_store_key)
+            .map(read)
+    }
+
+    /// Whether a store was marked `sensitive` in configuration.
+    pub fn is_sensitive(&self, obj_store_key: &str) -> bool {
+        match self.settings.read() {
+            Ok(settings) => settings.get(obj_store_key).is_some_and(|s| s.sensitive),
+            // fail closed
+            Err(_) => true,
+        }
+    }
+
+    /// The form a store's `metadata_format` setting requires of the metadata written to it, if it
+    /// has one.
+    pub fn metadata_format(&self, obj_store_key: &str) -> Option<MetadataFormat> {
+        self.setting(obj_store_key, |s| s.metadata_format)?
+    }
+
+    /// The largest value, in bytes, that may be written to a store: its `max_value_size` setting,
+    /// or production's limit if it has none.
+    pub fn max_value_size(&self, obj_store_key: &str) -> usize {
+        self.setting(obj_store_key, |s| s.max_value_size)
+            .flatten()
+            .unwrap_or(KV_STORE_VALUE_MAX_LEN)
+    }
+
+    /// The largest metadata, in bytes, that may be written to a store: its `max_metadata_size`
+    /// setting, or production's limit if it has none.
+    pub fn max_metadata_size(&self, obj_store_key: &str) -> usize {
+        self.setting(obj_store_key, |s| s.max_metadata_size)
+            .flatten()
+            .unwrap_or(KV_STORE_METADATA_MAX_LEN)
+    }
+
+    /// The limits a store holds writes to: its own settings, and production's limits for the rest.
+    /// The longest key is what its `key_prefix`, which counts toward the limit, leaves of it.
+    pub fn limits(&self, obj_store_key: &str) -> StoreLimits {
+        let defaults = StoreLimits::default();
+        let (max_value_size, max_metadata_size, max_key_len) = self
+            .setting(obj_store_key, |s| {
+                (
+                    s.max_value_size,
+                    s.max_metadata_size,
+                    max_key_len(s.key_prefix.as_deref().unwrap_or_default()),
+                )
+            })
+            .unwrap_or((None, None, defaults.max_key_len));
+        StoreLimits {
+            max_value_size: max_value_size.unwrap_or(defaults.max_value_size),
+            max_metadata_size: max_metadata_size.unwrap_or(defaults.max_metadata_size),
+            max_key_len,
+        }
+    }
+
+    /// The number of keys a store may hold, if its `max_keys` setting limits them.
+    pub fn max_keys(&self, obj_store_key: &str) -> Option<usize> {
+        self.setting(obj_store_key, |s| s.max_keys)?
+    }
+
+    /// Limit the number of keys a store may hold, or lift the limit with `None`, leaving its other
+    /// settings alone.
+    ///
+    /// Once a store holds `max_keys` live keys, inserts that would add another fail with
+    /// [`KvStoreError::TooManyKeys`]. Writes to keys it already holds, deletes, and lists carry on
+    /// as before. Lowering the limit below the number of keys already held removes none of them.
+    pub fn set_max_keys(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        max_keys: Option<usize>,
+    ) -> Result<(), ObjectStoreError> {
+        self.settings
+            .write()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .entry(obj_store_key)
+            .or_default()
+            .max_keys = max_keys;
+        Ok(())
+    }
+
+    /// Whether a store is `read_only`, refusing inserts and deletes.
+    pub fn is_read_only(&self, obj_store_key: &str) -> bool {
+        self.setting(obj_store_key, |s| s.read_only)
+            .unwrap_or(false)
+    }
+
+    /// Make a store read-only, or writable again, leaving its other settings alone.
+    ///
+    /// While a store is read-only, [`insert`][Self::insert] and [`delete`][Self::delete] fail with
+    /// [`KvStoreError::BadRequest`], and lookups and lists carry on as before. Seeding, imports,
+    /// and transactions, which only embedders make, still write to it.
+    pub fn set_read_only(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        read_only: bool,
+    ) -> Result<(), ObjectStoreError> {
+        self.settings
+            .write()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .entry(obj_store_key)
+            .or_default()
+            .read_only = read_only;
+        Ok(())
+    }
+
+    /// The size past which a store's `warn_value_size` setting has values that grow logged.
+    pub fn warn_value_size(&self, obj_store_key: &str) -> Option<usize> {
+        self.setting(obj_store_key, |s| s.warn_value_size)?
+    }
+
+    /// The time-to-live a store's `default_ttl` setting gives inserts that don't specify one.
+    pub fn default_ttl(&self, obj_store_key: &str) -> Option<Duration> {
+        self.setting(obj_store_key, |s| s.default_ttl)?
+    }
+
+    /// The key validation profile a store's `key_validation` setting gives every API, if it has
+    /// one.
+    pub fn key_validation(&self, obj_store_key: &str) -> Option<KeyValidationProfile> {
+        self.setting(obj_store_key, |s| s.key_validation)?
+    }
+
+    /// The prefix a store's `key_prefix` setting puts before the keys guests give it, if it has
+    /// one.
+    pub fn key_prefix(&self, obj_store_key: &str) -> Option<String> {
+        self.setting(obj_store_key, |s| s.key_prefix.clone())?
+    }
+
+    /// The profile that the keys and prefixes given to a store are checked against, unless they
+    /// come from a guest API with rules of its own: the store's `key_validation` setting, or the
+    /// KV store's rules.
+    fn key_profile(&self, obj_store_key: &str) -> KeyValidationProfile {
+        self.key_validation(obj_store_key).unwrap_or_default()
+    }
+
+    /// Copy the contents of every store, redacting sensitive stores as requested.
+    ///
+    /// Expired values are left out.
+    pub fn export(&self, options: impl Into<ExportOptions>) -> Result<KvExport, ObjectStoreError> {
+        let options = options.into();
+        self.take_over_all()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        let stores = self
+            .stores
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        let now = self.clock.now();
+
+        let mut export = KvExport::default();
+        for (store_key, store) in stores.iter() {
+            let sensitive = self.is_sensitive(store_key.as_str());
+            let items = store
+                .iter()
+                .filter(|(_, val)| !val.is_expired(now))
+                .map(|(key, val)| (key.to_string(), ExportedValue::new(val, sensitive, options)))
+                .collect();
+            export
+                .stores
+                .insert(store_key.to_string(), ExportedStore { sensitive, items });
+        }
+        Ok(export)
+    }
+
+    /// Like [`export`][Self::export], but only for the given keys of each store named in `keys`.
+    ///
+    /// Keys with no live value are left out; stores are included even if none of their keys are.
+    pub fn export_keys(
+        &self,
+        options: impl Into<ExportOptions>,
+        keys: &BTreeMap<String, BTreeSet<String>>,
+    ) -> Result<KvExport, ObjectStoreError> {
+        let options = options.into();
+        self.take_over_all()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        let stores = self
+            .stores
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        let now = self.clock.now();
+
+        let mut export = KvExport::default();
+        for (store_key, store_keys) in keys {
+            let sensitive = self.is_sensitive(store_key);
+            let items = match stores.get(store_key.as_str()) {
+                Some(store) => store_keys
+                    .iter()
+                    .filter_map(|key| Some((key, store.get(key.as_str())?)))
+                    .filter(|(_, val)| !val.is_expired(now))
+                    .map(|(key, val)| (key.clone(), ExportedValue::new(val, sensitive, options)))
+                    .collect(),
+                None => BTreeMap::new(),
+            };
+            export
+                .stores
+                .insert(store_key.clone(), ExportedStore { sensitive, items });
+        }
+        Ok(export)
+    }
+
+    /// Write the values in `export` to these stores, creating any stores that don't exist yet.
+    ///
+    /// Exports of the current [format version][KV_EXPORT_FORMAT_VERSION] and the one before it are
+    /// accepted. Each value is written as a new one, overwriting any value already under its key:
+    /// generations, creation and last-modified times, origins, and the requests that wrote values
+    /// are not carried over, and nor is whether a store was marked sensitive. Nothing is written
+    /// if any value was redacted, or any store name or key is invalid, and the stores are written
+    /// as one, so no reader sees them partly imported.
+    ///
+    /// Returns the number of values written.
+    pub fn import(&self, export: &KvExport) -> Result<usize, ObjectStoreError> {
+        match export.version {
+            // version 1 differs only in having no version field, which parsing fills in
+            1 | KV_EXPORT_FORMAT_VERSION => {}
+            v => return Err(ObjectStoreError::UnsupportedExportVersion(v)),
+        }
+
+        let mut store_keys = Vec::new();
+        let mut values = Vec::new();
+        for (store_name, store) in &export.stores {
+            let store_key = ObjectStoreKey::new(store_name)?;
+            for (key, val) in &store.items {
+                let invalid = |what: &str| {
+                    ObjectStoreError::InvalidExport(format!(
+                        "{what} for key {key:?} in store {store_name:?}"
+                    ))
+                };
+                let obj_key = ObjectKey::new_with_profile(key, self.key_profile(store_name))
+                    .map_err(|e| invalid(&e.to_string()))?;
+                let body = val.body.decode().ok_or_else(|| invalid("redacted body"))?;
+                let metadata = val
+                    .metadata
+                    .decode()
+                    .ok_or_else(|| invalid("redacted metadata"))?;
+                values.push((store_key.clone(), obj_key, body, metadata));
+            }
+            store_keys.push(store_key);
+        }
+
+        let count = values.len();
+        self.transaction(|txn| {
+            for store_key in store_keys {
+                txn.create_store(store_key);
+            }
+            for (store_key, obj_key, body, metadata) in values {
+                let options = SeedOptions {
+                    metadata: Some(metadata),
+                    ..SeedOptions::default()
+                };
+                txn.insert_seeded(store_key, obj_key, body, options)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| match e {
+            KvStoreError::InternalError => ObjectStoreError::PoisonedLock,
+            e => ObjectStoreError::InvalidExport(e.to_string()),
+        })?;
+        Ok(count)
+    }
+
+    /// The key of the store named `name`, if it exists, shared with the store map.
+    ///
+    /// A name that no store could have is an [`ObjectStoreError::InvalidStoreName`], rather than
+    /// just a store that doesn't exist.
+    pub(crate) fn store_key(&self, name: &str) -> Result<Option<ObjectStoreKey>, ObjectStoreError> {
+        is_valid_store_name(name)?;
+        let key = self
+            .stores
+            .read()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?
+            .get_key_value(name)
+            .map(|(key, _)| key.clone());
+        match (key, &self.overlay) {
+            (None, Some(overlay)) => overlay.base.store_key(name),
+            (key, _) => Ok(key),
+        }
+    }
+
+    pub fn lookup(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> Result<ObjectValue, KvStoreError> {
+        self.lookup_with_expiry(obj_store_key, obj_key).0
+    }
+
+    /// As [`lookup`][Self::lookup], along with whether a miss was because the key's value had
+    /// expired, rather than because it was never written or was deleted. Either miss is
+    /// `NotFound`.
+    pub(crate) fn lookup_with_expiry(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> (Result<ObjectValue, KvStoreError>, bool) {
+        let timer = self.latencies.time(&obj_store_key, KvOpKind::Lookup);
+        if self.observers.is_empty() {
+            return self.lookup_inner(obj_store_key, obj_key);
+        }
+
+        let (res, expired) = self.lookup_inner(obj_store_key.clone(), obj_key.clone());
+        drop(timer);
+        self.observers.notify(&KvEvent {
+            store: obj_store_key.as_str(),
+            request: self.request.as_deref(),
+            op: KvOp::Lookup {
+                key: obj_key.as_str(),
+                result: res.as_ref(),
+                expired,
+            },
+        });
+        (res, expired)
+    }
+
+    /// The metadata, generation, and body length of a key's value, without reading or copying its
+    /// body.
+    ///
+    /// This misses as [`lookup`][Self::lookup] does, but leaves an expired value in place for the
+    /// next lookup or write to remove. Observers see it as a [`KvOp::Head`], and its latency is
+    /// recorded as a lookup's.
+    pub fn head(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> Result<ObjectHead, KvStoreError> {
+        self.head_with_expiry(obj_store_key, obj_key).0
+    }
+
+    /// As [`head`][Self::head], along with whether a miss was because the key's value had
+    /// expired.
+    pub(crate) fn head_with_expiry(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> (Result<ObjectHead, KvStoreError>, bool) {
+        let timer = self.latencies.time(&obj_store_key, KvOpKind::Lookup);
+        if self.observers.is_empty() {
+            return self.head_inner(&obj_store_key, &obj_key);
+        }
+
+        let (res, expired) = self.head_inner(&obj_store_key, &obj_key);
+        drop(timer);
+        self.observers.notify(&KvEvent {
+            store: obj_store_key.as_str(),
+            request: self.request.as_deref(),
+            op: KvOp::Head {
+                key: obj_key.as_str(),
+                result: res.as_ref(),
+                expired,
+            },
+        });
+        (res, expired)
+    }
+
+    /// The body of [`head_with_expiry`][Self::head_with_expiry].
+    fn head_inner(
+        &self,
+        obj_store_key: &ObjectStoreKey,
+        obj_key: &ObjectKey,
+    ) -> (Result<ObjectHead, KvStoreError>, bool) {
+        let filter = self.filter(obj_store_key);
+        if let Some(e) = filter.and_then(|filter| filter.certain_miss(obj_key)) {
+            return (Err(e), false);
+        }
+        if let Err(e) = self.take_over(obj_store_key, Some(obj_key)) {
+            return (Err(e), false);
+        }
+        let Ok(stores) = self.stores.read() else {
+            return (Err(KvStoreError::InternalError), false);
+        };
+        let Some(store) = stores.get(obj_store_key) else {
+            return (Err(KvStoreError::Uninitialized), false);
+        };
+        match store.get(obj_key) {
+            Some(val) if val.is_expired(self.clock.now()) => (Err(KvStoreError::NotFound), true),
+            Some(val) => (Ok(val.into()), false),
+            None => (Err(KvStoreError::NotFound), false),
+        }
+    }
+
+    /// Wait until a key has been written, and return its value.
+    ///
+    /// Returns at once if the key is already present. Otherwise, this waits for a write to the key
+    /// through any handle to these stores, without polling, and fails with
+    /// [`AwaitKeyError::TimedOut`] if none arrives within `timeout`. It is meant for embedders,
+    /// such as test harnesses, that need one request to see a write made by another.
+    ///
+    /// Waiting doesn't count as a lookup: observers aren't notified of it, and it has no latency
+    /// recorded.
+    pub async fn await_key(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        timeout: Duration,
+    ) -> Result<ObjectValue, AwaitKeyError> {
+        let waiter = Arc::new(KeyWaiter::new(obj_store_key.clone(), obj_key.clone()));
+        // registered before the first lookup, so a write between the two isn't missed
+        let _registered = Registered::new(&self.observers, waiter.clone());
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            match self.lookup_inner(obj_store_key.clone(), obj_key.clone()).0 {
+                Ok(value) => return Ok(value),
+                Err(KvStoreError::NotFound | KvStoreError::Uninitialized) => {}
+                Err(e) => return Err(e.into()),
+            }
+            if tokio::time::timeout_at(deadline, waiter.written.notified())
+                .await
+                .is_err()
+            {
+                return Err(AwaitKeyError::TimedOut(timeout));
+            }
+        }
+    }
+
+    /// As [`await_key`][Self::await_key], but blocking the current thread until the key is
+    /// written, for embedder tests that have no async runtime of their own.
+    ///
+    /// Every other method of `ObjectStores` is already synchronous, and can be called from any
+    /// thread. This one runs the wait on a small runtime of its own, which is only safe outside of
+    /// an async context: blocking a runtime's thread could keep the write being waited for from
+    /// ever running. Rather than risk that, it fails at once with
+    /// [`AwaitKeyError::InAsyncContext`] when called from within a Tokio runtime, including from
+    /// its blocking threads.
+    pub fn blocking_await_key(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        timeout: Duration,
+    ) -> Result<ObjectValue, AwaitKeyError> {
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return Err(AwaitKeyError::InAsyncContext);
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .map_err(|e| {
+                warn!("failed to start a runtime for a blocking KV wait: {e}");
+                KvStoreError::InternalError
+            })?;
+        runtime.block_on(self.await_key(obj_store_key, obj_key, timeout))
+    }
+
+    /// The body of [`lookup_with_expiry`][Self::lookup_with_expiry].
+    fn lookup_inner(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> (Result<ObjectValue, KvStoreError>, bool) {
+        let filter = self.filter(&obj_store_key);
+        if let Some(e) = filter.and_then(|filter| filter.certain_miss(&obj_key)) {
+            return (Err(e), false);
+        }
+        if let Err(e) = self.take_over(&obj_store_key, Some(&obj_key)) {
+            return (Err(e), false);
+        }
+        {
+            let Ok(stores) = self.stores.read() else {
+                return (Err(KvStoreError::InternalError), false);
+            };
+            let Some(store) = stores.get(&obj_store_key) else {
+                return (Err(KvStoreError::Uninitialized), false);
+            };
+            match store.get(&obj_key) {
+                Some(val) if !val.is_expired(self.clock.now()) => {
+                    return (val.clone().decompressed(), false)
+                }
+                Some(_) => {}
+                None => return (Err(KvStoreError::NotFound), false),
+            }
+        }
+
+        // The value has expired, so evict it. Only lookups that find an expired value take the
+        // write lock, and the value may have been rewritten before it was taken, so it is looked
+        // at afresh.
+        let Ok(mut stores) = self.stores.write() else {
+            return (Err(KvStoreError::InternalError), false);
+        };
+        match stores.get_mut(&obj_store_key) {
+            Some(store) => looked_up_value(store, &obj_key, self.clock.now()),
+            None => (Err(KvStoreError::Uninitialized), false),
+        }
+    }
+
+    /// Create a store holding `values`, each a key, body, optional metadata, and optional
+    /// generation, or add them to it if it already exists.
+    ///
+    /// The values are written as [`KvInsertMode::Overwrite`] inserts with no TTL of their own, in
+    /// one [transaction][Self::transaction], so no reader can see the store before all of them are
+    /// in it.
+    ///
+    /// A value given a generation keeps it exactly, rather than being given the next one, and
+    /// every later write is given a greater generation, so it can't be confused with a seeded one.
+    /// Seeded generations should be unique to a store, and only given to keys that don't already
+    /// hold a greater one.
+    ///
+    /// A value's creation and last-modified times are the time it was seeded, unless given. When
+    /// only one is given, the other is the same, as for a value written once.
+    pub fn create_store(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        values: impl IntoIterator<Item = (ObjectKey, Vec<u8>, SeedOptions)>,
+    ) -> Result<(), KvStoreError> {
+        self.transaction(|txn| {
+            txn.create_store(obj_store_key.clone());
+            for (obj_key, body, options) in values {
+                txn.insert_seeded(obj_store_key.clone(), obj_key, body, options)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Write fully-formed values to a store under a single acquisition of the write lock,
+    /// creating the store if it doesn't exist, and return how many were written.
+    ///
+    /// Unlike [`insert`][Self::insert], each value is installed as it is given, replacing whatever
+    /// the key held: its metadata, generation, expiry, creation and last-modified times, origin,
+    /// and writer are all kept, so fixtures taken from elsewhere can be reproduced exactly. The
+    /// exceptions are a generation of zero, which is given the next one instead, and a missing
+    /// expiry in a store with a [`default_ttl`][Self::default_ttl], which is given that TTL from
+    /// now. Kept generations move the generation source past them, so later writes are given
+    /// greater ones; they should be unique to the store, as for [`create_store`][Self::create_store].
+    ///
+    /// Every value is checked against the store's [`max_value_size`][Self::max_value_size],
+    /// [`max_metadata_size`][Self::max_metadata_size], and [`max_keys`][Self::max_keys] before
+    /// any is written, so either all of them are written or, failing with
+    /// [`KvStoreError::PayloadTooLarge`] or [`KvStoreError::TooManyKeys`], none are. They are held
+    /// to the [memory budget][Self::set_memory_budget] together in the same way.
+    ///
+    /// Observers see each value written as an overwrite, though the values aren't counted in the
+    /// [insert stats][Self::insert_stats], as no guest wrote them.
+    pub fn insert_many(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        values: impl IntoIterator<Item = (ObjectKey, ObjectValue)>,
+    ) -> Result<usize, KvStoreError> {
+        let values = values
+            .into_iter()
+            .map(|(obj_key, val)| Ok((obj_key, val.decompressed()?)))
+            .collect::<Result<Vec<_>, KvStoreError>>()?;
+        let config = self
+            .store_config(obj_store_key.as_str())
+            .unwrap_or_default();
+        let max = config.max_value_size.unwrap_or(KV_STORE_VALUE_MAX_LEN);
+        if let Some((obj_key, val)) = values.iter().find(|(_, val)| val.body.len() > max) {
+            warn!(
+                "cannot insert {:?}: {} bytes is over the limit of {max}",
+                obj_key.as_str(),
+                val.body.len(),
+            );
+            return Err(KvStoreError::PayloadTooLarge);
+        }
+        let max = config
+            .max_metadata_size
+            .unwrap_or(KV_STORE_METADATA_MAX_LEN);
+        if let Some((obj_key, val)) = values.iter().find(|(_, val)| val.metadata.len() > max) {
+            warn!(
+                "cannot insert {:?}: {} bytes of metadata is over the limit of {max}",
+                obj_key.as_str(),
+                val.metadata.len(),
+            );
+            return Err(KvStoreError::PayloadTooLarge);
+        }
+
+        let now = self.clock.now();
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        // a store with a key limit is taken over whole, so the keys it holds can be counted
+        if config.max_keys.is_some() {
+            self.take_over_locked(&mut stores, &obj_store_key, None)?;
+        } else {
+            for (obj_key, _) in &values {
+                self.take_over_locked(&mut stores, &obj_store_key, Some(obj_key))?;
+            }
+        }
+
+        if let Some(max) = config.max_keys {
+            // expired values don't count against the limit
+            if let Some(store) = stores.get_mut(&obj_store_key) {
+                store.retain(|_, val| !val.is_expired(now));
+            }
+            let store = stores.get(&obj_store_key);
+            let added = values
+                .iter()
+                .map(|(obj_key, _)| obj_key)
+                .filter(|obj_key| !store.is_some_and(|store| store.contains_key(*obj_key)))
+                .collect::<BTreeSet<_>>()
+                .len();
+            let len = store.map_or(0, |store| store.len()) + added;
+            if len > max {
+                warn!(
+                    "cannot insert {} values: store {:?} would hold {len} keys, over its limit \
+                     of {max}",
+                    values.len(),
+                    obj_store_key.as_str()
+                );
+                return Err(KvStoreError::TooManyKeys);
+            }
+        }
+
+        // Kept generations are taken out of circulation first, so none can be given to a value
+        // that needs the next one.
+        for (_, val) in &values {
+            if val.generation != 0 {
+                self.generations.advance_past(val.generation);
+            }
+        }
+
+        let count = values.len();
+        let values = values
+            .into_iter()
+            .map(|(obj_key, mut val)| {
+                if val.generation == 0 {
+                    val.generation = self.next_generation();
+                }
+                if val.expiration.is_none() {
+                    val.expiration = config.default_ttl.map(|ttl| now + ttl);
+                }
+                val.body_len = val.body.len();
+                let observed = (!self.observers.is_empty()).then(|| val.clone());
+                (obj_key, val.compressed(config.compression), observed)
+            })
+            .collect::<Vec<_>>();
+
+        // a key given more than once holds only its last value
+        let incoming = values
+            .iter()
+            .map(|(obj_key, val, _)| (obj_key.clone(), val.held_bytes()))
+            .collect::<BTreeMap<_, _>>();
+        let replaced = stores.get(&obj_store_key).map_or(0, |store| {
+            incoming
+                .keys()
+                .filter_map(|obj_key| store.get(obj_key))
+                .map(ObjectValue::held_bytes)
+                .sum::<usize>()
+        });
+        let needed = held_bytes(&stores) - replaced + incoming.values().sum::<usize>();
+        self.make_room(&mut stores, needed, |store, key| {
+            *store == obj_store_key && incoming.contains_key(key)
+        })?;
+
+        let mut written = Vec::new();
+        let store = stores.entry(obj_store_key.clone()).or_default();
+        for (obj_key, val, observed) in values {
+            if let Some(observed) = observed {
+                written.push((obj_key.clone(), observed));
+            }
+            store.insert(obj_key, val);
+        }
+        if let Some(filter) = self.filter(&obj_store_key) {
+            filter.rebuild(stores.get(&obj_store_key));
+        }
+        drop(stores);
+
+        for (obj_key, val) in &written {
+            self.observers.notify(&KvEvent {
+                store: obj_store_key.as_str(),
+                request: self.request.as_deref(),
+                op: KvOp::Insert {
+                    key: obj_key.as_str(),
+                    body: &val.body,
+                    mode: KvInsertMode::Overwrite,
+                    generation: None,
+                    metadata: (!val.metadata.is_empty()).then_some(&val.metadata[..]),
+                    ttl: val
+                        .expiration
+                        .and_then(|expiration| expiration.duration_since(now).ok()),
+                    result: Ok(()),
+                    lost: false,
+                },
+            });
+        }
+        Ok(count)
+    }
+
+    /// Create a store with no values in it, if it doesn't already exist.
+    pub fn insert_empty_store(
+        &self,
+        obj_store_key: ObjectStoreKey,
+    ) -> Result<(), ObjectStoreError> {
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| ObjectStoreError::PoisonedLock)?;
+        stores.entry(obj_store_key.clone()).or_default();
+        if let Some(filter) = self.filter(&obj_store_key) {
+            filter.created();
+        }
+
+        Ok(())
+    }
+
+    /// Write a value to a store.
+    ///
+    /// The value expires after `ttl` if one is given, and otherwise after the store's
+    /// [`default_ttl`][Self::default_ttl], if it has one. As in production, there is no way for an
+    /// insert to opt out of a store's default. Every write sets the expiry afresh, including
+    /// appends and prepends, which do not inherit the expiry of the value they extend.
+    ///
+    /// A zero `ttl` is rejected with [`KvStoreError::BadRequest`] rather than read as immediate or
+    /// no expiry, since a computed TTL that rounds down to zero is almost always a bug in the
+    /// guest. Store defaults cannot be zero either, as configuration rejects them.
+    ///
+    /// A write that would leave a value larger than the store's
+    /// [`max_value_size`][Self::max_value_size] fails with [`KvStoreError::PayloadTooLarge`],
+    /// including an `Append` or `Prepend` that is small itself but takes the value over. So does
+    /// one given metadata larger than the store's
+    /// [`max_metadata_size`][Self::max_metadata_size].
+    ///
+    /// A store that is [read-only][Self::set_read_only] refuses every insert with
+    /// [`KvStoreError::BadRequest`], and one with a [`metadata_format`][Self::metadata_format]
+    /// refuses metadata that doesn't take that form in the same way.
+    ///
+    /// A write that would take the stores over their [memory budget][Self::set_memory_budget]
+    /// fails with [`KvStoreError::PayloadTooLarge`], or evicts older values to make room, as the
+    /// budget's policy says.
+    ///
+    /// A value that has expired is treated as missing even before it has been evicted: `Add`
+    /// succeeds, `Append` and `Prepend` start a new value, and a `generation` can only match a
+    /// value that is still live, failing with [`KvStoreError::PreconditionFailed`] otherwise. The
+    /// whole `generation` is matched, so the saturated one guests are given cannot match a value
+    /// whose generation is larger, unless the handle was made
+    /// [`with_abi_generations`][Self::with_abi_generations].
+    ///
+    /// A `generation` of zero, which no value is ever given, instead makes the write create-only:
+    /// it fails with [`KvStoreError::PreconditionFailed`] if the key holds a live value. Combined
+    /// with `Append` or `Prepend`, which need a value to extend, it fails with
+    /// [`KvStoreError::BadRequest`].
+    ///
+    /// Returns the generation the written value was given, for use in a later `generation`
+    /// check without a racy lookup in between. An insert dropped by a [`LostWriteRule`] returns
+    /// the generation it would have given the value, though nothing was written.
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        obj: Vec<u8>,
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<Vec<u8>>,
+        ttl: Option<Duration>,
+    ) -> Result<u64, KvStoreError> {
+        let timer = self.latencies.time(&obj_store_key, KvOpKind::Insert);
+        if self.observers.is_empty() {
+            return self
+                .insert_inner(obj_store_key, obj_key, obj, mode, generation, metadata, ttl)
+                .map(|(generation, _)| generation);
+        }
+
+        let res = self.insert_inner(
+            obj_store_key.clone(),
+            obj_key.clone(),
+            obj.clone(),
+            mode,
+            generation,
+            metadata.clone(),
+            ttl,
+        );
+        drop(timer);
+        self.observers.notify(&KvEvent {
+            store: obj_store_key.as_str(),
+            request: self.request.as_deref(),
+            op: KvOp::Insert {
+                key: obj_key.as_str(),
+                body: &obj,
+                mode,
+                generation,
+                metadata: metadata.as_deref(),
+                ttl,
+                result: res.as_ref().map(|_| ()),
+                lost: matches!(res, Ok((_, true))),
+            },
+        });
+        res.map(|(generation, _)| generation)
+    }
+
+    /// Returns the generation the written value was given, and whether a [`LostWriteRule`] lost
+    /// the write.
+    #[allow(clippy::too_many_arguments)]
+    fn insert_inner(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        obj: Vec<u8>,
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<Vec<u8>>,
+        ttl: Option<Duration>,
+    ) -> Result<(u64, bool), KvStoreError> {
+        // the settings are read once, so they aren't locked again for every check
+        let config = self
+            .store_config(obj_store_key.as_str())
+            .unwrap_or_default();
+        check_writable(&obj_store_key, config.read_only, "insert")?;
+        // the existing value is read, checked, and replaced under a single write lock, so
+        // concurrent writers can't both pass a generation check or be handed the same generation
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        // a store with a key limit is taken over whole, so the keys it holds can be counted
+        let taken = match config.max_keys {
+            Some(_) => None,
+            None => Some(&obj_key),
+        };
+        self.take_over_locked(&mut stores, &obj_store_key, taken)?;
+        let obj_val = self.prepare_insert(
+            &mut stores,
+            &config,
+            &obj_store_key,
+            &obj_key,
+            obj,
+            mode,
+            generation,
+            metadata,
+            ttl,
+        )?;
+        let generation = obj_val.generation;
+        let len = obj_val.body_len;
+        // decided only once the insert has passed every check, so the guest sees a success
+        if self
+            .faults
+            .loses_write(obj_store_key.as_str(), obj_key.as_str())
+        {
+            debug!(
+                "losing the insert of {:?} into store {:?}, as a lost write rule says",
+                obj_key.as_str(),
+                obj_store_key.as_str()
+            );
+            return Ok((generation, true));
+        }
+
+        // any expired value under the key was evicted above, so only a live one is replaced
+        let replaced = stores
+            .get(&obj_store_key)
+            .and_then(|store| store.get(&obj_key))
+            .map_or(0, ObjectValue::held_bytes);
+        let needed = held_bytes(&stores) - replaced + obj_val.held_bytes();
+        self.make_room(&mut stores, needed, |store, key| {
+            *store == obj_store_key && *key == obj_key
+        })?;
+
+        match self.filter(&obj_store_key) {
+            None => {
+                stores
+                    .entry(obj_store_key.clone())
+                    .or_default()
+                    .insert(obj_key, obj_val);
+            }
+            Some(filter) => {
+                let store = stores.entry(obj_store_key.clone()).or_default();
+                store.insert(obj_key.clone(), obj_val);
+                // before the lock is released, so no lookup can miss the new key
+                filter.added(&obj_key, store);
+            }
+        }
+        self.insert_stats.record(&obj_store_key, mode, len);
+        Ok((generation, false))
+    }
+
+    /// The body of [`insert`][Self::insert], against stores the caller holds the write lock for.
+    /// Returns the generation the written value was given, and the length of its body.
+    #[allow(clippy::too_many_arguments)]
+    fn insert_locked(
+        &self,
+        stores: &mut StoreMap,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        obj: Vec<u8>,
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<Vec<u8>>,
+        ttl: Option<Duration>,
+    ) -> Result<(u64, usize), KvStoreError> {
+        let config = self
+            .store_config(obj_store_key.as_str())
+            .unwrap_or_default();
+        let obj_val = self.prepare_insert(
+            stores,
+            &config,
+            &obj_store_key,
+            &obj_key,
+            obj,
+            mode,
+            generation,
+            metadata,
+            ttl,
+        )?;
+        let generation = obj_val.generation;
+        let len = obj_val.body_len;
+        stores
+            .entry(obj_store_key)
+            .or_default()
+            .insert(obj_key, obj_val);
+        Ok((generation, len))
+    }
+
+    /// Check an insert against stores the caller holds the write lock for, and against `config`,
+    /// the store's settings, returning the value it would write without writing it.
+    #[allow(clippy::too_many_arguments)]
+    fn prepare_insert(
+        &self,
+        stores: &mut StoreMap,
+        config: &StoreConfig,
+        obj_store_key: &ObjectStoreKey,
+        obj_key: &ObjectKey,
+        obj: Vec<u8>,
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<Vec<u8>>,
+        ttl: Option<Duration>,
+    ) -> Result<ObjectValue, KvStoreError> {
+        if ttl == Some(Duration::ZERO) {
+            warn!("cannot insert {:?} with a TTL of zero", obj_key.as_str());
+            return Err(KvStoreError::BadRequest);
+        }
+        let ttl = ttl.or(config.default_ttl);
+
+        let now = self.clock.now();
+        let existing = match stores.get_mut(obj_store_key) {
+            Some(store) => live_value(store, obj_key, now),
+            // this insert would create the store
+            None => Err(KvStoreError::Uninitialized),
+        };
+
+        if let Err(KvStoreError::InternalError) = existing {
+            return Err(KvStoreError::InternalError);
+        }
+
+        // Expired values were dropped above, so every mode, and the generation check, treat them
+        // just as they would a missing value. Neither has a generation that could match.
+        match generation {
+            // No value is ever given generation zero, so matching it means creating the key.
+            // Extending a value that can't exist is a contradiction rather than a failed match.
+            Some(0) if matches!(mode, KvInsertMode::Append | KvInsertMode::Prepend) => {
+                warn!(
+                    "cannot insert {:?} as {mode:?} with a generation of zero",
+                    obj_key.as_str()
+                );
+                return Err(KvStoreError::BadRequest);
+            }
+            Some(0) if existing.is_ok() => return Err(KvStoreError::PreconditionFailed),
+            Some(0) => {}
+            Some(g) => {
+                let matched = |val: &ObjectValue| val.matches_generation(g, self.abi_generations);
+                if !existing.as_ref().is_ok_and(matched) {
+                    return Err(KvStoreError::PreconditionFailed);
+                }
+            }
+            None => {}
+        }
+
+        if existing.is_err() {
+            if let Some(max) = config.max_keys {
+                // expired values don't count against the limit
+                if let Some(store) = stores.get_mut(obj_store_key) {
+                    if store.len() >= max {
+                        store.retain(|_, val| !val.is_expired(now));
+                    }
+                }
+                let len = stores.get(obj_store_key).map_or(0, |store| store.len());
+                if len >= max {
+                    warn!(
+                        "cannot insert {:?}: store {:?} already holds its limit of {max} keys",
+                        obj_key.as_str(),
+                        obj_store_key.as_str()
+                    );
+                    return Err(KvStoreError::TooManyKeys);
+                }
+            }
+        }
+
+        let origin = match (mode, &existing) {
+            (KvInsertMode::Append | KvInsertMode::Prepend, Ok(v)) => {
+                v.origin.extended_by(self.origin)
+            }
+            _ => self.origin,
+        };
+
+        let previous_len = existing.as_ref().map_or(0, |v| v.body.len());
+
+        // Only metadata given with the write is checked, so that appending to a value whose
+        // metadata is over a lowered limit still works.
+        let max = config
+            .max_metadata_size
+            .unwrap_or(KV_STORE_METADATA_MAX_LEN);
+        if let Some(len) = metadata.as_ref().map(Vec::len).filter(|len| *len > max) {
+            warn!(
+                "cannot insert {:?}: {len} bytes of metadata is over the limit of {max}",
+                obj_key.as_str(),
+            );
+            return Err(KvStoreError::PayloadTooLarge);
+        }
+        if let (Some(format), Some(metadata)) = (config.metadata_format, &metadata) {
+            // the guest is only told the write was refused, so the log says why
+            if let Err(e) = format.check(metadata) {
+                warn!(
+                    "cannot insert {:?}: its metadata is not {format}: {e}",
+                    obj_key.as_str(),
+                );
+                return Err(KvStoreError::BadRequest);
+            }
+        }
+
+        // appending or prepending without new metadata keeps the metadata already there
+        let metadata = match (mode, &existing) {
+            (KvInsertMode::Append | KvInsertMode::Prepend, Ok(v)) if metadata.is_none() => {
+                Some(v.metadata.clone())
+            }
+            _ => metadata,
+        };
+
+        let extends = matches!(mode, KvInsertMode::Append | KvInsertMode::Prepend);
+        let out_len = match &existing {
+            // key exists, add fails
+            Ok(_) if mode == KvInsertMode::Add => return Err(KvStoreError::PreconditionFailed),
+            Ok(v) if extends => v.body.len() + obj.len(),
+            Err(KvStoreError::NotFound) => obj.len(),
+            Err(_) if extends => return Err(KvStoreError::InternalError),
+            _ => obj.len(),
+        };
+
+        // Checked against the value an append or prepend would leave behind, since a small write
+        // can still take a value over, but before that value is built, so an oversized one never
+        // is.
+        let max = config.max_value_size.unwrap_or(KV_STORE_VALUE_MAX_LEN);
+        if out_len > max {
+            warn!(
+                "cannot insert {:?}: {out_len} bytes is over the limit of {max}",
+                obj_key.as_str(),
+            );
+            return Err(KvStoreError::PayloadTooLarge);
+        }
+
+        if let Some(threshold) = config.warn_value_size {
+            // warned once as the value crosses the threshold, rather than on every write after
+            if out_len > threshold && previous_len <= threshold {
+                warn!(
+                    "KV value {:?} in store {:?} has grown to {out_len} bytes, past the \
+                     `warn_value_size` of {threshold}; is the guest appending where it meant to \
+                     overwrite?",
+                    obj_key.as_str(),
+                    obj_store_key.as_str(),
+                );
+            }
+        }
+
+        let created_at = existing.as_ref().map_or(now, |v| v.created_at);
+        let out_obj = match (mode, existing) {
+            (KvInsertMode::Append, Ok(v)) => {
+                let mut out_obj = Vec::with_capacity(out_len);
+                out_obj.extend_from_slice(&v.body);
+                out_obj.extend_from_slice(&obj);
+                out_obj
+            }
+            (KvInsertMode::Prepend, Ok(v)) => {
+                let mut out_obj = Vec::with_capacity(out_len);
+                out_obj.extend_from_slice(&obj);
+                out_obj.extend_from_slice(&v.body);
+                out_obj
+            }
+            _ => obj,
+        };
+
+        let generation = self.next_generation();
+        let mut obj_val = ObjectValue::new(out_obj)
+            .generation(generation)
+            .created_at(created_at)
+            .origin(origin)
+            .written_by(self.request.clone());
+        if let Some(m) = metadata {
+            obj_val = obj_val.metadata(m);
+        }
+        if let Some(ttl) = ttl {
+            obj_val = obj_val.ttl(ttl);
+        }
+        let obj_val = obj_val.build(now).compressed(config.compression);
+
+        Ok(obj_val)
+    }
+
+    /// Assign the next generation. Must be called with the write lock held, so that generations
+    /// are published in the order they are assigned.
+    ///
+    /// A generation of zero makes an insert create-only, so it is skipped should a source ever
+    /// hand it out.
+    fn next_generation(&self) -> u64 {
+        loop {
+            let generation = self.generations.next_generation();
+            if generation != 0 {
+                return generation;
+            }
+        }
+    }
+
+    /// Make sure the stores are within their [memory budget][Self::set_memory_budget] once a write
+    /// leaves them holding `needed` bytes, against stores the caller holds the write lock for.
+    ///
+    /// Over the budget, the write is refused with [`KvStoreError::PayloadTooLarge`], or, if the
+    /// budget's policy is to evict, the least recently written values other than those `keep` is
+    /// true for are evicted to make room for it. Nothing is evicted unless that makes enough.
+    fn make_room(
+        &self,
+        stores: &mut StoreMap,
+        mut needed: usize,
+        keep: impl Fn(&ObjectStoreKey, &ObjectKey) -> bool,
+    ) -> Result<(), KvStoreError> {
+        let Some(budget) = self.budget.get() else {
+            return Ok(());
+        };
+        if needed <= budget.max_bytes {
+            return Ok(());
+        }
+        if self.budget.trip() {
+            warn!(
+                "a write would take the KV stores to {needed} bytes, over their memory budget of \
+                 {} bytes; writes past it are {}",
+                budget.max_bytes,
+                match budget.policy {
+                    BudgetPolicy::Reject => "refused",
+                    BudgetPolicy::EvictOldest => "making room by evicting the oldest values",
+                }
+            );
+        }
+        if budget.policy == BudgetPolicy::Reject {
+            return Err(KvStoreError::PayloadTooLarge);
+        }
+
+        let oldest = itertools::kmerge_by(
+            stores.iter().map(|(store_key, store)| {
+                store
+                    .oldest()
+                    .map(move |written| (written, store_key, store))
+            }),
+            |(a, ..): &(&Written, _, _), (b, ..): &(&Written, _, _)| a < b,
+        );
+        let mut evicted = Vec::new();
+        for ((_, _, key), store_key, store) in oldest {
+            if needed <= budget.max_bytes {
+                break;
+            }
+            if keep(store_key, key) {
+                continue;
+            }
+            needed -= store.get(key).map_or(0, ObjectValue::held_bytes);
+            evicted.push((store_key.clone(), key.clone()));
+        }
+        if needed > budget.max_bytes {
+            warn!(
+                "cannot write {needed} bytes into the KV stores: even evicting every other value \
+                 leaves them over their memory budget of {} bytes",
+                budget.max_bytes
+            );
+            return Err(KvStoreError::PayloadTooLarge);
+        }
+
+        debug!(
+            "evicting {} KV values to stay within the memory budget",
+            evicted.len()
+        );
+        for (store_key, key) in evicted {
+            if let Some(store) = stores.get_mut(&store_key) {
+                store.remove(&key);
+                if let Some(filter) = self.filter(&store_key) {
+                    filter.deleted(store);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete a key from a store.
+    ///
+    /// Deletes are atomic: when several deletes of the same live key race, exactly one succeeds
+    /// and the rest fail with [`KvStoreError::NotFound`]. An expired key is removed but reported
+    /// as `NotFound`.
+    ///
+    /// If a `generation` is given, the key is only deleted if its value has that generation, and
+    /// is otherwise left alone, failing with [`KvStoreError::PreconditionFailed`]. It is matched
+    /// as [`insert`][Self::insert] matches it. A missing key is `NotFound` whatever the
+    /// generation.
+    ///
+    /// A store that is [read-only][Self::set_read_only] refuses every delete with
+    /// [`KvStoreError::BadRequest`], before the key is looked for.
+    pub fn delete(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        generation: Option<u64>,
+    ) -> Result<(), KvStoreError> {
+        let timer = self.latencies.time(&obj_store_key, KvOpKind::Delete);
+        if self.observers.is_empty() {
+            return self.delete_inner(obj_store_key, obj_key, generation);
+        }
+
+        let res = self.delete_inner(obj_store_key.clone(), obj_key.clone(), generation);
+        drop(timer);
+        self.observers.notify(&KvEvent {
+            store: obj_store_key.as_str(),
+            request: self.request.as_deref(),
+            op: KvOp::Delete {
+                key: obj_key.as_str(),
+                result: res.as_ref().copied(),
+            },
+        });
+        res
+    }
+
+    fn delete_inner(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        generation: Option<u64>,
+    ) -> Result<(), KvStoreError> {
+        check_writable(
+            &obj_store_key,
+            self.is_read_only(obj_store_key.as_str()),
+            "delete",
+        )?;
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        self.take_over_locked(&mut stores, &obj_store_key, Some(&obj_key))?;
+        let now = self.clock.now();
+        let Some(filter) = self.filter(&obj_store_key) else {
+            return delete_locked(
+                &mut stores,
+                obj_store_key,
+                obj_key,
+                generation,
+                self.abi_generations,
+                now,
+            );
+        };
+
+        let res = delete_locked(
+            &mut stores,
+            obj_store_key.clone(),
+            obj_key,
+            generation,
+            self.abi_generations,
+            now,
+        );
+        if let (Ok(()), Some(store)) = (&res, stores.get(&obj_store_key)) {
+            filter.deleted(store);
+        }
+        res
+    }
+
+    /// Apply several writes to the stores as one.
+    ///
+    /// `f` stages its writes on a [`KvTransaction`], whose lookups see the writes staged before
+    /// them. If `f` returns `Ok`, every write it staged is applied together; if it returns an
+    /// error, none are. The stores are locked while `f` runs, so no other reader or writer can see
+    /// the writes half-applied, and `f` must use only the transaction, not these stores, to avoid
+    /// deadlocking.
+    ///
+    /// Observers are notified of each operation `f` performed, in order, once the transaction
+    /// commits, and not at all if it is rolled back. Generations are assigned as writes are
+    /// staged, so a rolled back transaction leaves a gap in them.
+    ///
+    /// The writes are held to the [memory budget][Self::set_memory_budget] together, as the
+    /// transaction commits. Values in the stores it wrote to are never evicted to make room for
+    /// it, and if there isn't room it is rolled back, failing with
+    /// [`KvStoreError::PayloadTooLarge`].
+    ///
+    /// A transaction through an [isolated][Self::isolated] view takes over every store first.
+    pub fn transaction<T, E>(
+        &self,
+        f: impl FnOnce(&mut KvTransaction<'_>) -> Result<T, E>,
+    ) -> Result<T, E>
+    where
+        E: From<KvStoreError>,
+    {
+        self.take_over_all()?;
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        let mut txn = KvTransaction::new(self, &stores, !self.observers.is_empty());
+        let out = f(&mut txn)?;
+
+        let (staged, inserted, ops) = txn.into_parts();
+        // the stores written to are replaced whole, so only the others can make room
+        let replaced = staged
+            .keys()
+            .filter_map(|obj_store_key| stores.get(obj_store_key))
+            .map(StoreValues::bytes)
+            .sum::<usize>();
+        let needed = held_bytes(&stores) - replaced + held_bytes(&staged);
+        self.make_room(&mut stores, needed, |store, _| staged.contains_key(store))?;
+
+        let touched = staged.keys().cloned().collect::<Vec<_>>();
+        stores.extend(staged);
+        for (obj_store_key, mode, len) in &inserted {
+            self.insert_stats.record(obj_store_key, *mode, *len);
+        }
+        for obj_store_key in touched {
+            if let Some(filter) = self.filter(&obj_store_key) {
+                filter.rebuild(stores.get(&obj_store_key));
+            }
+        }
+        drop(stores);
+
+        for op in &ops {
+            self.observers.notify(&op.event(self.request.as_deref()));
+        }
+        Ok(out)
+    }
+
+    /// List up to `limit` keys of a store in lexicographic order, as production's JSON response:
+    /// the keys under `data`, and the `limit`, `prefix`, and `next_cursor` under `meta`. The
+    /// response is a [`ListResponse`], which [`parse_list_response`][Self::parse_list_response]
+    /// reads back.
+    ///
+    /// Keys are written as JSON strings, escaped only as JSON requires: `"` and `\` are escaped
+    /// with a backslash, control characters as `\t`, `\b`, `\f`, or `\u00XX`, and everything else,
+    /// including DEL and multi-byte characters, is written as-is in UTF-8.
+    pub fn list(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        cursor: Option<String>,
+        prefix: Option<String>,
+        limit: u32,
+    ) -> Result<Vec<u8>, KvStoreError> {
+        self.list_ordered(
+            obj_store_key,
+            cursor,
+            prefix,
+            limit,
+            ListOrder::Lexicographic,
+        )
+    }
+
+    /// Parse a response written by [`list`][Self::list] or [`list_ordered`][Self::list_ordered],
+    /// or sent by production.
+    pub fn parse_list_response(body: &[u8]) -> Result<ListResponse, serde_json::Error> {
+        serde_json::from_slice(body)
+    }
+
+    /// List keys as [`list`][Self::list] does, but in the given order.
+    ///
+    /// Cursors are only meaningful for the order that produced them.
+    pub fn list_ordered(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        cursor: Option<String>,
+        prefix: Option<String>,
+        limit: u32,
+        order: ListOrder,
+    ) -> Result<Vec<u8>, KvStoreError> {
+        self.list_observed(obj_store_key, "", cursor, prefix, limit, order)
+    }
+
+    /// List keys as [`list`][Self::list] does, but only those starting with `key_prefix`, and
+    /// without it, as a guest of a store with that `key_prefix` setting sees them.
+    ///
+    /// The guest's `prefix` is matched after `key_prefix`, and the cursors taken and given are
+    /// relative to it too.
+    pub fn list_under(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        key_prefix: &str,
+        cursor: Option<String>,
+        prefix: Option<String>,
+        limit: u32,
+    ) -> Result<Vec<u8>, KvStoreError> {
+        self.list_observed(
+            obj_store_key,
+            key_prefix,
+            cursor,
+            prefix,
+            limit,
+            ListOrder::Lexicographic,
+        )
+    }
+
+    fn list_observed(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        key_prefix: &str,
+        cursor: Option<String>,
+        prefix: Option<String>,
+        limit: u32,
+        order: ListOrder,
+    ) -> Result<Vec<u8>, KvStoreError> {
+        // An empty prefix matches every key, so it is treated as no prefix at all and left out of
+        // the response metadata.
+        let prefix = prefix.filter(|p| !p.is_empty());
+
+        let timer = self.latencies.time(&obj_store_key, KvOpKind::List);
+        if self.observers.is_empty() {
+            return self.list_inner(obj_store_key, key_prefix, cursor, prefix, limit, order);
+        }
+
+        let res = self.list_inner(
+            obj_store_key.clone(),
+            key_prefix,
+            cursor.clone(),
+            prefix.clone(),
+            limit,
+            order,
+        );
+        drop(timer);
+        self.observers.notify(&KvEvent {
+            store: obj_store_key.as_str(),
+            request: self.request.as_deref(),
+            op: KvOp::List {
+                cursor: cursor.as_deref(),
+                prefix: prefix.as_deref(),
+                limit,
+                result: res.as_deref(),
+            },
+        });
+        res
+    }
+
+    fn list_inner(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        key_prefix: &str,
+        cursor: Option<String>,
+        prefix: Option<String>,
+        limit: u32,
+        order: ListOrder,
+    ) -> Result<Vec<u8>, KvStoreError> {
+        let mut res = Err(KvStoreError::InternalError);
+
+        // the keys are matched on as they are stored, with the store's prefix
+        let matched = format!("{key_prefix}{}", prefix.as_deref().unwrap_or_default());
+        if let Some(p) = &prefix {
+            match is_valid_prefix(&matched, self.key_profile(obj_store_key.as_str())) {
+                Ok(()) => {}
+                // don't log the whole of a prefix that could be any length
+                Err(e @ KeyValidationError::Over1024Bytes) => {
+                    warn!("invalid list prefix of {} bytes: {e}", p.len());
+                    return Err(KvStoreError::BadRequest);
+                }
+                Err(e) => {
+                    warn!(
+                        "invalid list prefix {p:?}: {e}. Prefixes are matched literally against \
+                         the start of each key, and do not support globs or patterns."
+                    );
+                    return Err(KvStoreError::BadRequest);
+                }
+            }
+        }
+
+        let cursor = match cursor {
+            // no cursor this long could have come from a list, so don't bother decoding it
+            Some(c) if c.len() > MAX_LIST_CURSOR_LEN => return Err(KvStoreError::BadRequest),
+            Some(c) => {
+                let cursor_bytes = BASE64_STANDARD
+                    .decode(c)
+                    .map_err(|_| KvStoreError::BadRequest)?;
+                let decoded =
+                    String::from_utf8(cursor_bytes).map_err(|_| KvStoreError::BadRequest)?;
+                let mut position = ListPosition::from_cursor(order, decoded)?;
+                position.key.insert_str(0, key_prefix);
+                Some(position)
+            }
+            None => None,
+        };
+
+        let now = self.clock.now();
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        // the view's keys and deletions are merged with the keys it hasn't used yet
+        self.take_over_locked(&mut stores, &obj_store_key, None)?;
+        stores.entry(obj_store_key.clone()).and_modify(|store| {
+            // manages ttl
+            // a bit wasteful to run this loop twice, but we need mutable access to store,
+            // and it's already claimed in the filters below
+            let ttl_list = store.keys().cloned().collect::<Vec<_>>();
+            ttl_list.into_iter().for_each(|k| {
+                let val = store.get(&k);
+                if let Some(v) = val {
+                    if let Some(exp) = v.expiration {
+                        if now >= exp {
+                            store.remove(&k);
+                        }
+                    }
+                }
+            });
+
+            let mut positions = store
+                .iter()
+                .filter(|(k, _)| k.as_str().starts_with(&matched))
+                .map(|(k, v)| ListPosition::new(order, k, v))
+                .collect::<Vec<_>>();
+            // the store is already in key order
+            if order == ListOrder::LastModified {
+                positions.sort();
+            }
+            if let Some(c) = &cursor {
+                positions.retain(|p| p > c);
+            }
+
+            // limit
+            let old_len = positions.len();
+            positions.truncate(limit as usize);
+            let new_len = positions.len();
+
+            // guests see their keys and cursors without the store's prefix
+            let skip = key_prefix.len();
+            let next_cursor = match old_len != new_len {
+                true => Some(BASE64_STANDARD.encode(positions[new_len - 1].to_cursor(skip))),
+                false => None,
+            };
+            let list = positions
+                .into_iter()
+                .map(|mut p| p.key.split_off(skip))
+                .collect::<Vec<_>>();
+
+            let body = ListResponse {
+                data: list,
+                meta: ListMeta {
+                    limit,
+                    prefix,
+                    next_cursor,
+                },
+            };
+
+            // serializing strings and integers to memory can't fail
+            res = serde_json::to_vec(&body).map_err(|_| KvStoreError::InternalError);
+        });
+        res
+    }
+
+    /// The number of live keys in a store that start with `prefix`, or of all its live keys if
+    /// there is no prefix.
+    ///
+    /// This counts the keys a [`list`][Self::list] with the same prefix would return across all of
+    /// its pages, without building the list: only the keys in the prefix's range are visited, and
+    /// none are copied. Expired keys are not counted. Prefixes are validated as they are for
+    /// lists, and an empty one is the same as none.
+    pub fn count(
+        &self,
+        obj_store_key: &ObjectStoreKey,
+        prefix: Option<&str>,
+    ) -> Result<u64, KvStoreError> {
+        let prefix = prefix.unwrap_or_default();
+        if let Err(e) = is_valid_prefix(prefix, self.key_profile(obj_store_key.as_str())) {
+            warn!("invalid count prefix {prefix:?}: {e}");
+            return Err(KvStoreError::BadRequest);
+        }
+
+        self.take_over(obj_store_key, None)?;
+        let stores = self
+            .stores
+            .read()
+            .map_err(|_| KvStoreError::InternalError)?;
+        let store = stores
+            .get(obj_store_key)
+            .ok_or(KvStoreError::Uninitialized)?;
+        let now = self.clock.now();
+        let count = store
+            .range(ObjectKey(prefix.to_string().into())..)
+            .take_while(|(k, _)| k.as_str().starts_with(prefix))
+            .filter(|(_, v)| !v.is_expired(now))
+            .count();
+        Ok(count as u64)
+    }
+
+    /// A copy of the live values in a store whose keys start with `prefix`, or of all of them if
+    /// there is no prefix, in key order.
+    ///
+    /// This is for embedders and tests to look at what a guest wrote, bodies, metadata, and
+    /// generations included, without going through [`list`][Self::list] and a lookup per key.
+    /// Bodies are decompressed, as guests see them. The read lock is only held while the values
+    /// are copied, so the caller can take its time with them, but every body is cloned: a
+    /// snapshot of a large store takes as much memory again. Prefixes are validated as they are
+    /// for lists, and no observers are notified.
+    pub fn snapshot(
+        &self,
+        obj_store_key: &ObjectStoreKey,
+        prefix: Option<&str>,
+    ) -> Result<Vec<(String, ObjectValue)>, KvStoreError> {
+        let prefix = prefix.unwrap_or_default();
+        if let Err(e) = is_valid_prefix(prefix, self.key_profile(obj_store_key.as_str())) {
+            warn!("invalid snapshot prefix {prefix:?}: {e}");
+            return Err(KvStoreError::BadRequest);
+        }
+
+        self.take_over(obj_store_key, None)?;
+        let stores = self
+            .stores
+            .read()
+            .map_err(|_| KvStoreError::InternalError)?;
+        let store = stores
+            .get(obj_store_key)
+            .ok_or(KvStoreError::Uninitialized)?;
+        let now = self.clock.now();
+        let values = store
+            .range(ObjectKey(prefix.to_string().into())..)
+            .take_while(|(k, _)| k.as_str().starts_with(prefix))
+            .filter(|(_, v)| !v.is_expired(now))
+            .map(|(k, v)| (k.as_str().to_string(), v.clone()))
+            .collect::<Vec<_>>();
+        drop(stores);
+
+        values
+            .into_iter()
+            .map(|(k, v)| Ok((k, v.decompressed()?)))
+            .collect()
+    }
+
+    /// Delete every key in a store that starts with `prefix`, returning how many live keys were
+    /// deleted.
+    ///
+    /// The keys are deleted under a single write lock, so no reader sees some of them gone and
+    /// others not. Expired keys with the prefix are removed too, but not counted. The store is
+    /// kept even if this empties it, so it can still be opened and listed. Prefixes are validated
+    /// as they are for lists, and an empty one matches every key, as [`clear`][Self::clear] does.
+    ///
+    /// Observers see a delete of each live key deleted.
+    pub fn delete_prefix(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        prefix: &str,
+    ) -> Result<usize, KvStoreError> {
+        if let Err(e) = is_valid_prefix(prefix, self.key_profile(obj_store_key.as_str())) {
+            warn!("invalid delete prefix {prefix:?}: {e}");
+            return Err(KvStoreError::BadRequest);
+        }
+
+        let mut stores = self
+            .stores
+            .write()
+            .map_err(|_| KvStoreError::InternalError)?;
+        self.take_over_locked(&mut stores, &obj_store_key, None)?;
+        let store = stores
+            .get_mut(&obj_store_key)
+            .ok_or(KvStoreError::Uninitialized)?;
+        let now = self.clock.now();
+        let keys = store
+            .range(ObjectKey(prefix.to_string().into())..)
+            .take_while(|(k, _)| k.as_str().starts_with(prefix))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        let mut deleted = Vec::new();
+        for key in keys {
+            if store.remove(&key).is_some_and(|val| !val.is_expired(now)) {
+                deleted.push(key);
+            }
+        }
+        if let Some(filter) = self.filter(&obj_store_key) {
+            filter.rebuild(Some(store));
+        }
+        drop(stores);
+
+        for key in &deleted {
+            self.observers.notify(&KvEvent {
+                store: obj_store_key.as_str(),
+                request: self.request.as_deref(),
+                op: KvOp::Delete {
+                    key: key.as_str(),
+                    result: Ok(()),
+                },
+            });
+        }
+        Ok(deleted.len())
+    }
+
+    /// Delete every key in a store, returning how many live keys were deleted.
+    ///
+    /// This is [`delete_prefix`][Self::delete_prefix] with an empty prefix: the store is kept,
+    /// empty, so it can still be opened and listed.
+    pub fn clear(&self, obj_store_key: ObjectStoreKey) -> Result<usize, KvStoreError> {
+        self.delete_prefix(obj_store_key, "")
+    }
+
+    /// Remove every expired value, from these stores and from those of their live namespaces,
+    /// returning how many were removed.
+    ///
+    /// Lookups only evict the expired values they come across, so this is what reclaims the
+    /// memory of values that are never looked up again. The write lock is taken for one store
+    /// at a time, so operations on other stores, and even on this one, can run between them.
+    pub fn purge_expired(&self) -> Result<usize, KvStoreError> {
+        let store_keys = self
+            .stores
+            .read()
+            .map_err(|_| KvStoreError::InternalError)?
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut purged = 0;
+        for store_key in store_keys {
+            let mut stores = self
+                .stores
+                .write()
+                .map_err(|_| KvStoreError::InternalError)?;
+            let Some(store) = stores.get_mut(&store_key) else {
+                continue;
+            };
+            let before = store.len();
+            let now = self.clock.now();
+            store.retain(|_, val| !val.is_expired(now));
+            if store.len() < before {
+                purged += before - store.len();
+                if let Some(filter) = self.filter(&store_key) {
+                    filter.rebuild(Some(store));
+                }
+            }
+        }
+
+        for namespace in self.namespaces.stores() {
+            purged += namespace.purge_expired()?;
+        }
+        Ok(purged)
+    }
+
+    /// Start a task that [purges expired values][Self::purge_expired] every `period`, until the
+    /// [`PurgeTask`] returned is stopped or dropped.
+    ///
+    /// A `period` of zero fails with [`KvStoreError::BadRequest`], as it would purge without
+    /// pause. Must be called from within a Tokio runtime.
+    pub fn purge_expired_every(&self, period: Duration) -> Result<PurgeTask, KvStoreError> {
+        if period.is_zero() {
+            warn!("cannot purge expired KV values with a period of zero");
+            return Err(KvStoreError::BadRequest);
+        }
+        let stores = self.clone();
+        let (stop, mut stopped) = tokio::sync::oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // the first tick completes immediately
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    // the sender is only ever dropped, when the task is stopped
+                    _ = &mut stopped => return,
+                }
+                match stores.purge_expired() {
+                    Ok(0) => {}
+                    Ok(purged) => debug!("purged {purged} expired KV values"),
+                    Err(e) => warn!("failed to purge expired KV values: {e:?}"),
+                }
+            }
+        });
+        Ok(PurgeTask { stop, handle })
+    }
+}
+
+/// A task started by [`ObjectStores::purge_expired_every`], which purges expired values until it
+/// is [stopped][Self::stop] or dropped.
+#[derive(Debug)]
+pub struct PurgeTask {
+    stop: tokio::sync::oneshot::Sender<()>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl PurgeTask {
+    /// Stop the task, waiting for a purge in progress to finish.
+    pub async fn stop(self) {
+        let PurgeTask { stop, handle } = self;
+        drop(stop);
+        if let Err(e) = handle.await {
+            warn!("KV purge task failed: {e}");
+        }
+    }
+}
+
+/// The body of [`ObjectStores::delete`], against stores the caller holds the write lock for.
+fn delete_locked(
+    stores: &mut StoreMap,
+    obj_store_key: ObjectStoreKey,
+    obj_key: ObjectKey,
+    generation: Option<u64>,
+    abi_generations: bool,
+    now: SystemTime,
+) -> Result<(), KvStoreError> {
+    let mut res = Ok(());
+
+    stores.entry(obj_store_key).and_modify(|store| {
+        // 404 if the key doesn't exist or has expired, a failed precondition if it doesn't have
+        // the expected generation, and otherwise delete. Inspecting and removing the value happen
+        // together under the write lock, so only one of several racing deletes can see the key.
+        res = match store.get(&obj_key) {
+            None => Err(KvStoreError::NotFound),
+            Some(val) if val.is_expired(now) => {
+                store.remove(&obj_key);
+                Err(KvStoreError::NotFound)
+            }
+            Some(val)
+                if generation.is_some_and(|g| !val.matches_generation(g, abi_generations)) =>
+            {
+                Err(KvStoreError::PreconditionFailed)
+            }
+            Some(_) => {
+                store.remove(&obj_key);
+                Ok(())
+            }
+        };
+    });
+
+    res
+}
+
+/// Refuse a write to a store that is `read_only`.
+fn check_writable(
+    obj_store_key: &ObjectStoreKey,
+    read_only: bool,
+    op: &str,
+) -> Result<(), KvStoreError> {
+    if read_only {
+        warn!(
+            "cannot {op} in store {:?}, which is read-only",
+            obj_store_key.as_str()
+        );
+        return Err(KvStoreError::BadRequest);
+    }
+    Ok(())
+}
+
+/// The bytes of bodies and metadata held across `stores`.
+fn held_bytes(stores: &StoreMap) -> usize {
+    stores.values().map(StoreValues::bytes).sum()
+}
+
+/// The value of `key` in `store`, decompressed, unless it is missing or has expired as of `now`.
+/// Expired values are removed.
+fn live_value(
+    store: &mut StoreValues,
+    key: &ObjectKey,
+    now: SystemTime,
+) -> Result<ObjectValue, KvStoreError> {
+    match store.get(key) {
+        Some(val) if val.is_expired(now) => {
+            store.remove(key);
+            Err(KvStoreError::NotFound)
+        }
+        Some(val) => val.clone().decompressed(),
+        None => Err(KvStoreError::NotFound),
+    }
+}
+
+/// As [`live_value`], along with whether the value was missing because it had expired.
+fn looked_up_value(
+    store: &mut StoreValues,
+    key: &ObjectKey,
+    now: SystemTime,
+) -> (Result<ObjectValue, KvStoreError>, bool) {
+    let expired = store.get(key).is_some_and(|val| val.is_expired(now));
+    (live_value(store, key, now), expired)
+}
+
+/// A generation as the guest ABIs expose it, in 32 bits. Generations past `u32::MAX`, such as
+/// those seeded from a production export, saturate at it rather than wrapping, since a wrapped
+/// generation could be zero, which means create-only when a guest gives it back, or collide with
+/// that of another write.
+pub(crate) fn abi_generation(generation: u64) -> u32 {
+    u32::try_from(generation).unwrap_or(u32::MAX)
+}
+
+/// Where a key falls in a listing, in the order the listing was requested in.
+///
+/// Positions compare in listing order, so a cursor is simply the position of the last key
+/// returned.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ListPosition {
+    /// The write time in nanoseconds since the epoch, newest first, for
+    /// [`ListOrder::LastModified`].
+    recency: Option<std::cmp::Reverse<u128>>,
+    key: String,
+}
+
+impl ListPosition {
+    fn new(order: ListOrder, key: &ObjectKey, val: &ObjectValue) -> Self {
+        let recency = match order {
+            ListOrder::Lexicographic => None,
+            ListOrder::LastModified => Some(std::cmp::Reverse(
+                val.updated_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            )),
+        };
+        Self {
+            recency,
+            key: key.to_string(),
+        }
+    }
+
+    /// Lexicographic cursors are just the key. Last-modified cursors also need the write time to
+    /// break ties, and are encoded as `<nanoseconds since the epoch>:<key>`.
+    ///
+    /// Cursors record only where a page ended, not how many keys it held, so each page of a
+    /// listing may ask for a different `limit`.
+    fn from_cursor(order: ListOrder, cursor: String) -> Result<Self, KvStoreError> {
+        match order {
+            ListOrder::Lexicographic => Ok(Self {
+                recency: None,
+                key: cursor,
+            }),
+            ListOrder::LastModified => {
+                let (nanos, key) = cursor.split_once(':').ok_or(KvStoreError::BadRequest)?;
+                let nanos = nanos.parse().map_err(|_| KvStoreError::BadRequest)?;
+                Ok(Self {
+                    recency: Some(std::cmp::Reverse(nanos)),
+                    key: key.to_string(),
+                })
+            }
+        }
+    }
+
+    /// The cursor for this position, leaving out the first `skip` bytes of the key: the store's
+    /// `key_prefix`, which guests' cursors don't include.
+    fn to_cursor(&self, skip: usize) -> String {
+        let key = &self.key[skip..];
+        match self.recency {
+            None => key.to_string(),
+            Some(std::cmp::Reverse(nanos)) => format!("{nanos}:{key}"),
+        }
+    }
+}
+
+/// The name of a store.
+///
+/// The name is shared between clones, so keys can be handed out from the store map, and held in
+/// every session that opens the store, without copying it.
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Hash, Clone)]
+pub struct ObjectStoreKey(Arc<str>);
+
+impl ObjectStoreKey {
+    /// The key for the store named `name`, which must be between 1 and 255 characters, each an
+    /// ASCII letter, digit, `-`, `_`, or `.`, as production requires.
+    pub fn new(name: impl ToString) -> Result<Self, StoreNameValidationError> {
+        let name = name.to_string();
+        is_valid_store_name(&name)?;
+        Ok(Self(name.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for ObjectStoreKey {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ObjectStoreKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A key within a store.
+///
+/// Like [`ObjectStoreKey`], the key is shared between clones, so the pending operations and events
+/// that hold onto it don't copy it.
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Default)]
+pub struct ObjectKey(Arc<str>);
+
+impl ObjectKey {
+    /// Make a key that follows the [`KvStore`][KeyValidationProfile::KvStore] rules.
+    pub fn new(key: impl ToString) -> Result<Self, KeyValidationError> {
+        Self::new_with_profile(key, KeyValidationProfile::KvStore)
+    }
+
+    /// Make a key that follows the rules of `profile`.
+    pub fn new_with_profile(
+        key: impl ToString,
+        profile: KeyValidationProfile,
+    ) -> Result<Self, KeyValidationError> {
+        let key = key.to_string();
+        is_valid_key(&key, profile)?;
+        Ok(Self(key.into()))
+    }
+
+    /// Make the key that `key` is stored under in a store with the given `key_prefix`.
+    ///
+    /// Both `key` and the prefixed key must follow the rules of `profile`, so the prefix counts
+    /// toward the limit on a key's length.
+    pub fn new_prefixed(
+        prefix: &str,
+        key: impl ToString,
+        profile: KeyValidationProfile,
+    ) -> Result<Self, KeyValidationError> {
+        let key = key.to_string();
+        if prefix.is_empty() {
+            return Self::new_with_profile(key, profile);
+        }
+        is_valid_key(&key, profile)?;
+        Self::new_with_profile(format!("{prefix}{key}"), profile)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for ObjectKey {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ObjectKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, thiserror::Error)]
+pub enum ObjectStoreError {
+    #[error("The object was not in the store")]
+    MissingObject,
+    #[error("Viceroy's ObjectStore lock was poisoned")]
+    PoisonedLock,
+    /// An Object Store with the given name was not found.
+    #[error("Unknown object-store: {0}")]
+    UnknownObjectStore(String),
+    #[error("Invalid store name: {0}")]
+    InvalidStoreName(#[from] StoreNameValidationError),
+    #[error("Unsupported KV export format version {0}")]
+    UnsupportedExportVersion(u32),
+    #[error("Invalid KV export: {0}")]
+    InvalidExport(String),
+}
+
+impl From<&ObjectStoreError> for FastlyStatus {
+    fn from(e: &ObjectStoreError) -> Self {
+        use ObjectStoreError::*;
+        match e {
+            MissingObject => FastlyStatus::None,
+            PoisonedLock => FastlyStatus::Error,
+            UnknownObjectStore(_) => FastlyStatus::Inval,
+            InvalidStoreName(_) => FastlyStatus::Inval,
+            UnsupportedExportVersion(_) | InvalidExport(_) => FastlyStatus::Inval,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, thiserror::Error)]
+pub enum KvStoreError {
+    #[error("The error was not set")]
+    Uninitialized,
+    #[error("There was no error")]
+    Ok,
+    #[error("KV store cannot or will not process the request due to something that is perceived to be a client error")]
+    BadRequest,
+    #[error("KV store cannot find the requested resource")]
+    NotFound,
+    #[error("KV store cannot fulfill the request, as definied by the client's prerequisites (ie. if-generation-match)")]
+    PreconditionFailed,
+    #[error("The size limit for a KV store key was exceeded")]
+    PayloadTooLarge,
+    #[error("The system encountered an unexpected internal error")]
+    InternalError,
+    #[error("Too many requests have been made to the KV store")]
+    TooManyRequests,
+    /// An insert would have added a key to a store that already holds its `max_keys`. Production
+    /// has no such limit, and so no KV error for it, so guests see it as a limit exceeded status
+    /// from the hostcall: from `insert_wait`, for the KV store interface. Reporting it as
+    /// `PayloadTooLarge` would leave it looking like a value over `max_value_size`.
+    #[error("The KV store already holds as many keys as it is allowed")]
+    TooManyKeys,
+}
+
+/// The error returned by [`ObjectStores::await_key`] and [`ObjectStores::blocking_await_key`].
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum AwaitKeyError {
+    #[error("The key was not written within {0:?}")]
+    TimedOut(Duration),
+    /// [`ObjectStores::blocking_await_key`] was called from within an async runtime, where
+    /// blocking could keep the awaited write from ever running.
+    #[error("A blocking KV wait was made from within an async runtime; use `await_key` instead")]
+    InAsyncContext,
+    #[error(transparent)]
+    Store(#[from] KvStoreError),
+}
+
+impl From<&KvError> for KvStoreError {
+    fn from(e: &KvError) -> Self {
+        match e {
+            KvError::Uninitialized => KvStoreError::Uninitialized,
+            KvError::Ok => KvStoreError::Ok,
+            KvError::BadRequest => KvStoreError::BadRequest,
+            KvError::NotFound => KvStoreError::NotFound,
+            KvError::PreconditionFailed => KvStoreError::PreconditionFailed,
+            KvError::PayloadTooLarge => KvStoreError::PayloadTooLarge,
+            KvError::InternalError => KvStoreError::InternalError,
+            KvError::TooManyRequests => KvStoreError::TooManyRequests,
+        }
+    }
+}
+
+impl From<&KvStoreError> for KvError {
+    fn from(e: &KvStoreError) -> Self {
+        match e {
+            KvStoreError::Uninitialized => KvError::Uninitialized,
+            KvStoreError::Ok => KvError::Ok,
+            KvStoreError::BadRequest => KvError::BadRequest,
+            KvStoreError::NotFound => KvError::NotFound,
+            KvStoreError::PreconditionFailed => KvError::PreconditionFailed,
+            KvStoreError::PayloadTooLarge => KvError::PayloadTooLarge,
+            KvStoreError::InternalError => KvError::InternalError,
+            KvStoreError::TooManyRequests => KvError::TooManyRequests,
+            // never reported this way: `insert_wait` fails with `Limitexceeded` instead
+            KvStoreError::TooManyKeys => KvError::Uninitialized,
+        }
+    }
+}
+
+impl From<&KvStoreError> for ObjectStoreError {
+    fn from(e: &KvStoreError) -> Self {
+        match e {
+            // the only real one
+            KvStoreError::NotFound => ObjectStoreError::MissingObject,
+            _ => ObjectStoreError::UnknownObjectStore("".to_string()),
+        }
+    }
+}
+
+impl From<&KvStoreError> for FastlyStatus {
+    fn from(e: &KvStoreError) -> Self {
+        match e {
+            KvStoreError::Uninitialized => FastlyStatus::Error,
+            KvStoreError::Ok => FastlyStatus::Ok,
+            KvStoreError::BadRequest => FastlyStatus::Inval,
+            KvStoreError::NotFound => FastlyStatus::None,
+            KvStoreError::PreconditionFailed => FastlyStatus::Inval,
+            KvStoreError::PayloadTooLarge => FastlyStatus::Inval,
+            KvStoreError::InternalError => FastlyStatus::Inval,
+            KvStoreError::TooManyRequests => FastlyStatus::Inval,
+            KvStoreError::TooManyKeys => FastlyStatus::Limitexceeded,
+        }
+    }
+}
+
+/// The longest a key can be, in bytes, when UTF-8 encoded.
+pub(crate) const MAX_KEY_BYTES: usize = 1024;
+
+/// The longest key, in bytes, that may be given to a store with the given `key_prefix`, as the
+/// prefix counts toward [`MAX_KEY_BYTES`].
+pub(crate) fn max_key_len(prefix: &str) -> usize {
+    MAX_KEY_BYTES.saturating_sub(prefix.len())
+}
+
+/// The longest a list cursor can be: the base64 encoding of the longest key, along with the
+/// 20-digit timestamp and separator that a [`ListOrder::LastModified`] cursor adds.
+pub(crate) const MAX_LIST_CURSOR_LEN: usize = (MAX_KEY_BYTES + 21).div_ceil(3) * 4;
+
+/// The prefix reserved for ACME challenges, which keys cannot start with.
+const ACME_CHALLENGE_PREFIX: &str = ".well-known/acme-challenge/";
+
+/// The printable characters keys cannot contain, which production reserves.
+const RESERVED_KEY_CHARS: [char; 5] = ['[', ']', '*', '?', '#'];
+
+/// The rules a key is checked against, which differ between the APIs guests reach the stores
+/// through.
+///
+/// Every profile limits keys to 1-1024 bytes. A store's `key_validation` setting picks the
+/// profile for every API, for stores that must hold keys one of them would refuse.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyValidationProfile {
+    /// The rules of the `fastly_kv_store` hostcalls and the component KV store interface, which
+    /// are [`ObjectKey::new`]'s.
+    #[default]
+    KvStore,
+    /// The rules of the legacy `fastly_obj_store` hostcalls and the component object store
+    /// interface: the KV store's, except that `[`, `]`, `*`, `?`, and `#` are allowed.
+    ObjectStore,
+    /// Only the length of a key is checked.
+    Permissive,
+}
+
+impl KeyValidationProfile {
+    /// The profile named `name` in configuration.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kv_store" => Some(KeyValidationProfile::KvStore),
+            "object_store" => Some(KeyValidationProfile::ObjectStore),
+            "permissive" => Some(KeyValidationProfile::Permissive),
+            _ => None,
+        }
+    }
+}
+
+/// Keys in the Object Store must follow the following rules, as `profile` applies them:
+///
+///   * Keys can contain any sequence of valid Unicode characters, of length 1-1024 bytes when
+///     UTF-8 encoded. The limit is on bytes, not characters, so a key of 256 four-byte emoji is
+///     as long as a key can be. This is the only rule the `Permissive` profile has.
+///   * Keys cannot contain control characters (U+0000 to U+001F), which include Carriage Return
+///     and Line Feed. Under the `KvStore` profile, they cannot contain any of `[`, `]`, `*`, `?`,
+///     and `#` either.
+///   * Keys cannot start with `.well-known/acme-challenge/`, including the final `/`, so keys
+///     such as `.well-known/acme-challenge` and `.well-known/acme-challenges` are allowed.
+///   * Keys cannot be named `.` or `..`.
+fn is_valid_key(key: &str, profile: KeyValidationProfile) -> Result<(), KeyValidationError> {
+    let len = key.len();
+    if len < 1 {
+        return Err(KeyValidationError::EmptyKey);
+    } else if len > MAX_KEY_BYTES {
+        return Err(KeyValidationError::Over1024Bytes);
+    }
+    if profile == KeyValidationProfile::Permissive {
+        return Ok(());
+    }
+
+    if key.starts_with(ACME_CHALLENGE_PREFIX) {
+        return Err(KeyValidationError::StartsWithWellKnown);
+    }
+
+    if key.eq("..") {
+        return Err(KeyValidationError::ContainsDotDot);
+    } else if key.eq(".") {
+        return Err(KeyValidationError::ContainsDot);
+    }
+
+    check_key_chars(key, profile)
+}
+
+/// Check that `s` contains none of the characters that keys cannot contain under `profile`.
+fn check_key_chars(s: &str, profile: KeyValidationProfile) -> Result<(), KeyValidationError> {
+    if profile == KeyValidationProfile::Permissive {
+        return Ok(());
+    }
+    for c in s.chars() {
+        if c <= '\u{1f}' {
+            return Err(KeyValidationError::ContainsControlCharacter(c));
+        } else if profile == KeyValidationProfile::KvStore && RESERVED_KEY_CHARS.contains(&c) {
+            return Err(KeyValidationError::Contains(c.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Store names follow the rules `fastly kv-store create` applies: between 1 and 255 characters,
+/// each an ASCII letter, digit, `-`, `_`, or `.`. This rules out whitespace anywhere in the name,
+/// including at either end.
+pub(crate) fn is_valid_store_name(name: &str) -> Result<(), StoreNameValidationError> {
+    if name.is_empty() {
+        return Err(StoreNameValidationError::Empty);
+    } else if name.chars().count() > 255 {
+        return Err(StoreNameValidationError::Over255Characters);
+    }
+
+    if name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace) {
+        return Err(StoreNameValidationError::SurroundingWhitespace);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(StoreNameValidationError::InvalidCharacter(c));
+    }
+
+    Ok(())
+}
+
+/// A list prefix can only match keys if it could itself be the start of a valid key, so prefixes
+/// follow a relaxed version of the rules for keys:
+///
+///   * Prefixes can be at most 1024 bytes when UTF-8 encoded.
+///   * Prefixes cannot contain any of the characters that keys cannot contain under `profile`.
+pub(crate) fn is_valid_prefix(
+    prefix: &str,
+    profile: KeyValidationProfile,
+) -> Result<(), KeyValidationError> {
+    if prefix.len() > MAX_KEY_BYTES {
+        return Err(KeyValidationError::Over1024Bytes);
+    }
+
+    check_key_chars(prefix, profile)
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum KeyValidationError {
+    #[error("Keys for objects cannot be empty")]
+    EmptyKey,
+    #[error("Keys for objects cannot be over 1024 bytes in size")]
+    Over1024Bytes,
+    #[error("Keys for objects cannot start with `.well-known/acme-challenge/`")]
+    StartsWithWellKnown,
+    #[error("Keys for objects cannot be named `.`")]
+    ContainsDot,
+    #[error("Keys for objects cannot be named `..`")]
+    ContainsDotDot,
+    #[error("Keys for objects cannot contain a `{0}`")]
+    Contains(String),
+    #[error("Keys for objects cannot contain control characters, such as {0:?}")]
+    ContainsControlCharacter(char),
+}
+
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, thiserror::Error)]
+pub enum StoreNameValidationError {
+    #[error("Store names cannot be empty")]
+    Empty,
+    #[error("Store names cannot be over 255 characters long")]
+    Over255Characters,
+    #[error("Store names cannot start or end with whitespace")]
+    SurroundingWhitespace,
+    #[error("Store names can only contain ASCII letters, digits, `-`, `_`, and `.`, not {0:?}")]
+    InvalidCharacter(char),
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::sync::atomic::Ordering};
+
+    const STORE_NAME: &'static str = "test_store";
+
+    #[test]
+    fn test_key_equality_and_ordering() {
+        let names = ["A", "a", "a/b", "aa", "b", "\u{e9}", "\u{1f600}"];
+        for x in names {
+            for y in names {
+                let (kx, ky) = (ObjectKey::new(x).unwrap(), ObjectKey::new(y).unwrap());
+                assert_eq!(kx == ky, x == y, "{x:?} == {y:?}");
+                assert_eq!(kx.cmp(&ky), x.cmp(y), "{x:?} cmp {y:?}");
+            }
+        }
+        let store_names = ["-", "A", "a", "a.b", "aa", "b"];
+        for x in store_names {
+            for y in store_names {
+                let (sx, sy) = (
+                    ObjectStoreKey::new(x).unwrap(),
+                    ObjectStoreKey::new(y).unwrap(),
+                );
+                assert_eq!(sx == sy, x == y, "{x:?} == {y:?}");
+                assert_eq!(sx.cmp(&sy), x.cmp(y), "{x:?} cmp {y:?}");
+            }
+        }
+
+        // clones share their name, and compare equal to the original
+        let key = ObjectKey::new("shared").unwrap();
+        let clone = key.clone();
+        assert_eq!(key, clone);
+        assert!(std::ptr::eq(key.as_str(), clone.as_str()));
+
+        // as map keys, they can be looked up by their string view
+        let map = store_names
+            .iter()
+            .map(|name| (ObjectStoreKey::new(name).unwrap(), name.len()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            map.keys().map(ObjectStoreKey::as_str).collect::<Vec<_>>(),
+            {
+                let mut sorted = store_names.to_vec();
+                sorted.sort();
+                sorted
+            }
+        );
+        assert_eq!(map.get("a.b"), Some(&3));
+
+        assert_eq!(ObjectStoreKey::new("store").unwrap().to_string(), "store");
+        assert_eq!(ObjectKey::new("a/b").unwrap().to_string(), "a/b");
+        assert!(matches!(
+            ObjectKey::new(""),
+            Err(KeyValidationError::EmptyKey)
+        ));
+    }
+
+    #[test]
+    fn test_kv_store_exists() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let res = stores.store_key(STORE_NAME);
+        match res {
+            Ok(Some(key)) => assert_eq!(key, ObjectStoreKey::new(STORE_NAME).unwrap()),
+            _ => panic!("should have been Ok(Some(_))"),
+        }
+        assert_eq!(stores.store_key("unknown"), Ok(None));
+    }
+
+    #[test]
+    fn test_kv_store_basics() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let key = "insert_key".to_string();
+        let val1 = "val1".to_string();
+
+        // insert
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+
+        // lookup
+        let res = stores.lookup(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+        );
+        match res {
+            Ok(ov) => {
+                assert_eq!(ov.body, val1.as_bytes().to_vec())
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // list
+        let limit = 1000;
+        let res = stores.list(ObjectStoreKey::new(STORE_NAME).unwrap(), None, None, limit);
+        match res {
+            Ok(ov) => {
+                let val = format!(r#"{{"data":["{key}"],"meta":{{"limit":{limit}}}}}"#);
+                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // delete
+        let res = stores.delete(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            None,
+        );
+        match res {
+            Ok(_) => {}
+            Err(_) => panic!("should have been OK"),
+        }
+    }
+
+    #[test]
+    fn test_kv_store_delete_generation() {
+        let stores = ObjectStores::with_generation_source(Arc::new(
+            CountingGenerations::starting_after(u64::from(u32::MAX)),
+        ));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k| {
+            stores
+                .insert(
+                    store.clone(),
+                    key(k),
+                    b"v".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+        let delete = |k, generation| stores.delete(store.clone(), key(k), generation);
+
+        // a mismatched generation leaves the value alone
+        let generation = insert("key");
+        assert_eq!(
+            delete("key", Some(generation + 1)),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        assert_eq!(
+            stores.lookup(store.clone(), key("key")).unwrap().generation,
+            generation
+        );
+        // only the whole generation matches, not the saturated one guests see
+        assert_eq!(
+            delete("key", Some(u64::from(abi_generation(generation)))),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        delete("key", Some(generation)).unwrap();
+
+        // a missing key is missing whatever the generation
+        assert_eq!(delete("key", Some(generation)), Err(KvStoreError::NotFound));
+        assert_eq!(delete("never", Some(1)), Err(KvStoreError::NotFound));
+    }
+
+    #[test]
+    fn test_kv_store_panic_with_stores_locked() {
+        /// Panics when asked for a generation, which the stores do with their write lock held.
+        #[derive(Debug)]
+        struct PanickingGenerations;
+
+        impl GenerationSource for PanickingGenerations {
+            fn next_generation(&self) -> u64 {
+                panic!("injected generation panic")
+            }
+
+            fn advance_past(&self, _generation: u64) {}
+        }
+
+        let stores = ObjectStores::with_generation_source(Arc::new(PanickingGenerations));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = ObjectKey::new("key").unwrap();
+        let insert = || {
+            stores.insert(
+                store.clone(),
+                key.clone(),
+                b"v".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+        };
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(insert)).is_err());
+
+        // the stores could have been left part way through a change, so every operation on them
+        // fails from then on, rather than seeing it
+        assert_eq!(insert(), Err(KvStoreError::InternalError));
+        assert_eq!(
+            stores.lookup(store.clone(), key.clone()),
+            Err(KvStoreError::InternalError)
+        );
+        assert_eq!(
+            stores.delete(store.clone(), key.clone(), None),
+            Err(KvStoreError::InternalError)
+        );
+    }
+
+    #[test]
+    fn test_kv_store_item_404s() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let res = stores.lookup(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey("bad_key".to_string().into()),
+        );
+        match res {
+            Ok(_) => panic!("should not have been OK"),
+            Err(e) => assert_eq!(e, KvStoreError::NotFound),
+        }
+
+        let res = stores.delete(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey("bad_key".to_string().into()),
+            None,
+        );
+        match res {
+            Ok(_) => panic!("should not have been OK"),
+            Err(e) => assert_eq!(e, KvStoreError::NotFound),
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_insert_modes() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let key = "insert_key".to_string();
+        let val1 = "val1".to_string();
+        let val2 = "val2".to_string();
+        let val3 = "val3".to_string();
+
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Add,
+            None,
+            None,
+            None,
+        );
+        assert!(res.is_ok());
+        // fail on Add, because key already exists
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Add,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Ok(_) => panic!("should not have been OK"),
+            Err(e) => assert_eq!(e, KvStoreError::PreconditionFailed),
+        }
+        // prepend val2
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val2.clone().into(),
+            KvInsertMode::Prepend,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+        // append val3
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val3.clone().into(),
+            KvInsertMode::Append,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+        let res = stores.lookup(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+        );
+        match res {
+            Ok(ov) => {
+                let val = format!("{val2}{val1}{val3}");
+                assert_eq!(ov.body, val.as_bytes().to_vec())
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // overwrite val3
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val3.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            Some(val2.as_bytes().to_vec()),
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+
+        // test overwrite
+        let res = stores.lookup(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+        );
+        match res {
+            Ok(ov) => {
+                assert_eq!(ov.body, val3.as_bytes().to_vec());
+                assert_eq!(ov.metadata, val2.as_bytes().to_vec());
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_append_keeps_metadata() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+        let insert = |body: &str, mode, metadata: Option<&str>| {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey::new("key").unwrap(),
+                    body.into(),
+                    mode,
+                    None,
+                    metadata.map(Into::into),
+                    None,
+                )
+                .unwrap();
+            stores
+                .lookup(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey::new("key").unwrap(),
+                )
+                .unwrap()
+        };
+
+        insert("a", KvInsertMode::Overwrite, Some("meta1"));
+        // without new metadata, the existing metadata is kept
+        let val = insert("b", KvInsertMode::Append, None);
+        assert_eq!(val.body, &b"ab"[..]);
+        assert_eq!(val.metadata, b"meta1");
+        assert_eq!(val.metadata_len(), 5);
+        let val = insert("_", KvInsertMode::Prepend, None);
+        assert_eq!(val.body, &b"_ab"[..]);
+        assert_eq!(val.metadata, b"meta1");
+
+        // new metadata replaces it
+        let val = insert("c", KvInsertMode::Append, Some("m2"));
+        assert_eq!(val.body, &b"_abc"[..]);
+        assert_eq!(val.metadata, b"m2");
+        assert_eq!(val.metadata_len(), 2);
+    }
+
+    #[test]
+    fn test_kv_store_item_prepend_missing_key() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+
+        stores
+            .insert(
+                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                key(),
+                b"val".to_vec(),
+                KvInsertMode::Prepend,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        let val = stores
+            .lookup(ObjectStoreKey::new(STORE_NAME).unwrap(), key())
+            .unwrap();
+        assert_eq!(val.body, &b"val"[..]);
+        assert!(val.metadata.is_empty());
+        assert_eq!(val.metadata_len(), 0);
+
+        stores
+            .insert(
+                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                ObjectKey::new("other").unwrap(),
+                b"val".to_vec(),
+                KvInsertMode::Prepend,
+                None,
+                Some(b"meta".to_vec()),
+                None,
+            )
+            .unwrap();
+        let val = stores
+            .lookup(
+                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                ObjectKey::new("other").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(val.metadata, b"meta");
+    }
+
+    #[test]
+    fn test_kv_store_item_insert_generation() {
+        // generations start after 1337, so that one can never match
+        let stores = ObjectStores::with_generation_source(Arc::new(
+            CountingGenerations::starting_after(1337),
+        ));
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let key = "insert_key".to_string();
+        let val1 = "val1".to_string();
+
+        // insert val1
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+
+        // test overwrite, get gen
+        let generation;
+        let res = stores.lookup(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+        );
+        match res {
+            Ok(ov) => {
+                assert_eq!(ov.body, val1.as_bytes().to_vec());
+                assert_eq!(ov.generation, 1338);
+                generation = ov.generation;
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // test generation match failure
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Overwrite,
+            Some(1337),
+            None,
+            None,
+        );
+        match res {
+            Err(KvStoreError::PreconditionFailed) => {}
+            _ => panic!("should have been Err(KvStoreError::PreconditionFailed)"),
+        }
+
+        // test generation match positive
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Overwrite,
+            Some(generation),
+            None,
+            None,
+        );
+        match res {
+            Ok(_) => {}
+            _ => panic!("should have been OK"),
+        }
+
+        // check result
         let res = stores.lookup(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+        );
+        match res {
+            Ok(ov) => {
+                assert_eq!(ov.body, val1.as_bytes().to_vec());
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_generations_increase() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = ObjectKey::new("insert_key").unwrap();
+
+        let mut last = None;
+        for i in 0..10_000 {
+            stores
+                .insert(
+                    store.clone(),
+                    key.clone(),
+                    format!("val{i}").into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            let generation = stores
+                .lookup(store.clone(), key.clone())
+                .unwrap()
+                .generation;
+            assert!(last < Some(generation), "{last:?} then {generation}");
+            last = Some(generation);
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_generation_past_u32() {
+        let stores = ObjectStores::with_generation_source(Arc::new(
+            CountingGenerations::starting_after(u64::from(u32::MAX)),
+        ));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = ObjectKey::new("insert_key").unwrap();
+        let insert = |generation| {
+            stores.insert(
+                store.clone(),
+                key.clone(),
+                b"val".to_vec(),
+                KvInsertMode::Overwrite,
+                generation,
+                None,
+                None,
+            )
+        };
+
+        insert(None).unwrap();
+        let val = stores.lookup(store.clone(), key.clone()).unwrap();
+        assert_eq!(val.generation, 1 << 32);
+        // saturated rather than wrapped, which would have been the create-only generation zero
+        assert_eq!(val.abi_generation(), u32::MAX);
+        assert_eq!(
+            stores
+                .head(store.clone(), key.clone())
+                .unwrap()
+                .abi_generation(),
+            u32::MAX
+        );
+        assert_eq!(abi_generation(u64::from(u32::MAX) - 1), u32::MAX - 1);
+
+        // the whole generation is matched, so the saturated one guests are given isn't enough
+        assert_eq!(
+            insert(Some(u64::from(val.abi_generation()))),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        insert(Some(val.generation)).unwrap();
+        assert_eq!(
+            insert(Some(val.generation)),
+            Err(KvStoreError::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn test_kv_store_abi_generation_checks() {
+        let max = u64::from(u32::MAX);
+        let stores = ObjectStores::with_generation_source(Arc::new(
+            CountingGenerations::starting_after(max - 2),
+        ));
+        let guest = stores.with_abi_generations();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = ObjectKey::new("key").unwrap();
+        let insert = |stores: &ObjectStores, generation| {
+            stores.insert(
+                store.clone(),
+                key.clone(),
+                b"val".to_vec(),
+                KvInsertMode::Overwrite,
+                Some(generation),
+                None,
+                None,
+            )
+        };
+
+        stores
+            .insert(
+                store.clone(),
+                key.clone(),
+                b"val".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        // generations guests can be given whole are matched whole
+        assert_eq!(insert(&guest, max - 1), Ok(max));
+        assert_eq!(
+            insert(&guest, max - 1),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        // and past them, the saturated generation guests are given matches
+        assert_eq!(insert(&guest, max), Ok(max + 1));
+        assert_eq!(insert(&guest, max), Ok(max + 2));
+        assert_eq!(insert(&stores, max), Err(KvStoreError::PreconditionFailed));
+        // namespaces carry the setting over
+        let config = KvNamespaceConfig::new(http::HeaderName::from_static("x-ns"));
+        let namespace = guest.namespace("ns", &config).unwrap();
+        assert_eq!(insert(&namespace, max), Ok(max + 3));
+
+        // guests being given a saturated generation is warned of once per key
+        let other = ObjectKey::new("other").unwrap();
+        assert!(!stores.saturated.note(&store, &key, max));
+        assert!(stores.saturated.note(&store, &key, max + 1));
+        assert!(!guest.saturated.note(&store, &key, max + 2));
+        assert!(guest.saturated.note(&store, &other, max + 2));
+    }
+
+    #[test]
+    fn test_kv_store_abi_generation_deletes() {
+        let max = u64::from(u32::MAX);
+        let stores = ObjectStores::with_generation_source(Arc::new(
+            CountingGenerations::starting_after(max),
+        ));
+        let guest = stores.with_abi_generations();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = ObjectKey::new("key").unwrap();
+        let delete = |stores: &ObjectStores, generation| {
+            stores.delete(store.clone(), key.clone(), Some(generation))
+        };
+
+        let generation = stores
+            .insert(
+                store.clone(),
+                key.clone(),
+                b"val".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(generation, max + 1);
+        // deletes match generations as inserts do: whole, unless guests were given them saturated
+        assert_eq!(delete(&stores, max), Err(KvStoreError::PreconditionFailed));
+        assert_eq!(delete(&guest, 0), Err(KvStoreError::PreconditionFailed));
+        assert_eq!(
+            delete(&guest, max - 1),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        assert_eq!(delete(&guest, max), Ok(()));
+        assert_eq!(delete(&guest, max), Err(KvStoreError::NotFound));
+    }
+
+    #[test]
+    fn test_kv_store_item_list_advanced() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let key = "insert_key".to_string();
+        let prefix = "key".to_string();
+        let key1 = format!("{prefix}1").to_string();
+        let key2 = format!("{prefix}2").to_string();
+        let key3 = format!("{prefix}3").to_string();
+        let val1 = "val1".to_string();
+        let val2 = "val2".to_string();
+        let val3 = "val3".to_string();
+
+        // insert insert_key
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+
+        // insert val1
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key1.clone().into()),
+            val1.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+        // insert val2
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key2.clone().into()),
+            val2.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+        // insert val3
+        let res = stores.insert(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectKey(key3.clone().into()),
+            val3.clone().into(),
+            KvInsertMode::Overwrite,
+            None,
+            None,
+            None,
+        );
+        match res {
+            Err(_) => panic!("should have been OK"),
+            _ => {}
+        }
+
+        // list
+        let limit = 1000;
+        let res = stores.list(ObjectStoreKey::new(STORE_NAME).unwrap(), None, None, limit);
+        match res {
+            Ok(ov) => {
+                let val = format!(
+                    r#"{{"data":["{key}","{key1}","{key2}","{key3}"],"meta":{{"limit":{limit}}}}}"#
+                );
+                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // list w/prefix
+        let limit = 1000;
+        let res = stores.list(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            None,
+            Some(prefix.clone()),
+            limit,
+        );
+        match res {
+            Ok(ov) => {
+                let val = format!(
+                    r#"{{"data":["{key1}","{key2}","{key3}"],"meta":{{"limit":{limit},"prefix":"{prefix}"}}}}"#
+                );
+                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // list w/prefix&limit
+        let limit = 1;
+        let res = stores.list(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            None,
+            Some(prefix.clone()),
+            limit,
+        );
+        match res {
+            Ok(ov) => {
+                let next_cursor = BASE64_STANDARD.encode(key1.clone());
+                let val = format!(
+                    r#"{{"data":["{key1}"],"meta":{{"limit":{limit},"prefix":"{prefix}","next_cursor":"{next_cursor}"}}}}"#
+                );
+                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // list w/prefix&limit&cursor
+        let limit = 1;
+        let last_cursor = BASE64_STANDARD.encode(key1.clone());
+        let res = stores.list(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            Some(last_cursor),
+            Some(prefix.clone()),
+            limit,
+        );
+        match res {
+            Ok(ov) => {
+                let next_cursor = BASE64_STANDARD.encode(key2.clone());
+                let val = format!(
+                    r#"{{"data":["{key2}"],"meta":{{"limit":{limit},"prefix":"{prefix}","next_cursor":"{next_cursor}"}}}}"#
+                );
+                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+
+        // list w/prefix&limit&cursor
+        let limit = 1;
+        let last_cursor = BASE64_STANDARD.encode(key2.clone());
+        let res = stores.list(
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            Some(last_cursor),
+            Some(prefix.clone()),
+            limit,
+        );
+        match res {
+            Ok(ov) => {
+                let val = format!(
+                    r#"{{"data":["{key3}"],"meta":{{"limit":{limit},"prefix":"{prefix}"}}}}"#
+                );
+                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
+            }
+            Err(_) => panic!("should have been OK"),
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_list_empty_prefix() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        for key in ["a", "b", "c"] {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey(key.to_string().into()),
+                    key.into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        let list = |cursor: Option<String>, prefix: Option<&str>, limit| {
+            let res = stores.list(
+                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                cursor,
+                prefix.map(str::to_string),
+                limit,
+            );
+            String::from_utf8(res.unwrap()).unwrap()
+        };
+
+        // an empty prefix is the same as no prefix, and is omitted from the metadata
+        let val = r#"{"data":["a","b","c"],"meta":{"limit":1000}}"#;
+        assert_eq!(list(None, None, 1000), val);
+        assert_eq!(list(None, Some(""), 1000), val);
+
+        // and the same holds when paging with a cursor
+        let next_cursor = BASE64_STANDARD.encode("b");
+        let val =
+            format!(r#"{{"data":["a","b"],"meta":{{"limit":2,"next_cursor":"{next_cursor}"}}}}"#);
+        assert_eq!(list(None, None, 2), val);
+        assert_eq!(list(None, Some(""), 2), val);
+
+        let val = r#"{"data":["c"],"meta":{"limit":2}}"#;
+        assert_eq!(list(Some(next_cursor.clone()), None, 2), val);
+        assert_eq!(list(Some(next_cursor), Some(""), 2), val);
+    }
+
+    #[test]
+    fn test_kv_store_item_list_prefix_is_literal() {
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        for key in ["img/a.png", "img/b.png", "caf\u{e9}"] {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey(key.to_string().into()),
+                    key.into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        let list = |prefix: &str| {
+            stores
+                .list(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    None,
+                    Some(prefix.to_string()),
+                    1000,
+                )
+                .map(|ov| String::from_utf8(ov).unwrap())
+        };
+
+        // glob characters can never appear in keys, so they are rejected rather than matching
+        // nothing
+        assert_eq!(list("img/*"), Err(KvStoreError::BadRequest));
+        assert_eq!(list("img/?.png"), Err(KvStoreError::BadRequest));
+
+        // as are prefixes longer than any key
+        assert_eq!(list(&"a".repeat(1025)), Err(KvStoreError::BadRequest));
+        assert!(list(&"a".repeat(1024)).is_ok());
+
+        // a literal prefix matches on bytes
+        assert_eq!(
+            list("img/").unwrap(),
+            r#"{"data":["img/a.png","img/b.png"],"meta":{"limit":1000,"prefix":"img/"}}"#
+        );
+
+        // a prefix ending at a character boundary within a multi-byte key matches it, but there
+        // is no normalization, so an unaccented prefix does not
+        assert_eq!(
+            list("caf").unwrap(),
+            r#"{"data":["café"],"meta":{"limit":1000,"prefix":"caf"}}"#
+        );
+        assert_eq!(
+            list("cafe").unwrap(),
+            r#"{"data":[],"meta":{"limit":1000,"prefix":"cafe"}}"#
+        );
+        // a prefix that ends partway through the encoding of `é` is not valid UTF-8, so it can't
+        // be passed as a prefix at all; the hostcalls reject it when decoding the list options
+        assert!(std::str::from_utf8(&"caf\u{e9}".as_bytes()[..4]).is_err());
+    }
+
+    #[test]
+    fn test_kv_store_item_list_last_modified() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        let insert = |key: &str| {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey(key.to_string().into()),
+                    key.into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+        let list = |cursor: Option<String>, order| {
+            let body = stores
+                .list_ordered(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    cursor,
+                    None,
+                    2,
+                    order,
+                )
+                .unwrap();
+            let page = ObjectStores::parse_list_response(&body).unwrap();
+            (page.data, page.meta.next_cursor)
+        };
+        let list_all = |order| {
+            let mut all = Vec::new();
+            let mut cursor = None;
+            loop {
+                let (keys, next) = list(cursor, order);
+                all.extend(keys);
+                match next {
+                    Some(next) => cursor = Some(next),
+                    None => return all,
+                }
+            }
+        };
+
+        insert("a");
+        clock.advance(Duration::from_secs(1));
+        // several keys written at the same instant
+        insert("d");
+        insert("b");
+        insert("c");
+        clock.advance(Duration::from_secs(1));
+        insert("e");
+        clock.advance(Duration::from_secs(1));
+        // rewriting a key moves it to the front
+        insert("a");
+
+        assert_eq!(
+            list_all(ListOrder::Lexicographic),
+            ["a", "b", "c", "d", "e"]
+        );
+        // ties are broken by key, so pages stay stable across the keys that share a timestamp
+        assert_eq!(list_all(ListOrder::LastModified), ["a", "e", "b", "c", "d"]);
+
+        // cursors from one order are not valid for the other
+        let (_, next) = list(None, ListOrder::Lexicographic);
+        assert_eq!(
+            stores.list_ordered(
+                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                next,
+                None,
+                2,
+                ListOrder::LastModified,
+            ),
+            Err(KvStoreError::BadRequest)
+        );
+    }
+
+    #[test]
+    fn test_kv_store_list_limit_can_change_between_pages() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        // groups of keys written at the same instant, so last-modified pages split ties too
+        for group in 0..15 {
+            insert_keys(&stores, &store, &format!("key{group:02}-"), 10);
+            clock.advance(Duration::from_secs(1));
+        }
+
+        for order in [ListOrder::Lexicographic, ListOrder::LastModified] {
+            let expected = {
+                let body = stores
+                    .list_ordered(store.clone(), None, None, 1000, order)
+                    .unwrap();
+                ObjectStores::parse_list_response(&body).unwrap().data
+            };
+            assert_eq!(expected.len(), 150);
+
+            // shrinking, then growing, then a last page larger than what's left
+            let mut listed = Vec::new();
+            let mut cursor = None;
+            for limit in [100, 10, 3, 1, 25, 1000] {
+                let body = stores
+                    .list_ordered(store.clone(), cursor, None, limit, order)
+                    .unwrap();
+                let page = ObjectStores::parse_list_response(&body).unwrap();
+                assert_eq!(page.meta.limit, limit);
+                assert_eq!(page.data.len(), (limit as usize).min(150 - listed.len()));
+                listed.extend(page.data);
+                cursor = page.meta.next_cursor;
+            }
+            assert_eq!(cursor, None);
+            assert_eq!(listed, expected, "{order:?}");
+        }
+    }
+
+    #[test]
+    fn test_kv_store_list_under_key_prefix() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        // two services sharing the store, with keys that would collide without their prefixes
+        insert_keys(&stores, &store, "checkout/user-", 5);
+        insert_keys(&stores, &store, "checkout/cart-", 3);
+        insert_keys(&stores, &store, "search/user-", 4);
+
+        let list = |cursor, prefix: Option<&str>| {
+            let body = stores
+                .list_under(
+                    store.clone(),
+                    "checkout/",
+                    cursor,
+                    prefix.map(str::to_string),
+                    2,
+                )
+                .unwrap();
+            ObjectStores::parse_list_response(&body).unwrap()
+        };
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = list(cursor, Some("user-"));
+            // the guest's prefix is given back as it was, without the store's
+            assert_eq!(page.meta.prefix.as_deref(), Some("user-"));
+            listed.extend(page.data);
+            cursor = page.meta.next_cursor;
+            let Some(cursor) = &cursor else { break };
+            let decoded = BASE64_STANDARD.decode(cursor).unwrap();
+            assert!(decoded.starts_with(b"user-"));
+        }
+        assert_eq!(listed, ["user-0", "user-1", "user-2", "user-3", "user-4"]);
+
+        // without a guest prefix, the store's still applies
+        let page = list(None, None);
+        assert_eq!(page.data, ["cart-0", "cart-1"]);
+        let page = list(page.meta.next_cursor, None);
+        assert_eq!(page.data, ["cart-2", "user-0"]);
+
+        // the prefix counts toward the limit on a key's length
+        let profile = KeyValidationProfile::KvStore;
+        let key = "k".repeat(MAX_KEY_BYTES - "checkout/".len());
+        assert!(ObjectKey::new_prefixed("checkout/", &key, profile).is_ok());
+        assert_eq!(
+            ObjectKey::new_prefixed("checkout/", format!("{key}k"), profile),
+            Err(KeyValidationError::Over1024Bytes)
+        );
+        assert!(ObjectKey::new(format!("{key}k")).is_ok());
+        assert_eq!(max_key_len("checkout/"), key.len());
+        assert_eq!(max_key_len(""), MAX_KEY_BYTES);
+        let prefix = Some("p".repeat(MAX_KEY_BYTES));
+        assert_eq!(
+            stores.list_under(store.clone(), "checkout/", None, prefix, 2),
+            Err(KvStoreError::BadRequest)
+        );
+    }
+
+    #[test]
+    fn test_kv_store_item_concurrent_deletes() {
+        const DELETERS: usize = 32;
+
+        let stores = ObjectStores::default();
+        stores
+            .insert_empty_store(ObjectStoreKey::new(STORE_NAME).unwrap())
+            .unwrap();
+
+        for _ in 0..50 {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey("racy".to_string().into()),
+                    "racy".into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let barrier = std::sync::Barrier::new(DELETERS);
+            let results = std::thread::scope(|s| {
+                let handles = (0..DELETERS)
+                    .map(|_| {
+                        s.spawn(|| {
+                            barrier.wait();
+                            stores.delete(
+                                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                                ObjectKey("racy".to_string().into()),
+                                None,
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap())
+                    .collect::<Vec<_>>()
+            });
+
+            assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+            assert!(results
+                .iter()
+                .all(|r| matches!(r, Ok(()) | Err(KvStoreError::NotFound))));
+        }
+    }
+
+    #[test]
+    fn test_kv_store_create_is_atomic() {
+        const KEYS: usize = 1000;
+        const READERS: usize = 8;
+
+        let mut config = KvNamespaceConfig::new(http::HeaderName::from_static("x-namespace"));
+        config.max_namespaces = usize::MAX;
+        let values = || {
+            (0..KEYS).map(|i| {
+                (
+                    ObjectKey::new(format!("key{i:04}")).unwrap(),
+                    vec![],
+                    SeedOptions::default(),
+                )
+            })
+        };
+
+        for _ in 0..20 {
+            let stores = ObjectStores::default();
+            let seeded = std::sync::atomic::AtomicBool::new(false);
+            let barrier = std::sync::Barrier::new(READERS + 1);
+            // a store that can be opened must hold every value it was created with
+            let check = |stores: &ObjectStores| match stores.store_key(STORE_NAME).unwrap() {
+                Some(key) => {
+                    assert_eq!(stores.count(&key, None), Ok(KEYS as u64));
+                    assert!(stores
+                        .lookup(key, ObjectKey::new(format!("key{:04}", KEYS - 1)).unwrap())
+                        .is_ok());
+                    true
+                }
+                None => false,
+            };
+
+            std::thread::scope(|s| {
+                for reader in 0..READERS {
+                    let (stores, seeded, barrier, config) = (&stores, &seeded, &barrier, &config);
+                    s.spawn(move || {
+                        barrier.wait();
+                        for i in 0.. {
+                            let done = seeded.load(Ordering::Acquire);
+                            let namespace =
+                                stores.namespace(&format!("{reader}-{i}"), config).unwrap();
+                            let visible = check(&namespace);
+                            assert!(check(stores) || !visible);
+                            if done {
+                                assert!(visible);
+                                break;
+                            }
+                        }
+                    });
+                }
+                barrier.wait();
+                stores
+                    .create_store(ObjectStoreKey::new(STORE_NAME).unwrap(), values())
+                    .unwrap();
+                seeded.store(true, Ordering::Release);
+            });
+        }
+    }
+
+    #[test]
+    fn test_kv_store_insert_returns_generation() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |mode, generation| {
+            stores.insert(
+                store.clone(),
+                key(),
+                b"v".to_vec(),
+                mode,
+                generation,
+                None,
+                None,
+            )
+        };
+        let current = || stores.lookup(store.clone(), key()).unwrap().generation;
+
+        let first = insert(KvInsertMode::Overwrite, None).unwrap();
+        assert_eq!(first, current());
+        // the returned generation can be used for the next write without looking it up
+        let second = insert(KvInsertMode::Overwrite, Some(first)).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(second, current());
+        for mode in [KvInsertMode::Append, KvInsertMode::Prepend] {
+            let before = current();
+            let generation = insert(mode, Some(before)).unwrap();
+            assert!(generation > before, "{mode:?}");
+            assert_eq!(generation, current(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn test_kv_store_generation_on_missing_key() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k, mode, generation| {
+            stores.insert(
+                store.clone(),
+                key(k),
+                b"v".to_vec(),
+                mode,
+                generation,
+                None,
+                None,
+            )
+        };
+        let missing =
+            |k| stores.lookup(store.clone(), key(k)).err() == Some(KvStoreError::NotFound);
+
+        // no generation matches a key that doesn't exist, whatever the mode
+        for mode in [
+            KvInsertMode::Overwrite,
+            KvInsertMode::Add,
+            KvInsertMode::Append,
+            KvInsertMode::Prepend,
+        ] {
+            assert_eq!(
+                insert("missing", mode, Some(1)),
+                Err(KvStoreError::PreconditionFailed),
+                "{mode:?}"
+            );
+            assert!(missing("missing"), "{mode:?}");
+        }
+
+        // an `Add` fails on a key that exists even if the generation matches
+        let generation = insert("key", KvInsertMode::Overwrite, None).unwrap();
+        assert_eq!(
+            insert("key", KvInsertMode::Add, Some(generation)),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        // while a zero generation agrees with it, creating the key
+        insert("added", KvInsertMode::Add, Some(0)).unwrap();
+        assert!(!missing("added"));
+    }
+
+    #[test]
+    fn test_kv_store_create_only() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k, body: &[u8], mode| {
+            stores.insert(
+                store.clone(),
+                key(k),
+                body.to_vec(),
+                mode,
+                Some(0),
+                None,
+                Some(Duration::from_secs(10)),
+            )
+        };
+        let body = |k| stores.lookup(store.clone(), key(k)).unwrap().body.to_vec();
+
+        // a fresh key is created
+        insert("key", b"first", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(body("key"), b"first");
+
+        // an existing one is left alone
+        assert_eq!(
+            insert("key", b"second", KvInsertMode::Overwrite),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        assert_eq!(body("key"), b"first");
+
+        // an expired one is as good as missing
+        clock.advance(Duration::from_secs(11));
+        insert("key", b"third", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(body("key"), b"third");
+
+        // there is nothing to extend, whether or not the key exists
+        for mode in [KvInsertMode::Append, KvInsertMode::Prepend] {
+            for k in ["key", "missing"] {
+                assert_eq!(
+                    insert(k, b"more", mode),
+                    Err(KvStoreError::BadRequest),
+                    "{mode:?} {k}"
+                );
+            }
+        }
+        assert_eq!(body("key"), b"third");
+        assert_eq!(
+            stores.lookup(store.clone(), key("missing")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+    }
+
+    #[test]
+    fn test_kv_store_seeded_generations() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let seeded = u64::from(u32::MAX) + 42;
+        stores
+            .create_store(
+                store.clone(),
+                [
+                    (
+                        key("pinned"),
+                        b"seed".to_vec(),
+                        SeedOptions {
+                            generation: Some(seeded),
+                            ..Default::default()
+                        },
+                    ),
+                    (key("unpinned"), b"seed".to_vec(), SeedOptions::default()),
+                ],
+            )
+            .unwrap();
+
+        // seeded generations are kept exactly
+        let pinned = stores.lookup(store.clone(), key("pinned")).unwrap();
+        assert_eq!(pinned.generation, seeded);
+        assert!(
+            stores
+                .lookup(store.clone(), key("unpinned"))
+                .unwrap()
+                .generation
+                < seeded
+        );
+
+        // and runtime writes, to any key, are given greater ones
+        for k in ["pinned", "unpinned", "new"] {
+            stores
+                .insert(
+                    store.clone(),
+                    key(k),
+                    b"runtime".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            assert!(stores.lookup(store.clone(), key(k)).unwrap().generation > seeded);
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_concurrent_generations() {
+        const WRITERS: usize = 8;
+        const WRITES: usize = 200;
+
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey("gen".to_string().into());
+        stores.insert_empty_store(store()).unwrap();
+        stores
+            .insert(
+                store(),
+                key(),
+                "0".into(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        // many concurrent overwrites, watched by a reader: generations only ever move forward,
+        // and each one belongs to exactly one write
+        let done = std::sync::atomic::AtomicBool::new(false);
+        let observed = std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let mut observed = Vec::new();
+                while !done.load(Ordering::Relaxed) {
+                    let val = stores.lookup(store(), key()).unwrap();
+                    observed.push((val.generation, val.body));
+                }
+                observed
+            });
+            let writers = (0..WRITERS)
+                .map(|w| {
+                    let stores = &stores;
+                    s.spawn(move || {
+                        for i in 0..WRITES {
+                            stores
+                                .insert(
+                                    store(),
+                                    key(),
+                                    format!("{w}-{i}").into(),
+                                    KvInsertMode::Overwrite,
+                                    None,
+                                    None,
+                                    None,
+                                )
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            writers.into_iter().for_each(|w| w.join().unwrap());
+            done.store(true, Ordering::Relaxed);
+            reader.join().unwrap()
+        });
+        let mut bodies = std::collections::HashMap::new();
+        for window in observed.windows(2) {
+            assert!(window[0].0 <= window[1].0, "generation went backwards");
+        }
+        for (generation, body) in observed {
+            assert_eq!(*bodies.entry(generation).or_insert(body.clone()), body);
+        }
+        // every write got its own generation: the seed and the overwrites
+        let last = stores.lookup(store(), key()).unwrap().generation;
+        assert_eq!(last as usize, 1 + WRITERS * WRITES);
+
+        // a compare-and-swap loop run concurrently by several tasks loses no increments
+        stores
+            .insert(
+                store(),
+                key(),
+                "0".into(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        std::thread::scope(|s| {
+            for _ in 0..WRITERS {
+                s.spawn(|| {
+                    for _ in 0..WRITES {
+                        loop {
+                            let val = stores.lookup(store(), key()).unwrap();
+                            let count: usize =
+                                std::str::from_utf8(&val.body).unwrap().parse().unwrap();
+                            match stores.insert(
+                                store(),
+                                key(),
+                                (count + 1).to_string().into(),
+                                KvInsertMode::Overwrite,
+                                Some(val.generation),
+                                None,
+                                None,
+                            ) {
+                                Ok(_) => break,
+                                Err(KvStoreError::PreconditionFailed) => continue,
+                                Err(e) => panic!("unexpected insert error: {e:?}"),
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(
+            stores.lookup(store(), key()).unwrap().body,
+            (WRITERS * WRITES).to_string().as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_kv_store_item_concurrent_appends() {
+        const WRITERS: usize = 8;
+        const WRITES: usize = 200;
+
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("log").unwrap();
+
+        // each append reads the value it extends under the same lock it writes with, so none is
+        // lost to another landing in between
+        std::thread::scope(|s| {
+            for w in 0..WRITERS {
+                let stores = &stores;
+                s.spawn(move || {
+                    let mode = if w % 2 == 0 {
+                        KvInsertMode::Append
+                    } else {
+                        KvInsertMode::Prepend
+                    };
+                    for _ in 0..WRITES {
+                        stores
+                            .insert(store(), key(), vec![b'a' + w as u8], mode, None, None, None)
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        let body = stores.lookup(store(), key()).unwrap().body;
+        assert_eq!(body.len(), WRITERS * WRITES);
+        for w in 0..WRITERS {
+            assert_eq!(
+                body.iter().filter(|b| **b == b'a' + w as u8).count(),
+                WRITES
+            );
+        }
+    }
+
+    #[test]
+    fn test_kv_store_key_validation() {
+        for key in [
+            "k",
+            "a/b/c",
+            "with space",
+            "del\u{7f}",
+            "caf\u{e9}",
+            "\u{1f600}",
+            "..a",
+            "a.",
+            "./",
+            ".well-known/acme-challenge",
+            ".well-known/acme-challenges/token",
+            "prefix/.well-known/acme-challenge/token",
+            &"a".repeat(1024),
+            // the limit is in bytes: 512 two-byte, or 256 four-byte, characters
+            &"\u{e9}".repeat(512),
+            &"\u{1f600}".repeat(256),
+        ] {
+            assert_eq!(
+                is_valid_key(key, KeyValidationProfile::KvStore),
+                Ok(()),
+                "{key:?} should be accepted"
+            );
+        }
+
+        use KeyValidationError::*;
+        for (key, err) in [
+            ("", EmptyKey),
+            (&"a".repeat(1025), Over1024Bytes),
+            (&"\u{e9}".repeat(513), Over1024Bytes),
+            (&format!("{}a", "\u{1f600}".repeat(256)), Over1024Bytes),
+            (".well-known/acme-challenge/", StartsWithWellKnown),
+            (".well-known/acme-challenge/token", StartsWithWellKnown),
+            (".", ContainsDot),
+            ("..", ContainsDotDot),
+            ("line\r", ContainsControlCharacter('\r')),
+            ("line\n", ContainsControlCharacter('\n')),
+            ("nul\0", ContainsControlCharacter('\0')),
+            ("tab\t", ContainsControlCharacter('\t')),
+            ("esc\u{1b}", ContainsControlCharacter('\u{1b}')),
+            ("unit\u{1f}", ContainsControlCharacter('\u{1f}')),
+            ("a[0]", Contains("[".to_owned())),
+            ("a]", Contains("]".to_owned())),
+            ("glob*", Contains("*".to_owned())),
+            ("what?", Contains("?".to_owned())),
+            ("page#anchor", Contains("#".to_owned())),
+        ] {
+            assert_eq!(
+                is_valid_key(key, KeyValidationProfile::KvStore),
+                Err(err),
+                "{key:?} should be rejected"
+            );
+        }
+
+        // prefixes can't contain what keys can't, but can be empty, or start like a reserved key
+        let prefix = |p| is_valid_prefix(p, KeyValidationProfile::KvStore);
+        assert_eq!(prefix(""), Ok(()));
+        assert_eq!(prefix(".well-known/acme-challenge/"), Ok(()));
+        assert_eq!(prefix("a\u{1}"), Err(ContainsControlCharacter('\u{1}')));
+        assert_eq!(prefix("a#"), Err(Contains("#".to_owned())));
+    }
+
+    #[test]
+    fn test_kv_store_key_validation_profiles() {
+        use {KeyValidationError::*, KeyValidationProfile::*};
+        let check = |key: &str, profile| ObjectKey::new_with_profile(key, profile).map(|_| ());
+
+        // the reserved characters are only refused by the KV store
+        assert_eq!(check("page#anchor", KvStore), Err(Contains("#".to_owned())));
+        assert_eq!(check("page#anchor", ObjectStore), Ok(()));
+        assert_eq!(check("page#anchor", Permissive), Ok(()));
+        assert_eq!(ObjectKey::new("a[0]"), Err(Contains("[".to_owned())));
+
+        // the object store refuses everything else the KV store does
+        assert_eq!(
+            check("line\n", ObjectStore),
+            Err(ContainsControlCharacter('\n'))
+        );
+        assert_eq!(check("..", ObjectStore), Err(ContainsDotDot));
+
+        // a permissive store checks only the length
+        for key in ["line\n", "..", ".well-known/acme-challenge/token"] {
+            assert_eq!(check(key, Permissive), Ok(()), "{key:?}");
+        }
+        assert_eq!(check("", Permissive), Err(EmptyKey));
+        assert_eq!(check(&"a".repeat(1025), Permissive), Err(Over1024Bytes));
+
+        // a store's setting applies to its prefixes, and to the keys imported into it
+        let stores = ObjectStores::new();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores.create_store(store.clone(), []).unwrap();
+        let list = || stores.list(store.clone(), None, Some("a#".to_owned()), 10);
+        assert_eq!(list(), Err(KvStoreError::BadRequest));
+        let settings = StoreConfig {
+            key_validation: Some(ObjectStore),
+            ..StoreConfig::default()
+        };
+        stores.configure_store(store.clone(), settings).unwrap();
+        assert_eq!(stores.key_validation(STORE_NAME), Some(ObjectStore));
+        assert!(list().is_ok());
+
+        let export = br#"{"stores":{"test_store":{"items":{"a#":{"body":"dg==","metadata":""}}}}}"#;
+        let export = KvExport::from_json(export).unwrap();
+        assert!(ObjectStores::new().import(&export).is_err());
+        assert_eq!(stores.import(&export), Ok(1));
+    }
+
+    #[test]
+    fn test_kv_store_name_validation() {
+        for name in [
+            "s",
+            "store",
+            "my-store_v1.2",
+            "UPPER_and_lower",
+            "0123456789",
+            &"a".repeat(255),
+        ] {
+            assert_eq!(
+                is_valid_store_name(name),
+                Ok(()),
+                "{name:?} should be accepted"
+            );
+        }
+
+        use StoreNameValidationError::*;
+        for (name, err) in [
+            ("", Empty),
+            (&"a".repeat(256), Over255Characters),
+            (&"\u{e9}".repeat(256), Over255Characters),
+            (" store", SurroundingWhitespace),
+            ("store\n", SurroundingWhitespace),
+            ("my store", InvalidCharacter(' ')),
+            ("caf\u{e9}", InvalidCharacter('\u{e9}')),
+            ("\u{1f5c4}", InvalidCharacter('\u{1f5c4}')),
+            ("store/name", InvalidCharacter('/')),
+            ("store:name", InvalidCharacter(':')),
+        ] {
+            assert_eq!(
+                is_valid_store_name(name),
+                Err(err),
+                "{name:?} should be rejected"
+            );
+        }
+
+        // no key can be made for an invalid name, and looking a store up by one is an error
+        // rather than a missing store
+        assert_eq!(ObjectStoreKey::new("my store"), Err(InvalidCharacter(' ')));
+        let stores = ObjectStores::default();
+        assert_eq!(
+            stores.store_key("my store"),
+            Err(ObjectStoreError::InvalidStoreName(InvalidCharacter(' ')))
+        );
+        assert_eq!(stores.store_key("store"), Ok(None));
+    }
+
+    #[test]
+    fn test_kv_store_item_ttl_precedence() {
+        let stores = ObjectStores::default();
+        let plain = ObjectStoreKey::new("plain").unwrap();
+        let defaulted = ObjectStoreKey::new("defaulted").unwrap();
+        stores.insert_empty_store(plain.clone()).unwrap();
+        stores.insert_empty_store(defaulted.clone()).unwrap();
+        stores
+            .configure_store(
+                defaulted.clone(),
+                StoreConfig {
+                    default_ttl: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let key = || ObjectKey::new("key").unwrap();
+        // insert, and return how long until the value expires, to the nearest second
+        let insert = |store: &ObjectStoreKey, mode, ttl: Option<u64>| {
+            stores
+                .insert(
+                    store.clone(),
+                    key(),
+                    "body".into(),
+                    mode,
+                    None,
+                    None,
+                    ttl.map(Duration::from_secs),
+                )
+                .unwrap();
+            stores
+                .lookup(store.clone(), key())
+                .unwrap()
+                .expiration
+                .map(|exp| {
+                    let remaining = exp.duration_since(SystemTime::now()).unwrap();
+                    remaining.as_secs_f64().round() as u64
+                })
+        };
+
+        use KvInsertMode::*;
+        // no TTL and no default: the value never expires
+        assert_eq!(insert(&plain, Overwrite, None), None);
+        // an explicit TTL is used as-is
+        assert_eq!(insert(&plain, Overwrite, Some(10)), Some(10));
+        // the store default fills in for a missing TTL
+        assert_eq!(insert(&defaulted, Overwrite, None), Some(60));
+        // but an explicit TTL takes precedence over it
+        assert_eq!(insert(&defaulted, Overwrite, Some(10)), Some(10));
+
+        // appends and prepends don't inherit the expiry of the value they extend
+        for mode in [Append, Prepend] {
+            assert_eq!(insert(&plain, Overwrite, Some(10)), Some(10));
+            assert_eq!(insert(&plain, mode, None), None);
+            assert_eq!(insert(&plain, mode, Some(20)), Some(20));
+
+            assert_eq!(insert(&defaulted, Overwrite, Some(10)), Some(10));
+            assert_eq!(insert(&defaulted, mode, None), Some(60));
+            assert_eq!(insert(&defaulted, mode, Some(20)), Some(20));
+        }
+
+        // a zero TTL is rejected rather than defaulted, and leaves the existing value alone
+        for store in [&plain, &defaulted] {
+            assert_eq!(insert(store, Overwrite, Some(10)), Some(10));
+            assert_eq!(
+                stores.insert(
+                    store.clone(),
+                    key(),
+                    "zero".into(),
+                    Overwrite,
+                    None,
+                    None,
+                    Some(Duration::ZERO),
+                ),
+                Err(KvStoreError::BadRequest)
+            );
+            assert_eq!(
+                stores.lookup(store.clone(), key()).unwrap().body,
+                &b"body"[..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_kv_store_item_ttl_expiry() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores.insert_empty_store(store.clone()).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |body: &str, mode, generation, ttl| {
+            stores.insert(
+                store.clone(),
+                key(),
+                body.into(),
+                mode,
+                generation,
+                None,
+                Some(Duration::from_secs(ttl)),
+            )
+        };
+        // move the value's expiry into the past, as if its TTL had elapsed
+        let expire = || {
+            stores
+                .stores
+                .write()
+                .unwrap()
+                .get_mut(&store)
+                .unwrap()
+                .get_mut(&key())
+                .unwrap()
+                .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+        };
+
+        use KvInsertMode::*;
+        // an expired value looks just like a missing one
+        insert("body", Overwrite, None, 10).unwrap();
+        expire();
+        let err = stores.lookup(store.clone(), key()).unwrap_err();
+        assert_eq!(err, KvStoreError::NotFound);
+        assert_eq!(
+            ObjectStoreError::from(&err),
+            ObjectStoreError::MissingObject
+        );
+
+        // appending to an expired value starts a new one, with a fresh TTL
+        insert("one", Overwrite, None, 10).unwrap();
+        expire();
+        insert("two", Append, None, 10).unwrap();
+        let val = stores.lookup(store.clone(), key()).unwrap();
+        assert_eq!(val.body, &b"two"[..]);
+        assert!(val.expiration.unwrap() > SystemTime::now());
+
+        // a generation match against a live value is checked, and a failed one leaves its TTL be
+        let generation = stores.lookup(store.clone(), key()).unwrap().generation;
+        let before = stores.lookup(store.clone(), key()).unwrap().expiration;
+        assert_eq!(
+            insert("stale", Overwrite, Some(generation.wrapping_add(1)), 60),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        assert_eq!(
+            stores.lookup(store.clone(), key()).unwrap().expiration,
+            before
+        );
+        insert("fresh", Overwrite, Some(generation), 60).unwrap();
+        let val = stores.lookup(store.clone(), key()).unwrap();
+        assert_eq!(val.body, &b"fresh"[..]);
+        assert!(val.expiration > before);
+    }
+
+    #[test]
+    fn test_kv_store_ttl_with_mock_clock() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |mode, generation| {
+            stores.insert(
+                store(),
+                key(),
+                b"value".to_vec(),
+                mode,
+                generation,
+                None,
+                Some(Duration::from_secs(10)),
+            )
+        };
+
+        insert(KvInsertMode::Overwrite, None).unwrap();
+        let val = stores.lookup(store(), key()).unwrap();
+        assert_eq!(val.updated_at, SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            val.expiration,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10))
+        );
+
+        // live until the very end of its TTL
+        clock.advance(Duration::from_millis(9_999));
+        assert!(stores.lookup(store(), key()).is_ok());
+        assert_eq!(stores.count(&store(), None), Ok(1));
+        assert_eq!(
+            stores.export(Redaction::None).unwrap().stores[STORE_NAME]
+                .items
+                .len(),
+            1
+        );
+        assert_eq!(stores.purge_expired(), Ok(0));
+
+        // and expired exactly at it
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(stores.count(&store(), None), Ok(0));
+        assert!(stores.export(Redaction::None).unwrap().stores[STORE_NAME]
+            .items
+            .is_empty());
+        assert_eq!(stores.purge_expired(), Ok(1));
+        assert_eq!(
+            insert(KvInsertMode::Overwrite, Some(val.generation)),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        assert_eq!(
+            stores.delete(store(), key(), None),
+            Err(KvStoreError::NotFound)
+        );
+
+        // a value written later expires relative to when it was written
+        insert(KvInsertMode::Add, None).unwrap();
+        clock.advance(Duration::from_secs(5));
+        let val = stores.lookup(store(), key()).unwrap();
+        assert_eq!(
+            val.expiration,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(20))
+        );
+        clock.advance(Duration::from_secs(5));
+        assert!(matches!(
+            stores.lookup(store(), key()),
+            Err(KvStoreError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_kv_store_lookups_share_the_stored_body() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("large").unwrap();
+        let body = vec![7; 16 * 1024 * 1024];
+        stores
+            .insert(
+                store(),
+                key(),
+                body.clone(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        // every lookup hands out the stored buffer itself, rather than a copy of it
+        let first = stores.lookup(store(), key()).unwrap();
+        for _ in 0..100 {
+            let val = stores.lookup(store(), key()).unwrap();
+            assert_eq!(val.body.as_ptr(), first.body.as_ptr());
+        }
+        assert_eq!(first.body, body);
+
+        // and extending the value leaves the buffers already handed out alone
+        stores
+            .insert(
+                store(),
+                key(),
+                b"!".to_vec(),
+                KvInsertMode::Append,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        let extended = stores.lookup(store(), key()).unwrap();
+        assert_ne!(extended.body.as_ptr(), first.body.as_ptr());
+        assert_eq!(extended.body.len(), body.len() + 1);
+        assert_eq!(first.body, body);
+    }
+
+    #[test]
+    fn test_kv_store_insert_many() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let then = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+
+        let pinned = ObjectValue::new(b"pinned".to_vec())
+            .metadata(b"meta".to_vec())
+            .generation(500)
+            .ttl(Duration::from_secs(60))
+            .created_at(then)
+            .updated_at(then + Duration::from_secs(1))
+            .origin(ValueOrigin::Seed)
+            .build(clock.now());
+        let values = vec![
+            (key("pinned"), pinned),
+            (
+                key("fresh"),
+                ObjectValue::new(b"fresh".to_vec()).build(then),
+            ),
+        ];
+        assert_eq!(stores.insert_many(store(), values), Ok(2));
+
+        // everything given is kept
+        let val = stores.lookup(store(), key("pinned")).unwrap();
+        assert_eq!(val.body, &b"pinned"[..]);
+        assert_eq!(
+            (val.metadata.as_slice(), val.metadata_len()),
+            (&b"meta"[..], 4)
+        );
+        assert_eq!(val.generation, 500);
+        assert_eq!(val.expiration, Some(clock.now() + Duration::from_secs(60)));
+        assert_eq!(val.created_at, then);
+        assert_eq!(val.updated_at, then + Duration::from_secs(1));
+        assert_eq!(val.origin, ValueOrigin::Seed);
+
+        // but a generation of zero is given the next one, past every kept generation
+        let fresh = stores.lookup(store(), key("fresh")).unwrap().generation;
+        assert!(fresh > 500);
+        let generation = stores
+            .insert(
+                store(),
+                key("later"),
+                b"later".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(generation > fresh);
+
+        // a value over the limits writes none of them
+        stores.set_max_keys(store(), Some(4)).unwrap();
+        let too_many = ["a", "b"].map(|k| (key(k), ObjectValue::new(b"v".to_vec()).build(then)));
+        assert_eq!(
+            stores.insert_many(store(), too_many),
+            Err(KvStoreError::TooManyKeys)
+        );
+        let too_large = vec![
+            (key("a"), ObjectValue::new(b"v".to_vec()).build(then)),
+            (
+                key("b"),
+                ObjectValue::new(vec![0; KV_STORE_VALUE_MAX_LEN + 1]).build(then),
+            ),
+        ];
+        assert_eq!(
+            stores.insert_many(store(), too_large),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        assert_eq!(
+            stores.lookup(store(), key("a")).map(|val| val.body),
+            Err(KvStoreError::NotFound)
+        );
+
+        // an empty batch still creates the store
+        let empty = ObjectStoreKey::new("empty").unwrap();
+        assert_eq!(stores.insert_many(empty.clone(), []), Ok(0));
+        assert_eq!(stores.list(empty, None, None, 10).map(|_| ()), Ok(()));
+    }
+
+    #[test]
+    fn test_kv_store_insert_many_compresses_and_notifies() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores
+            .configure_store(
+                store(),
+                StoreConfig {
+                    compression: Some(Compression::Deflate),
+                    default_ttl: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        type Written = (String, Vec<u8>, Option<Duration>);
+
+        #[derive(Default)]
+        struct Inserts(std::sync::Mutex<Vec<Written>>);
+
+        impl KvObserver for Inserts {
+            fn on_event(&self, event: &KvEvent<'_>) {
+                if let KvOp::Insert { key, body, ttl, .. } = &event.op {
+                    let written = (key.to_string(), body.to_vec(), *ttl);
+                    self.0.lock().unwrap().push(written);
+                }
+            }
+        }
+        let inserts = Arc::new(Inserts::default());
+        stores.add_observer(inserts.clone());
+
+        let body = "abc".repeat(1000).into_bytes();
+        let key = ObjectKey::new("k").unwrap();
+        let value = ObjectValue::new(body.clone()).build(stores.now());
+        assert_eq!(stores.insert_many(store(), [(key.clone(), value)]), Ok(1));
+
+        let val = stores.lookup(store(), key.clone()).unwrap();
+        assert_eq!(val.body, body);
+        assert_eq!(val.compression, None);
+        assert!(val.expiration.is_some(), "the store's default TTL applies");
+        assert_eq!(stores.head(store(), key).unwrap().body_len, body.len());
+
+        let inserts = inserts.0.lock().unwrap();
+        let [(key, written, ttl)] = inserts.as_slice() else {
+            panic!("one insert is observed, not {inserts:?}");
+        };
+        assert_eq!((key.as_str(), written), ("k", &body));
+        assert!(ttl.is_some());
+    }
+
+    #[test]
+    fn test_kv_store_share_and_deep_clone() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        stores
+            .configure_store(
+                store(),
+                StoreConfig {
+                    key_filter: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let insert = |stores: &ObjectStores, k: &str, body: &str| {
+            stores
+                .insert(
+                    store(),
+                    key(k),
+                    body.as_bytes().to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+        insert(&stores, "a", "original");
+
+        let shared = stores.share();
+        let copy = stores.deep_clone().unwrap();
+        let original = stores.lookup(store(), key("a")).unwrap();
+        let copied = copy.lookup(store(), key("a")).unwrap();
+        assert_eq!(copied.body, "original");
+        assert_eq!(
+            copied.body.as_ptr(),
+            original.body.as_ptr(),
+            "bodies are shared, not copied"
+        );
+
+        // writes through a deep clone stay there, as do writes to the original
+        insert(&copy, "a", "changed");
+        insert(&copy, "b", "new");
+        assert_eq!(stores.lookup(store(), key("a")).unwrap().body, "original");
+        assert_eq!(
+            stores.lookup(store(), key("b")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+        stores.delete(store(), key("a"), None).unwrap();
+        assert_eq!(copy.lookup(store(), key("a")).unwrap().body, "changed");
+        assert_eq!(copy.lookup(store(), key("b")).unwrap().body, "new");
+
+        // writes through a shared handle are visible through the original
+        insert(&shared, "c", "shared");
+        assert_eq!(stores.lookup(store(), key("c")).unwrap().body, "shared");
+        assert_eq!(
+            copy.lookup(store(), key("c")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+    }
+
+    #[test]
+    fn test_kv_store_isolated() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |stores: &ObjectStores, k: &str, body: &str, generation| {
+            stores.insert(
+                store(),
+                key(k),
+                body.as_bytes().to_vec(),
+                KvInsertMode::Overwrite,
+                generation,
+                None,
+                None,
+            )
+        };
+        let list = |stores: &ObjectStores| {
+            let body = stores.list(store(), None, None, 10).unwrap();
+            ObjectStores::parse_list_response(&body).unwrap().data
+        };
+        let a = insert(&stores, "a", "base", None).unwrap();
+        insert(&stores, "b", "base", None).unwrap();
+
+        let view = stores.isolated();
+        assert!(view.is_isolated() && !stores.is_isolated());
+        assert!(view.store_key(STORE_NAME).unwrap().is_some());
+        // reads fall through, and generation checks are made against the values read through
+        assert_eq!(view.lookup(store(), key("a")).unwrap().body, "base");
+        assert_eq!(
+            insert(&view, "b", "stale", Some(a)),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        insert(&view, "a", "view", Some(a)).unwrap();
+        insert(&view, "c", "view", None).unwrap();
+        view.delete(store(), key("b"), None).unwrap();
+
+        // the view's writes and deletes are merged into its lists, and stay out of the stores
+        assert_eq!(list(&view), ["a", "c"]);
+        assert_eq!(view.count(&store(), None), Ok(2));
+        assert_eq!(list(&stores), ["a", "b"]);
+        assert_eq!(stores.lookup(store(), key("a")).unwrap().body, "base");
+        assert_eq!(view.lookup(store(), key("a")).unwrap().body, "view");
+
+        // a key the view hasn't used yet is read from the stores as they are now
+        insert(&stores, "d", "later", None).unwrap();
+        let other = stores.isolated();
+        assert_eq!(other.lookup(store(), key("d")).unwrap().body, "later");
+        assert_eq!(list(&other), ["a", "b", "d"]);
+        // a key the view deleted stays deleted
+        insert(&stores, "b", "again", None).unwrap();
+        assert_eq!(
+            view.lookup(store(), key("b")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+
+        // dropping the view drops its writes
+        drop(view);
+        assert_eq!(list(&stores), ["a", "b", "d"]);
+        assert_eq!(
+            stores.lookup(store(), key("c")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+    }
+
+    #[test]
+    fn test_kv_store_lost_write_rules() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k: &str, body: &str, mode: KvInsertMode| {
+            stores.insert(
+                store(),
+                key(k),
+                body.as_bytes().to_vec(),
+                mode,
+                None,
+                None,
+                None,
+            )
+        };
+        assert!(insert("session/a", "before", KvInsertMode::Overwrite).is_ok());
+
+        #[derive(Default)]
+        struct Lost(std::sync::Mutex<Vec<(String, bool)>>);
+
+        impl KvObserver for Lost {
+            fn on_event(&self, event: &KvEvent<'_>) {
+                if let KvOp::Insert {
+                    key,
+                    result: Ok(()),
+                    lost,
+                    ..
+                } = &event.op
+                {
+                    self.0.lock().unwrap().push((key.to_string(), *lost));
+                }
+            }
+        }
+        let observed = Arc::new(Lost::default());
+        stores.add_observer(observed.clone());
+        stores.add_lost_write_rule(LostWriteRule {
+            store: Some(STORE_NAME.to_string()),
+            key_prefix: Some("session/".to_string()),
+            probability: 1.0,
+            seed: 0,
+        });
+
+        // the inserts succeed, but leave the keys as they were
+        assert!(insert("session/a", "after", KvInsertMode::Overwrite).is_ok());
+        assert!(insert("session/b", "new", KvInsertMode::Overwrite).is_ok());
+        assert!(insert("user/a", "kept", KvInsertMode::Overwrite).is_ok());
+        assert_eq!(
+            stores.lookup(store(), key("session/a")).unwrap().body,
+            "before"
+        );
+        assert_eq!(
+            stores.lookup(store(), key("session/b")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+        assert_eq!(stores.lookup(store(), key("user/a")).unwrap().body, "kept");
+        // and an insert that would fail anyway still fails
+        assert_eq!(
+            insert("session/a", "again", KvInsertMode::Add),
+            Err(KvStoreError::PreconditionFailed)
+        );
+        assert_eq!(
+            *observed.0.lock().unwrap(),
+            [
+                ("session/a".to_string(), true),
+                ("session/b".to_string(), true),
+                ("user/a".to_string(), false),
+            ]
+        );
+
+        stores.clear_lost_write_rules();
+        assert!(insert("session/b", "new", KvInsertMode::Overwrite).is_ok());
+        assert_eq!(
+            stores.lookup(store(), key("session/b")).unwrap().body,
+            "new"
+        );
+
+        // which inserts are lost depends only on the seed
+        let lost = |seed| {
+            let stores = ObjectStores::default();
+            stores.add_lost_write_rule(LostWriteRule {
+                store: None,
+                key_prefix: None,
+                probability: 0.5,
+                seed,
+            });
+            (0..64)
+                .filter(|i| {
+                    let key = key(&format!("k{i}"));
+                    stores
+                        .insert(
+                            store(),
+                            key.clone(),
+                            vec![],
+                            KvInsertMode::Overwrite,
+                            None,
+                            None,
+                            None,
+                        )
+                        .unwrap();
+                    stores.lookup(store(), key).is_err()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lost(7), lost(7));
+        assert_ne!(lost(7), lost(8));
+        assert!(!lost(7).is_empty() && lost(7).len() < 64);
+    }
+
+    #[test]
+    fn test_kv_store_head() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let generation = stores
+            .insert(
+                store(),
+                key("k"),
+                b"hello".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                Some(b"meta".to_vec()),
+                Some(Duration::from_secs(10)),
+            )
+            .unwrap();
+
+        assert_eq!(
+            stores.head(store(), key("k")).unwrap(),
+            ObjectHead {
+                metadata: b"meta".to_vec(),
+                generation,
+                body_len: 5,
+            }
+        );
+        assert_eq!(
+            stores.head(store(), key("missing")),
+            Err(KvStoreError::NotFound)
+        );
+        assert_eq!(
+            stores.head(ObjectStoreKey::new("nope").unwrap(), key("k")),
+            Err(KvStoreError::Uninitialized)
+        );
+
+        // an expired value is a miss, but is left for a lookup to remove
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            stores.head_with_expiry(store(), key("k")),
+            (Err(KvStoreError::NotFound), true)
+        );
+        assert!(
+            stores.lookup_with_expiry(store(), key("k")).1,
+            "the value was still there to expire"
+        );
+    }
+
+    #[test]
+    fn test_kv_store_head_of_a_compressed_value() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores
+            .configure_store(
+                store(),
+                StoreConfig {
+                    compression: Some(Compression::Gzip),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let body = "abc".repeat(1000).into_bytes();
+        stores
+            .insert(
+                store(),
+                ObjectKey::new("k").unwrap(),
+                body.clone(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        // the length is that of the body as a lookup returns it, not as it is stored
+        let head = stores.head(store(), ObjectKey::new("k").unwrap()).unwrap();
+        assert_eq!(head.body_len, body.len());
+        assert!(head.metadata.is_empty());
+    }
+
+    #[test]
+    fn test_kv_store_created_and_updated_times() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let insert = |k, mode, ttl| {
+            stores
+                .insert(store(), key(k), b"v".to_vec(), mode, None, None, ttl)
+                .unwrap();
+        };
+        let times = |k| {
+            let val = stores.lookup(store(), key(k)).unwrap();
+            (val.created_at, val.updated_at)
+        };
+
+        // seeded values are created when they are seeded, unless told otherwise
+        clock.advance(Duration::from_secs(1));
+        stores
+            .create_store(
+                store(),
+                [
+                    (key("seeded"), b"v".to_vec(), SeedOptions::default()),
+                    (
+                        key("both"),
+                        b"v".to_vec(),
+                        SeedOptions {
+                            created_at: Some(at(0)),
+                            updated_at: Some(at(0) + Duration::from_millis(500)),
+                            ..Default::default()
+                        },
+                    ),
+                    (
+                        key("created"),
+                        b"v".to_vec(),
+                        SeedOptions {
+                            created_at: Some(at(0)),
+                            ..Default::default()
+                        },
+                    ),
+                ],
+            )
+            .unwrap();
+        assert_eq!(times("seeded"), (at(1), at(1)));
+        assert_eq!(times("both"), (at(0), at(0) + Duration::from_millis(500)));
+        assert_eq!(times("created"), (at(0), at(0)));
+
+        // every write updates a value, but only the first creates it
+        clock.advance(Duration::from_secs(1));
+        insert("key", KvInsertMode::Overwrite, None);
+        assert_eq!(times("key"), (at(2), at(2)));
+        for (secs, mode) in [
+            (3, KvInsertMode::Append),
+            (4, KvInsertMode::Prepend),
+            (5, KvInsertMode::Overwrite),
+        ] {
+            clock.advance(Duration::from_secs(1));
+            insert("key", mode, None);
+            assert_eq!(times("key"), (at(2), at(secs)), "{mode:?}");
+        }
+
+        // a key that was deleted, or whose value expired, is created afresh
+        clock.advance(Duration::from_secs(1));
+        stores.delete(store(), key("key"), None).unwrap();
+        insert("key", KvInsertMode::Append, Some(Duration::from_secs(1)));
+        assert_eq!(times("key"), (at(6), at(6)));
+        clock.advance(Duration::from_secs(1));
+        insert("key", KvInsertMode::Append, None);
+        assert_eq!(times("key"), (at(7), at(7)));
+
+        // and exports include both
+        let export = stores.export(Redaction::None).unwrap();
+        let value = &export.stores[STORE_NAME].items["both"];
+        assert_eq!(
+            (value.created_ms, value.last_modified_ms),
+            (Some(0), Some(500))
+        );
+    }
+
+    #[test]
+    fn test_kv_store_expired_eviction() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores.insert_empty_store(store.clone()).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k: &str| {
+            let ttl = Some(Duration::from_secs(10));
+            stores
+                .insert(
+                    store.clone(),
+                    key(k),
+                    vec![],
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    ttl,
+                )
+                .unwrap();
+            stores.lookup(store.clone(), key(k)).unwrap().generation
+        };
+        let expire = |k: &str| {
+            stores
+                .stores
+                .write()
+                .unwrap()
+                .get_mut(&store)
+                .unwrap()
+                .get_mut(&key(k))
+                .unwrap()
+                .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+        };
+        let present = |k: &str| stores.stores.read().unwrap()[&store].contains_key(&key(k));
+
+        // looking up a live value only takes the read lock, so it works while one is held
+        insert("live");
+        {
+            let _reading = stores.stores.read().unwrap();
+            assert!(stores.lookup(store.clone(), key("live")).is_ok());
+        }
+
+        // an expired value is missing, and is removed once a lookup finds it
+        let generation = insert("expired");
+        expire("expired");
+        assert!(present("expired"));
+        assert_eq!(
+            stores.lookup(store.clone(), key("expired")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+        assert!(!present("expired"));
+        assert!(present("live"));
+
+        // writing it again gives it a new generation
+        assert!(insert("expired") > generation);
+
+        // deleting an expired value fails as if it had never been there
+        expire("expired");
+        assert_eq!(
+            stores.delete(store.clone(), key("expired"), None),
+            Err(KvStoreError::NotFound)
+        );
+        assert_eq!(
+            stores.delete(store.clone(), key("never"), None),
+            Err(KvStoreError::NotFound)
+        );
+        assert!(!present("expired"));
+    }
+
+    #[test]
+    fn test_kv_store_insert_over_expired() {
+        use KvInsertMode::*;
+
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores.insert_empty_store(store.clone()).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |body: &str, mode, generation| {
+            stores.insert(
+                store.clone(),
+                key(),
+                body.into(),
+                mode,
+                generation,
+                None,
+                None,
+            )
+        };
+        // write "old", and let it expire without it being evicted, returning its generation
+        let expired = || {
+            insert("old", Overwrite, None).unwrap();
+            let mut stores = stores.stores.write().unwrap();
+            let val = stores.get_mut(&store).unwrap().get_mut(&key()).unwrap();
+            val.expiration = Some(SystemTime::now() - Duration::from_secs(1));
+            val.generation
+        };
+        let body = || {
+            stores
+                .lookup(store.clone(), key())
+                .map(|val| val.body.to_vec())
+        };
+
+        // every mode writes the value afresh, as if there were none
+        for mode in [Overwrite, Add, Append, Prepend] {
+            expired();
+            assert!(insert("new", mode, None).is_ok(), "{mode:?}");
+            assert_eq!(body(), Ok(b"new".to_vec()), "{mode:?}");
+
+            // the old generation doesn't match, and nothing is written
+            let generation = expired();
+            assert_eq!(
+                insert("new", mode, Some(generation)),
+                Err(KvStoreError::PreconditionFailed),
+                "{mode:?}"
+            );
+            assert_eq!(body(), Err(KvStoreError::NotFound), "{mode:?}");
+        }
+
+        // as with a key that was never written
+        stores.delete(store.clone(), key(), None).unwrap_err();
+        assert_eq!(
+            insert("new", Overwrite, Some(1)),
+            Err(KvStoreError::PreconditionFailed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kv_store_concurrency_limit() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores
+            .configure_store(
+                store.clone(),
+                StoreConfig {
+                    max_concurrent_operations: Some(1),
+                    max_queued_operations: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // the first operation takes the only permit
+        let Ok(Turn::Now(Some(permit))) = stores.take_turn(&store) else {
+            panic!("the first operation didn't run at once");
+        };
+
+        // the next waits its turn
+        let Ok(Turn::Queued(queued)) = stores.take_turn(&store) else {
+            panic!("the second operation didn't queue");
+        };
+        assert_eq!(stores.queued_operations(STORE_NAME), 1);
+
+        // and with the queue full, the one after that is shed
+        assert!(matches!(
+            stores.take_turn(&store),
+            Err(KvStoreError::TooManyRequests)
+        ));
+
+        // the waiting operation gets the permit once it is released, which waiting doesn't block
+        let release = tokio::spawn(async move { drop(permit) });
+        let permit = queued.wait().await;
+        release.await.unwrap();
+        assert_eq!(stores.queued_operations(STORE_NAME), 0);
+
+        // an operation that stops waiting gives up its place in the queue
+        let Ok(Turn::Queued(queued)) = stores.take_turn(&store) else {
+            panic!("the operation didn't queue");
+        };
+        drop(queued);
+        assert_eq!(stores.queued_operations(STORE_NAME), 0);
+        drop(permit);
+
+        // other stores are unlimited
+        let other = ObjectStoreKey::new("other").unwrap();
+        assert!(matches!(stores.take_turn(&other), Ok(Turn::Now(None))));
+    }
+
+    #[test]
+    fn test_kv_store_export_is_stable() {
+        let seed = |keys: &[&str], start: u64| {
+            let clock = MockClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(start));
+            let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+            for store in ["b_store", "a_store"] {
+                for k in keys {
+                    clock.advance(Duration::from_millis(1500));
+                    stores
+                        .insert(
+                            ObjectStoreKey::new(store).unwrap(),
+                            ObjectKey::new(k).unwrap(),
+                            k.repeat(100).into_bytes(),
+                            KvInsertMode::Overwrite,
+                            None,
+                            Some(b"metadata".to_vec()),
+                            None,
+                        )
+                        .unwrap();
+                }
+            }
+            stores
+        };
+        // the same contents, written in a different order and at different times
+        let first = seed(&["x", "y", "z"], 0);
+        let second = seed(&["z", "x", "y", "x"], 1_000_000);
+        let stable = ExportOptions {
+            redaction: Redaction::None,
+            volatile: false,
+        };
+
+        let json = first.export(stable).unwrap().to_json();
+        assert_eq!(json, first.export(stable).unwrap().to_json());
+        assert_eq!(json, second.export(stable).unwrap().to_json());
+        assert_ne!(
+            first.export(Redaction::None).unwrap().to_json(),
+            second.export(Redaction::None).unwrap().to_json()
+        );
+
+        // stores and keys are sorted, and long values are not line-wrapped
+        let json = String::from_utf8(json).unwrap();
+        let order =
+            ["a_store", "\"x\"", "\"y\"", "\"z\"", "b_store"].map(|s| json.find(s).unwrap());
+        assert!(order.is_sorted());
+        assert!(json
+            .lines()
+            .any(|l| l.contains(&BASE64_STANDARD.encode("x".repeat(100)))));
+        assert!(!json.contains("generation"));
+        assert!(json.ends_with("}\n"));
+
+        // volatile fields are plain integers
+        let export = first.export(Redaction::None).unwrap();
+        let value = &export.stores["a_store"].items["x"];
+        assert_eq!(value.last_modified_ms, Some(6_000));
+        assert!(value.generation.is_some());
+    }
+
+    #[test]
+    fn test_kv_store_import() {
+        let stores = ObjectStores::default();
+        for (key, body) in [("x", "1"), ("y", "2")] {
+            stores
+                .insert(
+                    ObjectStoreKey::new("a_store").unwrap(),
+                    ObjectKey::new(key).unwrap(),
+                    body.into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    Some(b"metadata".to_vec()),
+                    None,
+                )
+                .unwrap();
+        }
+        stores
+            .insert_empty_store(ObjectStoreKey::new("empty_store").unwrap())
+            .unwrap();
+        let stable = ExportOptions {
+            redaction: Redaction::None,
+            volatile: false,
+        };
+        let json = stores.export(stable).unwrap().to_json();
+        let export = KvExport::from_json(&json).unwrap();
+        assert_eq!(export.version, KV_EXPORT_FORMAT_VERSION);
+
+        // importing an export reproduces the stores it came from
+        let imported = ObjectStores::default();
+        assert_eq!(imported.import(&export).unwrap(), 2);
+        assert_eq!(imported.export(stable).unwrap().to_json(), json);
+        let val = imported
+            .lookup(
+                ObjectStoreKey::new("a_store").unwrap(),
+                ObjectKey::new("y").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(val.body, &b"2"[..]);
+        assert_eq!(val.metadata, b"metadata");
+
+        // the previous version had no version field
+        let legacy = br#"{"stores":{"b_store":{"items":{"k":{"body":"dg==","metadata":""}}}}}"#;
+        let legacy = KvExport::from_json(legacy).unwrap();
+        assert_eq!(legacy.version, 1);
+        assert_eq!(imported.import(&legacy).unwrap(), 1);
+        let val = imported
+            .lookup(
+                ObjectStoreKey::new("b_store").unwrap(),
+                ObjectKey::new("k").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(val.body, &b"v"[..]);
+
+        // later versions are refused
+        let future = KvExport {
+            version: KV_EXPORT_FORMAT_VERSION + 1,
+            ..export.clone()
+        };
+        assert!(matches!(
+            ObjectStores::default().import(&future),
+            Err(ObjectStoreError::UnsupportedExportVersion(v)) if v == KV_EXPORT_FORMAT_VERSION + 1
+        ));
+
+        // a redacted value can't be imported, and nothing else is written either
+        let mut redacted = export.clone();
+        redacted
+            .stores
+            .get_mut("a_store")
+            .unwrap()
+            .items
+            .get_mut("y")
+            .unwrap()
+            .body = ExportedBytes::Redacted {
+            len: 1,
+            hash: "00000000".to_string(),
+        };
+        let target = ObjectStores::default();
+        assert!(matches!(
+            target.import(&redacted),
+            Err(ObjectStoreError::InvalidExport(_))
+        ));
+        assert_eq!(target.store_key("a_store").unwrap(), None);
+
+        assert!(matches!(
+            KvExport::from_json(b"{\"stores\": []}"),
+            Err(ObjectStoreError::InvalidExport(_))
+        ));
+    }
+
+    /// Check `value` against the subset of JSON Schema used by the checked-in schemas, recording
+    /// the path of every property it has.
+    fn check_schema(
+        value: &serde_json::Value,
+        schema: &serde_json::Value,
+        path: &str,
+        seen: &mut BTreeSet<String>,
+    ) {
+        use serde_json::Value;
+        let ty = schema["type"].as_str().unwrap();
+        match (ty, value) {
+            ("object", Value::Object(fields)) => {
+                let properties = schema["properties"].as_object().unwrap();
+                for required in schema["required"].as_array().unwrap() {
+                    let required = required.as_str().unwrap();
+                    assert!(fields.contains_key(required), "{path} lacks {required}");
+                }
+                for (name, field) in fields {
+                    let path = format!("{path}/{name}");
+                    let Some(field_schema) = properties.get(name) else {
+                        panic!("{path} is not in the schema");
+                    };
+                    check_schema(field, field_schema, &path, seen);
+                    seen.insert(path);
+                }
+            }
+            ("array", Value::Array(items)) => {
+                for item in items {
+                    check_schema(item, &schema["items"], &format!("{path}/*"), seen);
+                }
+            }
+            ("string", Value::String(_)) => {}
+            ("integer", Value::Number(n)) if n.is_u64() => {}
+            _ => panic!("{path} is not of type {ty}: {value}"),
+        }
+    }
+
+    /// The paths of every property a schema describes.
+    fn schema_properties(schema: &serde_json::Value, path: &str, paths: &mut BTreeSet<String>) {
+        if let Some(properties) = schema["properties"].as_object() {
+            for (name, property) in properties {
+                let path = format!("{path}/{name}");
+                schema_properties(property, &path, paths);
+                paths.insert(path);
+            }
+        }
+    }
+
+    // If this fails, the shape of list responses has changed. List responses match production,
+    // and guests and tools depend on their shape, so only change the schema alongside a
+    // deliberate change to the shape.
+    #[test]
+    fn test_kv_store_list_matches_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(include_str!("../../test-fixtures/data/kv-list-schema.json"))
+                .unwrap();
+
+        let stores = ObjectStores::default();
+        for key in ["a/1", "a/2", "b/1"] {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey::new(key).unwrap(),
+                    key.into(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // between them, these responses include every optional field
+        let first = stores
+            .list(
+                ObjectStoreKey::new(STORE_NAME).unwrap(),
+                None,
+                Some("a/".into()),
+                1,
+            )
+            .unwrap();
+        let first: serde_json::Value = serde_json::from_slice(&first).unwrap();
+        let cursor = first["meta"]["next_cursor"].as_str().map(str::to_string);
+        let responses = [
+            first,
+            stores
+                .list(ObjectStoreKey::new(STORE_NAME).unwrap(), cursor, None, 1000)
+                .map(|body| serde_json::from_slice(&body).unwrap())
+                .unwrap(),
+        ];
+
+        let mut seen = BTreeSet::new();
+        for response in &responses {
+            check_schema(response, &schema, "", &mut seen);
+        }
+        let mut described = BTreeSet::new();
+        schema_properties(&schema, "", &mut described);
+        assert_eq!(seen, described);
+    }
+
+    #[test]
+    fn test_kv_store_list_escapes_keys() {
+        let stores = ObjectStores::default();
+        let keys = [
+            "tab\there",
+            "del\u{7f}",
+            "bell\u{7}",
+            "quote\"back\\slash",
+            "caf\u{e9}/\u{1f600}",
+        ];
+        for key in keys {
+            stores
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey::new(key).unwrap(),
+                    b"v".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        let body = stores
+            .list(ObjectStoreKey::new(STORE_NAME).unwrap(), None, None, 1000)
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            concat!(
+                r#"{"data":["bell\u0007","#,
+                "\"caf\u{e9}/\u{1f600}\",\"del\u{7f}\",",
+                r#""quote\"back\\slash","tab\there"],"meta":{"limit":1000}}"#,
+            )
+        );
+        // and every key reads back exactly, paging through cursors included
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = stores
+                .list(ObjectStoreKey::new(STORE_NAME).unwrap(), cursor, None, 2)
+                .unwrap();
+            let page = ObjectStores::parse_list_response(&page).unwrap();
+            listed.extend(page.data);
+            cursor = page.meta.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        let mut sorted = keys.map(str::to_string).to_vec();
+        sorted.sort();
+        assert_eq!(listed, sorted);
+    }
+
+    #[test]
+    fn test_kv_store_list_response_round_trips() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        for key in ["a/1", "a/2", "b"] {
+            stores
+                .insert(
+                    store(),
+                    ObjectKey::new(key).unwrap(),
+                    b"v".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // a page with every optional field, and one with none
+        let first = stores
+            .list(store(), None, Some("a/".to_owned()), 1)
+            .unwrap();
+        let parsed = ObjectStores::parse_list_response(&first).unwrap();
+        assert_eq!(
+            parsed,
+            ListResponse {
+                data: vec!["a/1".to_owned()],
+                meta: ListMeta {
+                    limit: 1,
+                    prefix: Some("a/".to_owned()),
+                    next_cursor: Some(BASE64_STANDARD.encode("a/1")),
+                },
+            }
+        );
+        assert_eq!(serde_json::to_vec(&parsed).unwrap(), first);
+
+        let all = stores.list(store(), None, None, 1000).unwrap();
+        let parsed = ObjectStores::parse_list_response(&all).unwrap();
+        assert_eq!(
+            parsed.meta,
+            ListMeta {
+                limit: 1000,
+                ..ListMeta::default()
+            }
+        );
+        assert_eq!(serde_json::to_vec(&parsed).unwrap(), all);
+
+        // fields that production might add are ignored, while a response without keys is refused
+        let extra = br#"{"data":["k"],"meta":{"limit":1,"total":3},"extra":true}"#;
+        assert_eq!(
+            ObjectStores::parse_list_response(extra).unwrap().data,
+            ["k"]
+        );
+        assert!(ObjectStores::parse_list_response(br#"{"meta":{"limit":1}}"#).is_err());
+    }
+
+    #[test]
+    fn test_kv_store_value_origin() {
+        let stores = ObjectStores::default();
+        let seed = stores.with_origin(ValueOrigin::Seed);
+        let guest = stores.with_origin(ValueOrigin::Runtime { req_id: Some(7) });
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let write = |stores: &ObjectStores, k: &str, mode| {
+            stores
+                .insert(
+                    store(),
+                    ObjectKey::new(k).unwrap(),
+                    b"value".to_vec(),
+                    mode,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+        let origin = |k: &str| {
+            stores
+                .lookup(store(), ObjectKey::new(k).unwrap())
+                .unwrap()
+                .origin
+        };
+        use KvInsertMode::*;
+
+        // seeding
+        write(&seed, "seeded", Overwrite);
+        assert_eq!(origin("seeded"), ValueOrigin::Seed);
+        write(&seed, "seeded", Append);
+        assert_eq!(origin("seeded"), ValueOrigin::Seed);
+
+        // extending seed data mixes the origins, and overwriting it replaces them
+        write(&guest, "seeded", Append);
+        assert_eq!(origin("seeded"), ValueOrigin::Mixed { req_id: Some(7) });
+        write(&stores, "seeded", Prepend);
+        assert_eq!(origin("seeded"), ValueOrigin::Mixed { req_id: None });
+        write(&guest, "seeded", Overwrite);
+        assert_eq!(origin("seeded"), ValueOrigin::Runtime { req_id: Some(7) });
+
+        // extending runtime data leaves it runtime, unless the extension is seed data
+        write(&stores, "seeded", Append);
+        assert_eq!(origin("seeded"), ValueOrigin::Runtime { req_id: None });
+        write(&seed, "seeded", Prepend);
+        assert_eq!(origin("seeded"), ValueOrigin::Mixed { req_id: None });
+
+        // new keys take the writer's origin, whatever the mode
+        write(&guest, "added", Add);
+        assert_eq!(origin("added"), ValueOrigin::Runtime { req_id: Some(7) });
+        write(&guest, "appended", Append);
+        assert_eq!(origin("appended"), ValueOrigin::Runtime { req_id: Some(7) });
+        write(&seed, "prepended", Prepend);
+        assert_eq!(origin("prepended"), ValueOrigin::Seed);
+
+        // namespaces write with the origin of the handle they were opened from
+        let config = KvNamespaceConfig::new(http::HeaderName::from_static("x-namespace"));
+        let namespace = guest.namespace("ns", &config).unwrap();
+        write(&namespace, "namespaced", Overwrite);
+        let value = namespace
+            .lookup(store(), ObjectKey::new("namespaced").unwrap())
+            .unwrap();
+        assert_eq!(value.origin, ValueOrigin::Runtime { req_id: Some(7) });
+        // and keep the origins of the values they were seeded with
+        let value = namespace
+            .lookup(store(), ObjectKey::new("added").unwrap())
+            .unwrap();
+        assert_eq!(value.origin, ValueOrigin::Runtime { req_id: Some(7) });
+    }
+
+    #[test]
+    fn test_kv_store_transaction_rollback() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |txn: &mut KvTransaction<'_>, k: &str, v: &str, mode| {
+            txn.insert(
+                store.clone(),
+                key(k),
+                v.as_bytes().to_vec(),
+                mode,
+                None,
+                None,
+                None,
+            )
+        };
+        stores
+            .insert(
+                store.clone(),
+                key("existing"),
+                b"old".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        // staged reads see staged writes
+        stores
+            .transaction(|txn| {
+                insert(txn, "new", "1", KvInsertMode::Overwrite)?;
+                insert(txn, "new", "2", KvInsertMode::Append)?;
+                assert_eq!(txn.lookup(store.clone(), key("new"))?.body, &b"12"[..]);
+                txn.delete(store.clone(), key("new"))?;
+                assert_eq!(
+                    txn.lookup(store.clone(), key("new")).unwrap_err(),
+                    KvStoreError::NotFound
+                );
+                Ok::<_, KvStoreError>(())
+            })
+            .unwrap();
+        assert_eq!(
+            stores.lookup(store.clone(), key("new")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+
+        // an error returned by the transaction discards everything it staged
+        let res = stores.transaction(|txn| {
+            insert(txn, "existing", "new", KvInsertMode::Overwrite)?;
+            txn.delete(store.clone(), key("existing"))?;
+            insert(txn, "other", "value", KvInsertMode::Overwrite)?;
+            Err::<(), _>(KvStoreError::BadRequest)
+        });
+        assert_eq!(res, Err(KvStoreError::BadRequest));
+
+        // as does a failing operation propagated out of it
+        let res = stores.transaction(|txn| {
+            insert(txn, "other", "value", KvInsertMode::Overwrite)?;
+            insert(txn, "existing", "new", KvInsertMode::Add)
+        });
+        assert_eq!(res, Err(KvStoreError::PreconditionFailed));
+
+        assert_eq!(
+            stores.lookup(store.clone(), key("existing")).unwrap().body,
+            &b"old"[..]
+        );
+        assert_eq!(
+            stores.lookup(store.clone(), key("other")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+    }
+
+    #[test]
+    fn test_kv_store_transaction_is_atomic() {
+        use std::sync::atomic::AtomicBool;
+
+        const KEYS: [&str; 4] = ["a", "b", "c", "d"];
+
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let write_all = |value: &str| {
+            stores
+                .transaction(|txn| {
+                    for k in KEYS {
+                        txn.insert(
+                            store.clone(),
+                            ObjectKey::new(k).unwrap(),
+                            value.as_bytes().to_vec(),
+                            KvInsertMode::Overwrite,
+                            None,
+                            None,
+                            None,
+                        )?;
+                    }
+                    Ok::<_, KvStoreError>(())
+                })
+                .unwrap()
+        };
+        write_all("0");
+
+        // a concurrent reader sees every key as of the same transaction
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    let export = stores.export(Redaction::None).unwrap();
+                    let values = export.stores[STORE_NAME]
+                        .items
+                        .values()
+                        .map(|v| v.body.clone())
+                        .collect::<Vec<_>>();
+                    assert_eq!(values.len(), KEYS.len());
+                    assert!(
+                        values.iter().all(|v| *v == values[0]),
+                        "saw a half-applied transaction: {values:?}"
+                    );
+                }
+            });
+            for i in 1..200 {
+                write_all(&i.to_string());
+            }
+            done.store(true, Ordering::Relaxed);
+            reader.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_kv_store_transaction_events() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Log(Mutex<Vec<String>>);
+
+        impl KvObserver for Log {
+            fn on_event(&self, event: &KvEvent<'_>) {
+                let op = match &event.op {
+                    KvOp::Lookup { key, .. } => format!("lookup {key}"),
+                    KvOp::Head { key, .. } => format!("head {key}"),
+                    KvOp::Insert { key, result, .. } => format!("insert {key} {result:?}"),
+                    KvOp::Delete { key, result } => format!("delete {key} {result:?}"),
+                    KvOp::List { .. } => "list".to_string(),
+                };
+                self.0.lock().unwrap().push(op);
+            }
+        }
+
+        let stores = ObjectStores::default();
+        let log = Arc::new(Log::default());
+        stores.add_observer(log.clone());
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = ObjectKey::new("key").unwrap();
+        let run = |fail: bool| {
+            stores.transaction(|txn| {
+                txn.insert(
+                    store.clone(),
+                    key.clone(),
+                    b"value".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )?;
+                // nothing is reported until the transaction commits
+                assert!(log.0.lock().unwrap().is_empty());
+                txn.lookup(store.clone(), key.clone())?;
+                let _ = txn.delete(store.clone(), ObjectKey::new("missing").unwrap());
+                if fail {
+                    return Err(KvStoreError::InternalError);
+                }
+                Ok(())
+            })
+        };
+
+        // a rolled back transaction reports nothing
+        assert_eq!(run(true), Err(KvStoreError::InternalError));
+        assert!(log.0.lock().unwrap().is_empty());
+
+        run(false).unwrap();
+        assert_eq!(
+            *log.0.lock().unwrap(),
+            [
+                "insert key Ok(())",
+                "lookup key",
+                "delete missing Err(NotFound)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kv_store_expired_lookups_are_told_apart() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Misses(Mutex<Vec<(String, bool)>>);
+
+        impl KvObserver for Misses {
+            fn on_event(&self, event: &KvEvent<'_>) {
+                if let KvOp::Lookup {
+                    key,
+                    result: Err(KvStoreError::NotFound),
+                    expired,
+                } = &event.op
+                {
+                    self.0.lock().unwrap().push((key.to_string(), *expired));
+                }
+            }
+        }
+
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let misses = Arc::new(Misses::default());
+        stores.add_observer(misses.clone());
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        for k in ["short-lived", "in-txn"] {
+            stores
+                .insert(
+                    store.clone(),
+                    key(k),
+                    b"value".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    Some(Duration::from_secs(10)),
+                )
+                .unwrap();
+        }
+        clock.advance(Duration::from_secs(11));
+
+        // the guest sees the same status either way
+        for k in ["short-lived", "never", "short-lived"] {
+            assert_eq!(
+                stores.lookup(store.clone(), key(k)).unwrap_err(),
+                KvStoreError::NotFound
+            );
+        }
+        stores
+            .transaction(|txn| {
+                assert!(txn.lookup(store.clone(), key("in-txn")).is_err());
+                Ok::<_, KvStoreError>(())
+            })
+            .unwrap();
+
+        assert_eq!(
+            *misses.0.lock().unwrap(),
+            [
+                ("short-lived".to_string(), true),
+                ("never".to_string(), false),
+                // evicted by the first lookup, so as good as never written
+                ("short-lived".to_string(), false),
+                ("in-txn".to_string(), true),
+            ]
+        );
+    }
+
+    /// Insert `count` keys named `{prefix}{i}` into `store`.
+    fn insert_keys(stores: &ObjectStores, store: &ObjectStoreKey, prefix: &str, count: usize) {
+        for i in 0..count {
+            stores
+                .insert(
+                    store.clone(),
+                    ObjectKey::new(format!("{prefix}{i}")).unwrap(),
+                    b"value".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_kv_store_count() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        assert_eq!(stores.count(&store, None), Err(KvStoreError::Uninitialized));
+
+        stores.insert_empty_store(store.clone()).unwrap();
+        assert_eq!(stores.count(&store, None), Ok(0));
+        assert_eq!(stores.count(&store, Some("session/")), Ok(0));
+
+        insert_keys(&stores, &store, "session/", 5);
+        insert_keys(&stores, &store, "sessions", 2);
+        insert_keys(&stores, &store, "user/", 3);
+        assert_eq!(stores.count(&store, None), Ok(10));
+        assert_eq!(stores.count(&store, Some("")), Ok(10));
+        assert_eq!(stores.count(&store, Some("session")), Ok(7));
+        assert_eq!(stores.count(&store, Some("session/")), Ok(5));
+        assert_eq!(stores.count(&store, Some("user/1")), Ok(1));
+        assert_eq!(stores.count(&store, Some("nothing/")), Ok(0));
+        assert_eq!(stores.count(&store, Some("zzz")), Ok(0));
+        assert_eq!(
+            stores.count(&store, Some("session/*")),
+            Err(KvStoreError::BadRequest)
+        );
+
+        // expired keys are not counted
+        for key in ["session/0", "session/1"] {
+            stores
+                .stores
+                .write()
+                .unwrap()
+                .get_mut(&store)
+                .unwrap()
+                .get_mut(&ObjectKey::new(key).unwrap())
+                .unwrap()
+                .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+        }
+        assert_eq!(stores.count(&store, Some("session/")), Ok(3));
+        assert_eq!(stores.count(&store, None), Ok(8));
+    }
+
+    #[test]
+    fn test_kv_store_snapshot() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        assert_eq!(
+            stores.snapshot(&store, None).unwrap_err(),
+            KvStoreError::Uninitialized
+        );
+        stores
+            .configure_store(
+                store.clone(),
+                StoreConfig {
+                    compression: Some(Compression::Gzip),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        insert_keys(&stores, &store, "session/", 3);
+        insert_keys(&stores, &store, "user/", 2);
+        stores
+            .insert(
+                store.clone(),
+                ObjectKey::new("session/1").unwrap(),
+                b"rewritten".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                Some(b"meta".to_vec()),
+                None,
+            )
+            .unwrap();
+        stores
+            .stores
+            .write()
+            .unwrap()
+            .get_mut(&store)
+            .unwrap()
+            .get_mut(&ObjectKey::new("session/2").unwrap())
+            .unwrap()
+            .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+
+        // expired values are left out, and bodies are decompressed
+        let snapshot = stores.snapshot(&store, Some("session/")).unwrap();
+        let keys = snapshot.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, ["session/0", "session/1"]);
+        let (_, rewritten) = &snapshot[1];
+        assert_eq!(&rewritten.body()[..], b"rewritten");
+        assert_eq!(rewritten.metadata(), b"meta");
+        let looked_up = stores
+            .lookup(store.clone(), ObjectKey::new("session/1").unwrap())
+            .unwrap();
+        assert_eq!(rewritten.generation(), looked_up.generation());
+        assert!(rewritten.compression.is_none());
+        assert_eq!(stores.snapshot(&store, None).unwrap().len(), 4);
+
+        // the snapshot is a copy, so later writes don't show up in it
+        stores
+            .delete(store.clone(), ObjectKey::new("session/0").unwrap(), None)
+            .unwrap();
+        assert_eq!(snapshot[0].1.body()[..], b"value"[..]);
+        assert_eq!(
+            stores.snapshot(&store, Some("session/*")).unwrap_err(),
+            KvStoreError::BadRequest
+        );
+    }
+
+    #[test]
+    fn test_kv_store_delete_prefix_and_clear() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        assert_eq!(
+            stores.delete_prefix(store.clone(), "session/"),
+            Err(KvStoreError::Uninitialized)
+        );
+
+        insert_keys(&stores, &store, "session/", 5);
+        insert_keys(&stores, &store, "sessions", 2);
+        insert_keys(&stores, &store, "user/", 3);
+        stores
+            .stores
+            .write()
+            .unwrap()
+            .get_mut(&store)
+            .unwrap()
+            .get_mut(&ObjectKey::new("session/0").unwrap())
+            .unwrap()
+            .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+
+        // the expired key is removed, but not counted
+        assert_eq!(stores.delete_prefix(store.clone(), "session/"), Ok(4));
+        assert_eq!(stores.count(&store, Some("session")), Ok(2));
+        assert_eq!(stores.stores.read().unwrap()[&store].len(), 5);
+        assert_eq!(stores.delete_prefix(store.clone(), "session/"), Ok(0));
+        assert_eq!(
+            stores.delete_prefix(store.clone(), "session/*"),
+            Err(KvStoreError::BadRequest)
+        );
+
+        // clearing keeps the store, so it still lists
+        assert_eq!(stores.clear(store.clone()), Ok(5));
+        assert_eq!(
+            stores.list(store.clone(), None, None, 1000).unwrap(),
+            br#"{"data":[],"meta":{"limit":1000}}"#
+        );
+        assert_eq!(stores.clear(store), Ok(0));
+    }
+
+    #[test]
+    fn test_kv_store_purge_expired() {
+        let stores = ObjectStores::default();
+        let (one, two) = (
+            ObjectStoreKey::new("one").unwrap(),
+            ObjectStoreKey::new("two").unwrap(),
+        );
+        stores
+            .configure_store(
+                two.clone(),
+                StoreConfig {
+                    key_filter: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        insert_keys(&stores, &one, "key", 10);
+        insert_keys(&stores, &two, "key", 10);
+        let expire = |stores: &ObjectStores, store: &ObjectStoreKey, keys: &[&str]| {
+            let mut stores = stores.stores.write().unwrap();
+            for key in keys {
+                stores
+                    .get_mut(store)
+                    .unwrap()
+                    .get_mut(&ObjectKey::new(key).unwrap())
+                    .unwrap()
+                    .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+            }
+        };
+        let len = |stores: &ObjectStores, store: &ObjectStoreKey| {
+            stores.stores.read().unwrap()[store].len()
+        };
+
+        expire(&stores, &one, &["key0", "key1", "key2"]);
+        expire(&stores, &two, &["key9"]);
+        assert_eq!(stores.purge_expired(), Ok(4));
+        assert_eq!((len(&stores, &one), len(&stores, &two)), (7, 9));
+        assert_eq!(stores.purge_expired(), Ok(0));
+        // the rebuilt filter still finds every live key
+        for i in 0..9 {
+            let key = ObjectKey::new(format!("key{i}")).unwrap();
+            assert!(stores.lookup(two.clone(), key).is_ok());
+        }
+
+        // namespaces are purged along with the stores they were copied from
+        let config = KvNamespaceConfig::new(http::HeaderName::from_static("x-ns"));
+        let namespace = stores.namespace("ns", &config).unwrap();
+        expire(&namespace, &one, &["key3"]);
+        assert_eq!(stores.purge_expired(), Ok(1));
+        assert_eq!((len(&stores, &one), len(&namespace, &one)), (7, 6));
+    }
+
+    #[tokio::test]
+    async fn test_kv_store_await_key() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+
+        // a key that is already present is returned at once
+        stores
+            .insert(
+                store.clone(),
+                key("present"),
+                b"here".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        let value = stores
+            .await_key(store.clone(), key("present"), Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(value.body, &b"here"[..]);
+
+        // a key written after the wait starts wakes it, even when written to another key first
+        let writer = stores.clone();
+        let written = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            for (k, body) in [("other", "no"), ("later", "now")] {
+                writer
+                    .insert(
+                        ObjectStoreKey::new(STORE_NAME).unwrap(),
+                        ObjectKey::new(k).unwrap(),
+                        body.into(),
+                        KvInsertMode::Overwrite,
+                        None,
+                        None,
+                        None,
+                    )
+                    .unwrap();
+            }
+        });
+        let value = stores
+            .await_key(store.clone(), key("later"), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(value.body, &b"now"[..]);
+        written.await.unwrap();
+
+        // a key that is never written times out, leaving no observer behind
+        let res = stores
+            .await_key(store.clone(), key("never"), Duration::from_millis(20))
+            .await;
+        assert_eq!(
+            res.map(|value| value.body),
+            Err(AwaitKeyError::TimedOut(Duration::from_millis(20)))
+        );
+        assert!(stores.observers.is_empty());
+    }
+
+    #[test]
+    fn test_kv_store_blocking_await_key() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+
+        let writer = stores.clone();
+        let written = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            writer
+                .insert(
+                    ObjectStoreKey::new(STORE_NAME).unwrap(),
+                    ObjectKey::new("later").unwrap(),
+                    b"now".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+        let value = stores
+            .blocking_await_key(store(), key("later"), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(value.body, &b"now"[..]);
+        written.join().unwrap();
+
+        assert_eq!(
+            stores
+                .blocking_await_key(store(), key("never"), Duration::from_millis(20))
+                .map(|value| value.body),
+            Err(AwaitKeyError::TimedOut(Duration::from_millis(20)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kv_store_blocking_await_key_in_async_context() {
+        let stores = ObjectStores::default();
+        let store = || ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("present").unwrap();
+        stores
+            .insert(
+                store(),
+                key(),
+                b"here".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        // even a key that is already present is refused, so a misplaced call fails every time
+        // rather than only when it would have had to wait
+        assert_eq!(
+            stores
+                .blocking_await_key(store(), key(), Duration::from_secs(5))
+                .map(|value| value.body),
+            Err(AwaitKeyError::InAsyncContext)
+        );
+        let blocking = stores.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            blocking.blocking_await_key(store(), key(), Duration::from_secs(5))
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            res.map(|value| value.body),
+            Err(AwaitKeyError::InAsyncContext)
+        );
+        assert!(stores.observers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kv_store_purge_task() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        insert_keys(&stores, &store, "key", 2);
+        stores
+            .stores
+            .write()
+            .unwrap()
+            .get_mut(&store)
+            .unwrap()
+            .get_mut(&ObjectKey::new("key0").unwrap())
+            .unwrap()
+            .expiration = Some(SystemTime::now() - Duration::from_secs(1));
+
+        assert_eq!(
+            stores.purge_expired_every(Duration::ZERO).err(),
+            Some(KvStoreError::BadRequest)
+        );
+        let task = stores
+            .purge_expired_every(Duration::from_millis(10))
+            .unwrap();
+        let len = || stores.stores.read().unwrap()[&store].len();
+        for _ in 0..500 {
+            if len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(len(), 1);
+
+        // the task stops when told to, even while other handles to the stores are held
+        let _shared = stores.clone();
+        tokio::time::timeout(Duration::from_secs(5), task.stop())
+            .await
+            .expect("purge task should stop");
+    }
+
+    #[test]
+    fn test_kv_store_key_filter() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: String| ObjectKey::new(k).unwrap();
+        let settings = StoreConfig {
+            key_filter: true,
+            ..Default::default()
+        };
+
+        // a filtered store that doesn't exist yet is still uninitialized
+        stores
+            .configure_store(store.clone(), settings.clone())
+            .unwrap();
+        assert_eq!(
+            stores.lookup(store.clone(), key("a".into())).err(),
+            Some(KvStoreError::Uninitialized)
+        );
+        stores.insert_empty_store(store.clone()).unwrap();
+        assert_eq!(
+            stores.lookup(store.clone(), key("a".into())).err(),
+            Some(KvStoreError::NotFound)
+        );
+
+        // enough inserts to outgrow the filter, then enough deletes to force a rebuild
+        insert_keys(&stores, &store, "key", 5000);
+        for i in (0..5000).step_by(2) {
+            stores
+                .delete(store.clone(), key(format!("key{i}")), None)
+                .unwrap();
+        }
+        for i in 0..5000 {
+            let res = stores.lookup(store.clone(), key(format!("key{i}")));
+            if i % 2 == 0 {
+                assert_eq!(res.err(), Some(KvStoreError::NotFound), "key{i}");
+            } else {
+                assert!(res.is_ok(), "key{i}");
+            }
+        }
+
+        // keys written in a transaction are seen once it commits
+        stores
+            .transaction(|txn| {
+                txn.delete(store.clone(), key("key1".into()))?;
+                txn.insert(
+                    store.clone(),
+                    key("txn".into()),
+                    b"value".to_vec(),
+                    KvInsertMode::Add,
+                    None,
+                    None,
+                    None,
+                )
+            })
+            .unwrap();
+        assert!(stores.lookup(store.clone(), key("txn".into())).is_ok());
+        assert_eq!(
+            stores.lookup(store.clone(), key("key1".into())).err(),
+            Some(KvStoreError::NotFound)
+        );
+
+        // configuring a filter on a store with keys already in it covers them
+        let other = ObjectStoreKey::new("other").unwrap();
+        insert_keys(&stores, &other, "key", 100);
+        stores.configure_store(other.clone(), settings).unwrap();
+        for i in 0..100 {
+            assert!(stores.lookup(other.clone(), key(format!("key{i}"))).is_ok());
+        }
+
+        // and a namespace filters its own copy of the store
+        let config = KvNamespaceConfig::new(http::HeaderName::from_static("x-namespace"));
+        let namespace = stores.namespace("ns", &config).unwrap();
+        insert_keys(&namespace, &store, "ns", 10);
+        assert!(namespace.lookup(store.clone(), key("key3".into())).is_ok());
+        assert!(namespace.lookup(store.clone(), key("ns3".into())).is_ok());
+        assert_eq!(
+            stores.lookup(store.clone(), key("ns3".into())).err(),
+            Some(KvStoreError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_kv_store_insert_stats() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let insert = |key: &str, body: &[u8], mode| {
+            stores.insert(
+                store.clone(),
+                ObjectKey::new(key).unwrap(),
+                body.to_vec(),
+                mode,
+                None,
+                None,
+                None,
+            )
+        };
+
+        insert("a", b"12", KvInsertMode::Overwrite).unwrap();
+        insert("b", b"1", KvInsertMode::Add).unwrap();
+        for _ in 0..3 {
+            insert("a", b"345", KvInsertMode::Append).unwrap();
+        }
+        insert("b", b"0", KvInsertMode::Prepend).unwrap();
+        // failed inserts aren't counted
+        insert("b", b"1", KvInsertMode::Add).unwrap_err();
+
+        // namespaces count towards the same stores
+        let config = KvNamespaceConfig::new(http::HeaderName::from_static("x-ns"));
+        let namespace = stores.namespace("ns", &config).unwrap();
+        namespace
+            .insert(
+                store.clone(),
+                ObjectKey::new("a").unwrap(),
+                b"!".to_vec(),
+                KvInsertMode::Append,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let stats = stores.insert_stats();
+        assert_eq!(
+            stats[STORE_NAME],
+            InsertStats {
+                overwrites: 1,
+                adds: 1,
+                appends: 4,
+                prepends: 1,
+                largest_value: 12,
+            }
+        );
+        assert_eq!(stats[STORE_NAME].inserts(), 7);
+
+        // inserts that don't land aren't counted: those the memory budget refuses, those lost
+        // write rules drop, and those staged by a transaction that doesn't commit
+        stores.set_memory_budget(Some(MemoryBudget {
+            max_bytes: 12,
+            policy: BudgetPolicy::Reject,
+        }));
+        insert("big", &[0; 64], KvInsertMode::Overwrite).unwrap_err();
+        stores.set_memory_budget(None);
+        stores.add_lost_write_rule(LostWriteRule {
+            store: Some(STORE_NAME.to_string()),
+            key_prefix: Some("lost/".to_string()),
+            probability: 1.0,
+            seed: 0,
+        });
+        insert("lost/a", b"1", KvInsertMode::Overwrite).unwrap();
+        let rolled_back: Result<(), KvStoreError> = stores.transaction(|txn| {
+            txn.insert(
+                store.clone(),
+                ObjectKey::new("c").unwrap(),
+                b"1".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )?;
+            Err(KvStoreError::BadRequest)
+        });
+        assert_eq!(rolled_back, Err(KvStoreError::BadRequest));
+        // nor are values seeded or written whole, as no guest wrote them
+        stores
+            .create_store(
+                store.clone(),
+                [(
+                    ObjectKey::new("seeded").unwrap(),
+                    b"1".to_vec(),
+                    SeedOptions::default(),
+                )],
+            )
+            .unwrap();
+        let value = ObjectValue::new(b"1".to_vec()).build(SystemTime::now());
+        stores
+            .insert_many(store.clone(), [(ObjectKey::new("many").unwrap(), value)])
+            .unwrap();
+        assert_eq!(stores.insert_stats(), stats);
+
+        // while those a committed transaction staged are
+        stores
+            .transaction(|txn| {
+                txn.insert(
+                    store.clone(),
+                    ObjectKey::new("c").unwrap(),
+                    b"1".to_vec(),
+                    KvInsertMode::Add,
+                    None,
+                    None,
+                    None,
+                )
+            })
+            .unwrap();
+        assert_eq!(stores.insert_stats()[STORE_NAME].adds, 2);
+    }
+
+    #[test]
+    fn test_kv_store_stats() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k: &str, body: &[u8], mode, metadata: Option<&[u8]>, ttl| {
+            stores
+                .insert(
+                    store.clone(),
+                    key(k),
+                    body.to_vec(),
+                    mode,
+                    None,
+                    metadata.map(<[u8]>::to_vec),
+                    ttl,
+                )
+                .unwrap();
+        };
+        // the running totals, against totals found by walking every value
+        let check = |key_count, value_bytes, metadata_bytes| {
+            let stats = stores.stats_for(STORE_NAME).unwrap();
+            let walked = stores.stores.read().unwrap()[&store].iter().fold(
+                (0, 0),
+                |(value_bytes, metadata_bytes), (_, val)| {
+                    (
+                        value_bytes + val.body.len(),
+                        metadata_bytes + val.metadata.len(),
+                    )
+                },
+            );
+            assert_eq!((stats.value_bytes, stats.metadata_bytes), walked);
+            assert_eq!(
+                stats,
+                StoreStats {
+                    name: STORE_NAME.to_string(),
+                    key_count,
+                    value_bytes,
+                    metadata_bytes,
+                }
+            );
+        };
+
+        assert_eq!(stores.stats_for(STORE_NAME), None);
+        insert("a", b"12", KvInsertMode::Overwrite, Some(b"m"), None);
+        insert("b", b"345", KvInsertMode::Overwrite, None, None);
+        check(2, 5, 1);
+        insert("a", b"6", KvInsertMode::Append, None, None);
+        insert("b", b"7", KvInsertMode::Overwrite, Some(b"meta"), None);
+        check(2, 4, 5);
+        stores.delete(store.clone(), key("a"), None).unwrap();
+        check(1, 1, 4);
+
+        // expired values are counted until they are evicted
+        insert(
+            "c",
+            b"89",
+            KvInsertMode::Overwrite,
+            None,
+            Some(Duration::from_secs(1)),
+        );
+        clock.advance(Duration::from_secs(2));
+        check(2, 3, 4);
+        assert_eq!(
+            stores.lookup(store.clone(), key("c")).unwrap_err(),
+            KvStoreError::NotFound
+        );
+        check(1, 1, 4);
+
+        stores
+            .transaction(|txn| {
+                txn.insert(
+                    store.clone(),
+                    key("d"),
+                    b"txn".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+            })
+            .unwrap();
+        check(2, 4, 4);
+        assert_eq!(stores.clear(store.clone()), Ok(2));
+        check(0, 0, 0);
+
+        let other = ObjectStoreKey::new("other").unwrap();
+        stores.insert_empty_store(other).unwrap();
+        let names = stores
+            .stats()
+            .into_iter()
+            .map(|stats| stats.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["other", STORE_NAME]);
+    }
+
+    #[test]
+    fn test_kv_store_memory_budget() {
+        let stores = ObjectStores::default();
+        let (one, two) = (
+            ObjectStoreKey::new("one").unwrap(),
+            ObjectStoreKey::new("two").unwrap(),
+        );
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |store: &ObjectStoreKey, k: &str, body: &[u8], mode| {
+            stores.insert(
+                store.clone(),
+                key(k),
+                body.to_vec(),
+                mode,
+                None,
+                Some(b"m".to_vec()),
+                None,
+            )
+        };
+        let keys = |store: &ObjectStoreKey| {
+            stores
+                .snapshot(store, None)
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>()
+        };
+
+        // values already held are left alone when the budget is set
+        insert(&one, "a", b"1234", KvInsertMode::Overwrite).unwrap();
+        insert(&two, "b", b"1234", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(stores.memory_usage(), 10);
+        stores.set_memory_budget(Some(MemoryBudget {
+            max_bytes: 12,
+            policy: BudgetPolicy::Reject,
+        }));
+        assert_eq!(stores.memory_usage(), 10);
+
+        // overwriting frees the bytes of the value replaced, and appending counts the whole value
+        insert(&one, "a", b"123456", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(stores.memory_usage(), 12);
+        assert_eq!(
+            insert(&one, "a", b"7", KvInsertMode::Append),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        assert_eq!(
+            insert(&two, "c", b"", KvInsertMode::Overwrite),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        stores.delete(two.clone(), key("b"), None).unwrap();
+        assert_eq!(stores.memory_usage(), 7);
+        insert(&two, "c", b"1234", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(stores.memory_usage(), 12);
+
+        // a transaction over the budget is rolled back
+        assert_eq!(
+            stores.transaction(|txn| {
+                txn.insert(
+                    two.clone(),
+                    key("d"),
+                    b"1".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+            }),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        assert_eq!(keys(&two), ["c"]);
+
+        // evicting takes the least recently written values first, from any store, but never the
+        // key being written
+        stores.set_memory_budget(Some(MemoryBudget {
+            max_bytes: 12,
+            policy: BudgetPolicy::EvictOldest,
+        }));
+        insert(&two, "d", b"12", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(keys(&one), Vec::<String>::new());
+        assert_eq!(keys(&two), ["c", "d"]);
+        assert_eq!(stores.memory_usage(), 8);
+        insert(&two, "c", b"1234567", KvInsertMode::Append).unwrap();
+        assert_eq!(keys(&two), ["c"]);
+        assert_eq!(stores.memory_usage(), 12);
+
+        // a value that can't fit even alone is refused, and nothing is evicted for it
+        assert_eq!(
+            insert(&one, "e", b"123456789012", KvInsertMode::Overwrite),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        assert_eq!(keys(&two), ["c"]);
+
+        // a transaction can evict from the stores it doesn't write to
+        stores
+            .transaction(|txn| {
+                txn.insert(
+                    one.clone(),
+                    key("f"),
+                    b"12345".to_vec(),
+                    KvInsertMode::Overwrite,
+                    None,
+                    None,
+                    None,
+                )
+            })
+            .unwrap();
+        assert_eq!(keys(&one), ["f"]);
+        assert_eq!(keys(&two), Vec::<String>::new());
+
+        stores.set_memory_budget(None);
+        insert(&one, "g", &[0; 100], KvInsertMode::Overwrite).unwrap();
+        assert_eq!(stores.memory_usage(), 106);
+    }
+
+    #[test]
+    fn test_kv_store_scoped_observers_and_stats() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Keys(Mutex<Vec<String>>);
+
+        impl KvObserver for Keys {
+            fn on_event(&self, event: &KvEvent<'_>) {
+                let key = match &event.op {
+                    KvOp::Lookup { key, .. }
+                    | KvOp::Head { key, .. }
+                    | KvOp::Insert { key, .. }
+                    | KvOp::Delete { key, .. } => key.to_string(),
+                    KvOp::List { prefix, .. } => format!("list {}", prefix.unwrap_or_default()),
+                };
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}: {key}", event.store));
+            }
+        }
+
+        let stores = ObjectStores::default();
+        let scope = |store: Option<&str>, prefix: &str| KvScope {
+            store: store.map(str::to_string),
+            key_prefix: Some(prefix.to_string()),
+        };
+        let (carts, wishlists) = (Arc::new(Keys::default()), Arc::new(Keys::default()));
+        stores.add_observer_in(scope(None, "cart/"), carts.clone());
+        stores.add_observer_in(scope(Some(STORE_NAME), "wishlist/"), wishlists.clone());
+        assert!(stores.add_stats_scope("carts", scope(None, "cart/")));
+        assert!(!stores.add_stats_scope("carts", KvScope::default()));
+
+        let (store, other) = (
+            ObjectStoreKey::new(STORE_NAME).unwrap(),
+            ObjectStoreKey::new("other").unwrap(),
+        );
+        let insert = |store: &ObjectStoreKey, key: &str, mode| {
+            stores.insert(
+                store.clone(),
+                ObjectKey::new(key).unwrap(),
+                b"item".to_vec(),
+                mode,
+                None,
+                None,
+                None,
+            )
+        };
+        insert(&store, "cart/1", KvInsertMode::Overwrite).unwrap();
+        insert(&store, "cart/1", KvInsertMode::Add).unwrap_err();
+        insert(&store, "wishlist/1", KvInsertMode::Overwrite).unwrap();
+        insert(&store, "session/1", KvInsertMode::Overwrite).unwrap();
+        insert(&other, "cart/2", KvInsertMode::Overwrite).unwrap();
+        insert(&other, "wishlist/2", KvInsertMode::Overwrite).unwrap();
+        let lookup = |key: &str| stores.lookup(store.clone(), ObjectKey::new(key).unwrap());
+        lookup("cart/1").unwrap();
+        lookup("cart/3").unwrap_err();
+        lookup("wishlist/1").unwrap();
+        stores
+            .delete(store.clone(), ObjectKey::new("cart/1").unwrap(), None)
+            .unwrap();
+        // a list is only in a scope if all it could return is
+        for prefix in [None, Some("cart".to_string()), Some("cart/".to_string())] {
+            stores.list(store.clone(), None, prefix, 10).unwrap();
+        }
+
+        assert_eq!(
+            *carts.0.lock().unwrap(),
+            [
+                "test_store: cart/1",
+                "test_store: cart/1",
+                "other: cart/2",
+                "test_store: cart/1",
+                "test_store: cart/3",
+                "test_store: cart/1",
+                "test_store: list cart/",
+            ]
+        );
+        assert_eq!(
+            *wishlists.0.lock().unwrap(),
+            ["test_store: wishlist/1", "test_store: wishlist/1"]
+        );
+        assert_eq!(
+            stores.scope_stats(),
+            BTreeMap::from([(
+                "carts".to_string(),
+                ScopeStats {
+                    lookups: 2,
+                    hits: 1,
+                    misses: 1,
+                    inserts: 3,
+                    deletes: 1,
+                    lists: 1,
+                    bytes_written: 8,
+                    errors: 1,
+                }
+            )])
+        );
+    }
+
+    #[test]
+    fn test_kv_store_latencies() {
+        const DELAY: Duration = Duration::from_millis(50);
+        const SLOW: u64 = 5;
+
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        stores.insert_empty_store(store.clone()).unwrap();
+        let namespace = stores
+            .namespace(
+                "ns",
+                &KvNamespaceConfig::new(http::HeaderName::from_static("x-ns")),
+            )
+            .unwrap();
+
+        // delay lookups by holding the write lock while they wait for it, less however long the
+        // lookup's thread takes to start, hence the tolerance below
+        for _ in 0..SLOW {
+            std::thread::scope(|s| {
+                let guard = stores.stores.write().unwrap();
+                let lookup = s.spawn(|| stores.lookup(store.clone(), key()));
+                std::thread::sleep(DELAY);
+                drop(guard);
+                assert_eq!(lookup.join().unwrap().err(), Some(KvStoreError::NotFound));
+            });
+        }
+        namespace.lookup(store.clone(), key()).unwrap_err();
+        stores
+            .insert(
+                store.clone(),
+                key(),
+                b"v".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let latencies = stores.latencies();
+        let lookups = &latencies[STORE_NAME][&KvOpKind::Lookup];
+        // the namespace's lookup is counted with the rest, and wasn't held up
+        assert_eq!(lookups.count, SLOW + 1);
+        assert!(lookups.quantile(0.0).unwrap() < DELAY);
+        let mean_slow = lookups.total / SLOW as u32;
+        assert!(
+            mean_slow >= DELAY * 3 / 4 && mean_slow < DELAY * 2,
+            "{mean_slow:?}"
+        );
+        let p50 = lookups.quantile(0.5).unwrap();
+        assert!(p50 >= DELAY * 3 / 4 && p50 <= lookups.max, "{p50:?}");
+        assert_eq!(latencies[STORE_NAME][&KvOpKind::Insert].count, 1);
+        assert!(!latencies[STORE_NAME].contains_key(&KvOpKind::Delete));
+
+        // nothing more is recorded once tracking is turned off
+        stores.set_latency_tracking(false);
+        stores.lookup(store.clone(), key()).unwrap();
+        namespace.lookup(store.clone(), key()).unwrap_err();
+        assert_eq!(stores.latencies(), latencies);
+    }
+
+    #[test]
+    fn test_kv_store_max_value_size() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |body: &[u8], mode| {
+            stores.insert(store.clone(), key(), body.to_vec(), mode, None, None, None)
+        };
+
+        // production's limit applies unless a store sets its own
+        assert_eq!(stores.max_value_size(STORE_NAME), KV_STORE_VALUE_MAX_LEN);
+        assert_eq!(stores.limits(STORE_NAME), StoreLimits::default());
+        assert_eq!(
+            insert(
+                &vec![0; KV_STORE_VALUE_MAX_LEN + 1],
+                KvInsertMode::Overwrite
+            ),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        insert(&vec![0; KV_STORE_VALUE_MAX_LEN], KvInsertMode::Overwrite).unwrap();
+
+        stores
+            .configure_store(
+                store.clone(),
+                StoreConfig {
+                    max_value_size: Some(4),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(stores.limits(STORE_NAME).max_value_size, 4);
+        insert(b"12", KvInsertMode::Overwrite).unwrap();
+        assert_eq!(
+            insert(b"12345", KvInsertMode::Overwrite),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        insert(b"3", KvInsertMode::Append).unwrap();
+        // small writes that would take the combined value over are rejected, leaving it alone
+        assert_eq!(
+            insert(b"45", KvInsertMode::Append),
+            Err(KvStoreError::PayloadTooLarge)
         );
-        match res {
-            Ok(ov) => {
-                assert_eq!(ov.body, val1.as_bytes().to_vec());
-                generation = ov.generation;
-            }
-            Err(_) => panic!("should have been OK"),
-        }
+        assert_eq!(
+            insert(b"01", KvInsertMode::Prepend),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        insert(b"0", KvInsertMode::Prepend).unwrap();
+        assert_eq!(
+            stores.lookup(store.clone(), key()).unwrap().body,
+            &b"0123"[..]
+        );
+    }
 
-        // test generation match failure
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
+    #[test]
+    fn test_kv_store_max_metadata_size() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |metadata: Option<&[u8]>, mode| {
+            stores.insert(
+                store.clone(),
+                key(),
+                b"v".to_vec(),
+                mode,
+                None,
+                metadata.map(<[u8]>::to_vec),
+                None,
+            )
+        };
+
+        // production's limit applies unless a store sets its own
+        assert_eq!(
+            stores.max_metadata_size(STORE_NAME),
+            KV_STORE_METADATA_MAX_LEN
+        );
+        assert_eq!(
+            insert(
+                Some(&vec![0; KV_STORE_METADATA_MAX_LEN + 1]),
+                KvInsertMode::Overwrite
+            ),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        insert(
+            Some(&vec![0; KV_STORE_METADATA_MAX_LEN]),
             KvInsertMode::Overwrite,
-            Some(1337),
-            None,
-            None,
+        )
+        .unwrap();
+
+        // lowering the limit doesn't stop writes that keep the metadata already held
+        stores
+            .configure_store(
+                store.clone(),
+                StoreConfig {
+                    max_metadata_size: Some(4),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(stores.limits(STORE_NAME).max_metadata_size, 4);
+        insert(None, KvInsertMode::Append).unwrap();
+        assert_eq!(
+            insert(Some(b"12345"), KvInsertMode::Overwrite),
+            Err(KvStoreError::PayloadTooLarge)
+        );
+        insert(Some(b"1234"), KvInsertMode::Overwrite).unwrap();
+        assert_eq!(
+            stores.lookup(store.clone(), key()).unwrap().metadata,
+            &b"1234"[..]
         );
-        match res {
-            Err(KvStoreError::PreconditionFailed) => {}
-            _ => panic!("should have been Err(KvStoreError::PreconditionFailed)"),
-        }
 
-        // test generation match positive
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
-            KvInsertMode::Overwrite,
-            Some(generation),
-            None,
-            None,
+        // batches are checked before any of them is written
+        let other = ObjectKey::new("other").unwrap();
+        let now = SystemTime::now();
+        assert_eq!(
+            stores.insert_many(
+                store.clone(),
+                vec![
+                    (other.clone(), ObjectValue::new(b"v".to_vec()).build(now)),
+                    (
+                        key(),
+                        ObjectValue::new(b"v".to_vec())
+                            .metadata(b"12345".to_vec())
+                            .build(now),
+                    ),
+                ],
+            ),
+            Err(KvStoreError::PayloadTooLarge)
         );
-        match res {
-            Ok(_) => {}
-            _ => panic!("should have been OK"),
+        assert_eq!(
+            stores.lookup(store.clone(), other).err(),
+            Some(KvStoreError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_kv_store_metadata_format() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert = |metadata: &[u8], mode| {
+            stores.insert(
+                store.clone(),
+                key(),
+                b"v".to_vec(),
+                mode,
+                None,
+                Some(metadata.to_vec()),
+                None,
+            )
+        };
+        let configure = |object_only| {
+            stores
+                .configure_store(
+                    store.clone(),
+                    StoreConfig {
+                        metadata_format: Some(MetadataFormat::Json { object_only }),
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+        };
+
+        // metadata is opaque unless a store says otherwise
+        assert_eq!(stores.metadata_format(STORE_NAME), None);
+        insert(b"not json", KvInsertMode::Overwrite).unwrap();
+
+        configure(false);
+        insert(
+            br#"{"owner":"checkout","version":2}"#,
+            KvInsertMode::Overwrite,
+        )
+        .unwrap();
+        insert(b"[1, 2]", KvInsertMode::Overwrite).unwrap();
+        for bad in [&b"not json"[..], b"{\"a\":", b"\"\xff\""] {
+            assert_eq!(
+                insert(bad, KvInsertMode::Overwrite),
+                Err(KvStoreError::BadRequest),
+                "{bad:?}"
+            );
         }
+        // the refused writes left the value as it was
+        assert_eq!(
+            stores.lookup(store.clone(), key()).unwrap().metadata,
+            &b"[1, 2]"[..]
+        );
 
-        // check result
-        let res = stores.lookup(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
+        configure(true);
+        assert_eq!(
+            insert(b"[1, 2]", KvInsertMode::Overwrite),
+            Err(KvStoreError::BadRequest)
         );
-        match res {
-            Ok(ov) => {
-                assert_eq!(ov.body, val1.as_bytes().to_vec());
-            }
-            Err(_) => panic!("should have been OK"),
-        }
+        assert_eq!(
+            insert(b"\"string\"", KvInsertMode::Append),
+            Err(KvStoreError::BadRequest)
+        );
+        insert(b"{}", KvInsertMode::Overwrite).unwrap();
+        // writes that keep the metadata already there aren't checked
+        stores
+            .insert(
+                store.clone(),
+                key(),
+                b"w".to_vec(),
+                KvInsertMode::Append,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(stores.lookup(store, key()).unwrap().body, &b"vw"[..]);
     }
 
     #[test]
-    fn test_kv_store_item_list_advanced() {
+    fn test_kv_store_max_keys() {
+        let clock = MockClock::default();
+        let stores = ObjectStores::with_clock(Arc::new(clock.clone()));
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k, ttl| {
+            stores.insert(
+                store.clone(),
+                key(k),
+                b"v".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                ttl,
+            )
+        };
+
+        assert_eq!(stores.max_keys(STORE_NAME), None);
+        stores.set_max_keys(store.clone(), Some(2)).unwrap();
+        assert_eq!(stores.max_keys(STORE_NAME), Some(2));
+
+        insert("a", None).unwrap();
+        insert("b", Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(insert("c", None), Err(KvStoreError::TooManyKeys));
+        // keys the store already holds can still be written, and the store listed
+        insert("a", None).unwrap();
+        insert("b", Some(Duration::from_secs(1))).unwrap();
+        stores.list(store.clone(), None, None, 10).unwrap();
+
+        // expired values don't count against the limit
+        clock.advance(Duration::from_secs(2));
+        insert("c", None).unwrap();
+        assert_eq!(insert("d", None), Err(KvStoreError::TooManyKeys));
+
+        // deleting a key frees its slot
+        stores.delete(store.clone(), key("a"), None).unwrap();
+        insert("d", None).unwrap();
+        assert_eq!(insert("e", None), Err(KvStoreError::TooManyKeys));
+
+        // lowering the limit keeps the keys already held, and lifting it lets writes through
+        stores.set_max_keys(store.clone(), Some(1)).unwrap();
+        insert("c", None).unwrap();
+        assert_eq!(insert("e", None), Err(KvStoreError::TooManyKeys));
+        stores.set_max_keys(store.clone(), None).unwrap();
+        insert("e", None).unwrap();
+    }
+
+    #[test]
+    fn test_kv_store_read_only() {
         let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
+        let insert = |k| {
+            stores.insert(
+                store.clone(),
+                key(k),
+                b"v".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            )
+        };
+        insert("a").unwrap();
+
+        assert!(!stores.is_read_only(STORE_NAME));
+        stores.set_read_only(store.clone(), true).unwrap();
+        assert!(stores.is_read_only(STORE_NAME));
+        // the flag is kept when the store is created again
+        stores.insert_empty_store(store.clone()).unwrap();
+        assert!(stores.is_read_only(STORE_NAME));
+
+        assert_eq!(insert("a"), Err(KvStoreError::BadRequest));
+        assert_eq!(insert("b"), Err(KvStoreError::BadRequest));
+        assert_eq!(
+            stores.delete(store.clone(), key("a"), None),
+            Err(KvStoreError::BadRequest)
+        );
+        // reads carry on as before
+        assert_eq!(
+            stores.lookup(store.clone(), key("a")).unwrap().body,
+            &b"v"[..]
+        );
+        assert_eq!(
+            stores.lookup(store.clone(), key("b")).err(),
+            Some(KvStoreError::NotFound)
+        );
+        stores.list(store.clone(), None, None, 10).unwrap();
+        // seeding still writes to it
         stores
-            .insert_empty_store(ObjectStoreKey(STORE_NAME.to_string()))
+            .insert_many(
+                store.clone(),
+                [(
+                    key("b"),
+                    ObjectValue::new(b"s".to_vec()).build(stores.now()),
+                )],
+            )
             .unwrap();
+        assert_eq!(
+            stores.lookup(store.clone(), key("b")).unwrap().body,
+            &b"s"[..]
+        );
 
-        let key = "insert_key".to_string();
-        let prefix = "key".to_string();
-        let key1 = format!("{prefix}1").to_string();
-        let key2 = format!("{prefix}2").to_string();
-        let key3 = format!("{prefix}3").to_string();
-        let val1 = "val1".to_string();
-        let val2 = "val2".to_string();
-        let val3 = "val3".to_string();
+        stores.set_read_only(store.clone(), false).unwrap();
+        stores.delete(store.clone(), key("a"), None).unwrap();
+        insert("a").unwrap();
+    }
 
-        // insert insert_key
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key.clone()),
-            val1.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            None,
-            None,
-        );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
-        }
+    #[test]
+    fn test_kv_store_read_only_with_settings_poisoned() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        stores.set_read_only(store.clone(), true).unwrap();
 
-        // insert val1
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key1.clone()),
-            val1.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            None,
-            None,
+        let settings = stores.settings.clone();
+        std::thread::spawn(move || {
+            let _settings = settings.write().unwrap();
+            panic!("poisoning the settings lock");
+        })
+        .join()
+        .unwrap_err();
+        assert!(stores.settings.is_poisoned());
+
+        // the store's settings are still read, so it stays read-only
+        assert!(stores.is_read_only(STORE_NAME));
+        assert_eq!(
+            stores.insert(
+                store.clone(),
+                ObjectKey::new("a").unwrap(),
+                b"v".to_vec(),
+                KvInsertMode::Overwrite,
+                None,
+                None,
+                None,
+            ),
+            Err(KvStoreError::BadRequest)
         );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
-        }
-        // insert val2
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key2.clone()),
-            val2.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            None,
-            None,
+    }
+
+    #[test]
+    fn test_kv_store_append_one_byte_over_the_limit() {
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let key = || ObjectKey::new("key").unwrap();
+        let insert =
+            |body: Vec<u8>, mode| stores.insert(store.clone(), key(), body, mode, None, None, None);
+
+        insert(vec![0; KV_STORE_VALUE_MAX_LEN - 1], KvInsertMode::Overwrite).unwrap();
+        // filling the value up to the limit exactly is allowed
+        insert(vec![1], KvInsertMode::Append).unwrap();
+        // one byte past it is not, whichever end it is added to
+        assert_eq!(
+            insert(vec![2], KvInsertMode::Append),
+            Err(KvStoreError::PayloadTooLarge)
         );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
-        }
-        // insert val3
-        let res = stores.insert(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            ObjectKey(key3.clone()),
-            val3.clone().into(),
-            KvInsertMode::Overwrite,
-            None,
-            None,
-            None,
+        assert_eq!(
+            insert(vec![2], KvInsertMode::Prepend),
+            Err(KvStoreError::PayloadTooLarge)
         );
-        match res {
-            Err(_) => panic!("should have been OK"),
-            _ => {}
-        }
+        let body = stores.lookup(store.clone(), key()).unwrap().body;
+        assert_eq!(body.len(), KV_STORE_VALUE_MAX_LEN);
+        assert_eq!(body.last(), Some(&1));
+    }
 
-        // list
-        let limit = 1000;
-        let res = stores.list(ObjectStoreKey(STORE_NAME.to_string()), None, None, limit);
-        match res {
-            Ok(ov) => {
-                let val = format!(
-                    r#"{{"data":["{key}","{key1}","{key2}","{key3}"],"meta":{{"limit":{limit}}}}}"#
-                );
-                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
-            }
-            Err(_) => panic!("should have been OK"),
-        }
+    #[test]
+    fn test_kv_store_compression() {
+        let text = "{\"catalog\": [".to_string() + &"{\"sku\": 1}, ".repeat(1000) + "]}";
+        let binary = (0..=255u8).cycle().take(4096).collect::<Vec<_>>();
+        let key = |k: &str| ObjectKey::new(k).unwrap();
 
-        // list w/prefix
-        let limit = 1000;
-        let res = stores.list(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            None,
-            Some(prefix.clone()),
-            limit,
-        );
-        match res {
-            Ok(ov) => {
-                let val = format!(
-                    r#"{{"data":["{key1}","{key2}","{key3}"],"meta":{{"limit":{limit},"prefix":"{prefix}"}}}}"#
-                );
-                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
-            }
-            Err(_) => panic!("should have been OK"),
+        for compression in [Compression::Gzip, Compression::Deflate] {
+            let stores = ObjectStores::default();
+            let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+            stores
+                .configure_store(
+                    store.clone(),
+                    StoreConfig {
+                        compression: Some(compression),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            let insert = |k, body: &[u8], mode| {
+                stores.insert(
+                    store.clone(),
+                    key(k),
+                    body.to_vec(),
+                    mode,
+                    None,
+                    Some(b"meta".to_vec()),
+                    None,
+                )
+            };
+
+            insert("text", text.as_bytes(), KvInsertMode::Overwrite).unwrap();
+            insert("binary", &binary, KvInsertMode::Overwrite).unwrap();
+            insert("empty", b"", KvInsertMode::Overwrite).unwrap();
+            let val = stores.lookup(store.clone(), key("text")).unwrap();
+            assert_eq!(val.body, text.as_bytes());
+            assert_eq!(val.metadata, b"meta");
+            assert_eq!(val.compression, None);
+            assert_eq!(
+                stores.lookup(store.clone(), key("binary")).unwrap().body,
+                binary
+            );
+            assert!(stores
+                .lookup(store.clone(), key("empty"))
+                .unwrap()
+                .body
+                .is_empty());
+
+            // only the stored copy is compressed
+            let stored = |k| stores.stores.read().unwrap()[&store][&key(k)].body.len();
+            assert!(stored("text") < text.len() / 10);
+
+            // appends and prepends extend the uncompressed body
+            insert("text", b"<", KvInsertMode::Prepend).unwrap();
+            insert("text", b">", KvInsertMode::Append).unwrap();
+            let val = stores.lookup(store.clone(), key("text")).unwrap();
+            assert_eq!(val.body, format!("<{text}>").as_bytes());
+
+            // generation matches behave as they would uncompressed
+            insert("binary", b"new", KvInsertMode::Add).unwrap_err();
+            let generation = stores
+                .lookup(store.clone(), key("binary"))
+                .unwrap()
+                .generation;
+            stores
+                .insert(
+                    store.clone(),
+                    key("binary"),
+                    b"new".to_vec(),
+                    KvInsertMode::Overwrite,
+                    Some(generation),
+                    None,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(
+                stores.lookup(store.clone(), key("binary")).unwrap().body,
+                &b"new"[..]
+            );
+
+            // exports count the uncompressed body
+            let export = stores.export(Redaction::None).unwrap();
+            assert_eq!(
+                export.stores[STORE_NAME].items["text"].body.decode(),
+                Some(format!("<{text}>").into_bytes())
+            );
         }
+    }
 
-        // list w/prefix&limit
-        let limit = 1;
-        let res = stores.list(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            None,
-            Some(prefix.clone()),
-            limit,
+    /// Compare lookups of missing keys in a large store with and without a key filter, under
+    /// contention from other readers.
+    ///
+    /// Run with `cargo test -p viceroy-lib -- --ignored bench_kv_store_key_filter --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_kv_store_key_filter_misses() {
+        use std::time::Instant;
+
+        const KEYS: usize = 200_000;
+        const THREADS: usize = 8;
+        const LOOKUPS: usize = 100_000;
+
+        let stores = ObjectStores::default();
+        let plain = ObjectStoreKey::new("plain").unwrap();
+        let filtered = ObjectStoreKey::new("filtered").unwrap();
+        stores
+            .configure_store(
+                filtered.clone(),
+                StoreConfig {
+                    key_filter: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        insert_keys(&stores, &plain, "key", KEYS);
+        insert_keys(&stores, &filtered, "key", KEYS);
+
+        let misses = |store: &ObjectStoreKey| {
+            let start = Instant::now();
+            std::thread::scope(|s| {
+                for t in 0..THREADS {
+                    let stores = &stores;
+                    s.spawn(move || {
+                        for i in 0..LOOKUPS {
+                            let key = ObjectKey::new(format!("missing{t}-{i}")).unwrap();
+                            let _ = stores.lookup(store.clone(), key);
+                        }
+                    });
+                }
+            });
+            start.elapsed()
+        };
+        let plain = misses(&plain);
+        let filtered = misses(&filtered);
+        println!(
+            "{} misses in a store of {KEYS} keys, over {THREADS} threads: \
+             {plain:?} unfiltered, {filtered:?} filtered",
+            THREADS * LOOKUPS
         );
-        match res {
-            Ok(ov) => {
-                let next_cursor = BASE64_STANDARD.encode(key1.clone());
-                let val = format!(
-                    r#"{{"data":["{key1}"],"meta":{{"limit":{limit},"prefix":"{prefix}","next_cursor":"{next_cursor}"}}}}"#
-                );
-                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
-            }
-            Err(_) => panic!("should have been OK"),
-        }
+    }
 
-        // list w/prefix&limit&cursor
-        let limit = 1;
-        let last_cursor = BASE64_STANDARD.encode(key1.clone());
-        let res = stores.list(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            Some(last_cursor),
-            Some(prefix.clone()),
-            limit,
+    /// Deep clone a large store of large values, whose bodies are shared with the copy rather
+    /// than copied, and make an isolated view of it, which copies nothing up front.
+    ///
+    /// Run with `cargo test -p viceroy-lib -- --ignored bench_kv_store_deep_clone --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_kv_store_deep_clone() {
+        use std::time::Instant;
+
+        const KEYS: usize = 50_000;
+        const BODY_LEN: usize = 4 * 1024;
+
+        let stores = ObjectStores::default();
+        let store = ObjectStoreKey::new(STORE_NAME).unwrap();
+        let values = (0..KEYS).map(|i| {
+            let key = ObjectKey::new(format!("key{i}")).unwrap();
+            (key, vec![0; BODY_LEN], SeedOptions::default())
+        });
+        stores.create_store(store.clone(), values).unwrap();
+
+        let start = Instant::now();
+        let copy = stores.deep_clone().unwrap();
+        let cloned = start.elapsed();
+        let start = Instant::now();
+        let view = stores.isolated();
+        let isolated = start.elapsed();
+
+        let key = ObjectKey::new("key0").unwrap();
+        let original = stores.lookup(store.clone(), key.clone()).unwrap();
+        assert_eq!(
+            copy.lookup(store.clone(), key.clone())
+                .unwrap()
+                .body
+                .as_ptr(),
+            original.body.as_ptr()
         );
-        match res {
-            Ok(ov) => {
-                let next_cursor = BASE64_STANDARD.encode(key2.clone());
-                let val = format!(
-                    r#"{{"data":["{key2}"],"meta":{{"limit":{limit},"prefix":"{prefix}","next_cursor":"{next_cursor}"}}}}"#
-                );
-                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
-            }
-            Err(_) => panic!("should have been OK"),
+        assert_eq!(
+            view.lookup(store, key).unwrap().body.as_ptr(),
+            original.body.as_ptr()
+        );
+        println!(
+            "a store of {KEYS} values of {BODY_LEN} bytes: deep cloned in {cloned:?}, isolated in \
+             {isolated:?}"
+        );
+    }
+
+    /// Look up stores by name in a map of many stores, as opening a store does.
+    ///
+    /// Run with `cargo test -p viceroy-lib -- --ignored bench_kv_store_key --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_kv_store_key() {
+        use std::time::Instant;
+
+        const STORES: usize = 10_000;
+        const OPENS: usize = 1_000_000;
+
+        let stores = ObjectStores::default();
+        for i in 0..STORES {
+            stores
+                .insert_empty_store(ObjectStoreKey::new(format!("store{i}")).unwrap())
+                .unwrap();
         }
+        let names = (0..STORES)
+            .map(|i| format!("store{i}"))
+            .chain((0..STORES).map(|i| format!("missing{i}")))
+            .collect::<Vec<_>>();
 
-        // list w/prefix&limit&cursor
-        let limit = 1;
-        let last_cursor = BASE64_STANDARD.encode(key2.clone());
-        let res = stores.list(
-            ObjectStoreKey(STORE_NAME.to_string()),
-            Some(last_cursor),
-            Some(prefix.clone()),
-            limit,
-        );
-        match res {
-            Ok(ov) => {
-                let val = format!(
-                    r#"{{"data":["{key3}"],"meta":{{"limit":{limit},"prefix":"{prefix}"}}}}"#
-                );
-                assert_eq!(std::str::from_utf8(&ov).unwrap(), val)
-            }
-            Err(_) => panic!("should have been OK"),
+        let start = Instant::now();
+        let mut found = 0;
+        for name in names.iter().cycle().take(OPENS) {
+            found += stores.store_key(name).unwrap().is_some() as usize;
         }
+        let elapsed = start.elapsed();
+        assert_eq!(found, OPENS / 2);
+        println!(
+            "{OPENS} store lookups, half of them misses, in a map of {STORES} stores: {elapsed:?}"
+        );
     }
 }
@@ -0,0 +1,65 @@
+//! A limit on the memory the values in a set of stores take up.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
+
+/// A limit on the bytes of bodies and metadata a set of stores holds, set with
+/// [`ObjectStores::set_memory_budget`].
+///
+/// Bodies are counted as held, so compressed for a store with `compression`. Keys and the
+/// bookkeeping kept for each value are not counted, so the memory actually used is somewhat more.
+///
+/// [`ObjectStores::set_memory_budget`]: super::ObjectStores::set_memory_budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub max_bytes: usize,
+    /// What a write that would take the stores over `max_bytes` does.
+    pub policy: BudgetPolicy,
+}
+
+/// What happens to a write that would take the stores over their [`MemoryBudget`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// The write fails with [`KvStoreError::PayloadTooLarge`][super::KvStoreError::PayloadTooLarge].
+    #[default]
+    Reject,
+    /// The least recently written values, from any store, are evicted until the write fits. A
+    /// write too large to fit even in empty stores still fails.
+    EvictOldest,
+}
+
+/// The budget of a set of stores, shared by every handle to them, and whether it has been hit.
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    budget: RwLock<Option<MemoryBudget>>,
+    tripped: AtomicBool,
+}
+
+impl Budget {
+    pub(crate) fn new(budget: Option<MemoryBudget>) -> Self {
+        Self {
+            budget: RwLock::new(budget),
+            tripped: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<MemoryBudget> {
+        self.budget.read().ok().and_then(|budget| *budget)
+    }
+
+    /// Replace the budget. A new budget warns afresh the first time it is hit.
+    pub(crate) fn set(&self, budget: Option<MemoryBudget>) {
+        if let Ok(mut current) = self.budget.write() {
+            *current = budget;
+            self.tripped.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Note that a write would take the stores over the budget, returning whether this is the
+    /// first time it has.
+    pub(crate) fn trip(&self) -> bool {
+        !self.tripped.swap(true, Ordering::Relaxed)
+    }
+}
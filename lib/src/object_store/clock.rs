@@ -0,0 +1,57 @@
+//! Sources of the current time for the object stores.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// A source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+///
+/// Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct MockClock(Arc<Mutex<SystemTime>>);
+
+impl MockClock {
+    /// A clock stopped at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    /// Move the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().expect("clock lock poisoned") += by;
+    }
+
+    /// Set the clock to `now`.
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().expect("clock lock poisoned") = now;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().expect("clock lock poisoned")
+    }
+}
@@ -0,0 +1,57 @@
+//! Compressing values at rest, for stores configured with a `compression` setting.
+
+use {
+    flate2::{
+        read::{DeflateDecoder, GzDecoder},
+        write::{DeflateEncoder, GzEncoder},
+    },
+    std::io::{self, Read, Write},
+};
+
+/// How a store's values are compressed at rest.
+///
+/// Compression is invisible to guests: values are decompressed before they are handed out, and
+/// everything that measures a value, such as exports and metadata lengths, counts its
+/// uncompressed size. Only the memory the stores take up sees the difference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    /// The compression named `name` in configuration.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(Compression::Gzip),
+            "deflate" => Some(Compression::Deflate),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn compress(self, body: &[u8]) -> Vec<u8> {
+        fn finish<W: Write>(mut encoder: W, body: &[u8]) -> io::Result<W> {
+            encoder.write_all(body)?;
+            Ok(encoder)
+        }
+        let level = flate2::Compression::default();
+        // writing to a `Vec` can't fail
+        match self {
+            Compression::Gzip => finish(GzEncoder::new(Vec::new(), level), body)
+                .and_then(GzEncoder::finish)
+                .expect("compressing into memory"),
+            Compression::Deflate => finish(DeflateEncoder::new(Vec::new(), level), body)
+                .and_then(DeflateEncoder::finish)
+                .expect("compressing into memory"),
+        }
+    }
+
+    pub(crate) fn decompress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Compression::Gzip => GzDecoder::new(body).read_to_end(&mut out)?,
+            Compression::Deflate => DeflateDecoder::new(body).read_to_end(&mut out)?,
+        };
+        Ok(out)
+    }
+}
@@ -0,0 +1,226 @@
+//! Exporting the contents of a set of stores, with optional redaction of sensitive stores, and
+//! reading exports back in.
+
+use {
+    super::{KvRequest, ObjectStoreError, ObjectValue, ValueOrigin},
+    base64::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{borrow::Cow, collections::BTreeMap, fmt, time::SystemTime},
+};
+
+/// The version of the [`KvExport`] format that [`ObjectStores::export`] produces, recorded in the
+/// `version` field of each export.
+///
+/// * 1: the original format, which had no `version` field.
+/// * 2: adds the `version` field.
+///
+/// [`ObjectStores::import`] accepts this version and the one before it.
+///
+/// [`ObjectStores::export`]: super::ObjectStores::export
+/// [`ObjectStores::import`]: super::ObjectStores::import
+pub const KV_EXPORT_FORMAT_VERSION: u32 = 2;
+
+/// The version of exports written before the `version` field was added.
+fn unversioned() -> u32 {
+    1
+}
+
+/// How [`ObjectStores::export`] treats the contents of stores marked `sensitive`.
+///
+/// Stores that are not marked sensitive are always exported as-is.
+///
+/// [`ObjectStores::export`]: super::ObjectStores::export
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Redaction {
+    /// Export sensitive stores as-is.
+    #[default]
+    None,
+    /// Replace the bodies in sensitive stores with their length and a short hash.
+    Values,
+    /// Redact metadata as well as bodies.
+    ValuesAndMetadata,
+}
+
+/// What [`ObjectStores::export`] includes.
+///
+/// A [`Redaction`] converts into the options for exporting everything with that redaction.
+///
+/// [`ObjectStores::export`]: super::ObjectStores::export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub redaction: Redaction,
+    /// Whether to include generations, creation and last-modified times, origins, and the
+    /// requests that wrote values. These depend on the history of the stores rather than just
+    /// their contents, so leaving them out makes exports of the same data identical.
+    pub volatile: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Redaction::default().into()
+    }
+}
+
+impl From<Redaction> for ExportOptions {
+    fn from(redaction: Redaction) -> Self {
+        Self {
+            redaction,
+            volatile: true,
+        }
+    }
+}
+
+/// A serializable copy of the contents of a set of stores.
+///
+/// Stores and keys are in sorted order, so the same contents always serialize the same way.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KvExport {
+    /// The [format version][KV_EXPORT_FORMAT_VERSION] of the export.
+    #[serde(default = "unversioned")]
+    pub version: u32,
+    pub stores: BTreeMap<String, ExportedStore>,
+}
+
+impl Default for KvExport {
+    fn default() -> Self {
+        Self {
+            version: KV_EXPORT_FORMAT_VERSION,
+            stores: BTreeMap::new(),
+        }
+    }
+}
+
+impl KvExport {
+    /// Parse an export from JSON, as written by [`to_json`][Self::to_json].
+    ///
+    /// This only checks the shape of the JSON; [`ObjectStores::import`] checks the version and
+    /// contents.
+    ///
+    /// [`ObjectStores::import`]: super::ObjectStores::import
+    pub fn from_json(json: &[u8]) -> Result<Self, ObjectStoreError> {
+        serde_json::from_slice(json).map_err(|e| ObjectStoreError::InvalidExport(e.to_string()))
+    }
+
+    /// The export as pretty-printed JSON with a trailing newline, the form it is written to files
+    /// in.
+    pub fn to_json(&self) -> Vec<u8> {
+        let mut json = serde_json::to_vec_pretty(self).expect("exports serialize to JSON");
+        json.push(b'\n');
+        json
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ExportedStore {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitive: bool,
+    pub items: BTreeMap<String, ExportedValue>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedValue {
+    pub body: ExportedBytes,
+    pub metadata: ExportedBytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    /// When the key was first written, in milliseconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_ms: Option<u64>,
+    /// When the value was last written, in milliseconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<ValueOrigin>,
+    /// The request that last wrote the value, if a guest did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written_by: Option<KvRequest>,
+}
+
+/// Exported bytes: base64-encoded without line breaks, or just a summary if they were redacted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExportedBytes {
+    Base64(String),
+    Redacted { len: usize, hash: String },
+}
+
+impl ExportedBytes {
+    fn new(bytes: &[u8], redact: bool) -> Self {
+        if redact {
+            ExportedBytes::Redacted {
+                len: bytes.len(),
+                hash: short_hash(bytes),
+            }
+        } else {
+            ExportedBytes::Base64(BASE64_STANDARD.encode(bytes))
+        }
+    }
+
+    /// The number of bytes exported, whether or not they were redacted.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            ExportedBytes::Base64(_) => self.decode().map_or(0, |bytes| bytes.len()),
+            ExportedBytes::Redacted { len, .. } => *len,
+        }
+    }
+
+    /// The bytes, if they weren't redacted.
+    pub(crate) fn decode(&self) -> Option<Vec<u8>> {
+        match self {
+            ExportedBytes::Base64(encoded) => BASE64_STANDARD.decode(encoded).ok(),
+            ExportedBytes::Redacted { .. } => None,
+        }
+    }
+}
+
+impl ExportedValue {
+    pub(crate) fn new(val: &ObjectValue, sensitive: bool, options: ExportOptions) -> Self {
+        let redact_body = sensitive && options.redaction != Redaction::None;
+        let redact_metadata = sensitive && options.redaction == Redaction::ValuesAndMetadata;
+        let ms = |at: SystemTime| {
+            at.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64)
+        };
+        // exports hold what guests would see, so lengths and hashes are of the uncompressed body
+        let body = match val.compression {
+            Some(compression) => Cow::Owned(
+                compression
+                    .decompress(&val.body)
+                    .expect("stores only hold values they compressed themselves"),
+            ),
+            None => Cow::Borrowed(&val.body[..]),
+        };
+        Self {
+            body: ExportedBytes::new(&body, redact_body),
+            metadata: ExportedBytes::new(&val.metadata, redact_metadata),
+            generation: options.volatile.then_some(val.generation),
+            created_ms: options.volatile.then(|| ms(val.created_at)),
+            last_modified_ms: options.volatile.then(|| ms(val.updated_at)),
+            origin: options.volatile.then_some(val.origin),
+            written_by: val
+                .written_by
+                .as_deref()
+                .filter(|_| options.volatile)
+                .cloned(),
+        }
+    }
+}
+
+/// A short, stable fingerprint of `bytes`, enough to tell values apart without revealing them.
+///
+/// This is 32-bit FNV-1a, which is not a cryptographic hash.
+pub(crate) fn short_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0x811c9dc5_u32, |hash, b| {
+        (hash ^ u32::from(*b)).wrapping_mul(0x01000193)
+    });
+    format!("{hash:08x}")
+}
+
+/// Formats bytes as their length and [`short_hash`], so that `Debug` output never contains them.
+pub(crate) struct RedactedBytes<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for RedactedBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} bytes, {}>", self.0.len(), short_hash(self.0))
+    }
+}
@@ -0,0 +1,77 @@
+//! Faults injected into KV writes, for testing how guests cope with production's failure modes.
+
+use std::sync::{Mutex, PoisonError};
+
+/// Makes some inserts report success without being applied, as if production had accepted a
+/// write and then lost it.
+///
+/// An insert is only lost once every check it is subject to has passed, so the guest sees just
+/// what it would for a successful write, while lookups afterwards find whatever the key held
+/// before. Observers see the insert succeed, [marked as lost][super::KvOp::Insert].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LostWriteRule {
+    /// The store the rule applies to, or every store if unset.
+    pub store: Option<String>,
+    /// The prefix of the keys the rule applies to, or every key if unset.
+    pub key_prefix: Option<String>,
+    /// The chance that a matching insert is lost, from 0 for never to 1 for always.
+    pub probability: f64,
+    /// Seeds the draws that decide which matching inserts are lost, so that a run can be repeated
+    /// with the same losses.
+    pub seed: u64,
+}
+
+impl LostWriteRule {
+    fn applies_to(&self, store: &str, key: &str) -> bool {
+        self.store.as_deref().map_or(true, |s| s == store)
+            && self
+                .key_prefix
+                .as_deref()
+                .map_or(true, |prefix| key.starts_with(prefix))
+    }
+}
+
+/// The fault rules for a set of stores, each with the state of the generator it draws from.
+///
+/// A panic can't leave the rules half-changed, so a poisoned lock is recovered from.
+#[derive(Debug, Default)]
+pub(crate) struct Faults(Mutex<Vec<(LostWriteRule, u64)>>);
+
+impl Faults {
+    pub(crate) fn add(&self, rule: LostWriteRule) {
+        let state = rule.seed;
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((rule, state));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Whether an insert of `key` into `store`, which has otherwise succeeded, is to be lost.
+    ///
+    /// Each rule that applies draws in turn, until one loses the write.
+    pub(crate) fn loses_write(&self, store: &str, key: &str) -> bool {
+        let mut rules = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        rules
+            .iter_mut()
+            .filter(|(rule, _)| rule.applies_to(store, key))
+            .any(|(rule, state)| next_draw(state) < rule.probability)
+    }
+}
+
+/// The next draw from the generator with `state`, uniform in `[0, 1)`.
+fn next_draw(state: &mut u64) -> f64 {
+    // SplitMix64, as KV chaos mode uses, which is plenty for this and needs no dependencies
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,140 @@
+//! Approximate key membership, to answer lookups of missing keys without locking the stores.
+
+use {
+    super::{values::StoreValues, KvStoreError, ObjectKey},
+    std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        sync::RwLock,
+    },
+};
+
+/// The fewest keys a filter is sized for.
+const MIN_CAPACITY: usize = 1024;
+/// Bits per key of capacity. With [`HASHES`] hashes this gives about a 1% false positive rate at
+/// capacity.
+const BITS_PER_KEY: usize = 10;
+const HASHES: u64 = 7;
+
+/// A bloom filter over the keys of one store.
+///
+/// A key is only ever reported absent if it was never added since the filter was last rebuilt, so
+/// a lookup the filter rejects is a guaranteed miss. Bits can't be cleared when a key is deleted,
+/// so deletes only add to the false positive rate, and the filter is rebuilt from the store once
+/// they add up. It is also rebuilt, twice the size, once more keys have been added than it was
+/// sized for.
+///
+/// The filter is only changed under the store write lock, so a rebuild sees every key.
+#[derive(Debug)]
+pub(crate) struct KeyFilter(RwLock<Bloom>);
+
+#[derive(Debug)]
+struct Bloom {
+    bits: Vec<u64>,
+    capacity: usize,
+    /// Whether the store exists. Stores are never removed, so once set this stays set.
+    exists: bool,
+    /// Keys added since the last rebuild, including those added then.
+    added: usize,
+    /// Keys deleted since the last rebuild.
+    deleted: usize,
+}
+
+impl KeyFilter {
+    /// A filter over the keys of `store`.
+    pub(crate) fn new(store: Option<&StoreValues>) -> Self {
+        Self(RwLock::new(Bloom::build(store)))
+    }
+
+    /// The error a lookup of `key` certainly fails with, if the filter can tell without looking
+    /// at the store.
+    pub(crate) fn certain_miss(&self, key: &ObjectKey) -> Option<KvStoreError> {
+        // no answer is a safe answer
+        let bloom = self.0.read().ok()?;
+        if !bloom.exists {
+            Some(KvStoreError::Uninitialized)
+        } else if !bloom.may_contain(key) {
+            Some(KvStoreError::NotFound)
+        } else {
+            None
+        }
+    }
+
+    /// Note that the store was created, empty.
+    pub(crate) fn created(&self) {
+        if let Ok(mut bloom) = self.0.write() {
+            bloom.exists = true;
+        }
+    }
+
+    /// Note that `key` was written to `store`, which is what the store looks like now.
+    pub(crate) fn added(&self, key: &ObjectKey, store: &StoreValues) {
+        let Ok(mut bloom) = self.0.write() else {
+            return;
+        };
+        bloom.add(key);
+        if bloom.added > bloom.capacity {
+            *bloom = Bloom::build(Some(store));
+        }
+    }
+
+    /// Note that a key was deleted from `store`, which is what the store looks like now.
+    pub(crate) fn deleted(&self, store: &StoreValues) {
+        let Ok(mut bloom) = self.0.write() else {
+            return;
+        };
+        bloom.deleted += 1;
+        // once half the keys added have been deleted, the filter has lost most of its value
+        if bloom.deleted * 2 > bloom.added.max(MIN_CAPACITY) {
+            *bloom = Bloom::build(Some(store));
+        }
+    }
+
+    /// Rebuild the filter from the current contents of `store`.
+    pub(crate) fn rebuild(&self, store: Option<&StoreValues>) {
+        if let Ok(mut bloom) = self.0.write() {
+            *bloom = Bloom::build(store);
+        }
+    }
+}
+
+impl Bloom {
+    fn build(store: Option<&StoreValues>) -> Self {
+        let len = store.map_or(0, |store| store.len());
+        let capacity = (len * 2).max(MIN_CAPACITY);
+        let mut bloom = Bloom {
+            bits: vec![0; (capacity * BITS_PER_KEY).div_ceil(64)],
+            capacity,
+            exists: store.is_some(),
+            added: 0,
+            deleted: 0,
+        };
+        for key in store.into_iter().flat_map(|store| store.keys()) {
+            bloom.add(key);
+        }
+        bloom
+    }
+
+    /// The bit positions for `key`, by double hashing.
+    fn positions(&self, key: &ObjectKey) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.as_str().hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash & 0xffff_ffff, hash >> 32 | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    fn add(&mut self, key: &ObjectKey) {
+        for pos in self.positions(key) {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+        self.exists = true;
+        self.added += 1;
+    }
+
+    fn may_contain(&self, key: &ObjectKey) -> bool {
+        self.positions(key)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+}
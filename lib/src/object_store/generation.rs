@@ -0,0 +1,44 @@
+//! Sources of the generations assigned to values as they are written.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A source of generations.
+///
+/// The stores call [`next_generation`][Self::next_generation] once per write, with their write
+/// lock held, so generations are published in the order they are handed out. Each call must
+/// return a greater generation than every one before it, so that successive writes to a key
+/// always have increasing generations.
+pub trait GenerationSource: fmt::Debug + Send + Sync {
+    fn next_generation(&self) -> u64;
+
+    /// Skip past `generation`, which a seeded value has been given, so that every later call to
+    /// [`next_generation`][Self::next_generation] returns a greater one.
+    fn advance_past(&self, generation: u64);
+}
+
+/// Counts up by one for each write.
+///
+/// This is the source the stores use unless given another. Tests that need to know the
+/// generations ahead of time can use one that starts from a known value.
+#[derive(Debug, Default)]
+pub struct CountingGenerations(AtomicU64);
+
+impl CountingGenerations {
+    /// A source whose first generation is `last + 1`.
+    pub fn starting_after(last: u64) -> Self {
+        Self(AtomicU64::new(last))
+    }
+}
+
+impl GenerationSource for CountingGenerations {
+    fn next_generation(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+    }
+
+    fn advance_past(&self, generation: u64) {
+        self.0.fetch_max(generation, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,65 @@
+//! Per-store counts of inserts by mode, and the largest values they leave behind.
+
+use {
+    super::ObjectStoreKey,
+    crate::wiggle_abi::types::KvInsertMode,
+    std::{collections::BTreeMap, sync::Mutex},
+};
+
+/// The inserts made to one store.
+///
+/// Only inserts whose values land in the store are counted: not those refused, nor those a lost
+/// write rule drops, nor those staged by a transaction that never commits. Values seeded from
+/// configuration or imported aren't counted either, as no guest wrote them.
+///
+/// A store whose appends far outnumber its overwrites, or whose largest value keeps growing, may
+/// have a guest appending where it meant to overwrite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InsertStats {
+    pub overwrites: u64,
+    pub adds: u64,
+    pub appends: u64,
+    pub prepends: u64,
+    /// The largest value, in bytes, any insert has left behind. Appends and prepends count the
+    /// whole value, not just what they added.
+    pub largest_value: usize,
+}
+
+impl InsertStats {
+    /// The total number of inserts, of every mode.
+    pub fn inserts(&self) -> u64 {
+        self.overwrites + self.adds + self.appends + self.prepends
+    }
+}
+
+/// The insert stats for a set of stores, by store.
+#[derive(Debug, Default)]
+pub(crate) struct InsertStatsByStore(Mutex<BTreeMap<ObjectStoreKey, InsertStats>>);
+
+impl InsertStatsByStore {
+    /// Record an insert that left a value of `len` bytes behind, once it has been written. Called
+    /// with the stores' write lock held, so this lock is never contended.
+    pub(crate) fn record(&self, store: &ObjectStoreKey, mode: KvInsertMode, len: usize) {
+        let Ok(mut stats) = self.0.lock() else {
+            return;
+        };
+        let stats = stats.entry(store.clone()).or_default();
+        match mode {
+            KvInsertMode::Overwrite => stats.overwrites += 1,
+            KvInsertMode::Add => stats.adds += 1,
+            KvInsertMode::Append => stats.appends += 1,
+            KvInsertMode::Prepend => stats.prepends += 1,
+        }
+        stats.largest_value = stats.largest_value.max(len);
+    }
+
+    pub(crate) fn snapshot(&self) -> BTreeMap<String, InsertStats> {
+        let Ok(stats) = self.0.lock() else {
+            return BTreeMap::new();
+        };
+        stats
+            .iter()
+            .map(|(store, stats)| (store.to_string(), *stats))
+            .collect()
+    }
+}
@@ -0,0 +1,178 @@
+//! Latency histograms for the operations run against each store.
+
+use {
+    super::ObjectStoreKey,
+    std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc, RwLock,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// The kinds of operation latencies are tracked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KvOpKind {
+    Lookup,
+    Insert,
+    Delete,
+    List,
+}
+
+/// The number of buckets. Each bucket's upper bound is double the last, from 1µs up to about four
+/// seconds, with a final bucket for anything slower.
+const BUCKETS: usize = 24;
+
+/// The inclusive upper bound of bucket `i`.
+fn bucket_bound(i: usize) -> Duration {
+    if i + 1 == BUCKETS {
+        Duration::MAX
+    } else {
+        Duration::from_micros(1 << i)
+    }
+}
+
+fn bucket_for(elapsed: Duration) -> usize {
+    let micros = elapsed.as_nanos().div_ceil(1000);
+    match micros.checked_next_power_of_two() {
+        Some(bound) => (bound.trailing_zeros() as usize).min(BUCKETS - 1),
+        None => BUCKETS - 1,
+    }
+}
+
+/// A fixed-bucket histogram, updated with relaxed atomics so that recording never waits.
+#[derive(Debug, Default)]
+struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl LatencyHistogram {
+    fn record(&self, elapsed: Duration) {
+        let ns = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[bucket_for(elapsed)].fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (bucket_bound(i), count.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        LatencySnapshot {
+            count: buckets.iter().map(|(_, count)| count).sum(),
+            total: Duration::from_nanos(self.total_ns.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_ns.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+}
+
+/// A point-in-time copy of the latencies of one kind of operation against one store.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+    /// Each bucket's inclusive upper bound, and the number of operations that took longer than
+    /// the bucket before's bound but no longer than its own. The last bound is [`Duration::MAX`].
+    pub buckets: Vec<(Duration, u64)>,
+}
+
+impl LatencySnapshot {
+    /// The mean latency, if any operations were recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count).ok().filter(|c| *c > 0)?;
+        Some(self.total / count)
+    }
+
+    /// An upper bound on the `q`th quantile, for `q` between 0 and 1: the bound of the bucket it
+    /// falls in, capped at the slowest operation recorded. `None` if no operations were recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let rank = ((self.count as f64) * q.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        self.buckets.iter().find_map(|(bound, count)| {
+            seen += count;
+            (seen >= rank).then(|| (*bound).min(self.max))
+        })
+    }
+}
+
+/// The latency histograms for a set of stores, created as each store sees its first operation of
+/// each kind.
+#[derive(Debug)]
+pub(crate) struct Latencies {
+    enabled: AtomicBool,
+    #[allow(clippy::type_complexity)]
+    histograms: RwLock<BTreeMap<(ObjectStoreKey, KvOpKind), Arc<LatencyHistogram>>>,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            histograms: RwLock::default(),
+        }
+    }
+}
+
+impl Latencies {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Start timing an operation, which is recorded when the timer is dropped. When tracking is
+    /// disabled, this is a single atomic load.
+    pub(crate) fn time(&self, store: &ObjectStoreKey, op: KvOpKind) -> Option<LatencyTimer> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let key = (store.clone(), op);
+        let existing = self.histograms.read().ok()?.get(&key).cloned();
+        let histogram = match existing {
+            Some(histogram) => histogram,
+            None => self
+                .histograms
+                .write()
+                .ok()?
+                .entry(key)
+                .or_default()
+                .clone(),
+        };
+        Some(LatencyTimer {
+            histogram,
+            start: Instant::now(),
+        })
+    }
+
+    pub(crate) fn snapshot(&self) -> BTreeMap<String, BTreeMap<KvOpKind, LatencySnapshot>> {
+        let mut out = BTreeMap::<_, BTreeMap<_, _>>::new();
+        let Ok(histograms) = self.histograms.read() else {
+            return out;
+        };
+        for ((store, op), histogram) in histograms.iter() {
+            out.entry(store.to_string())
+                .or_default()
+                .insert(*op, histogram.snapshot());
+        }
+        out
+    }
+}
+
+/// Records the time since it was started when dropped.
+pub(crate) struct LatencyTimer {
+    histogram: Arc<LatencyHistogram>,
+    start: Instant,
+}
+
+impl Drop for LatencyTimer {
+    fn drop(&mut self) {
+        self.histogram.record(self.start.elapsed());
+    }
+}
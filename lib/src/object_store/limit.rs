@@ -0,0 +1,91 @@
+//! Limits on the number of operations running against a store at once.
+
+use {
+    super::KvStoreError,
+    std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError},
+};
+
+/// Admits at most `max_concurrent` operations against a store at a time.
+///
+/// Operations over the limit wait their turn, unless `max_queued` are already waiting, in which
+/// case they are shed with [`KvStoreError::TooManyRequests`]. Waiting is asynchronous, so queued
+/// operations don't hold up the threads running other guests.
+#[derive(Debug)]
+pub(crate) struct StoreLimiter {
+    permits: Arc<Semaphore>,
+    max_queued: Option<usize>,
+    queued: AtomicUsize,
+}
+
+/// An operation's turn to run against a store, taken when the operation is issued.
+#[derive(Debug)]
+pub(crate) enum Turn {
+    /// The operation may run now, holding the permit, if the store is limited, until it is done.
+    Now(Option<OwnedSemaphorePermit>),
+    /// The operation must wait for another to finish first.
+    Queued(QueuedTurn),
+}
+
+/// A place in a store's queue, given up when the wait for a permit ends or is abandoned.
+#[derive(Debug)]
+pub(crate) struct QueuedTurn {
+    limiter: Arc<StoreLimiter>,
+}
+
+impl StoreLimiter {
+    pub(crate) fn new(max_concurrent: usize, max_queued: Option<usize>) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take a permit if one is free, or else a place in the queue, or fail if the queue is
+    /// already full.
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Result<Turn, KvStoreError> {
+        match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => return Ok(Turn::Now(Some(permit))),
+            Err(TryAcquireError::NoPermits) => {}
+            Err(TryAcquireError::Closed) => return Err(KvStoreError::InternalError),
+        }
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                match self.max_queued {
+                    Some(max) if queued >= max => None,
+                    _ => Some(queued + 1),
+                }
+            })
+            .map_err(|_| KvStoreError::TooManyRequests)?;
+        Ok(Turn::Queued(QueuedTurn {
+            limiter: self.clone(),
+        }))
+    }
+
+    /// The number of operations currently waiting for a permit.
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+impl QueuedTurn {
+    /// Wait for a permit, giving up the place in the queue once there is one.
+    pub(crate) async fn wait(self) -> OwnedSemaphorePermit {
+        self.limiter
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("store limiter semaphores are never closed")
+    }
+}
+
+impl Drop for QueuedTurn {
+    fn drop(&mut self) {
+        self.limiter.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
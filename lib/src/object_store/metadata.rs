@@ -0,0 +1,46 @@
+//! Checking metadata as it is written, for stores configured with a `metadata_format` setting.
+
+use std::fmt;
+
+/// The form a store's metadata must take. Without one, metadata is opaque bytes, as in production.
+///
+/// Only metadata given with a write is checked, so values already in the store, and appends that
+/// keep the metadata they extend, are left alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataFormat {
+    /// Metadata must be a JSON document, and, if `object_only`, a JSON object.
+    Json { object_only: bool },
+}
+
+impl MetadataFormat {
+    /// The format named `name` in configuration.
+    pub(crate) fn from_name(name: &str, object_only: bool) -> Option<Self> {
+        match name {
+            "json" => Some(MetadataFormat::Json { object_only }),
+            _ => None,
+        }
+    }
+
+    /// Check that `metadata` takes this form, or say why it doesn't.
+    pub(crate) fn check(self, metadata: &[u8]) -> Result<(), String> {
+        match self {
+            MetadataFormat::Json { object_only } => {
+                let value = serde_json::from_slice::<serde_json::Value>(metadata)
+                    .map_err(|e| e.to_string())?;
+                if object_only && !value.is_object() {
+                    return Err("expected a JSON object".to_string());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for MetadataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataFormat::Json { object_only: false } => f.write_str("JSON"),
+            MetadataFormat::Json { object_only: true } => f.write_str("a JSON object"),
+        }
+    }
+}
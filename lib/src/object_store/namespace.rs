@@ -0,0 +1,95 @@
+//! Per-request namespacing of KV stores.
+//!
+//! When enabled, requests carrying the configured header see their own copy of every store,
+//! created from the seed data the first time the namespace is used.
+
+use {
+    super::{KvStoreError, ObjectStores},
+    http::HeaderName,
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex, PoisonError},
+        time::{Duration, Instant},
+    },
+};
+
+/// How requests are mapped to KV store namespaces.
+#[derive(Clone, Debug)]
+pub struct KvNamespaceConfig {
+    /// The request header whose value names the namespace. Requests without it use the shared
+    /// stores.
+    pub header: HeaderName,
+    /// The maximum number of namespaces that may be live at once.
+    pub max_namespaces: usize,
+    /// How long a namespace may go unused before it is discarded.
+    pub idle_timeout: Duration,
+}
+
+impl KvNamespaceConfig {
+    /// Namespace stores by `header`, allowing 64 live namespaces with a five minute idle timeout.
+    pub fn new(header: HeaderName) -> Self {
+        Self {
+            header,
+            max_namespaces: 64,
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// The live namespaces created from a set of stores.
+///
+/// A namespace is only added once its stores are created, so a panic while creating them leaves
+/// the map as it was, and a poisoned lock is recovered from.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Namespaces(Arc<Mutex<HashMap<String, Namespace>>>);
+
+#[derive(Debug)]
+struct Namespace {
+    stores: ObjectStores,
+    last_used: Instant,
+}
+
+impl Namespaces {
+    /// Get the stores for namespace `name`, creating them with `create` if needed.
+    ///
+    /// Namespaces idle for longer than the configured timeout are discarded first. Fails with
+    /// [`KvStoreError::TooManyRequests`] if the namespace would exceed the configured limit.
+    pub(crate) fn get_or_create(
+        &self,
+        name: &str,
+        config: &KvNamespaceConfig,
+        create: impl FnOnce() -> Result<ObjectStores, KvStoreError>,
+    ) -> Result<ObjectStores, KvStoreError> {
+        let mut namespaces = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        namespaces.retain(|_, ns| now.duration_since(ns.last_used) < config.idle_timeout);
+
+        if let Some(ns) = namespaces.get_mut(name) {
+            ns.last_used = now;
+            return Ok(ns.stores.clone());
+        }
+        if namespaces.len() >= config.max_namespaces {
+            return Err(KvStoreError::TooManyRequests);
+        }
+
+        let stores = create()?;
+        namespaces.insert(
+            name.to_string(),
+            Namespace {
+                stores: stores.clone(),
+                last_used: now,
+            },
+        );
+        Ok(stores)
+    }
+
+    /// The stores of every live namespace.
+    pub(crate) fn stores(&self) -> Vec<ObjectStores> {
+        let namespaces = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        namespaces.values().map(|ns| ns.stores.clone()).collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
@@ -0,0 +1,153 @@
+//! Hooks for observing the operations performed against an [`ObjectStores`].
+//!
+//! [`ObjectStores`]: super::ObjectStores
+
+use {
+    super::{KvRequest, KvStoreError, ObjectHead, ObjectValue},
+    crate::wiggle_abi::types::KvInsertMode,
+    std::{
+        fmt,
+        sync::{Arc, PoisonError, RwLock},
+        time::Duration,
+    },
+};
+
+/// A receiver of [`KvEvent`]s.
+///
+/// Observers are called synchronously, after an operation completes and outside of the store
+/// lock, so they should return quickly.
+pub trait KvObserver: Send + Sync {
+    fn on_event(&self, event: &KvEvent<'_>);
+}
+
+/// A KV operation performed against a store, along with its outcome.
+#[derive(Debug)]
+pub struct KvEvent<'a> {
+    pub store: &'a str,
+    /// The request the operation was made for, if it was made through a handle for one. See
+    /// [`ObjectStores::with_request`].
+    ///
+    /// [`ObjectStores::with_request`]: super::ObjectStores::with_request
+    pub request: Option<&'a KvRequest>,
+    pub op: KvOp<'a>,
+}
+
+#[derive(Debug)]
+pub enum KvOp<'a> {
+    Lookup {
+        key: &'a str,
+        result: Result<&'a ObjectValue, &'a KvStoreError>,
+        /// Whether the lookup missed because the key's value had expired, rather than because it
+        /// was never written or was deleted. The result is `NotFound` either way.
+        expired: bool,
+    },
+    /// A [head][super::ObjectStores::head], which finds everything about a value but its body.
+    Head {
+        key: &'a str,
+        result: Result<&'a ObjectHead, &'a KvStoreError>,
+        /// As for a lookup.
+        expired: bool,
+    },
+    Insert {
+        key: &'a str,
+        body: &'a [u8],
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<&'a [u8]>,
+        ttl: Option<Duration>,
+        result: Result<(), &'a KvStoreError>,
+        /// Whether the insert succeeded but was dropped rather than written, by a
+        /// [`LostWriteRule`][super::LostWriteRule].
+        lost: bool,
+    },
+    Delete {
+        key: &'a str,
+        result: Result<(), &'a KvStoreError>,
+    },
+    List {
+        cursor: Option<&'a str>,
+        prefix: Option<&'a str>,
+        limit: u32,
+        result: Result<&'a [u8], &'a KvStoreError>,
+    },
+}
+
+/// The observers registered with a set of stores.
+///
+/// Observers added with [`ObjectStores::add_observer`] are shared by every clone of the stores.
+/// Scoped observers belong to a single handle, and the clones made from it.
+///
+/// A panic can't leave the list half-changed, so a poisoned lock is recovered from.
+///
+/// [`ObjectStores::add_observer`]: super::ObjectStores::add_observer
+#[derive(Clone, Default)]
+pub(crate) struct Observers {
+    shared: Arc<RwLock<Vec<Arc<dyn KvObserver>>>>,
+    scoped: Vec<Arc<dyn KvObserver>>,
+}
+
+impl Observers {
+    pub(crate) fn push(&self, observer: Arc<dyn KvObserver>) {
+        self.shared
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(observer);
+    }
+
+    /// Remove a shared observer added with [`push`][Self::push].
+    pub(crate) fn remove(&self, observer: &Arc<dyn KvObserver>) {
+        self.shared
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|o| !Arc::ptr_eq(o, observer));
+    }
+
+    /// These observers, plus `observer` for this copy only.
+    pub(crate) fn scoped(&self, observer: Arc<dyn KvObserver>) -> Self {
+        let mut scoped = self.clone();
+        scoped.scoped.push(observer);
+        scoped
+    }
+
+    /// These observers, without any scoped ones.
+    pub(crate) fn unscoped(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            scoped: Vec::new(),
+        }
+    }
+
+    /// Replace the scoped observers with those of `other`.
+    pub(crate) fn rescope(&mut self, other: &Observers) {
+        self.scoped = other.scoped.clone();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scoped.is_empty()
+            && self
+                .shared
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_empty()
+    }
+
+    pub(crate) fn notify(&self, event: &KvEvent<'_>) {
+        let shared = self.shared.read().unwrap_or_else(PoisonError::into_inner);
+        for observer in shared.iter() {
+            observer.on_event(event);
+        }
+        for observer in &self.scoped {
+            observer.on_event(event);
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.read().map(|o| o.len()).unwrap_or_default();
+        f.debug_struct("Observers")
+            .field("shared", &shared)
+            .field("scoped", &self.scoped.len())
+            .finish()
+    }
+}
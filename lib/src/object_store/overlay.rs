@@ -0,0 +1,105 @@
+//! Copy-on-write views of a set of stores, for sessions isolated from one another. See
+//! [`ObjectStores::isolated`].
+//!
+//! [`ObjectStores::isolated`]: super::ObjectStores::isolated
+
+use {
+    super::{KvStoreError, ObjectKey, ObjectStoreKey, ObjectStores, StoreMap},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::Mutex,
+    },
+};
+
+/// What an isolated view reads through to, and what it has taken over from there.
+///
+/// A view's own stores start out empty. Before an operation reads or writes a key, the key's
+/// value in the shared stores, if any, is copied into the view, and from then on the view's copy
+/// is the key's value, or its absence is: a key the view deleted stays deleted, whatever the
+/// shared stores hold. Operations over a whole store, such as lists, take over every key of it at
+/// once, which merges the two with the view's deletions masking the shared keys.
+#[derive(Debug)]
+pub(crate) struct Overlay {
+    /// The stores the view was made from.
+    pub(super) base: ObjectStores,
+    /// The keys taken over from each store, or `None` once the whole store has been.
+    taken: Mutex<BTreeMap<ObjectStoreKey, Option<BTreeSet<ObjectKey>>>>,
+}
+
+impl Overlay {
+    pub(super) fn new(base: ObjectStores) -> Self {
+        Self {
+            base,
+            taken: Mutex::default(),
+        }
+    }
+
+    /// Copy `key`, or every key of the store if there is none, into `stores`, the view's own,
+    /// unless it was copied before. The caller holds the view's write lock.
+    ///
+    /// The store is created in the view if it exists in the shared stores, even if the key
+    /// doesn't, so that operations on it don't fail as though it didn't exist.
+    pub(super) fn take_over(
+        &self,
+        stores: &mut StoreMap,
+        obj_store_key: &ObjectStoreKey,
+        key: Option<&ObjectKey>,
+    ) -> Result<(), KvStoreError> {
+        let mut taken = self.taken.lock().map_err(|_| KvStoreError::InternalError)?;
+        let entry = taken
+            .entry(obj_store_key.clone())
+            .or_insert_with(|| Some(BTreeSet::new()));
+        let Some(keys) = entry else {
+            return Ok(());
+        };
+        if key.is_some_and(|key| keys.contains(key)) {
+            return Ok(());
+        }
+
+        // the shared stores may themselves be a view, which takes the keys over first
+        self.base.take_over(obj_store_key, key)?;
+        let base = self
+            .base
+            .stores
+            .read()
+            .map_err(|_| KvStoreError::InternalError)?;
+        let base_store = base.get(obj_store_key);
+        if let Some(base_store) = base_store {
+            let store = stores.entry(obj_store_key.clone()).or_default();
+            match key {
+                Some(key) => {
+                    if let Some(val) = base_store.get(key) {
+                        store.insert(key.clone(), val.clone());
+                    }
+                }
+                None => {
+                    for (key, val) in base_store.iter() {
+                        if !keys.contains(key) {
+                            store.insert(key.clone(), val.clone());
+                        }
+                    }
+                }
+            }
+        }
+        match key {
+            Some(key) => {
+                keys.insert(key.clone());
+            }
+            None => *entry = None,
+        }
+        Ok(())
+    }
+
+    /// The stores that exist in the shared stores, to take over whole.
+    pub(super) fn base_store_keys(&self) -> Result<Vec<ObjectStoreKey>, KvStoreError> {
+        self.base.take_over_all()?;
+        Ok(self
+            .base
+            .stores
+            .read()
+            .map_err(|_| KvStoreError::InternalError)?
+            .keys()
+            .cloned()
+            .collect())
+    }
+}
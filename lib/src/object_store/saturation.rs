@@ -0,0 +1,41 @@
+//! Noting the generations guests are given saturated, as the guest ABIs only have 32 bits for
+//! them.
+
+use {
+    super::{abi_generation, ObjectKey, ObjectStoreKey},
+    std::{
+        collections::HashSet,
+        sync::{Mutex, PoisonError},
+    },
+    tracing::warn,
+};
+
+/// The keys whose generations guests have been given saturated, so each is only warned of once.
+#[derive(Debug, Default)]
+pub(crate) struct SaturatedKeys {
+    keys: Mutex<HashSet<(ObjectStoreKey, ObjectKey)>>,
+}
+
+impl SaturatedKeys {
+    /// Note that a guest is being given `generation`, the generation of `key` in `store`, warning
+    /// the first time one of the key's is past what the guest ABIs can give. Returns whether this
+    /// was that first time.
+    pub(crate) fn note(&self, store: &ObjectStoreKey, key: &ObjectKey, generation: u64) -> bool {
+        if u64::from(abi_generation(generation)) == generation {
+            return false;
+        }
+        let mut keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        if !keys.insert((store.clone(), key.clone())) {
+            return false;
+        }
+        warn!(
+            "the generation of {:?} in store {:?}, {generation}, is past what guests can be \
+             given, so they are given {} instead; a guest's `if_generation_match` with it matches \
+             any value whose generation is as large",
+            key.as_str(),
+            store.as_str(),
+            u32::MAX,
+        );
+        true
+    }
+}
@@ -0,0 +1,168 @@
+//! Observers and stats confined to a store, a key prefix, or both.
+
+use {
+    super::{KvEvent, KvObserver, KvOp, KvStoreError},
+    std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+/// A part of a set of stores: the keys under a prefix, in one store or in every store.
+///
+/// An operation on a single key is in the scope if the key is. A list is in it only if its own
+/// prefix is under the scope's, so that every key it could return is in the scope too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KvScope {
+    /// The store the scope covers, or every store if unset.
+    pub store: Option<String>,
+    /// The prefix of the keys the scope covers, or every key if unset.
+    pub key_prefix: Option<String>,
+}
+
+impl KvScope {
+    /// Whether `event` is for an operation within this scope.
+    pub fn contains(&self, event: &KvEvent<'_>) -> bool {
+        if self
+            .store
+            .as_deref()
+            .is_some_and(|store| store != event.store)
+        {
+            return false;
+        }
+        let Some(prefix) = self.key_prefix.as_deref() else {
+            return true;
+        };
+        let key = match &event.op {
+            KvOp::Lookup { key, .. }
+            | KvOp::Head { key, .. }
+            | KvOp::Insert { key, .. }
+            | KvOp::Delete { key, .. } => *key,
+            KvOp::List { prefix: list, .. } => list.unwrap_or_default(),
+        };
+        key.starts_with(prefix)
+    }
+}
+
+/// An observer that is only notified of the events within a scope.
+pub(crate) struct InScope {
+    scope: KvScope,
+    observer: Arc<dyn KvObserver>,
+}
+
+impl InScope {
+    pub(crate) fn new(scope: KvScope, observer: Arc<dyn KvObserver>) -> Self {
+        Self { scope, observer }
+    }
+}
+
+impl KvObserver for InScope {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        if self.scope.contains(event) {
+            self.observer.on_event(event);
+        }
+    }
+}
+
+/// Counts of the operations within a scope, as registered with
+/// [`ObjectStores::add_stats_scope`][super::ObjectStores::add_stats_scope].
+///
+/// Heads count as lookups, and a miss is counted as a miss rather than an error, as in a
+/// session's KV summary.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScopeStats {
+    pub lookups: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub deletes: u64,
+    pub lists: u64,
+    /// The bytes given by successful inserts. Appends and prepends count only what they added.
+    pub bytes_written: u64,
+    pub errors: u64,
+}
+
+/// The counters behind a [`ScopeStats`], updated as an observer.
+#[derive(Debug, Default)]
+pub(crate) struct ScopeCounters {
+    lookups: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    deletes: AtomicU64,
+    lists: AtomicU64,
+    bytes_written: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl ScopeCounters {
+    fn snapshot(&self) -> ScopeStats {
+        ScopeStats {
+            lookups: self.lookups.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            lists: self.lists.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl KvObserver for ScopeCounters {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        let (count, err) = match &event.op {
+            KvOp::Lookup { result, .. } => (&self.lookups, result.err()),
+            KvOp::Head { result, .. } => (&self.lookups, result.err()),
+            KvOp::Insert { body, result, .. } => {
+                if result.is_ok() {
+                    self.bytes_written
+                        .fetch_add(body.len() as u64, Ordering::Relaxed);
+                }
+                (&self.inserts, result.err())
+            }
+            KvOp::Delete { result, .. } => (&self.deletes, result.err()),
+            KvOp::List { result, .. } => (&self.lists, result.err()),
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        let outcome = match (&event.op, err) {
+            (KvOp::Lookup { .. } | KvOp::Head { .. }, None) => &self.hits,
+            (KvOp::Lookup { .. } | KvOp::Head { .. }, Some(KvStoreError::NotFound)) => &self.misses,
+            (_, None) => return,
+            (_, Some(_)) => &self.errors,
+        };
+        outcome.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The stats scopes registered with a set of stores, by name.
+#[derive(Debug, Default)]
+pub(crate) struct ScopeStatsByName(Mutex<BTreeMap<String, Arc<ScopeCounters>>>);
+
+impl ScopeStatsByName {
+    /// Start counting under `name`, returning the counters to register as an observer, or `None`
+    /// if a scope by that name is already counted.
+    pub(crate) fn add(&self, name: String) -> Option<Arc<ScopeCounters>> {
+        let mut scopes = self.0.lock().expect("scope stats lock poisoned");
+        if scopes.contains_key(&name) {
+            return None;
+        }
+        let counters = Arc::new(ScopeCounters::default());
+        scopes.insert(name, counters.clone());
+        Some(counters)
+    }
+
+    pub(crate) fn snapshot(&self) -> BTreeMap<String, ScopeStats> {
+        let Ok(scopes) = self.0.lock() else {
+            return BTreeMap::new();
+        };
+        scopes
+            .iter()
+            .map(|(name, counters)| (name.clone(), counters.snapshot()))
+            .collect()
+    }
+}
@@ -0,0 +1,317 @@
+//! Applying several writes to a set of stores as one. See [`ObjectStores::transaction`].
+//!
+//! [`ObjectStores::transaction`]: super::ObjectStores::transaction
+
+use {
+    super::{
+        delete_locked, looked_up_value, KvEvent, KvOp, KvRequest, KvStoreError, ObjectKey,
+        ObjectStoreKey, ObjectStores, ObjectValue, SeedOptions, StoreMap,
+    },
+    crate::wiggle_abi::types::KvInsertMode,
+    std::{fmt, time::Duration},
+};
+
+/// The writes staged by an [`ObjectStores::transaction`].
+///
+/// Each store is copied the first time the transaction touches it, and operations run against the
+/// copies, which replace the originals when the transaction commits.
+///
+/// [`ObjectStores::transaction`]: super::ObjectStores::transaction
+pub struct KvTransaction<'a> {
+    stores: &'a ObjectStores,
+    committed: &'a StoreMap,
+    staged: StoreMap,
+    /// The operations performed, to notify observers of on commit. Only recorded if there are
+    /// observers.
+    ops: Option<Vec<Staged>>,
+    /// The inserts staged, counted in the stores' insert stats only if the transaction commits.
+    inserted: Vec<StagedInsert>,
+}
+
+/// The store an insert was staged in, its mode, and the length of the body it left behind.
+pub(super) type StagedInsert = (ObjectStoreKey, KvInsertMode, usize);
+
+impl<'a> KvTransaction<'a> {
+    pub(super) fn new(stores: &'a ObjectStores, committed: &'a StoreMap, observed: bool) -> Self {
+        Self {
+            stores,
+            committed,
+            staged: StoreMap::new(),
+            ops: observed.then(Vec::new),
+            inserted: Vec::new(),
+        }
+    }
+
+    /// Look up a value, as of the writes staged so far.
+    pub fn lookup(
+        &mut self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> Result<ObjectValue, KvStoreError> {
+        self.stage_store(&obj_store_key);
+        let (res, expired) = match self.staged.get_mut(&obj_store_key) {
+            Some(store) => looked_up_value(store, &obj_key, self.stores.clock.now()),
+            None => (Err(KvStoreError::Uninitialized), false),
+        };
+        if let Some(ops) = &mut self.ops {
+            ops.push(Staged::Lookup {
+                store: obj_store_key,
+                key: obj_key,
+                result: res.clone(),
+                expired,
+            });
+        }
+        res
+    }
+
+    /// Stage a write, as [`ObjectStores::insert`] would make it, returning the generation it was
+    /// given.
+    ///
+    /// [`ObjectStores::insert`]: super::ObjectStores::insert
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        &mut self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        obj: Vec<u8>,
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<Vec<u8>>,
+        ttl: Option<Duration>,
+    ) -> Result<u64, KvStoreError> {
+        self.stage_store(&obj_store_key);
+        let Some(ops) = &mut self.ops else {
+            let (generation, len) = self.stores.insert_locked(
+                &mut self.staged,
+                obj_store_key.clone(),
+                obj_key,
+                obj,
+                mode,
+                generation,
+                metadata,
+                ttl,
+            )?;
+            self.inserted.push((obj_store_key, mode, len));
+            return Ok(generation);
+        };
+
+        let res = self
+            .stores
+            .insert_locked(
+                &mut self.staged,
+                obj_store_key.clone(),
+                obj_key.clone(),
+                obj.clone(),
+                mode,
+                generation,
+                metadata.clone(),
+                ttl,
+            )
+            .map(|(generation, len)| {
+                self.inserted.push((obj_store_key.clone(), mode, len));
+                generation
+            });
+        ops.push(Staged::Insert {
+            store: obj_store_key,
+            key: obj_key,
+            body: obj,
+            mode,
+            generation,
+            metadata,
+            ttl,
+            result: res.clone().map(|_| ()),
+        });
+        res
+    }
+
+    /// Stage a delete, as [`ObjectStores::delete`] would make it.
+    ///
+    /// [`ObjectStores::delete`]: super::ObjectStores::delete
+    pub fn delete(
+        &mut self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> Result<(), KvStoreError> {
+        self.stage_store(&obj_store_key);
+        let Some(ops) = &mut self.ops else {
+            return delete_locked(
+                &mut self.staged,
+                obj_store_key,
+                obj_key,
+                None,
+                self.stores.abi_generations,
+                self.stores.clock.now(),
+            );
+        };
+
+        let res = delete_locked(
+            &mut self.staged,
+            obj_store_key.clone(),
+            obj_key.clone(),
+            None,
+            self.stores.abi_generations,
+            self.stores.clock.now(),
+        );
+        ops.push(Staged::Delete {
+            store: obj_store_key,
+            key: obj_key,
+            result: res.clone(),
+        });
+        res
+    }
+
+    /// Stage the creation of an empty store, if it doesn't already exist.
+    pub fn create_store(&mut self, obj_store_key: ObjectStoreKey) {
+        self.stage_store(&obj_store_key);
+        self.staged.entry(obj_store_key).or_default();
+    }
+
+    /// Stage a seeded value, as [`ObjectStores::create_store`] describes. A value that keeps its
+    /// generation, rather than being given the next one, moves the stores' generation source past
+    /// it, so later writes are given greater ones. Seeded values aren't counted in the stores'
+    /// insert stats, as no guest wrote them.
+    pub(super) fn insert_seeded(
+        &mut self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+        obj: Vec<u8>,
+        options: SeedOptions,
+    ) -> Result<(), KvStoreError> {
+        self.insert(
+            obj_store_key.clone(),
+            obj_key.clone(),
+            obj,
+            KvInsertMode::Overwrite,
+            None,
+            options.metadata,
+            None,
+        )?;
+        self.inserted.pop();
+        if let Some(val) = self
+            .staged
+            .get_mut(&obj_store_key)
+            .and_then(|store| store.get_mut(&obj_key))
+        {
+            if let Some(generation) = options.generation {
+                val.generation = generation;
+            }
+            match (options.created_at, options.updated_at) {
+                (Some(created_at), Some(updated_at)) => {
+                    val.created_at = created_at;
+                    val.updated_at = updated_at;
+                }
+                (Some(at), None) | (None, Some(at)) => {
+                    val.created_at = at;
+                    val.updated_at = at;
+                }
+                (None, None) => {}
+            }
+        }
+        if let Some(generation) = options.generation {
+            self.stores.generations.advance_past(generation);
+        }
+        Ok(())
+    }
+
+    /// Copy a store into the staged stores, if it exists and hasn't been copied already.
+    fn stage_store(&mut self, obj_store_key: &ObjectStoreKey) {
+        if self.staged.contains_key(obj_store_key) {
+            return;
+        }
+        if let Some(store) = self.committed.get(obj_store_key) {
+            self.staged.insert(obj_store_key.clone(), store.clone());
+        }
+    }
+
+    /// The stores this transaction touched, the inserts it staged, and the operations it
+    /// performed.
+    pub(super) fn into_parts(self) -> (StoreMap, Vec<StagedInsert>, Vec<Staged>) {
+        (self.staged, self.inserted, self.ops.unwrap_or_default())
+    }
+}
+
+impl fmt::Debug for KvTransaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvTransaction")
+            .field("staged", &self.staged.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// An operation performed in a transaction, kept until it commits.
+pub(super) enum Staged {
+    Lookup {
+        store: ObjectStoreKey,
+        key: ObjectKey,
+        result: Result<ObjectValue, KvStoreError>,
+        expired: bool,
+    },
+    Insert {
+        store: ObjectStoreKey,
+        key: ObjectKey,
+        body: Vec<u8>,
+        mode: KvInsertMode,
+        generation: Option<u64>,
+        metadata: Option<Vec<u8>>,
+        ttl: Option<Duration>,
+        result: Result<(), KvStoreError>,
+    },
+    Delete {
+        store: ObjectStoreKey,
+        key: ObjectKey,
+        result: Result<(), KvStoreError>,
+    },
+}
+
+impl Staged {
+    /// The event for the operation, made through a handle for `request`.
+    pub(super) fn event<'a>(&'a self, request: Option<&'a KvRequest>) -> KvEvent<'a> {
+        match self {
+            Staged::Lookup {
+                store,
+                key,
+                result,
+                expired,
+            } => KvEvent {
+                store: store.as_str(),
+                request,
+                op: KvOp::Lookup {
+                    key: key.as_str(),
+                    result: result.as_ref(),
+                    expired: *expired,
+                },
+            },
+            Staged::Insert {
+                store,
+                key,
+                body,
+                mode,
+                generation,
+                metadata,
+                ttl,
+                result,
+            } => KvEvent {
+                store: store.as_str(),
+                request,
+                op: KvOp::Insert {
+                    key: key.as_str(),
+                    body,
+                    mode: *mode,
+                    generation: *generation,
+                    metadata: metadata.as_deref(),
+                    ttl: *ttl,
+                    result: result.as_ref().copied(),
+                    lost: false,
+                },
+            },
+            Staged::Delete { store, key, result } => KvEvent {
+                store: store.as_str(),
+                request,
+                op: KvOp::Delete {
+                    key: key.as_str(),
+                    result: result.as_ref().copied(),
+                },
+            },
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The values of a single store, with running totals of their sizes.
+
+use {
+    super::{ObjectKey, ObjectValue},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        ops::Deref,
+        time::SystemTime,
+    },
+};
+
+/// The size of one store, as [`ObjectStores::stats`] reports it.
+///
+/// Values that have expired but not yet been evicted are counted, as they still take up memory.
+///
+/// [`ObjectStores::stats`]: super::ObjectStores::stats
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub name: String,
+    pub key_count: usize,
+    /// The bytes of the values' bodies, as held: compressed, for a store with `compression`.
+    pub value_bytes: usize,
+    pub metadata_bytes: usize,
+}
+
+/// When a value was written, by last-modified time and then generation, and its key.
+pub(crate) type Written = (SystemTime, u64, ObjectKey);
+
+/// The values of one store, in key order.
+///
+/// Reads go through the map this derefs to. Writes go through the methods here, which keep the
+/// totals and the order the values were written in step, so that [`stats`][Self::stats] never
+/// has to walk the values, nor [`oldest`][Self::oldest] search them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StoreValues {
+    values: BTreeMap<ObjectKey, ObjectValue>,
+    written: BTreeSet<Written>,
+    value_bytes: usize,
+    metadata_bytes: usize,
+}
+
+impl Deref for StoreValues {
+    type Target = BTreeMap<ObjectKey, ObjectValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl StoreValues {
+    pub(crate) fn insert(&mut self, key: ObjectKey, val: ObjectValue) -> Option<ObjectValue> {
+        let (value_bytes, metadata_bytes) = (val.body.len(), val.metadata.len());
+        let written = (val.updated_at, val.generation, key.clone());
+        let old = self.values.insert(key.clone(), val);
+        // forgotten first, in case it was written at the same moment as its replacement
+        if let Some(old) = &old {
+            self.forget(&key, old);
+        }
+        self.value_bytes += value_bytes;
+        self.metadata_bytes += metadata_bytes;
+        self.written.insert(written);
+        old
+    }
+
+    pub(crate) fn remove(&mut self, key: &ObjectKey) -> Option<ObjectValue> {
+        let old = self.values.remove(key);
+        if let Some(old) = &old {
+            self.forget(key, old);
+        }
+        old
+    }
+
+    /// Keep only the values `keep` is true for.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&ObjectKey, &ObjectValue) -> bool) {
+        let (mut value_bytes, mut metadata_bytes) = (0, 0);
+        let written = &mut self.written;
+        self.values.retain(|key, val| {
+            let kept = keep(key, val);
+            if !kept {
+                value_bytes += val.body.len();
+                metadata_bytes += val.metadata.len();
+                written.remove(&(val.updated_at, val.generation, key.clone()));
+            }
+            kept
+        });
+        self.value_bytes -= value_bytes;
+        self.metadata_bytes -= metadata_bytes;
+    }
+
+    /// A value to change in place. Its body, metadata, generation, and last-modified time must be
+    /// left as they are, or the totals and the order of writes will be wrong.
+    pub(crate) fn get_mut(&mut self, key: &ObjectKey) -> Option<&mut ObjectValue> {
+        self.values.get_mut(key)
+    }
+
+    /// The bytes of bodies and metadata the store holds.
+    pub(crate) fn bytes(&self) -> usize {
+        self.value_bytes + self.metadata_bytes
+    }
+
+    /// The least recently written values, oldest first.
+    pub(crate) fn oldest(&self) -> impl Iterator<Item = &Written> {
+        self.written.iter()
+    }
+
+    /// The store's size, under the name `name`.
+    pub(crate) fn stats(&self, name: &str) -> StoreStats {
+        StoreStats {
+            name: name.to_string(),
+            key_count: self.values.len(),
+            value_bytes: self.value_bytes,
+            metadata_bytes: self.metadata_bytes,
+        }
+    }
+
+    fn forget(&mut self, key: &ObjectKey, val: &ObjectValue) {
+        self.value_bytes -= val.body.len();
+        self.metadata_bytes -= val.metadata.len();
+        self.written
+            .remove(&(val.updated_at, val.generation, key.clone()));
+    }
+}
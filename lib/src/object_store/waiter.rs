@@ -0,0 +1,66 @@
+//! Waiting for a key to be written. See [`ObjectStores::await_key`].
+//!
+//! [`ObjectStores::await_key`]: super::ObjectStores::await_key
+
+use {
+    super::{observer::Observers, KvEvent, KvObserver, KvOp, ObjectKey, ObjectStoreKey},
+    std::sync::Arc,
+    tokio::sync::Notify,
+};
+
+/// An observer that wakes its waiter whenever its key is written.
+pub(super) struct KeyWaiter {
+    store: ObjectStoreKey,
+    key: ObjectKey,
+    pub(super) written: Notify,
+}
+
+impl KeyWaiter {
+    pub(super) fn new(store: ObjectStoreKey, key: ObjectKey) -> Self {
+        Self {
+            store,
+            key,
+            written: Notify::new(),
+        }
+    }
+}
+
+impl KvObserver for KeyWaiter {
+    fn on_event(&self, event: &KvEvent<'_>) {
+        if let KvOp::Insert {
+            key,
+            result: Ok(()),
+            lost: false,
+            ..
+        } = event.op
+        {
+            // a write the waiter misses leaves a permit behind, so it is never lost
+            if event.store == self.store.as_str() && key == self.key.as_str() {
+                self.written.notify_one();
+            }
+        }
+    }
+}
+
+/// Keeps an observer registered until dropped, so that a wait that is given up on, or that times
+/// out, leaves nothing behind.
+pub(super) struct Registered<'a> {
+    observers: &'a Observers,
+    observer: Arc<dyn KvObserver>,
+}
+
+impl<'a> Registered<'a> {
+    pub(super) fn new(observers: &'a Observers, observer: Arc<dyn KvObserver>) -> Self {
+        observers.push(observer.clone());
+        Self {
+            observers,
+            observer,
+        }
+    }
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        self.observers.remove(&self.observer);
+    }
+}
@@ -14,6 +14,7 @@ use {
         net::SocketAddr,
         pin::Pin,
         task::{self, Poll},
+        time::Duration,
     },
     tracing::{event, Level},
 };
@@ -69,6 +70,102 @@ impl ViceroyService {
         server.await?;
         Ok(())
     }
+
+    /// Bind this service to the given address and serve responses until `signal` resolves.
+    ///
+    /// Once `signal` resolves, the server stops accepting new connections and waits up to
+    /// `grace_period` for in-flight requests, and the KV operations they issue, to finish before
+    /// returning. See [`ExecuteCtx::drain_in_flight_requests`] for what counts as finished. An
+    /// error from the server during the grace period is returned once in-flight requests have been
+    /// drained and the KV stores summarized.
+    pub async fn serve_with_graceful_shutdown(
+        self,
+        addr: SocketAddr,
+        signal: impl Future<Output = ()>,
+        grace_period: Duration,
+    ) -> Result<(), hyper::Error> {
+        let ctx = self.ctx.clone();
+        let server = hyper::Server::bind(&addr).serve(self);
+        event!(Level::INFO, "Listening on http://{}", server.local_addr());
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let server = server.with_graceful_shutdown(async move {
+            signal.await;
+            let _ = shutdown_tx.send(tokio::time::Instant::now() + grace_period);
+        });
+        tokio::pin!(server);
+
+        let deadline = tokio::select! {
+            res = &mut server => {
+                // The server stopped on its own, which only happens on error.
+                res?;
+                return Ok(());
+            }
+            deadline = shutdown_rx => match deadline {
+                Ok(deadline) => deadline,
+                Err(_) => return server.await,
+            },
+        };
+
+        let in_flight = ctx.in_flight_requests();
+        event!(
+            Level::INFO,
+            "shutting down; waiting up to {:?} for {} in-flight request(s)",
+            grace_period,
+            in_flight
+        );
+        let served = match tokio::time::timeout_at(deadline, &mut server).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => {
+                event!(Level::ERROR, "server failed while shutting down: {}", e);
+                Err(e)
+            }
+            Err(_) => {
+                event!(
+                    Level::WARN,
+                    "grace period expired with connections still open"
+                );
+                Ok(())
+            }
+        };
+
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        let abandoned = ctx.drain_in_flight_requests(remaining).await.abandoned;
+        let flushed = in_flight.saturating_sub(abandoned);
+        if abandoned == 0 {
+            event!(
+                Level::INFO,
+                "shutdown complete: {} in-flight request(s) and their KV operations flushed",
+                flushed
+            );
+        } else {
+            event!(
+                Level::WARN,
+                "shutdown complete: {} in-flight request(s) and their KV operations flushed, {} abandoned",
+                flushed,
+                abandoned
+            );
+        }
+        for stats in ctx.object_stores().stats() {
+            event!(
+                Level::INFO,
+                "KV store {:?}: {} key(s), {} value bytes, {} metadata bytes",
+                stats.name,
+                stats.key_count,
+                stats.value_bytes,
+                stats.metadata_bytes
+            );
+        }
+        if let Some(budget) = ctx.object_stores().memory_budget() {
+            event!(
+                Level::INFO,
+                "KV stores hold {} of their {} byte memory budget",
+                ctx.object_stores().memory_usage(),
+                budget.max_bytes
+            );
+        }
+        served
+    }
 }
 
 impl<'addr> Service<&'addr AddrStream> for ViceroyService {
@@ -2,40 +2,50 @@
 
 mod async_item;
 mod downstream;
+mod kv_chaos;
+mod kv_guard;
+mod kv_stats;
 
 pub use async_item::{
-    AsyncItem, PeekableTask, PendingKvDeleteTask, PendingKvInsertTask, PendingKvListTask,
-    PendingKvLookupTask,
+    AsyncItem, PeekableTask, PendingKvDeleteTask, PendingKvHeadTask, PendingKvInsertTask,
+    PendingKvListTask, PendingKvLookupTask,
 };
+pub use kv_guard::install_kv_panic_hook;
+pub use kv_stats::KvSummary;
 
 use std::collections::HashMap;
 use std::future::Future;
 use std::io::Write;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::object_store::KvStoreError;
 
 use {
-    self::downstream::DownstreamResponse,
+    self::{
+        downstream::DownstreamResponse, kv_chaos::KvChaos, kv_guard::guarded, kv_stats::KvStats,
+    },
     crate::{
         body::Body,
         config::{Backend, Backends, DeviceDetection, Dictionaries, Geolocation, LoadedDictionary},
         error::{Error, HandleError},
         logging::LogEndpoint,
-        object_store::{ObjectKey, ObjectStoreKey, ObjectStores, ObjectValue},
+        object_store::{
+            KeyValidationError, KeyValidationProfile, KvNamespaceConfig, KvRequest, ObjectHead,
+            ObjectKey, ObjectStoreKey, ObjectStores, ObjectValue, StoreLimits, Turn,
+        },
         secret_store::{SecretLookup, SecretStores},
         streaming_body::StreamingBody,
         upstream::{SelectTarget, TlsConfig},
         wiggle_abi::types::{
             self, BodyHandle, ContentEncodings, DictionaryHandle, EndpointHandle, KvInsertMode,
-            KvStoreDeleteHandle, KvStoreHandle, KvStoreInsertHandle, KvStoreListHandle,
-            KvStoreLookupHandle, PendingKvDeleteHandle, PendingKvInsertHandle, PendingKvListHandle,
-            PendingKvLookupHandle, PendingRequestHandle, RequestHandle, ResponseHandle,
-            SecretHandle, SecretStoreHandle,
+            KvStoreDeleteHandle, KvStoreHandle, KvStoreHeadHandle, KvStoreInsertHandle,
+            KvStoreListHandle, KvStoreLookupHandle, PendingKvDeleteHandle, PendingKvInsertHandle,
+            PendingKvListHandle, PendingKvLookupHandle, PendingRequestHandle, RequestHandle,
+            ResponseHandle, SecretHandle, SecretStoreHandle,
         },
         ExecuteCtx,
     },
@@ -133,6 +143,25 @@ pub struct Session {
     ///
     /// Populated prior to guest execution.
     kv_store_by_name: PrimaryMap<KvStoreHandle, ObjectStoreKey>,
+    /// The handles of the stores opened by the guest, by name.
+    kv_store_handles: HashMap<ObjectStoreKey, KvStoreHandle>,
+    /// How this session's KV namespace is chosen, until it has been resolved.
+    ///
+    /// Cleared once the guest first opens a store.
+    kv_namespaces: Option<KvNamespaceConfig>,
+    /// Whether `select` picks its winner reproducibly. See
+    /// [`ExecuteCtx::with_deterministic_select`].
+    deterministic_select: bool,
+    /// Whether the guest may use the hostcalls Viceroy offers beyond production's. See
+    /// [`ExecuteCtx::with_local_extensions`].
+    local_extensions: bool,
+    /// Picks how long to delay KV operations by, in chaos mode. See
+    /// [`ExecuteCtx::with_kv_chaos`].
+    kv_chaos: Option<KvChaos>,
+    /// Counters for the KV operations performed during this execution.
+    ///
+    /// Summarized on the end-of-request log event.
+    kv_stats: Arc<KvStats>,
     /// The secret stores configured for this execution.
     ///
     /// Populated prior to guest execution, and never modified.
@@ -175,6 +204,11 @@ impl Session {
     ) -> Session {
         let (parts, body) = req.into_parts();
         let downstream_req_original_headers = parts.headers.clone();
+        let kv_request = KvRequest {
+            req_id,
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+        };
 
         let mut async_items: PrimaryMap<AsyncItemHandle, Option<AsyncItem>> = PrimaryMap::new();
         let mut req_parts = PrimaryMap::new();
@@ -205,8 +239,14 @@ impl Session {
             tls_config,
             dictionaries,
             loaded_dictionaries: PrimaryMap::new(),
-            kv_store,
+            kv_store: kv_store.with_request(kv_request).with_abi_generations(),
             kv_store_by_name: PrimaryMap::new(),
+            kv_store_handles: HashMap::new(),
+            kv_namespaces: ctx.kv_namespaces().cloned(),
+            deterministic_select: ctx.deterministic_select(),
+            local_extensions: ctx.local_extensions(),
+            kv_chaos: ctx.kv_chaos().map(|seed| KvChaos::new(seed, req_id)),
+            kv_stats: Arc::default(),
             secret_stores,
             secret_stores_by_name: PrimaryMap::new(),
             secrets_by_name: PrimaryMap::new(),
@@ -685,32 +725,200 @@ impl Session {
     }
 
     // ----- KV Store API -----
-    pub fn kv_store_handle(&mut self, key: &str) -> Result<KvStoreHandle, Error> {
-        let obj_key = ObjectStoreKey::new(key);
-        Ok(self.kv_store_by_name.push(obj_key))
+    /// Open the store named `name`, returning its handle, or `None` if there is no such store.
+    ///
+    /// A name that isn't a valid store name is an error, rather than `None`.
+    pub fn kv_store_open(&mut self, name: &str) -> Result<Option<KvStoreHandle>, Error> {
+        // Store handles are never closed, and stores are never removed, so opening the same store
+        // again reuses its handle without looking at the stores.
+        if let Some(handle) = self.kv_store_handles.get(name) {
+            return Ok(Some(*handle));
+        }
+        self.resolve_kv_namespace()?;
+        let Some(key) = self.kv_store.store_key(name)? else {
+            return Ok(None);
+        };
+        let handle = self.kv_store_by_name.push(key.clone());
+        self.kv_store_handles.insert(key, handle);
+        Ok(Some(handle))
+    }
+
+    /// The number of distinct KV stores opened by the guest.
+    pub fn kv_store_handle_count(&self) -> usize {
+        self.kv_store_by_name.len()
     }
 
-    pub fn get_kv_store_key(&self, handle: KvStoreHandle) -> Option<&ObjectStoreKey> {
-        self.kv_store_by_name.get(handle)
+    /// The number of KV lookups, heads, inserts, deletes, and lists the guest has started but not
+    /// yet waited on.
+    pub fn pending_kv_handle_count(&self) -> usize {
+        self.async_items
+            .values()
+            .filter(|item| {
+                matches!(
+                    item,
+                    Some(
+                        AsyncItem::PendingKvLookup(_)
+                            | AsyncItem::PendingKvInsert(_)
+                            | AsyncItem::PendingKvDelete(_)
+                            | AsyncItem::PendingKvList(_)
+                            | AsyncItem::PendingKvHead(_)
+                    )
+                )
+            })
+            .count()
+    }
+
+    /// Get the store a [`KvStoreHandle`] was opened for.
+    ///
+    /// Returns a [`HandleError`] if the guest hasn't opened a store with that handle.
+    pub fn get_kv_store_key(&self, handle: KvStoreHandle) -> Result<&ObjectStoreKey, HandleError> {
+        self.kv_store_by_name
+            .get(handle)
+            .ok_or(HandleError::InvalidKvStoreHandle(handle))
+    }
+
+    /// The limits that writes to the store opened with `handle` are held to.
+    ///
+    /// Production has no way to ask for these, so this is a [local
+    /// extension](ExecuteCtx::with_local_extensions), and is `Unsupported` unless they are enabled.
+    pub fn kv_store_limits(&self, handle: KvStoreHandle) -> Result<StoreLimits, Error> {
+        if !self.local_extensions {
+            return Err(Error::Unsupported {
+                msg: "KV store limits are a local extension, which is not enabled",
+            });
+        }
+        let store = self.get_kv_store_key(handle)?;
+        Ok(self.kv_store.limits(store.as_str()))
     }
 
+    /// The rules for keys in `store` given through an API whose keys follow those of `profile`:
+    /// that profile, unless the store's `key_validation` setting overrides it.
+    pub fn kv_key_profile(
+        &self,
+        store: &ObjectStoreKey,
+        profile: KeyValidationProfile,
+    ) -> KeyValidationProfile {
+        self.kv_store
+            .key_validation(store.as_str())
+            .unwrap_or(profile)
+    }
+
+    /// The prefix that `store`'s `key_prefix` setting puts before the keys the guest gives it,
+    /// which is empty if it has none.
+    pub fn kv_key_prefix(&self, store: &ObjectStoreKey) -> String {
+        self.kv_store.key_prefix(store.as_str()).unwrap_or_default()
+    }
+
+    /// Make a key for `store` out of one the guest gave through an API whose keys follow the
+    /// rules of `profile`, as [`kv_key_profile`][Self::kv_key_profile] picks them, under the
+    /// store's [`kv_key_prefix`][Self::kv_key_prefix].
+    pub fn kv_key(
+        &self,
+        store: &ObjectStoreKey,
+        key: impl ToString,
+        profile: KeyValidationProfile,
+    ) -> Result<ObjectKey, KeyValidationError> {
+        ObjectKey::new_prefixed(
+            &self.kv_key_prefix(store),
+            key,
+            self.kv_key_profile(store, profile),
+        )
+    }
+
+    /// Switch this session to its KV namespace, if namespacing is enabled and the downstream
+    /// request names one.
+    ///
+    /// This is called whenever the guest opens a store, so that namespaces are only created for
+    /// requests that use them.
+    pub fn resolve_kv_namespace(&mut self) -> Result<(), KvStoreError> {
+        let Some(config) = &self.kv_namespaces else {
+            return Ok(());
+        };
+        if let Some(name) = self.downstream_req_original_headers.get(&config.header) {
+            let name = name.to_str().map_err(|_| KvStoreError::BadRequest)?;
+            self.kv_store = self.kv_store.namespace(name, config)?;
+        }
+        self.kv_namespaces = None;
+        Ok(())
+    }
+
+    /// Run a KV operation against `store`, in its turn under the store's
+    /// `max_concurrent_operations` limit.
+    ///
+    /// An operation that needn't wait its turn runs before this returns, so that operations
+    /// issued together apply in the order they were issued, and one that must wait runs once the
+    /// returned future gets its turn. Either way, the future holds the operation's permit until it
+    /// completes, after any chaos mode delay. `record` is given the result, whether the operation
+    /// had to wait, and how long it took, for the session's KV stats.
+    fn kv_task<T: Send + 'static>(
+        &self,
+        op_name: &'static str,
+        store: ObjectStoreKey,
+        op: impl FnOnce() -> Result<T, KvStoreError> + Send + 'static,
+        record: impl FnOnce(&KvStats, &Result<T, KvStoreError>, bool, Duration) + Send + 'static,
+    ) -> impl Future<Output = Result<T, KvStoreError>> + Send + 'static {
+        let start = Instant::now();
+        let delay = self.kv_chaos.as_ref().map(KvChaos::next_delay);
+        let turn = match self.kv_store.take_turn(&store) {
+            Ok(turn) => turn,
+            Err(e) => {
+                let res = Err(e);
+                record(&self.kv_stats, &res, false, start.elapsed());
+                return future::ready(res).left_future();
+            }
+        };
+
+        let stats = self.kv_stats.clone();
+        let run = move |queued| {
+            let res = guarded(op_name, &store, op);
+            record(&stats, &res, queued, start.elapsed());
+            res
+        };
+        let ran = match turn {
+            Turn::Now(permit) => Ok((run(false), permit)),
+            Turn::Queued(queued) => Err((queued, run)),
+        };
+        async move {
+            let (res, _permit) = match ran {
+                Ok(ran) => ran,
+                Err((queued, run)) => {
+                    let permit = queued.wait().await;
+                    (run(true), Some(permit))
+                }
+            };
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            res
+        }
+        .right_future()
+    }
+
+    /// Insert a value into a store, as [`ObjectStores::insert`] does, returning the generation it
+    /// was given.
     pub fn kv_insert(
         &self,
         obj_store_key: ObjectStoreKey,
         obj_key: ObjectKey,
         obj: Vec<u8>,
         mode: Option<KvInsertMode>,
-        generation: Option<u32>,
+        generation: Option<u64>,
         metadata: Option<Vec<u8>>,
         ttl: Option<Duration>,
-    ) -> Result<(), KvStoreError> {
+    ) -> impl Future<Output = Result<u64, KvStoreError>> + Send + 'static {
         let mode = match mode {
             None => KvInsertMode::Overwrite,
             Some(m) => m,
         };
 
-        self.kv_store
-            .insert(obj_store_key, obj_key, obj, mode, generation, metadata, ttl)
+        let len = obj.len();
+        let kv_store = self.kv_store.clone();
+        self.kv_task(
+            "insert",
+            obj_store_key.clone(),
+            move || kv_store.insert(obj_store_key, obj_key, obj, mode, generation, metadata, ttl),
+            move |stats, res, queued, elapsed| stats.record_insert(mode, len, res, queued, elapsed),
+        )
     }
 
     /// Insert a [`PendingKvInsert`] into the session.
@@ -729,7 +937,8 @@ impl Session {
     /// Take ownership of a [`PendingKvInsert`], given its [`PendingKvInsertHandle`].
     ///
     /// Returns a [`HandleError`] if the handle is not associated with a pending insert in the
-    /// session.
+    /// session. Taking a handle leaves it unassociated, so waiting twice on the same
+    /// handle is a `HandleError` too.
     pub fn take_pending_kv_insert(
         &mut self,
         handle: PendingKvInsertHandle,
@@ -759,12 +968,21 @@ impl Session {
             .ok_or(HandleError::InvalidPendingKvInsertHandle(handle))
     }
 
+    /// Delete a key from a store, as [`ObjectStores::delete`] does, only if its value has
+    /// `generation`, if one is given.
     pub fn kv_delete(
         &self,
         obj_store_key: ObjectStoreKey,
         obj_key: ObjectKey,
-    ) -> Result<(), KvStoreError> {
-        self.kv_store.delete(obj_store_key, obj_key)
+        generation: Option<u64>,
+    ) -> impl Future<Output = Result<(), KvStoreError>> + Send + 'static {
+        let kv_store = self.kv_store.clone();
+        self.kv_task(
+            "delete",
+            obj_store_key.clone(),
+            move || kv_store.delete(obj_store_key, obj_key, generation),
+            |stats, res, queued, elapsed| stats.record_delete(res, queued, elapsed),
+        )
     }
 
     /// Insert a [`PendingKvDelete`] into the session.
@@ -783,7 +1001,8 @@ impl Session {
     /// Take ownership of a [`PendingKvDelete`], given its [`PendingKvDeleteHandle`].
     ///
     /// Returns a [`HandleError`] if the handle is not associated with a pending delete in the
-    /// session.
+    /// session. Taking a handle leaves it unassociated, so waiting twice on the same
+    /// handle is a `HandleError` too.
     pub fn take_pending_kv_delete(
         &mut self,
         handle: PendingKvDeleteHandle,
@@ -817,8 +1036,40 @@ impl Session {
         &self,
         obj_store_key: ObjectStoreKey,
         obj_key: ObjectKey,
-    ) -> Result<ObjectValue, KvStoreError> {
-        self.kv_store.lookup(obj_store_key, obj_key)
+    ) -> impl Future<Output = Result<ObjectValue, KvStoreError>> + Send + 'static {
+        let kv_store = self.kv_store.clone();
+        let expired = Arc::new(AtomicBool::new(false));
+        let was_expired = expired.clone();
+        self.kv_task(
+            "lookup",
+            obj_store_key.clone(),
+            move || {
+                let (res, expired) = kv_store.lookup_with_expiry(obj_store_key, obj_key);
+                was_expired.store(expired, Ordering::Relaxed);
+                res
+            },
+            move |stats, res, queued, elapsed| {
+                stats.record_lookup(res, expired.load(Ordering::Relaxed), queued, elapsed)
+            },
+        )
+    }
+
+    /// As [`obj_lookup`][Self::obj_lookup], for the KV store interfaces, which give the guest the
+    /// value's generation too. Generations past what they can give are given saturated, with a
+    /// warning the first time a key's is.
+    pub fn kv_lookup(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> impl Future<Output = Result<ObjectValue, KvStoreError>> + Send + 'static {
+        let kv_store = self.kv_store.clone();
+        let (store, key) = (obj_store_key.clone(), obj_key.clone());
+        self.obj_lookup(obj_store_key, obj_key).map(move |res| {
+            if let Ok(value) = &res {
+                kv_store.note_abi_generation(&store, &key, value.generation);
+            }
+            res
+        })
     }
 
     /// Insert a [`PendingLookup`] into the session.
@@ -837,7 +1088,8 @@ impl Session {
     /// Take ownership of a [`PendingLookup`], given its [`PendingKvLookupHandle`].
     ///
     /// Returns a [`HandleError`] if the handle is not associated with a pending lookup in the
-    /// session.
+    /// session. Taking a handle leaves it unassociated, so waiting twice on the same
+    /// handle is a `HandleError` too.
     pub fn take_pending_kv_lookup(
         &mut self,
         handle: PendingKvLookupHandle,
@@ -852,6 +1104,21 @@ impl Session {
             .ok_or(HandleError::InvalidPendingKvLookupHandle(handle))
     }
 
+    /// Put a lookup taken with [`take_pending_kv_lookup`][Self::take_pending_kv_lookup] back
+    /// under its original handle.
+    pub fn reinsert_pending_kv_lookup(
+        &mut self,
+        handle: PendingKvLookupHandle,
+        pending: PendingKvLookupTask,
+    ) -> Result<(), HandleError> {
+        *self
+            .async_items
+            .get_mut(handle.into())
+            .ok_or(HandleError::InvalidPendingKvLookupHandle(handle))? =
+            Some(AsyncItem::PendingKvLookup(pending));
+        Ok(())
+    }
+
     /// Get a reference to a [`PendingLookup`], given its [`PendingKvLookupHandle`].
     ///
     /// Returns a [`HandleError`] if the handle is not associated with a lookup in the
@@ -867,16 +1134,125 @@ impl Session {
             .ok_or(HandleError::InvalidPendingKvLookupHandle(handle))
     }
 
+    /// As [`obj_lookup`][Self::obj_lookup], but finding only the value's metadata, generation,
+    /// and body length. It counts as a lookup in the session's KV stats.
+    ///
+    /// The generation is noted as being given to the guest, as for [`kv_lookup`][Self::kv_lookup].
+    pub fn obj_head(
+        &self,
+        obj_store_key: ObjectStoreKey,
+        obj_key: ObjectKey,
+    ) -> impl Future<Output = Result<ObjectHead, KvStoreError>> + Send + 'static {
+        let kv_store = self.kv_store.clone();
+        let (store, key) = (obj_store_key.clone(), obj_key.clone());
+        let expired = Arc::new(AtomicBool::new(false));
+        let was_expired = expired.clone();
+        let noted = self.kv_store.clone();
+        self.kv_task(
+            "head",
+            obj_store_key.clone(),
+            move || {
+                let (res, expired) = kv_store.head_with_expiry(obj_store_key, obj_key);
+                was_expired.store(expired, Ordering::Relaxed);
+                res
+            },
+            move |stats, res, queued, elapsed| {
+                stats.record_lookup(res, expired.load(Ordering::Relaxed), queued, elapsed)
+            },
+        )
+        .map(move |res| {
+            if let Ok(head) = &res {
+                noted.note_abi_generation(&store, &key, head.generation);
+            }
+            res
+        })
+    }
+
+    /// Insert a [`PendingKvHeadTask`] into the session.
+    ///
+    /// This method returns a new [`KvStoreHeadHandle`], which can then be used to access
+    /// and mutate the pending head.
+    pub fn insert_pending_kv_head(&mut self, pending: PendingKvHeadTask) -> KvStoreHeadHandle {
+        self.async_items
+            .push(Some(AsyncItem::PendingKvHead(pending)))
+            .into()
+    }
+
+    /// Take ownership of a [`PendingKvHeadTask`], given its [`KvStoreHeadHandle`].
+    ///
+    /// Returns a [`HandleError`] if the handle is not associated with a pending head in the
+    /// session. Taking a handle leaves it unassociated, so waiting twice on the same
+    /// handle is a `HandleError` too.
+    pub fn take_pending_kv_head(
+        &mut self,
+        handle: KvStoreHeadHandle,
+    ) -> Result<PendingKvHeadTask, HandleError> {
+        // check that this is a pending request before removing it
+        let _ = self.pending_kv_head(handle)?;
+
+        self.async_items
+            .get_mut(handle.into())
+            .and_then(Option::take)
+            .and_then(AsyncItem::into_pending_kv_head)
+            .ok_or(HandleError::InvalidPendingKvHeadHandle(handle))
+    }
+
+    /// Put a head taken with [`take_pending_kv_head`][Self::take_pending_kv_head] back under its
+    /// original handle.
+    pub fn reinsert_pending_kv_head(
+        &mut self,
+        handle: KvStoreHeadHandle,
+        pending: PendingKvHeadTask,
+    ) -> Result<(), HandleError> {
+        *self
+            .async_items
+            .get_mut(handle.into())
+            .ok_or(HandleError::InvalidPendingKvHeadHandle(handle))? =
+            Some(AsyncItem::PendingKvHead(pending));
+        Ok(())
+    }
+
+    /// Get a reference to a [`PendingKvHeadTask`], given its [`KvStoreHeadHandle`].
+    ///
+    /// Returns a [`HandleError`] if the handle is not associated with a head in the
+    /// session.
+    pub fn pending_kv_head(
+        &self,
+        handle: KvStoreHeadHandle,
+    ) -> Result<&PendingKvHeadTask, HandleError> {
+        self.async_items
+            .get(handle.into())
+            .and_then(Option::as_ref)
+            .and_then(AsyncItem::as_pending_kv_head)
+            .ok_or(HandleError::InvalidPendingKvHeadHandle(handle))
+    }
+
     pub fn kv_list(
         &self,
         obj_store_key: ObjectStoreKey,
         cursor: Option<String>,
         prefix: Option<String>,
         limit: Option<u32>,
-    ) -> Result<Vec<u8>, KvStoreError> {
+    ) -> impl Future<Output = Result<Vec<u8>, KvStoreError>> + Send + 'static {
         let limit = limit.unwrap_or(1000);
 
-        self.kv_store.list(obj_store_key, cursor, prefix, limit)
+        let key_prefix = self.kv_key_prefix(&obj_store_key);
+        let kv_store = self.kv_store.clone();
+        self.kv_task(
+            "list",
+            obj_store_key.clone(),
+            move || kv_store.list_under(obj_store_key, &key_prefix, cursor, prefix, limit),
+            |stats, res, queued, elapsed| stats.record_list(res, queued, elapsed),
+        )
+    }
+
+    /// A snapshot of the KV operations performed so far by this session.
+    pub fn kv_summary(&self) -> KvSummary {
+        KvSummary {
+            store_handles: self.kv_store_handle_count(),
+            pending_handles: self.pending_kv_handle_count(),
+            ..self.kv_stats.summary()
+        }
     }
 
     /// Insert a [`PendingList`] into the session.
@@ -892,7 +1268,8 @@ impl Session {
     /// Take ownership of a [`PendingList`], given its [`PendingKvListHandle`].
     ///
     /// Returns a [`HandleError`] if the handle is not associated with a pending list in the
-    /// session.
+    /// session. Taking a handle leaves it unassociated, so waiting twice on the same
+    /// handle is a `HandleError` too.
     pub fn take_pending_kv_list(
         &mut self,
         handle: PendingKvListHandle,
@@ -1099,8 +1476,13 @@ impl Session {
         // we have to temporarily move the async items out of the session table,
         // because we need &mut borrows of all of them simultaneously.
         let targets = self.prepare_select_targets(handles)?;
+        let deterministic = self.deterministic_select;
         let mut selected = SelectedTargets::new(self, targets);
-        let done_index = selected.future().await;
+        let done_index = if deterministic {
+            selected.deterministic().await
+        } else {
+            selected.future().await
+        };
 
         Ok(done_index)
     }
@@ -1131,6 +1513,26 @@ impl<'session> SelectedTargets<'session> {
             Box::new(future::select_all(futures).map(|f| f.1))
         }
     }
+
+    /// Wait for a target to be ready, and return the lowest index of those that are.
+    ///
+    /// Pending KV operations always complete, so they are all waited for first, so that which of
+    /// them are ready doesn't depend on how they were scheduled.
+    async fn deterministic(&mut self) -> usize {
+        future::join_all(
+            self.targets
+                .iter_mut()
+                .filter(|target| target.item.is_pending_kv())
+                .map(|target| target.item.await_ready()),
+        )
+        .await;
+        loop {
+            if let Some(index) = self.targets.iter_mut().position(|t| t.item.is_ready()) {
+                return index;
+            }
+            self.future().await;
+        }
+    }
 }
 
 impl<'session> Drop for SelectedTargets<'session> {
@@ -1164,10 +1566,14 @@ entity_impl!(AsyncItemHandle, "async_item");
 // only one type at a type. But the underlying tables for all async items are combined, so the handles
 // are interchangeable. Keeping them as separate types helps ensure intentional view shifts between
 // them, using `.into()`.
+//
+// Handles from the guest are converted without `AsyncItemHandle::from_u32`, which asserts that
+// the value isn't `u32::MAX`. Any value is a well-formed handle; ones that aren't in the table are
+// a `HandleError` when they are looked up.
 
 impl From<BodyHandle> for AsyncItemHandle {
     fn from(h: BodyHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1179,7 +1585,7 @@ impl From<AsyncItemHandle> for BodyHandle {
 
 impl From<PendingRequestHandle> for AsyncItemHandle {
     fn from(h: PendingRequestHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1191,7 +1597,7 @@ impl From<AsyncItemHandle> for PendingRequestHandle {
 
 impl From<types::AsyncItemHandle> for AsyncItemHandle {
     fn from(h: types::AsyncItemHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1203,7 +1609,7 @@ impl From<AsyncItemHandle> for types::AsyncItemHandle {
 
 impl From<PendingKvLookupHandle> for AsyncItemHandle {
     fn from(h: PendingKvLookupHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1215,7 +1621,7 @@ impl From<AsyncItemHandle> for PendingKvLookupHandle {
 
 impl From<PendingKvInsertHandle> for AsyncItemHandle {
     fn from(h: PendingKvInsertHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1227,7 +1633,7 @@ impl From<AsyncItemHandle> for PendingKvInsertHandle {
 
 impl From<PendingKvDeleteHandle> for AsyncItemHandle {
     fn from(h: PendingKvDeleteHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1239,7 +1645,7 @@ impl From<AsyncItemHandle> for PendingKvDeleteHandle {
 
 impl From<PendingKvListHandle> for AsyncItemHandle {
     fn from(h: PendingKvListHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1251,7 +1657,7 @@ impl From<AsyncItemHandle> for PendingKvListHandle {
 
 impl From<KvStoreLookupHandle> for AsyncItemHandle {
     fn from(h: KvStoreLookupHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1263,7 +1669,7 @@ impl From<AsyncItemHandle> for KvStoreLookupHandle {
 
 impl From<KvStoreInsertHandle> for AsyncItemHandle {
     fn from(h: KvStoreInsertHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1275,7 +1681,7 @@ impl From<AsyncItemHandle> for KvStoreInsertHandle {
 
 impl From<KvStoreDeleteHandle> for AsyncItemHandle {
     fn from(h: KvStoreDeleteHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1287,7 +1693,7 @@ impl From<AsyncItemHandle> for KvStoreDeleteHandle {
 
 impl From<KvStoreListHandle> for AsyncItemHandle {
     fn from(h: KvStoreListHandle) -> AsyncItemHandle {
-        AsyncItemHandle::from_u32(h.into())
+        AsyncItemHandle(h.into())
     }
 }
 
@@ -1296,3 +1702,15 @@ impl From<AsyncItemHandle> for KvStoreListHandle {
         KvStoreListHandle::from(h.as_u32())
     }
 }
+
+impl From<KvStoreHeadHandle> for AsyncItemHandle {
+    fn from(h: KvStoreHeadHandle) -> AsyncItemHandle {
+        AsyncItemHandle(h.into())
+    }
+}
+
+impl From<AsyncItemHandle> for KvStoreHeadHandle {
+    fn from(h: AsyncItemHandle) -> KvStoreHeadHandle {
+        KvStoreHeadHandle::from(h.as_u32())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::object_store::{KvStoreError, ObjectValue};
+use crate::object_store::{KvStoreError, ObjectHead, ObjectValue};
 use crate::{body::Body, error::Error, streaming_body::StreamingBody};
 use anyhow::anyhow;
 use futures::Future;
@@ -18,12 +18,12 @@ impl PendingKvLookupTask {
 }
 
 #[derive(Debug)]
-pub struct PendingKvInsertTask(PeekableTask<Result<(), KvStoreError>>);
+pub struct PendingKvInsertTask(PeekableTask<Result<u64, KvStoreError>>);
 impl PendingKvInsertTask {
-    pub fn new(t: PeekableTask<Result<(), KvStoreError>>) -> PendingKvInsertTask {
+    pub fn new(t: PeekableTask<Result<u64, KvStoreError>>) -> PendingKvInsertTask {
         PendingKvInsertTask(t)
     }
-    pub fn task(self) -> PeekableTask<Result<(), KvStoreError>> {
+    pub fn task(self) -> PeekableTask<Result<u64, KvStoreError>> {
         self.0
     }
 }
@@ -50,6 +50,17 @@ impl PendingKvListTask {
     }
 }
 
+#[derive(Debug)]
+pub struct PendingKvHeadTask(PeekableTask<Result<ObjectHead, KvStoreError>>);
+impl PendingKvHeadTask {
+    pub fn new(t: PeekableTask<Result<ObjectHead, KvStoreError>>) -> PendingKvHeadTask {
+        PendingKvHeadTask(t)
+    }
+    pub fn task(self) -> PeekableTask<Result<ObjectHead, KvStoreError>> {
+        self.0
+    }
+}
+
 /// Represents either a full body, or the write end of a streaming body.
 ///
 /// This enum is needed because we reuse the handle for a body when it is transformed into a streaming
@@ -63,6 +74,7 @@ pub enum AsyncItem {
     PendingKvInsert(PendingKvInsertTask),
     PendingKvDelete(PendingKvDeleteTask),
     PendingKvList(PendingKvListTask),
+    PendingKvHead(PendingKvHeadTask),
 }
 
 impl AsyncItem {
@@ -70,6 +82,18 @@ impl AsyncItem {
         matches!(self, Self::StreamingBody(_))
     }
 
+    /// Whether this is a pending KV operation of any kind.
+    pub fn is_pending_kv(&self) -> bool {
+        matches!(
+            self,
+            Self::PendingKvLookup(_)
+                | Self::PendingKvInsert(_)
+                | Self::PendingKvDelete(_)
+                | Self::PendingKvList(_)
+                | Self::PendingKvHead(_)
+        )
+    }
+
     pub fn as_body(&self) -> Option<&Body> {
         match self {
             Self::Body(body) => Some(body),
@@ -175,6 +199,20 @@ impl AsyncItem {
         }
     }
 
+    pub fn as_pending_kv_head(&self) -> Option<&PendingKvHeadTask> {
+        match self {
+            Self::PendingKvHead(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub fn into_pending_kv_head(self) -> Option<PendingKvHeadTask> {
+        match self {
+            Self::PendingKvHead(req) => Some(req),
+            _ => None,
+        }
+    }
+
     pub fn as_pending_req(&self) -> Option<&PeekableTask<Response<Body>>> {
         match self {
             Self::PendingReq(req) => Some(req),
@@ -205,6 +243,7 @@ impl AsyncItem {
             Self::PendingKvInsert(req) => req.0.await_ready().await,
             Self::PendingKvDelete(req) => req.0.await_ready().await,
             Self::PendingKvList(req) => req.0.await_ready().await,
+            Self::PendingKvHead(req) => req.0.await_ready().await,
         }
     }
 
@@ -243,6 +282,12 @@ impl From<PendingKvListTask> for AsyncItem {
     }
 }
 
+impl From<PendingKvHeadTask> for AsyncItem {
+    fn from(task: PendingKvHeadTask) -> Self {
+        Self::PendingKvHead(task)
+    }
+}
+
 #[derive(Debug)]
 pub enum PeekableTask<T> {
     Waiting(oneshot::Receiver<Result<T, Error>>),
@@ -0,0 +1,39 @@
+//! Random delays for KV operations. See [`ExecuteCtx::with_kv_chaos`].
+//!
+//! [`ExecuteCtx::with_kv_chaos`]: crate::ExecuteCtx::with_kv_chaos
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// The longest a KV operation is delayed by.
+const MAX_DELAY_MS: u64 = 5;
+
+/// Picks the delays for one session's KV operations.
+///
+/// Each session draws from its own generator, seeded from the context's seed and the request's
+/// ID, so the delays a request sees don't depend on what other requests run alongside it.
+#[derive(Debug)]
+pub(super) struct KvChaos(AtomicU64);
+
+impl KvChaos {
+    pub(super) fn new(seed: u64, req_id: u64) -> Self {
+        Self(AtomicU64::new(
+            seed ^ req_id.wrapping_mul(0x9e37_79b9_7f4a_7c15),
+        ))
+    }
+
+    /// The delay for the next operation, of up to [`MAX_DELAY_MS`] milliseconds.
+    pub(super) fn next_delay(&self) -> Duration {
+        // SplitMix64, which is plenty for shuffling completions, and needs no dependencies
+        let mut z = self
+            .0
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Duration::from_millis(z % (MAX_DELAY_MS + 1))
+    }
+}
@@ -0,0 +1,86 @@
+//! Containing panics in KV operations to the request that made them.
+//!
+//! Every KV hostcall, in both the witx and component interfaces, runs its store operation through
+//! [`Session`][super::Session], which runs it through [`guarded`]. A panic there, whether from a
+//! bug in the stores or from an observer, fails just that operation with
+//! [`KvStoreError::InternalError`], which the guest sees as it would any other KV error, and is
+//! logged loudly, with the backtrace of the panic if [`install_kv_panic_hook`] has been called.
+//! Without this, the panic would unwind through the hostcall and take down the whole server.
+//!
+//! The panics this used to catch have been removed; it remains as a backstop.
+
+use {
+    crate::object_store::{KvStoreError, ObjectStoreKey},
+    std::{
+        any::Any,
+        backtrace::Backtrace,
+        cell::{Cell, RefCell},
+        panic::{self, AssertUnwindSafe},
+        sync::Once,
+    },
+    tracing::error,
+};
+
+thread_local! {
+    /// Whether this thread is running a guarded operation.
+    static GUARDED: Cell<bool> = const { Cell::new(false) };
+    /// The backtrace of the last panic in a guarded operation on this thread.
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+/// Install a panic hook that records the backtrace of panics in KV operations, for the error logged
+/// when one is contained, since by the time [`catch_unwind`][panic::catch_unwind] returns, the
+/// stack it would show is gone.
+///
+/// The panic hook belongs to the whole process, so the library leaves it alone unless this is
+/// called, as the CLI does at startup. The hook that was installed before still runs, for every
+/// panic, and calling this more than once has no further effect.
+pub fn install_kv_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARDED.with(Cell::get) {
+                BACKTRACE.with(|bt| *bt.borrow_mut() = Some(Backtrace::force_capture()));
+            }
+            previous(info)
+        }));
+    });
+}
+
+/// Run the KV operation `op` against `store`, turning a panic into an internal error.
+pub(super) fn guarded<T>(
+    op: &'static str,
+    store: &ObjectStoreKey,
+    f: impl FnOnce() -> Result<T, KvStoreError>,
+) -> Result<T, KvStoreError> {
+    let was_guarded = GUARDED.with(|g| g.replace(true));
+    // A panic while the stores are locked poisons the lock, after which every operation on them
+    // fails with an internal error, so a half-made change can't be seen. The other locks KV
+    // operations take guard nothing a panic can leave half-made, so those are recovered from.
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    GUARDED.with(|g| g.set(was_guarded));
+
+    res.unwrap_or_else(|payload| {
+        let backtrace = BACKTRACE.with(|bt| bt.borrow_mut().take()).map_or_else(
+            || "unavailable, as the KV panic hook isn't installed".to_string(),
+            |bt| bt.to_string(),
+        );
+        error!(
+            "KV {op} on {store:?} panicked, and failed with an internal error: {}\n\
+             backtrace:\n{backtrace}",
+            panic_message(&*payload),
+        );
+        Err(KvStoreError::InternalError)
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "non-string panic payload"
+    }
+}
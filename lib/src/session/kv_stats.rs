@@ -0,0 +1,154 @@
+//! Per-session KV activity counters.
+
+use {
+    crate::{object_store::KvStoreError, wiggle_abi::types::KvInsertMode},
+    std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+};
+
+/// Counters for the KV operations performed by a single session.
+///
+/// These are summarized on the end-of-request log event, so recording an operation is kept to a
+/// handful of relaxed atomic increments.
+#[derive(Debug, Default)]
+pub struct KvStats {
+    lookups: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expired_misses: AtomicU64,
+    inserts: AtomicU64,
+    appends: AtomicU64,
+    prepends: AtomicU64,
+    deletes: AtomicU64,
+    lists: AtomicU64,
+    bytes_written: AtomicU64,
+    errors: AtomicU64,
+    queued: AtomicU64,
+    time_ns: AtomicU64,
+}
+
+/// A point-in-time copy of a session's KV activity counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KvSummary {
+    pub lookups: u64,
+    pub hits: u64,
+    /// The number of lookups of keys that were never written, or were deleted.
+    pub misses: u64,
+    /// The number of lookups of keys whose values had expired. The guest sees these as misses
+    /// too.
+    pub expired_misses: u64,
+    pub inserts: u64,
+    /// The number of inserts that appended or prepended, counted in `inserts` too.
+    pub appends: u64,
+    pub prepends: u64,
+    pub deletes: u64,
+    pub lists: u64,
+    pub bytes_written: u64,
+    pub errors: u64,
+    /// The number of operations that waited for a store's concurrency limit.
+    pub queued: u64,
+    pub time: Duration,
+    /// The number of distinct stores the guest has open.
+    pub store_handles: usize,
+    /// The number of KV operations started but never waited on.
+    pub pending_handles: usize,
+}
+
+impl KvStats {
+    pub(crate) fn record_lookup<T>(
+        &self,
+        res: &Result<T, KvStoreError>,
+        expired: bool,
+        queued: bool,
+        elapsed: Duration,
+    ) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let outcome = match res {
+            Ok(_) => Some(&self.hits),
+            Err(KvStoreError::NotFound) if expired => Some(&self.expired_misses),
+            Err(KvStoreError::NotFound) => Some(&self.misses),
+            Err(_) => None,
+        };
+        if let Some(outcome) = outcome {
+            outcome.fetch_add(1, Ordering::Relaxed);
+        }
+        self.record(res, queued, elapsed);
+    }
+
+    pub(crate) fn record_insert<T>(
+        &self,
+        mode: KvInsertMode,
+        len: usize,
+        res: &Result<T, KvStoreError>,
+        queued: bool,
+        elapsed: Duration,
+    ) {
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        let extended = match mode {
+            KvInsertMode::Append => Some(&self.appends),
+            KvInsertMode::Prepend => Some(&self.prepends),
+            KvInsertMode::Overwrite | KvInsertMode::Add => None,
+        };
+        if let Some(extended) = extended {
+            extended.fetch_add(1, Ordering::Relaxed);
+        }
+        if res.is_ok() {
+            self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+        }
+        self.record(res, queued, elapsed);
+    }
+
+    pub(crate) fn record_delete<T>(
+        &self,
+        res: &Result<T, KvStoreError>,
+        queued: bool,
+        elapsed: Duration,
+    ) {
+        self.deletes.fetch_add(1, Ordering::Relaxed);
+        self.record(res, queued, elapsed);
+    }
+
+    pub(crate) fn record_list<T>(
+        &self,
+        res: &Result<T, KvStoreError>,
+        queued: bool,
+        elapsed: Duration,
+    ) {
+        self.lists.fetch_add(1, Ordering::Relaxed);
+        self.record(res, queued, elapsed);
+    }
+
+    /// A missing key is an expected outcome rather than an error, so `NotFound` is not counted.
+    fn record<T>(&self, res: &Result<T, KvStoreError>, queued: bool, elapsed: Duration) {
+        if matches!(res, Err(e) if *e != KvStoreError::NotFound) {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if queued {
+            self.queued.fetch_add(1, Ordering::Relaxed);
+        }
+        self.time_ns
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> KvSummary {
+        KvSummary {
+            lookups: self.lookups.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expired_misses: self.expired_misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            appends: self.appends.load(Ordering::Relaxed),
+            prepends: self.prepends.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            lists: self.lists.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            time: Duration::from_nanos(self.time_ns.load(Ordering::Relaxed)),
+            store_handles: 0,
+            pending_handles: 0,
+        }
+    }
+}
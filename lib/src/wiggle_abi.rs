@@ -77,8 +77,8 @@ wiggle::from_witx!({
     errors: { fastly_status => Error },
     async: {
         fastly_async_io::{select},
-        fastly_object_store::{delete_async, pending_delete_wait, insert, insert_async, pending_insert_wait, lookup_async, pending_lookup_wait, list},
-        fastly_kv_store::{lookup, lookup_wait, insert, insert_wait, delete, delete_wait, list, list_wait},
+        fastly_object_store::{delete_async, pending_delete_wait, insert, insert_async, pending_insert_wait, lookup, lookup_async, pending_lookup_wait, list},
+        fastly_kv_store::{lookup, lookup_wait, insert, insert_wait, delete, delete_wait, list, list_wait, head, head_wait},
         fastly_http_body::{append, read, write},
         fastly_http_cache::{lookup, transaction_lookup, insert, transaction_insert, transaction_insert_and_stream_back, transaction_update, transaction_update_and_return_fresh, transaction_record_not_cacheable, transaction_abandon, found, close, get_suggested_backend_request, get_suggested_cache_options, prepare_response_for_storage, get_found_response, get_state, get_length, get_max_age_ns, get_stale_while_revalidate_ns, get_age_ns, get_hits, get_sensitive_data, get_surrogate_keys, get_vary_rule},
         fastly_http_req::{
@@ -90,71 +90,61 @@ wiggle::from_witx!({
 
 impl From<types::ObjectStoreHandle> for types::KvStoreHandle {
     fn from(h: types::ObjectStoreHandle) -> types::KvStoreHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::KvStoreHandle> for types::ObjectStoreHandle {
     fn from(h: types::KvStoreHandle) -> types::ObjectStoreHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::KvStoreLookupHandle> for types::PendingKvLookupHandle {
     fn from(h: types::KvStoreLookupHandle) -> types::PendingKvLookupHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::PendingKvLookupHandle> for types::KvStoreLookupHandle {
     fn from(h: types::PendingKvLookupHandle) -> types::KvStoreLookupHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::KvStoreInsertHandle> for types::PendingKvInsertHandle {
     fn from(h: types::KvStoreInsertHandle) -> types::PendingKvInsertHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::PendingKvInsertHandle> for types::KvStoreInsertHandle {
     fn from(h: types::PendingKvInsertHandle) -> types::KvStoreInsertHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::KvStoreDeleteHandle> for types::PendingKvDeleteHandle {
     fn from(h: types::KvStoreDeleteHandle) -> types::PendingKvDeleteHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::PendingKvDeleteHandle> for types::KvStoreDeleteHandle {
     fn from(h: types::PendingKvDeleteHandle) -> types::KvStoreDeleteHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::KvStoreListHandle> for types::PendingKvListHandle {
     fn from(h: types::KvStoreListHandle) -> types::PendingKvListHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
 impl From<types::PendingKvListHandle> for types::KvStoreListHandle {
     fn from(h: types::PendingKvListHandle) -> types::KvStoreListHandle {
-        let s = unsafe { h.inner() };
-        s.into()
+        u32::from(h).into()
     }
 }
 
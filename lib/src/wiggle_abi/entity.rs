@@ -27,9 +27,13 @@ macro_rules! wiggle_entity {
         /// `PrimaryMap`, `SecondaryMap`, or `SparseMap`.
         impl cranelift_entity::EntityRef for $entity {
             /// Create a new entity reference from a small integer.
+            ///
+            /// Panics if `index` doesn't fit in a handle, rather than truncating it to one that
+            /// might already be in use.
             fn new(index: usize) -> Self {
-                debug_assert!(index < (std::u32::MAX as usize));
-                (index as u32).into()
+                u32::try_from(index)
+                    .expect("entity index does not fit in a 32-bit handle")
+                    .into()
             }
             /// Get the index that was used to create this entity reference.
             fn index(self) -> usize {
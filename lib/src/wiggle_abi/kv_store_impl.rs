@@ -3,27 +3,141 @@
 use crate::object_store::KvStoreError;
 use crate::session::PeekableTask;
 use crate::session::{
-    PendingKvDeleteTask, PendingKvInsertTask, PendingKvListTask, PendingKvLookupTask,
+    PendingKvDeleteTask, PendingKvHeadTask, PendingKvInsertTask, PendingKvListTask,
+    PendingKvLookupTask,
 };
 
 use {
     crate::{
         error::Error,
-        object_store::{ObjectKey, ObjectStoreError},
+        object_store::{
+            max_key_len, KeyValidationError, KeyValidationProfile, ObjectKey, ObjectStoreError,
+            MAX_KEY_BYTES, MAX_LIST_CURSOR_LEN,
+        },
         session::Session,
         wiggle_abi::{
             fastly_kv_store::FastlyKvStore,
             types::{
                 BodyHandle, KvDeleteConfig, KvDeleteConfigOptions, KvError, KvInsertConfig,
-                KvInsertConfigOptions, KvListConfig, KvListConfigOptions, KvLookupConfig,
-                KvLookupConfigOptions, KvStoreDeleteHandle, KvStoreHandle, KvStoreInsertHandle,
-                KvStoreListHandle, KvStoreLookupHandle,
+                KvInsertConfigOptions, KvInsertMode, KvListConfig, KvListConfigOptions,
+                KvLookupConfig, KvLookupConfigOptions, KvStoreDeleteHandle, KvStoreHandle,
+                KvStoreHeadHandle, KvStoreInsertHandle, KvStoreLimits, KvStoreListHandle,
+                KvStoreLookupHandle,
             },
         },
     },
-    wiggle::{GuestMemory, GuestPtr},
+    futures::future::{self, FutureExt},
+    tracing::warn,
+    wiggle::{GuestError, GuestMemory, GuestPtr},
 };
 
+// Arguments are always copied out of guest memory, using `GuestMemory::as_cow_str` and
+// `GuestMemory::to_vec`, rather than borrowed with `as_str` or `as_slice`. Borrowing fails for
+// guests using a shared memory, whereas copying works the same for both kinds of memory, so no KV
+// hostcall fails with `Error::SharedMemory`.
+//
+// Out-pointers are checked before a hostcall has any side effect: before it takes a body handle,
+// performs or schedules an operation, or consumes a pending handle. A call that fails because of
+// a bad out-pointer leaves the stores and the guest's handles as they were, and once the checks
+// have passed, writing the results cannot fail.
+
+/// Read a key argument out of guest memory, checking it against the rules of `profile`, and put
+/// the store's `key_prefix` before it.
+///
+/// Keys that are too long are a buffer length error from the hostcall, so that guests can tell
+/// them apart, carrying the longest key the store takes, which its `key_prefix` makes shorter.
+/// Any other invalid key is not an error for the hostcall, but the `BadRequest` the operation
+/// results in, which the guest learns of when it waits on the operation, as it would from
+/// production.
+fn read_key(
+    memory: &GuestMemory<'_>,
+    key: GuestPtr<str>,
+    prefix: &str,
+    profile: KeyValidationProfile,
+) -> Result<Result<ObjectKey, KvStoreError>, Error> {
+    match ObjectKey::new_prefixed(prefix, memory.as_cow_str(key)?, profile) {
+        Ok(key) => Ok(Ok(key)),
+        Err(KeyValidationError::Over1024Bytes) => Err(Error::KvKeyTooLong {
+            max_len: max_key_len(prefix),
+        }),
+        Err(_) => Ok(Err(KvStoreError::BadRequest)),
+    }
+}
+
+/// Check that `ptr` is in bounds and aligned, without writing to it.
+///
+/// Every out-pointer in these hostcalls points to a 32-bit value: a handle, a length, a
+/// generation, or a `KvError`. The one exception, the body length written by `head_wait`, is
+/// checked by reading it as the `u64` it is.
+pub(super) fn check_out_ptr<T>(memory: &GuestMemory<'_>, ptr: GuestPtr<T>) -> Result<(), Error> {
+    memory.read(ptr.cast::<u32>())?;
+    Ok(())
+}
+
+/// Check that the `len` bytes at `buf` are in bounds, without reading or copying them.
+///
+/// Guest memory is contiguous, so the buffer is in bounds if its last byte is. Copying the buffer
+/// with `as_cow` would check it too, but on a shared memory that copies up to the whole memory.
+pub(super) fn check_out_buf(
+    memory: &GuestMemory<'_>,
+    buf: GuestPtr<u8>,
+    len: u32,
+) -> Result<(), Error> {
+    match len.checked_sub(1) {
+        None => {
+            memory.as_cow(buf.as_array(0))?;
+        }
+        Some(last) => {
+            let last = buf
+                .offset()
+                .checked_add(last)
+                .ok_or(GuestError::PtrOverflow)?;
+            memory.read(GuestPtr::<u8>::new(last))?;
+        }
+    }
+    Ok(())
+}
+
+/// Read an insert config out of guest memory.
+///
+/// The mode is checked before the rest is read, so that one this version doesn't know, most likely
+/// sent by a newer SDK, is a `BadRequest` rather than an opaque error from decoding the config. It
+/// is never taken for an overwrite.
+fn read_insert_config(
+    memory: &GuestMemory<'_>,
+    config: GuestPtr<KvInsertConfig>,
+) -> Result<KvInsertConfig, Error> {
+    // the mode is the first field of the config
+    let mode = memory.read(config.cast::<u32>())?;
+    if KvInsertMode::try_from(mode).is_err() {
+        warn!(
+            "unknown KV insert mode {mode}; the guest's SDK may be newer than this Viceroy, \
+             which may need upgrading"
+        );
+        return Err(KvStoreError::BadRequest.into());
+    }
+    Ok(memory.read(config)?)
+}
+
+/// Read an optional byte-string field of a config struct out of guest memory.
+///
+/// Returns `None` if the field's flag isn't set in the config mask, and an `InvalidArgument`
+/// error if it is set but the field is empty.
+fn read_config_bytes(
+    memory: &GuestMemory<'_>,
+    enabled: bool,
+    ptr: GuestPtr<u8>,
+    len: u32,
+) -> Result<Option<Vec<u8>>, Error> {
+    if !enabled {
+        return Ok(None);
+    }
+    if len == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(Some(memory.to_vec(ptr.as_array(len))?))
+}
+
 #[wiggle::async_trait]
 impl FastlyKvStore for Session {
     fn open(
@@ -31,13 +145,12 @@ impl FastlyKvStore for Session {
         memory: &mut GuestMemory<'_>,
         name: GuestPtr<str>,
     ) -> Result<KvStoreHandle, Error> {
-        let name = memory.as_str(name)?.ok_or(Error::SharedMemory)?;
-        if self.kv_store.store_exists(&name)? {
-            self.kv_store_handle(&name)
-        } else {
-            Err(Error::ObjectStoreError(
-                ObjectStoreError::UnknownObjectStore(name.to_owned()),
-            ))
+        let name = memory.as_cow_str(name)?;
+        match self.kv_store_open(&name)? {
+            Some(handle) => Ok(handle),
+            None => Err(Error::ObjectStoreError(
+                ObjectStoreError::UnknownObjectStore(name.into_owned()),
+            )),
         }
     }
 
@@ -50,12 +163,15 @@ impl FastlyKvStore for Session {
         _lookup_configuration: GuestPtr<KvLookupConfig>,
         handle_out: GuestPtr<KvStoreLookupHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store).unwrap();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())
-            .map_err(|_| KvStoreError::BadRequest)?;
-        // just create a future that's already ready
-        let fut = futures::future::ok(self.obj_lookup(store.clone(), key));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.get_kv_store_key(store)?;
+        let profile = self.kv_key_profile(store, KeyValidationProfile::KvStore);
+        let key = read_key(memory, key, &self.kv_key_prefix(store), profile)?;
+        check_out_ptr(memory, handle_out)?;
+        let fut = match key {
+            Ok(key) => self.kv_lookup(store.clone(), key).left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             handle_out,
             self.insert_pending_kv_lookup(PendingKvLookupTask::new(task))
@@ -75,6 +191,12 @@ impl FastlyKvStore for Session {
         generation_out: GuestPtr<u32>,
         kv_error_out: GuestPtr<KvError>,
     ) -> Result<(), Error> {
+        check_out_ptr(memory, body_handle_out)?;
+        check_out_ptr(memory, nwritten_out)?;
+        check_out_ptr(memory, generation_out)?;
+        check_out_ptr(memory, kv_error_out)?;
+        check_out_buf(memory, metadata_buf, metadata_buf_len)?;
+
         let resp = self
             .take_pending_kv_lookup(pending_kv_lookup_handle.into())?
             .task()
@@ -83,16 +205,18 @@ impl FastlyKvStore for Session {
 
         match resp {
             Ok(value) => {
-                let body_handle = self.insert_body(value.body.into());
-
-                memory.write(body_handle_out, body_handle)?;
-                match value.metadata_len {
+                match value.metadata_len() {
                     0 => memory.write(nwritten_out, 0)?,
                     len => {
                         let meta_len_u32 =
-                            u32::try_from(len).expect("metadata len is outside the bounds of u32");
+                            u32::try_from(len).map_err(|_| KvStoreError::InternalError)?;
                         memory.write(nwritten_out, meta_len_u32)?;
                         if meta_len_u32 > metadata_buf_len {
+                            // keep the result, so the guest can retry with a larger buffer
+                            self.reinsert_pending_kv_lookup(
+                                pending_kv_lookup_handle.into(),
+                                PendingKvLookupTask::new(PeekableTask::complete(Ok(value))),
+                            )?;
                             return Err(Error::BufferLengthError {
                                 buf: "metadata",
                                 len: "specified length",
@@ -104,7 +228,10 @@ impl FastlyKvStore for Session {
                         )?;
                     }
                 }
-                memory.write(generation_out, value.generation)?;
+                let generation = value.abi_generation();
+                let body_handle = self.insert_body(value.body.into());
+                memory.write(body_handle_out, body_handle)?;
+                memory.write(generation_out, generation)?;
                 memory.write(kv_error_out, KvError::Ok)?;
                 Ok(())
             }
@@ -125,24 +252,10 @@ impl FastlyKvStore for Session {
         insert_configuration: GuestPtr<KvInsertConfig>,
         pending_handle_out: GuestPtr<KvStoreInsertHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store).unwrap().clone();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())
-            .map_err(|_| KvStoreError::BadRequest)?;
-        let body = self.take_body(body_handle)?.read_into_vec().await?;
-
-        let config = memory.read(insert_configuration)?;
-
-        let config_str_or_none = |flag, str_field: GuestPtr<u8>, len_field| {
-            if insert_config_mask.contains(flag) {
-                if len_field == 0 {
-                    return Err(Error::InvalidArgument);
-                }
-
-                Ok(Some(memory.to_vec(str_field.as_array(len_field))?))
-            } else {
-                Ok(None)
-            }
-        };
+        let store = self.get_kv_store_key(store)?.clone();
+        let profile = self.kv_key_profile(&store, KeyValidationProfile::KvStore);
+        let key = read_key(memory, key, &self.kv_key_prefix(&store), profile)?;
+        let config = read_insert_config(memory, insert_configuration)?;
 
         let mode = config.mode;
 
@@ -150,13 +263,14 @@ impl FastlyKvStore for Session {
         // let bgf = insert_config_mask.contains(KvInsertConfigOptions::BACKGROUND_FETCH);
 
         let igm = if insert_config_mask.contains(KvInsertConfigOptions::IF_GENERATION_MATCH) {
-            Some(config.if_generation_match)
+            Some(u64::from(config.if_generation_match))
         } else {
             None
         };
 
-        let meta = config_str_or_none(
-            KvInsertConfigOptions::METADATA,
+        let meta = read_config_bytes(
+            memory,
+            insert_config_mask.contains(KvInsertConfigOptions::METADATA),
             config.metadata,
             config.metadata_len,
         )?;
@@ -169,8 +283,15 @@ impl FastlyKvStore for Session {
             None
         };
 
-        let fut = futures::future::ok(self.kv_insert(store, key, body, Some(mode), igm, meta, ttl));
-        let task = PeekableTask::spawn(fut).await;
+        check_out_ptr(memory, pending_handle_out)?;
+        let body = self.take_body(body_handle)?.read_into_vec().await?;
+        let fut = match key {
+            Ok(key) => self
+                .kv_insert(store, key, body, Some(mode), igm, meta, ttl)
+                .left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             pending_handle_out,
             self.insert_pending_kv_insert(PendingKvInsertTask::new(task)),
@@ -185,6 +306,7 @@ impl FastlyKvStore for Session {
         pending_insert_handle: KvStoreInsertHandle,
         kv_error_out: GuestPtr<KvError>,
     ) -> Result<(), Error> {
+        check_out_ptr(memory, kv_error_out)?;
         let resp = self
             .take_pending_kv_insert(pending_insert_handle.into())?
             .task()
@@ -196,6 +318,12 @@ impl FastlyKvStore for Session {
                 memory.write(kv_error_out, KvError::Ok)?;
                 Ok(())
             }
+            // there's no `KvError` for a full store, so the hostcall fails instead, as it does
+            // for the adapter
+            Err(e @ KvStoreError::TooManyKeys) => {
+                memory.write(kv_error_out, KvError::Uninitialized)?;
+                Err(e.into())
+            }
             Err(e) => {
                 memory.write(kv_error_out, (&e).into())?;
                 Ok(())
@@ -212,11 +340,15 @@ impl FastlyKvStore for Session {
         _delete_configuration: GuestPtr<KvDeleteConfig>,
         pending_handle_out: GuestPtr<KvStoreDeleteHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store).unwrap().clone();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())
-            .map_err(|_| KvStoreError::BadRequest)?;
-        let fut = futures::future::ok(self.kv_delete(store, key));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.get_kv_store_key(store)?.clone();
+        let profile = self.kv_key_profile(&store, KeyValidationProfile::KvStore);
+        let key = read_key(memory, key, &self.kv_key_prefix(&store), profile)?;
+        check_out_ptr(memory, pending_handle_out)?;
+        let fut = match key {
+            Ok(key) => self.kv_delete(store, key, None).left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             pending_handle_out,
             self.insert_pending_kv_delete(PendingKvDeleteTask::new(task))
@@ -231,6 +363,7 @@ impl FastlyKvStore for Session {
         pending_delete_handle: KvStoreDeleteHandle,
         kv_error_out: GuestPtr<KvError>,
     ) -> Result<(), Error> {
+        check_out_ptr(memory, kv_error_out)?;
         let resp = self
             .take_pending_kv_delete(pending_delete_handle.into())?
             .task()
@@ -257,24 +390,34 @@ impl FastlyKvStore for Session {
         list_configuration: GuestPtr<KvListConfig>,
         pending_handle_out: GuestPtr<KvStoreListHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store).unwrap().clone();
+        let store = self.get_kv_store_key(store)?.clone();
 
         let config = memory.read(list_configuration)?;
 
-        let config_string_or_none = |flag, str_field: GuestPtr<u8>, len_field| {
-            if list_config_mask.contains(flag) {
-                if len_field == 0 {
-                    return Err(Error::InvalidArgument);
-                }
-
-                let byte_vec = memory.to_vec(str_field.as_array(len_field))?;
+        // Neither option can usefully be longer than these, so longer ones are refused before
+        // they are copied out of guest memory, however long the guest says they are.
+        let too_long = |flag, len: u32, max| list_config_mask.contains(flag) && len as usize > max;
+        if too_long(
+            KvListConfigOptions::CURSOR,
+            config.cursor_len,
+            MAX_LIST_CURSOR_LEN,
+        ) || too_long(
+            KvListConfigOptions::PREFIX,
+            config.prefix_len,
+            MAX_KEY_BYTES,
+        ) {
+            return Err(KvStoreError::BadRequest.into());
+        }
 
-                Ok(Some(
-                    String::from_utf8(byte_vec).map_err(|_| Error::InvalidArgument)?,
-                ))
-            } else {
-                Ok(None)
-            }
+        let config_string_or_none = |flag, str_field, len_field| {
+            read_config_bytes(
+                memory,
+                list_config_mask.contains(flag),
+                str_field,
+                len_field,
+            )?
+            .map(|bytes| String::from_utf8(bytes).map_err(|_| Error::InvalidArgument))
+            .transpose()
         };
 
         let cursor = config_string_or_none(
@@ -283,19 +426,24 @@ impl FastlyKvStore for Session {
             config.cursor_len,
         )?;
 
-        let prefix = config_string_or_none(
-            KvListConfigOptions::PREFIX,
-            config.prefix,
-            config.prefix_len,
-        )?;
+        // An empty prefix is the same as no prefix, rather than an invalid argument.
+        let prefix = match config.prefix_len {
+            0 => None,
+            _ => config_string_or_none(
+                KvListConfigOptions::PREFIX,
+                config.prefix,
+                config.prefix_len,
+            )?,
+        };
 
         let limit = match list_config_mask.contains(KvListConfigOptions::LIMIT) {
             true => Some(config.limit),
             false => None,
         };
 
-        let fut = futures::future::ok(self.kv_list(store, cursor, prefix, limit));
-        let task = PeekableTask::spawn(fut).await;
+        check_out_ptr(memory, pending_handle_out)?;
+        let fut = self.kv_list(store, cursor, prefix, limit);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             pending_handle_out,
             self.insert_pending_kv_list(PendingKvListTask::new(task))
@@ -311,6 +459,8 @@ impl FastlyKvStore for Session {
         body_handle_out: GuestPtr<BodyHandle>,
         kv_error_out: GuestPtr<KvError>,
     ) -> Result<(), Error> {
+        check_out_ptr(memory, body_handle_out)?;
+        check_out_ptr(memory, kv_error_out)?;
         let resp = self
             .take_pending_kv_list(pending_kv_list_handle.into())?
             .task()
@@ -319,9 +469,13 @@ impl FastlyKvStore for Session {
 
         match resp {
             Ok(value) => {
-                let body_handle = self.insert_body(value.into()).into();
+                let body_handle = self.insert_body(value.into());
 
-                memory.write(body_handle_out, body_handle)?;
+                // Don't leave the body behind if the guest can't be told about it.
+                if let Err(e) = memory.write(body_handle_out, body_handle.into()) {
+                    self.take_body(body_handle)?;
+                    return Err(e.into());
+                }
 
                 memory.write(kv_error_out, KvError::Ok)?;
                 Ok(())
@@ -332,4 +486,233 @@ impl FastlyKvStore for Session {
             }
         }
     }
+
+    async fn head(
+        &mut self,
+        memory: &mut GuestMemory<'_>,
+        store: KvStoreHandle,
+        key: GuestPtr<str>,
+        handle_out: GuestPtr<KvStoreHeadHandle>,
+    ) -> Result<(), Error> {
+        let store = self.get_kv_store_key(store)?;
+        let profile = self.kv_key_profile(store, KeyValidationProfile::KvStore);
+        let key = read_key(memory, key, &self.kv_key_prefix(store), profile)?;
+        check_out_ptr(memory, handle_out)?;
+        let fut = match key {
+            Ok(key) => self.obj_head(store.clone(), key).left_future(),
+            Err(e) => future::err(e).right_future(),
+        };
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
+        memory.write(
+            handle_out,
+            self.insert_pending_kv_head(PendingKvHeadTask::new(task)),
+        )?;
+        Ok(())
+    }
+
+    async fn head_wait(
+        &mut self,
+        memory: &mut GuestMemory<'_>,
+        pending_kv_head_handle: KvStoreHeadHandle,
+        metadata_buf: GuestPtr<u8>,
+        metadata_buf_len: u32,
+        nwritten_out: GuestPtr<u32>,
+        generation_out: GuestPtr<u32>,
+        body_len_out: GuestPtr<u64>,
+        kv_error_out: GuestPtr<KvError>,
+    ) -> Result<(), Error> {
+        check_out_ptr(memory, nwritten_out)?;
+        check_out_ptr(memory, generation_out)?;
+        memory.read(body_len_out)?;
+        check_out_ptr(memory, kv_error_out)?;
+        check_out_buf(memory, metadata_buf, metadata_buf_len)?;
+
+        let resp = self
+            .take_pending_kv_head(pending_kv_head_handle)?
+            .task()
+            .recv()
+            .await?;
+
+        match resp {
+            Ok(head) => {
+                let meta_len_u32 =
+                    u32::try_from(head.metadata.len()).map_err(|_| KvStoreError::InternalError)?;
+                memory.write(nwritten_out, meta_len_u32)?;
+                if meta_len_u32 > metadata_buf_len {
+                    // keep the result, so the guest can retry with a larger buffer
+                    self.reinsert_pending_kv_head(
+                        pending_kv_head_handle,
+                        PendingKvHeadTask::new(PeekableTask::complete(Ok(head))),
+                    )?;
+                    return Err(Error::BufferLengthError {
+                        buf: "metadata",
+                        len: "specified length",
+                    });
+                }
+                memory.copy_from_slice(&head.metadata, metadata_buf.as_array(meta_len_u32))?;
+                memory.write(generation_out, head.abi_generation())?;
+                memory.write(body_len_out, head.body_len as u64)?;
+                memory.write(kv_error_out, KvError::Ok)?;
+                Ok(())
+            }
+            Err(e) => {
+                memory.write(kv_error_out, (&e).into())?;
+                Ok(())
+            }
+        }
+    }
+
+    fn limits(
+        &mut self,
+        memory: &mut GuestMemory<'_>,
+        store: KvStoreHandle,
+        limits_out: GuestPtr<KvStoreLimits>,
+    ) -> Result<(), Error> {
+        let limits = self.kv_store_limits(store)?;
+        memory.write(
+            limits_out,
+            KvStoreLimits {
+                max_value_size: limits.max_value_size as u64,
+                max_metadata_size: limits.max_metadata_size as u64,
+                max_key_len: limits.max_key_len as u64,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::wiggle_abi::types::FastlyStatus, std::cell::UnsafeCell};
+
+    /// Run `f` against `bytes` laid out in an unshared memory, and again in a shared one.
+    fn with_memories(bytes: &[u8], f: impl Fn(&GuestMemory<'_>)) {
+        let mut unshared = bytes.to_vec();
+        f(&GuestMemory::Unshared(&mut unshared));
+
+        let shared = bytes
+            .iter()
+            .map(|b| UnsafeCell::new(*b))
+            .collect::<Vec<_>>();
+        f(&GuestMemory::Shared(&shared));
+    }
+
+    #[test]
+    fn keys_are_read_from_any_memory() {
+        // lookup, insert, and delete all read their key the same way
+        with_memories(b"....my-key..", |memory| {
+            let read = |ptr| read_key(memory, ptr, "", KeyValidationProfile::KvStore);
+            let key = read(GuestPtr::new((4, 6))).unwrap();
+            assert_eq!(key, Ok(ObjectKey::new("my-key").unwrap()));
+
+            // an invalid key is a failed operation, rather than a failed hostcall
+            assert!(matches!(
+                read(GuestPtr::new((0, 1))),
+                Ok(Err(KvStoreError::BadRequest))
+            ));
+            assert!(matches!(
+                read(GuestPtr::new((4, 64))),
+                Err(Error::GuestError(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn over_long_keys_are_a_buffer_length_error() {
+        let bytes = "k".repeat(1025);
+        with_memories(bytes.as_bytes(), |memory| {
+            let read = |ptr| read_key(memory, ptr, "", KeyValidationProfile::KvStore);
+            assert!(matches!(read(GuestPtr::new((0, 1024))), Ok(Ok(_))));
+
+            let err = read(GuestPtr::new((0, 1025))).unwrap_err();
+            assert_eq!(err.to_fastly_status(), FastlyStatus::Buflen);
+            assert!(matches!(
+                crate::component::fastly::api::types::Error::from(err),
+                crate::component::fastly::api::types::Error::BufferLen(1024)
+            ));
+        });
+    }
+
+    #[test]
+    fn prefixes_shorten_the_longest_key_reported() {
+        let bytes = "k".repeat(1024);
+        with_memories(bytes.as_bytes(), |memory| {
+            let read = |ptr| read_key(memory, ptr, "app/", KeyValidationProfile::KvStore);
+            assert!(matches!(read(GuestPtr::new((0, 1020))), Ok(Ok(_))));
+
+            let err = read(GuestPtr::new((0, 1021))).unwrap_err();
+            assert_eq!(err.to_fastly_status(), FastlyStatus::Buflen);
+            assert!(matches!(
+                crate::component::fastly::api::types::Error::from(err),
+                crate::component::fastly::api::types::Error::BufferLen(1020)
+            ));
+        });
+    }
+
+    #[test]
+    fn out_pointers_are_checked_without_writing() {
+        with_memories(&[0xaa; 12], |memory| {
+            let ptr = |offset| GuestPtr::<KvError>::new(offset);
+            check_out_ptr(memory, ptr(8)).unwrap();
+            assert!(matches!(
+                check_out_ptr(memory, ptr(12)),
+                Err(Error::GuestError(_))
+            ));
+            assert!(matches!(
+                check_out_ptr(memory, ptr(2)),
+                Err(Error::GuestError(_))
+            ));
+            // 0xaaaaaaaa isn't a valid `KvError`, but the pointer is only checked, not read
+            assert_eq!(
+                memory.to_vec(GuestPtr::<[u8]>::new((0, 12))).unwrap(),
+                [0xaa; 12]
+            );
+        });
+    }
+
+    #[test]
+    fn out_buffers_are_checked_without_copying() {
+        with_memories(&[0xaa; 12], |memory| {
+            let check = |offset, len| check_out_buf(memory, GuestPtr::new(offset), len);
+            check(0, 12).unwrap();
+            check(12, 0).unwrap();
+            assert!(matches!(check(4, 9), Err(Error::GuestError(_))));
+            assert!(matches!(check(13, 0), Err(Error::GuestError(_))));
+            assert!(matches!(check(u32::MAX, 2), Err(Error::GuestError(_))));
+        });
+    }
+
+    #[test]
+    fn unknown_insert_modes_are_a_bad_request() {
+        // mode, if_generation_match, metadata, metadata_len, and time_to_live_sec
+        let config = |mode: u32| {
+            [mode, 0, 0, 0, 0]
+                .into_iter()
+                .flat_map(u32::to_le_bytes)
+                .collect::<Vec<_>>()
+        };
+        with_memories(&config(2), |memory| {
+            let config = read_insert_config(memory, GuestPtr::new(0)).unwrap();
+            assert_eq!(config.mode, KvInsertMode::Append);
+        });
+        for mode in [4, 9, u32::MAX] {
+            with_memories(&config(mode), |memory| {
+                let err = read_insert_config(memory, GuestPtr::new(0)).unwrap_err();
+                assert!(matches!(err, Error::KvStoreError(KvStoreError::BadRequest)));
+                assert_eq!(err.to_fastly_status(), FastlyStatus::Inval);
+            });
+        }
+    }
+
+    #[test]
+    fn config_fields_are_read_from_any_memory() {
+        // insert metadata, and list cursors and prefixes
+        with_memories(b"..metadata", |memory| {
+            let read = |enabled, len| read_config_bytes(memory, enabled, GuestPtr::new(2), len);
+            assert_eq!(read(true, 8).unwrap(), Some(b"metadata".to_vec()));
+            assert_eq!(read(false, 8).unwrap(), None);
+            assert!(matches!(read(true, 0), Err(Error::InvalidArgument)));
+            assert!(matches!(read(true, 64), Err(Error::GuestError(_))));
+        });
+    }
 }
@@ -1,5 +1,19 @@
 //! fastly_obj_store` hostcall implementations.
+//!
+//! These are the legacy interface to the same stores the `fastly_kv_store` hostcalls use, so a
+//! guest part way through moving from one to the other sees the same data through both. The
+//! differences are those of the interface:
+//!
+//! * Inserts are always overwrites with no metadata or TTL of their own. They are given a
+//!   generation like any other write, and clear any metadata the key had.
+//! * Lookups give only the value, not its metadata or generation, and a missing key is reported
+//!   by leaving the body handle unwritten rather than as an error.
+//! * Deleting a missing key fails with `FastlyStatus::None`.
+//! * Keys may contain `[`, `]`, `*`, `?`, and `#`, following the
+//!   [`ObjectStore`][KeyValidationProfile::ObjectStore] rules rather than the KV store's, unless
+//!   the store's `key_validation` setting says otherwise.
 
+use super::kv_store_impl::check_out_ptr;
 use super::types::{PendingKvDeleteHandle, PendingKvInsertHandle, PendingKvLookupHandle};
 use crate::session::PeekableTask;
 use crate::session::{PendingKvDeleteTask, PendingKvInsertTask, PendingKvLookupTask};
@@ -8,16 +22,21 @@ use {
     crate::{
         body::Body,
         error::Error,
-        object_store::{KvStoreError, ObjectKey, ObjectStoreError},
+        object_store::{KeyValidationProfile, KvStoreError, ObjectStoreError},
         session::Session,
         wiggle_abi::{
             fastly_object_store::FastlyObjectStore,
             types::{BodyHandle, ObjectStoreHandle},
         },
     },
+    futures::FutureExt,
     wiggle::{GuestMemory, GuestPtr},
 };
 
+// As with the `fastly_kv_store` hostcalls, arguments are copied out of guest memory so that these
+// work the same for guests using a shared memory, and out-pointers are checked before any side
+// effects.
+
 #[wiggle::async_trait]
 impl FastlyObjectStore for Session {
     fn open(
@@ -25,26 +44,30 @@ impl FastlyObjectStore for Session {
         memory: &mut GuestMemory<'_>,
         name: GuestPtr<str>,
     ) -> Result<ObjectStoreHandle, Error> {
-        let name = memory.as_str(name)?.ok_or(Error::SharedMemory)?;
-        if self.kv_store.store_exists(name)? {
-            Ok(self.kv_store_handle(name)?.into())
-        } else {
-            Err(Error::ObjectStoreError(
-                ObjectStoreError::UnknownObjectStore(name.to_owned()),
-            ))
+        let name = memory.as_cow_str(name)?;
+        match self.kv_store_open(&name)? {
+            Some(handle) => Ok(handle.into()),
+            None => Err(Error::ObjectStoreError(
+                ObjectStoreError::UnknownObjectStore(name.into_owned()),
+            )),
         }
     }
 
-    fn lookup(
+    async fn lookup(
         &mut self,
         memory: &mut GuestMemory<'_>,
         store: ObjectStoreHandle,
         key: GuestPtr<str>,
         opt_body_handle_out: GuestPtr<BodyHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store.into()).unwrap();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())?;
-        match self.obj_lookup(store.clone(), key) {
+        let store = self.get_kv_store_key(store.into())?;
+        let key = self.kv_key(
+            store,
+            memory.as_cow_str(key)?,
+            KeyValidationProfile::ObjectStore,
+        )?;
+        check_out_ptr(memory, opt_body_handle_out)?;
+        match self.obj_lookup(store.clone(), key).await {
             Ok(obj) => {
                 let new_handle = self.insert_body(Body::from(obj.body));
                 memory.write(opt_body_handle_out, new_handle)?;
@@ -65,11 +88,15 @@ impl FastlyObjectStore for Session {
         key: GuestPtr<str>,
         opt_pending_body_handle_out: GuestPtr<PendingKvLookupHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store.into()).unwrap();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())?;
-        // just create a future that's already ready
-        let fut = futures::future::ok(self.obj_lookup(store.clone(), key));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.get_kv_store_key(store.into())?;
+        let key = self.kv_key(
+            store,
+            memory.as_cow_str(key)?,
+            KeyValidationProfile::ObjectStore,
+        )?;
+        check_out_ptr(memory, opt_pending_body_handle_out)?;
+        let fut = self.obj_lookup(store.clone(), key);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             opt_pending_body_handle_out,
             self.insert_pending_kv_lookup(PendingKvLookupTask::new(task)),
@@ -83,6 +110,7 @@ impl FastlyObjectStore for Session {
         pending_body_handle: PendingKvLookupHandle,
         opt_body_handle_out: GuestPtr<BodyHandle>,
     ) -> Result<(), Error> {
+        check_out_ptr(memory, opt_body_handle_out)?;
         let pending_obj = self
             .take_pending_kv_lookup(pending_body_handle)?
             .task()
@@ -107,10 +135,15 @@ impl FastlyObjectStore for Session {
         key: GuestPtr<str>,
         body_handle: BodyHandle,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store.into()).unwrap().clone();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())?;
+        let store = self.get_kv_store_key(store.into())?.clone();
+        let key = self.kv_key(
+            &store,
+            memory.as_cow_str(key)?,
+            KeyValidationProfile::ObjectStore,
+        )?;
         let bytes = self.take_body(body_handle)?.read_into_vec().await?;
-        self.kv_insert(store, key, bytes, None, None, None, None)?;
+        self.kv_insert(store, key, bytes, None, None, None, None)
+            .await?;
 
         Ok(())
     }
@@ -123,11 +156,16 @@ impl FastlyObjectStore for Session {
         body_handle: BodyHandle,
         opt_pending_body_handle_out: GuestPtr<PendingKvInsertHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store.into()).unwrap().clone();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())?;
+        let store = self.get_kv_store_key(store.into())?.clone();
+        let key = self.kv_key(
+            &store,
+            memory.as_cow_str(key)?,
+            KeyValidationProfile::ObjectStore,
+        )?;
+        check_out_ptr(memory, opt_pending_body_handle_out)?;
         let bytes = self.take_body(body_handle)?.read_into_vec().await?;
-        let fut = futures::future::ok(self.kv_insert(store, key, bytes, None, None, None, None));
-        let task = PeekableTask::spawn(fut).await;
+        let fut = self.kv_insert(store, key, bytes, None, None, None, None);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             opt_pending_body_handle_out,
             self.insert_pending_kv_insert(PendingKvInsertTask::new(task))
@@ -141,11 +179,11 @@ impl FastlyObjectStore for Session {
         _memory: &mut GuestMemory<'_>,
         pending_insert_handle: PendingKvInsertHandle,
     ) -> Result<(), Error> {
-        Ok((self
-            .take_pending_kv_insert(pending_insert_handle)?
+        self.take_pending_kv_insert(pending_insert_handle)?
             .task()
             .recv()
-            .await?)?)
+            .await??;
+        Ok(())
     }
 
     async fn delete_async(
@@ -155,10 +193,15 @@ impl FastlyObjectStore for Session {
         key: GuestPtr<str>,
         opt_pending_delete_handle_out: GuestPtr<PendingKvDeleteHandle>,
     ) -> Result<(), Error> {
-        let store = self.get_kv_store_key(store.into()).unwrap().clone();
-        let key = ObjectKey::new(memory.as_str(key)?.ok_or(Error::SharedMemory)?.to_string())?;
-        let fut = futures::future::ok(self.kv_delete(store, key));
-        let task = PeekableTask::spawn(fut).await;
+        let store = self.get_kv_store_key(store.into())?.clone();
+        let key = self.kv_key(
+            &store,
+            memory.as_cow_str(key)?,
+            KeyValidationProfile::ObjectStore,
+        )?;
+        check_out_ptr(memory, opt_pending_delete_handle_out)?;
+        let fut = self.kv_delete(store, key, None);
+        let task = PeekableTask::spawn(fut.map(Ok)).await;
         memory.write(
             opt_pending_delete_handle_out,
             self.insert_pending_kv_delete(PendingKvDeleteTask::new(task)),
@@ -0,0 +1,217 @@
+//! A guest program that makes every kind of KV hostcall through the `fastly_kv_store` module
+//! directly, for testing those hostcalls once the module has been adapted to a component.
+//!
+//! The store `store` is seeded with `seed` = `s` and `gone` = `g`. The guest looks up `seed`,
+//! inserts `new`, appends to `seed`, deletes `gone` and the never-written `missing`, looks `gone`
+//! up again, tries to insert `future` with an insert mode from beyond the ABI, and then lists the
+//! store. The response body reports what it saw, one result per line:
+//!
+//! ```text
+//! lookup seed: s
+//! delete missing: not found
+//! lookup gone: not found
+//! insert unknown mode: invalid argument
+//! list: {"data":["new","seed"],"meta":{"limit":1000}}
+//! ```
+
+use {
+    crate::kv_abi::{
+        delete, delete_wait, insert, insert_wait, list, list_wait, lookup, lookup_wait, open,
+        DeleteConfig, DeleteHandle, InsertConfig, InsertHandle, ListConfig, ListHandle,
+        LookupConfig, LookupHandle, INSERT_MODE_APPEND, INSERT_MODE_OVERWRITE, KV_ERROR_NOT_FOUND,
+        KV_ERROR_OK,
+    },
+    fastly::Response,
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+/// A mode that a newer SDK might send, but no version of the ABI has defined yet.
+const INSERT_MODE_UNKNOWN: u32 = 9;
+
+/// Describe a KV error as the response body reports it.
+fn describe(kv_error: u32) -> &'static str {
+    match kv_error {
+        KV_ERROR_OK => "ok",
+        KV_ERROR_NOT_FOUND => "not found",
+        _ => "unexpected error",
+    }
+}
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(nwritten, contents.len());
+        body
+    }
+}
+
+fn read_body(body: BodyHandle) -> String {
+    let mut contents = vec![0u8; 4096];
+    let mut nread = 0;
+    assert_eq!(
+        unsafe { http_body::read(body, contents.as_mut_ptr(), contents.len(), &mut nread) },
+        FastlyStatus::OK
+    );
+    contents.truncate(nread);
+    String::from_utf8(contents).unwrap()
+}
+
+/// Look up `key`, returning its value, or the error the lookup failed with.
+fn lookup_key(store: KVStoreHandle, key: &str) -> String {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut metadata = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+    }
+    match kv_error {
+        KV_ERROR_OK => read_body(body),
+        e => describe(e).to_string(),
+    }
+}
+
+/// Start inserting `key` with the raw insert `mode`, returning the status of the call.
+fn start_insert(
+    store: KVStoreHandle,
+    key: &str,
+    value: &str,
+    mode: u32,
+    pending: &mut InsertHandle,
+) -> FastlyStatus {
+    let config = InsertConfig {
+        mode,
+        if_generation_match: 0,
+        metadata: std::ptr::null(),
+        metadata_len: 0,
+        time_to_live_sec: 0,
+    };
+    unsafe {
+        insert(
+            store,
+            key.as_ptr(),
+            key.len(),
+            body(value),
+            0,
+            &config,
+            pending,
+        )
+    }
+}
+
+fn insert_key(store: KVStoreHandle, key: &str, value: &str, mode: u32) {
+    let mut pending: InsertHandle = 0;
+    let mut kv_error = 0;
+    assert_eq!(
+        start_insert(store, key, value, mode, &mut pending),
+        FastlyStatus::OK
+    );
+    assert_eq!(
+        unsafe { insert_wait(pending, &mut kv_error) },
+        FastlyStatus::OK
+    );
+    assert_eq!(kv_error, KV_ERROR_OK);
+}
+
+/// Delete `key`, returning the outcome.
+fn delete_key(store: KVStoreHandle, key: &str) -> &'static str {
+    let config = DeleteConfig { reserved: 0 };
+    let mut pending: DeleteHandle = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            delete(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(delete_wait(pending, &mut kv_error), FastlyStatus::OK);
+    }
+    describe(kv_error)
+}
+
+fn list_keys(store: KVStoreHandle) -> String {
+    let config = ListConfig {
+        mode: 0,
+        cursor: std::ptr::null(),
+        cursor_len: 0,
+        limit: 0,
+        prefix: std::ptr::null(),
+        prefix_len: 0,
+    };
+    let mut pending: ListHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(list(store, 0, &config, &mut pending), FastlyStatus::OK);
+        assert_eq!(
+            list_wait(pending, &mut body, &mut kv_error),
+            FastlyStatus::OK
+        );
+    }
+    assert_eq!(kv_error, KV_ERROR_OK);
+    read_body(body)
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let mut report = vec![format!("lookup seed: {}", lookup_key(store, "seed"))];
+    insert_key(store, "new", "n", INSERT_MODE_OVERWRITE);
+    insert_key(store, "seed", "+", INSERT_MODE_APPEND);
+    assert_eq!(delete_key(store, "gone"), "ok");
+    report.push(format!("delete missing: {}", delete_key(store, "missing")));
+    report.push(format!("lookup gone: {}", lookup_key(store, "gone")));
+    let mut pending: InsertHandle = 0;
+    let status = start_insert(store, "future", "f", INSERT_MODE_UNKNOWN, &mut pending);
+    report.push(format!(
+        "insert unknown mode: {}",
+        if status == FastlyStatus::INVAL {
+            "invalid argument"
+        } else {
+            "accepted"
+        }
+    ));
+    report.push(format!("list: {}", list_keys(store)));
+
+    let mut body = report.join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
@@ -0,0 +1,12 @@
+//! A guest program that writes the request's method to the key named by its path, for testing
+//! that each write is attributed to the request that made it.
+
+use fastly::{kv_store::KVStore, Request, Response};
+
+fn main() {
+    let req = Request::from_client();
+    let mut store = KVStore::open("store").unwrap().unwrap();
+    let key = req.get_path().trim_start_matches('/');
+    store.insert(key, req.get_method_str()).unwrap();
+    Response::new().send_to_client();
+}
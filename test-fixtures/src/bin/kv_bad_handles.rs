@@ -0,0 +1,127 @@
+//! A guest program to test that KV hostcalls given a bad handle fail cleanly.
+
+use {
+    crate::kv_abi::{
+        delete_wait, insert, insert_wait, lookup, lookup_wait, open, InsertConfig, InsertHandle,
+        LookupConfig, LookupHandle, KV_ERROR_OK,
+    },
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn main() {
+    let name = "store";
+    let key = "key";
+
+    unsafe {
+        let mut store: KVStoreHandle = 0;
+        assert_eq!(
+            open(name.as_ptr(), name.len(), &mut store),
+            FastlyStatus::OK
+        );
+
+        // store handles the guest never opened
+        let config = LookupConfig { reserved: 0 };
+        let mut pending: LookupHandle = 0;
+        for bad_store in [store + 1, u32::MAX] {
+            assert_eq!(
+                lookup(bad_store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+                FastlyStatus::BADF
+            );
+        }
+
+        // start an insert and a lookup
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let value = "value";
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                value.as_ptr(),
+                value.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        let config = InsertConfig {
+            mode: 0,
+            if_generation_match: 0,
+            metadata: std::ptr::null(),
+            metadata_len: 0,
+            time_to_live_sec: 0,
+        };
+        let mut pending_insert: InsertHandle = 0;
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                0,
+                &config,
+                &mut pending_insert
+            ),
+            FastlyStatus::OK
+        );
+        let config = LookupConfig { reserved: 0 };
+        let mut pending_lookup: LookupHandle = 0;
+        assert_eq!(
+            lookup(
+                store,
+                key.as_ptr(),
+                key.len(),
+                0,
+                &config,
+                &mut pending_lookup
+            ),
+            FastlyStatus::OK
+        );
+
+        // waiting with the wrong kind of handle, or one that was never handed out, is a bad handle
+        let mut metadata = [0u8; 16];
+        let mut generation = 0;
+        let mut kv_error = 0;
+        for bad_lookup in [pending_insert, body, u32::MAX] {
+            assert_eq!(
+                lookup_wait(
+                    bad_lookup,
+                    &mut body,
+                    metadata.as_mut_ptr(),
+                    metadata.len(),
+                    &mut nwritten,
+                    &mut generation,
+                    &mut kv_error
+                ),
+                FastlyStatus::BADF
+            );
+        }
+        for bad_insert in [pending_lookup, u32::MAX] {
+            assert_eq!(insert_wait(bad_insert, &mut kv_error), FastlyStatus::BADF);
+        }
+        for bad_delete in [pending_insert, pending_lookup, u32::MAX] {
+            assert_eq!(delete_wait(bad_delete, &mut kv_error), FastlyStatus::BADF);
+        }
+
+        // and leaves the real operations pending
+        assert_eq!(insert_wait(pending_insert, &mut kv_error), FastlyStatus::OK);
+        assert_eq!(kv_error, KV_ERROR_OK);
+        assert_eq!(
+            lookup_wait(
+                pending_lookup,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+}
@@ -0,0 +1,124 @@
+//! A guest program to test that inserting, looking up, or deleting an invalid KV key starts the
+//! operation, which then fails with a bad request, rather than failing the hostcall.
+
+use {
+    crate::kv_abi::{
+        delete, delete_wait, insert, insert_wait, lookup, lookup_wait, open, DeleteConfig,
+        DeleteHandle, InsertConfig, InsertHandle, LookupConfig, LookupHandle, KV_ERROR_BAD_REQUEST,
+    },
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        body
+    }
+}
+
+/// Insert `key`, returning the status of the insert and the error it waited on.
+fn insert_key(store: KVStoreHandle, key: &str) -> (FastlyStatus, u32) {
+    let config = InsertConfig {
+        mode: 0,
+        if_generation_match: 0,
+        metadata: std::ptr::null(),
+        metadata_len: 0,
+        time_to_live_sec: 0,
+    };
+    let mut pending: InsertHandle = 0;
+    let status = unsafe {
+        insert(
+            store,
+            key.as_ptr(),
+            key.len(),
+            body("value"),
+            0,
+            &config,
+            &mut pending,
+        )
+    };
+    let mut kv_error = 0;
+    if status == FastlyStatus::OK {
+        assert_eq!(
+            unsafe { insert_wait(pending, &mut kv_error) },
+            FastlyStatus::OK
+        );
+    }
+    (status, kv_error)
+}
+
+/// Look up `key`, returning the status of the lookup and the error it waited on.
+fn lookup_key(store: KVStoreHandle, key: &str) -> (FastlyStatus, u32) {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let status = unsafe { lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending) };
+    let mut kv_error = 0;
+    if status == FastlyStatus::OK {
+        let mut body: BodyHandle = 0;
+        let mut buf = [0u8; 16];
+        let mut nwritten = 0;
+        let mut generation = 0;
+        assert_eq!(
+            unsafe {
+                lookup_wait(
+                    pending,
+                    &mut body,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut nwritten,
+                    &mut generation,
+                    &mut kv_error,
+                )
+            },
+            FastlyStatus::OK
+        );
+    }
+    (status, kv_error)
+}
+
+/// Delete `key`, returning the status of the delete and the error it waited on.
+fn delete_key(store: KVStoreHandle, key: &str) -> (FastlyStatus, u32) {
+    let config = DeleteConfig { reserved: 0 };
+    let mut pending: DeleteHandle = 0;
+    let status = unsafe { delete(store, key.as_ptr(), key.len(), 0, &config, &mut pending) };
+    let mut kv_error = 0;
+    if status == FastlyStatus::OK {
+        assert_eq!(
+            unsafe { delete_wait(pending, &mut kv_error) },
+            FastlyStatus::OK
+        );
+    }
+    (status, kv_error)
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let bad_request = (FastlyStatus::OK, KV_ERROR_BAD_REQUEST);
+    for key in ["line\nbreak", "..", "page#anchor"] {
+        assert_eq!(insert_key(store, key), bad_request, "insert {key:?}");
+        assert_eq!(lookup_key(store, key), bad_request, "lookup {key:?}");
+        assert_eq!(delete_key(store, key), bad_request, "delete {key:?}");
+    }
+}
@@ -0,0 +1,219 @@
+//! A guest program to test that KV hostcalls check their out-pointers before doing anything.
+//!
+//! Each call is first made with an out-pointer that is out of bounds, or misaligned, and should
+//! fail without touching the store or consuming any handles. The same call is then repeated with
+//! a good pointer, and should succeed. The host checks the store afterwards: "kept" holds
+//! "value", and "deleted" is gone.
+
+use {
+    crate::kv_abi::{
+        delete, delete_wait, insert, insert_wait, list, list_wait, lookup, lookup_wait, open,
+        DeleteConfig, DeleteHandle, InsertConfig, InsertHandle, ListConfig, ListHandle,
+        LookupConfig, LookupHandle, KV_ERROR_OK,
+    },
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+/// An out-pointer past the end of any memory the guest could have.
+fn out_of_bounds<T>() -> *mut T {
+    0xffff_fff0_usize as *mut T
+}
+
+/// An out-pointer to the middle of `place`, so that it isn't aligned.
+fn misaligned<T>(place: &mut u64) -> *mut T {
+    (place as *mut u64 as usize + 1) as *mut T
+}
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        body
+    }
+}
+
+fn insert_key(store: KVStoreHandle, key: &str, value: &str) {
+    unsafe {
+        let config = InsertConfig {
+            mode: 0,
+            if_generation_match: 0,
+            metadata: std::ptr::null(),
+            metadata_len: 0,
+            time_to_live_sec: 0,
+        };
+        let body = body(value);
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                0,
+                &config,
+                out_of_bounds()
+            ),
+            FastlyStatus::INVAL
+        );
+        let mut place = 0u64;
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                0,
+                &config,
+                misaligned(&mut place)
+            ),
+            FastlyStatus::BADALIGN
+        );
+
+        // the body handle wasn't consumed, so the insert can be retried with it
+        let mut pending: InsertHandle = 0;
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                0,
+                &config,
+                &mut pending
+            ),
+            FastlyStatus::OK
+        );
+
+        // nor was the pending handle
+        assert_eq!(insert_wait(pending, out_of_bounds()), FastlyStatus::INVAL);
+        let mut kv_error = 0;
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::OK);
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+}
+
+fn main() {
+    let name = "store";
+
+    unsafe {
+        let mut store: KVStoreHandle = 0;
+        assert_eq!(
+            open(name.as_ptr(), name.len(), &mut store),
+            FastlyStatus::OK
+        );
+
+        insert_key(store, "kept", "value");
+        insert_key(store, "deleted", "value");
+
+        // lookup
+        let key = "kept";
+        let config = LookupConfig { reserved: 0 };
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, out_of_bounds()),
+            FastlyStatus::INVAL
+        );
+        let mut pending: LookupHandle = 0;
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        let mut buf = [0u8; 16];
+        let mut body: BodyHandle = 0;
+        let mut nwritten = 0;
+        let mut generation = 0;
+        let mut kv_error = 0;
+        assert_eq!(
+            lookup_wait(
+                pending,
+                out_of_bounds(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::INVAL
+        );
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                out_of_bounds(),
+                buf.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::INVAL
+        );
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+
+        // delete
+        let key = "deleted";
+        let config = DeleteConfig { reserved: 0 };
+        assert_eq!(
+            delete(store, key.as_ptr(), key.len(), 0, &config, out_of_bounds()),
+            FastlyStatus::INVAL
+        );
+        let mut pending: DeleteHandle = 0;
+        assert_eq!(
+            delete(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(delete_wait(pending, out_of_bounds()), FastlyStatus::INVAL);
+        let mut kv_error = 0;
+        assert_eq!(delete_wait(pending, &mut kv_error), FastlyStatus::OK);
+        assert_eq!(kv_error, KV_ERROR_OK);
+
+        // list
+        let config = ListConfig {
+            mode: 0,
+            cursor: std::ptr::null(),
+            cursor_len: 0,
+            limit: 0,
+            prefix: std::ptr::null(),
+            prefix_len: 0,
+        };
+        assert_eq!(
+            list(store, 0, &config, out_of_bounds()),
+            FastlyStatus::INVAL
+        );
+        let mut pending: ListHandle = 0;
+        assert_eq!(list(store, 0, &config, &mut pending), FastlyStatus::OK);
+        assert_eq!(
+            list_wait(pending, out_of_bounds(), &mut kv_error),
+            FastlyStatus::INVAL
+        );
+        assert_eq!(
+            list_wait(pending, &mut body, &mut kv_error),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+}
@@ -0,0 +1,87 @@
+//! A guest program that starts several KV lookups together, for testing stores' limits on how
+//! many operations run at once.
+//!
+//! The store `store` is seeded with `k0` through `k3`, whose values are `v0` through `v3`. The
+//! guest starts a lookup of each before waiting on any, then waits on each in turn. The response
+//! body reports what each found, one per line, as `k0: v0`, or as `k0: too many requests` for a
+//! lookup that was shed.
+
+use {
+    crate::kv_abi::{
+        lookup, lookup_wait, open, LookupConfig, LookupHandle, KV_ERROR_OK,
+        KV_ERROR_TOO_MANY_REQUESTS,
+    },
+    fastly::Response,
+    fastly_shared::FastlyStatus,
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn read_body(body: BodyHandle) -> String {
+    let mut contents = vec![0u8; 4096];
+    let mut nread = 0;
+    assert_eq!(
+        unsafe { http_body::read(body, contents.as_mut_ptr(), contents.len(), &mut nread) },
+        FastlyStatus::OK
+    );
+    contents.truncate(nread);
+    String::from_utf8(contents).unwrap()
+}
+
+/// Start a lookup of `key`, without waiting on it.
+fn start_lookup(store: KVStoreHandle, key: &str) -> LookupHandle {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    assert_eq!(
+        unsafe { lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending) },
+        FastlyStatus::OK
+    );
+    pending
+}
+
+/// Wait on a lookup, describing what it found.
+fn wait_lookup(pending: LookupHandle) -> String {
+    let mut body: BodyHandle = 0;
+    let mut metadata = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    assert_eq!(
+        unsafe {
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error,
+            )
+        },
+        FastlyStatus::OK
+    );
+    match kv_error {
+        KV_ERROR_OK => read_body(body),
+        KV_ERROR_TOO_MANY_REQUESTS => "too many requests".to_string(),
+        other => panic!("unexpected KV error {other}"),
+    }
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let keys = ["k0", "k1", "k2", "k3"];
+    let pending = keys.map(|key| start_lookup(store, key));
+    let mut body = String::new();
+    for (key, pending) in keys.iter().zip(pending) {
+        body.push_str(&format!("{key}: {}\n", wait_lookup(pending)));
+    }
+    Response::from_body(body).send_to_client();
+}
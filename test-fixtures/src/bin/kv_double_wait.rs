@@ -0,0 +1,123 @@
+//! A guest program to test that waiting twice on the same pending KV operation is a bad handle.
+
+use {
+    crate::kv_abi::{
+        delete, delete_wait, insert, insert_wait, lookup, lookup_wait, open, DeleteConfig,
+        DeleteHandle, InsertConfig, InsertHandle, LookupConfig, LookupHandle, KV_ERROR_OK,
+    },
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn main() {
+    let name = "store";
+    let key = "key";
+
+    unsafe {
+        let mut store: KVStoreHandle = 0;
+        assert_eq!(
+            open(name.as_ptr(), name.len(), &mut store),
+            FastlyStatus::OK
+        );
+
+        // insert
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let value = "value";
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                value.as_ptr(),
+                value.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        let config = InsertConfig {
+            mode: 0,
+            if_generation_match: 0,
+            metadata: std::ptr::null(),
+            metadata_len: 0,
+            time_to_live_sec: 0,
+        };
+        let mut pending: InsertHandle = 0;
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                0,
+                &config,
+                &mut pending
+            ),
+            FastlyStatus::OK
+        );
+        let mut kv_error = 0;
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::OK);
+        assert_eq!(kv_error, KV_ERROR_OK);
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::BADF);
+
+        // lookup
+        let config = LookupConfig { reserved: 0 };
+        let mut pending: LookupHandle = 0;
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        let mut metadata = [0u8; 16];
+        let mut generation = 0;
+        let mut body: BodyHandle = 0;
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+        let mut second_body: BodyHandle = 0;
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut second_body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::BADF
+        );
+
+        // the body from the first wait is still good
+        let mut buf = [0u8; 16];
+        let mut nread = 0;
+        assert_eq!(
+            http_body::read(body, buf.as_mut_ptr(), buf.len(), &mut nread),
+            FastlyStatus::OK
+        );
+        assert_eq!(&buf[..nread], value.as_bytes());
+
+        // delete
+        let config = DeleteConfig { reserved: 0 };
+        let mut pending: DeleteHandle = 0;
+        assert_eq!(
+            delete(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(delete_wait(pending, &mut kv_error), FastlyStatus::OK);
+        assert_eq!(kv_error, KV_ERROR_OK);
+        assert_eq!(delete_wait(pending, &mut kv_error), FastlyStatus::BADF);
+    }
+}
@@ -0,0 +1,146 @@
+//! A guest program that compares-and-swaps a value whose generation is past what the
+//! `fastly_kv_store` hostcalls can give, for testing that such generations are saturated.
+//!
+//! The store `store` is seeded with `seeded` = `s`, with a generation of 5000000000. The guest looks
+//! `seeded` up, overwrites it if its generation still matches the one it was given, tries to
+//! overwrite it again with a generation below that, and looks it up again. The response body
+//! reports what it saw, one result per line:
+//!
+//! ```text
+//! lookup seeded: s, generation 4294967295
+//! insert seeded if generation 4294967295: ok
+//! insert seeded if generation 7: precondition failed
+//! lookup seeded: swapped, generation 4294967295
+//! ```
+
+use {
+    crate::kv_abi::{
+        insert, insert_wait, lookup, lookup_wait, open, InsertConfig, InsertHandle, LookupConfig,
+        LookupHandle, INSERT_CONFIG_IF_GENERATION_MATCH, KV_ERROR_OK, KV_ERROR_PRECONDITION_FAILED,
+    },
+    fastly::Response,
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(nwritten, contents.len());
+        body
+    }
+}
+
+fn read_body(body: BodyHandle) -> String {
+    let mut contents = vec![0u8; 4096];
+    let mut nread = 0;
+    assert_eq!(
+        unsafe { http_body::read(body, contents.as_mut_ptr(), contents.len(), &mut nread) },
+        FastlyStatus::OK
+    );
+    contents.truncate(nread);
+    String::from_utf8(contents).unwrap()
+}
+
+/// Look up `key`, returning its value and the generation the guest was given.
+fn lookup_key(store: KVStoreHandle, key: &str) -> (String, u32) {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut metadata = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+    }
+    assert_eq!(kv_error, KV_ERROR_OK);
+    (read_body(body), generation)
+}
+
+/// Overwrite `key` if its generation matches `generation`, describing the outcome.
+fn insert_if(store: KVStoreHandle, key: &str, value: &str, generation: u32) -> &'static str {
+    let config = InsertConfig {
+        mode: 0,
+        if_generation_match: generation,
+        metadata: std::ptr::null(),
+        metadata_len: 0,
+        time_to_live_sec: 0,
+    };
+    let mut pending: InsertHandle = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body(value),
+                INSERT_CONFIG_IF_GENERATION_MATCH,
+                &config,
+                &mut pending
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::OK);
+    }
+    match kv_error {
+        KV_ERROR_OK => "ok",
+        KV_ERROR_PRECONDITION_FAILED => "precondition failed",
+        other => panic!("unexpected KV error {other}"),
+    }
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let (value, generation) = lookup_key(store, "seeded");
+    let mut report = vec![format!("lookup seeded: {value}, generation {generation}")];
+    for generation in [generation, 7] {
+        report.push(format!(
+            "insert seeded if generation {generation}: {}",
+            insert_if(store, "seeded", "swapped", generation)
+        ));
+    }
+    let (value, generation) = lookup_key(store, "seeded");
+    report.push(format!("lookup seeded: {value}, generation {generation}"));
+
+    let mut body = report.join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
@@ -0,0 +1,40 @@
+//! A guest program to test KV store handle bookkeeping.
+//!
+//! This program uses the `fastly_sys` crate directly, rather than the `fastly` crate, so that it
+//! can see store handles and leave an asynchronous lookup pending.
+//!
+//! The store is opened twice, which should yield the same handle both times. If the request has an
+//! `x-leak` header, a lookup is started and never waited on.
+
+use {
+    fastly::Request,
+    fastly_sys::{fastly_kv_store as kv_store, KVStoreHandle, PendingObjectStoreLookupHandle},
+};
+
+fn open(name: &str) -> KVStoreHandle {
+    let mut handle = KVStoreHandle::MAX;
+    unsafe {
+        kv_store::open(name.as_ptr(), name.len(), &mut handle)
+            .result()
+            .expect("can open the store");
+    }
+    handle
+}
+
+fn main() {
+    let req = Request::from_client();
+
+    let first = open("store");
+    let second = open("store");
+    assert_eq!(first, second);
+
+    if req.contains_header("x-leak") {
+        let key = "key";
+        let mut pending: PendingObjectStoreLookupHandle = 0;
+        unsafe {
+            kv_store::lookup_async(first, key.as_ptr(), key.len(), &mut pending)
+                .result()
+                .expect("can start a lookup");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! A guest program that heads keys with the `fastly_kv_store` `head` hostcalls, which find a
+//! value's metadata, generation, and body length without its body.
+//!
+//! The store `store` is seeded with `seeded` = `hello`, with metadata `meta` and generation 7. The
+//! guest heads `seeded` first with a metadata buffer that is too small, then again on the same
+//! handle with one that is large enough, and then heads `missing`. The response body reports what
+//! it saw, one result per line:
+//!
+//! ```text
+//! head seeded: metadata meta, generation 7, length 5
+//! head missing: not found
+//! ```
+
+use {
+    crate::kv_abi::{head, head_wait, open, HeadHandle, KV_ERROR_NOT_FOUND, KV_ERROR_OK},
+    fastly::Response,
+    fastly_shared::FastlyStatus,
+    fastly_sys::KVStoreHandle,
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+/// Head `key`, describing its metadata, generation, and body length.
+///
+/// The first wait is made with a one-byte metadata buffer, so a key with longer metadata is
+/// waited on a second time with a buffer of the size the first reported.
+fn head_key(store: KVStoreHandle, key: &str) -> String {
+    let mut pending: HeadHandle = 0;
+    assert_eq!(
+        unsafe { head(store, key.as_ptr(), key.len(), &mut pending) },
+        FastlyStatus::OK
+    );
+
+    let mut metadata = vec![0u8; 1];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut body_len = 0;
+    let mut kv_error = 0;
+    loop {
+        let status = unsafe {
+            head_wait(
+                pending,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut body_len,
+                &mut kv_error,
+            )
+        };
+        if status != FastlyStatus::BUFLEN {
+            assert_eq!(status, FastlyStatus::OK);
+            break;
+        }
+        assert!(nwritten > metadata.len());
+        metadata = vec![0u8; nwritten];
+    }
+
+    match kv_error {
+        KV_ERROR_OK => format!(
+            "metadata {}, generation {generation}, length {body_len}",
+            String::from_utf8(metadata[..nwritten].to_vec()).unwrap()
+        ),
+        KV_ERROR_NOT_FOUND => "not found".to_string(),
+        other => panic!("unexpected KV error {other}"),
+    }
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let mut body = [
+        format!("head seeded: {}", head_key(store, "seeded")),
+        format!("head missing: {}", head_key(store, "missing")),
+    ]
+    .join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
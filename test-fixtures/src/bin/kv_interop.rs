@@ -0,0 +1,187 @@
+//! A guest program that reads and writes one store through both the legacy `fastly_object_store`
+//! hostcalls, which the `fastly` crate's `KVStore` uses, and the `fastly_kv_store` hostcalls, for
+//! testing that the two are views of the same data.
+//!
+//! The store `store` is seeded with `seeded` = `s`, with metadata `m` and generation 7. The guest
+//! reads `seeded` through both interfaces, writes `legacy` through the legacy one and reads it
+//! through the current one, writes `current` with metadata through the current one and reads it
+//! through the legacy one, overwrites `seeded` through the legacy one, and then lists the store.
+//! The response body reports what it saw, one result per line:
+//!
+//! ```text
+//! lookup seeded: s, metadata m, generation 7
+//! legacy lookup seeded: s
+//! lookup legacy: l, metadata none, generation 8
+//! legacy lookup current: c
+//! lookup seeded: o, metadata none, generation 10
+//! list: {"data":["current","legacy","seeded"],"meta":{"limit":1000}}
+//! ```
+
+use {
+    crate::kv_abi::{
+        insert, insert_wait, list, list_wait, lookup, lookup_wait, open, InsertConfig,
+        InsertHandle, ListConfig, ListHandle, LookupConfig, LookupHandle, INSERT_CONFIG_METADATA,
+        KV_ERROR_OK,
+    },
+    fastly::{kv_store::KVStore, Response},
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(nwritten, contents.len());
+        body
+    }
+}
+
+fn read_body(body: BodyHandle) -> String {
+    let mut contents = vec![0u8; 4096];
+    let mut nread = 0;
+    assert_eq!(
+        unsafe { http_body::read(body, contents.as_mut_ptr(), contents.len(), &mut nread) },
+        FastlyStatus::OK
+    );
+    contents.truncate(nread);
+    String::from_utf8(contents).unwrap()
+}
+
+/// Look up `key` through the current interface, describing its value, metadata, and generation.
+fn lookup_key(store: KVStoreHandle, key: &str) -> String {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut metadata = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+    }
+    assert_eq!(kv_error, KV_ERROR_OK);
+    let metadata = match nwritten {
+        0 => "none".to_string(),
+        n => String::from_utf8(metadata[..n].to_vec()).unwrap(),
+    };
+    format!(
+        "{}, metadata {metadata}, generation {generation}",
+        read_body(body)
+    )
+}
+
+/// Look up `key` through the legacy interface, which only gives its value.
+fn legacy_lookup(store: &KVStore, key: &str) -> String {
+    store.lookup_str(key).unwrap().unwrap()
+}
+
+/// Overwrite `key` through the current interface, with `metadata`.
+fn insert_key(store: KVStoreHandle, key: &str, value: &str, metadata: &str) {
+    let config = InsertConfig {
+        mode: 0,
+        if_generation_match: 0,
+        metadata: metadata.as_ptr(),
+        metadata_len: metadata.len() as u32,
+        time_to_live_sec: 0,
+    };
+    let mut pending: InsertHandle = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body(value),
+                INSERT_CONFIG_METADATA,
+                &config,
+                &mut pending
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::OK);
+    }
+    assert_eq!(kv_error, KV_ERROR_OK);
+}
+
+fn list_keys(store: KVStoreHandle) -> String {
+    let config = ListConfig {
+        mode: 0,
+        cursor: std::ptr::null(),
+        cursor_len: 0,
+        limit: 0,
+        prefix: std::ptr::null(),
+        prefix_len: 0,
+    };
+    let mut pending: ListHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(list(store, 0, &config, &mut pending), FastlyStatus::OK);
+        assert_eq!(
+            list_wait(pending, &mut body, &mut kv_error),
+            FastlyStatus::OK
+        );
+    }
+    assert_eq!(kv_error, KV_ERROR_OK);
+    read_body(body)
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+    let mut legacy = KVStore::open(name).unwrap().unwrap();
+
+    let mut report = vec![
+        format!("lookup seeded: {}", lookup_key(store, "seeded")),
+        format!("legacy lookup seeded: {}", legacy_lookup(&legacy, "seeded")),
+    ];
+    legacy.insert("legacy", "l").unwrap();
+    report.push(format!("lookup legacy: {}", lookup_key(store, "legacy")));
+    insert_key(store, "current", "c", "meta");
+    report.push(format!(
+        "legacy lookup current: {}",
+        legacy_lookup(&legacy, "current")
+    ));
+    legacy.insert("seeded", "o").unwrap();
+    report.push(format!("lookup seeded: {}", lookup_key(store, "seeded")));
+    report.push(format!("list: {}", list_keys(store)));
+
+    let mut body = report.join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
@@ -0,0 +1,48 @@
+//! A guest program that reads the limits of three stores, `small`, `prefixed`, and `plain`, with
+//! the `fastly_kv_store` `limits` hostcall, which Viceroy only offers when local extensions are
+//! enabled.
+//!
+//! The response body reports what the guest saw, one store per line, such as:
+//!
+//! ```text
+//! small: value 26214400, metadata 2000, key 1024
+//! ```
+//!
+//! or, if local extensions are disabled, `unsupported` in place of each store's limits.
+
+use {
+    crate::kv_abi::{limits, open, Limits},
+    fastly::Response,
+    fastly_shared::FastlyStatus,
+    fastly_sys::KVStoreHandle,
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+/// Open the store `name`, and describe its limits.
+fn store_limits(name: &str) -> String {
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let mut out = Limits::default();
+    match unsafe { limits(store, &mut out) } {
+        FastlyStatus::OK => format!(
+            "value {}, metadata {}, key {}",
+            out.max_value_size, out.max_metadata_size, out.max_key_len
+        ),
+        FastlyStatus::UNSUPPORTED => "unsupported".to_string(),
+        other => panic!("unexpected status {other:?}"),
+    }
+}
+
+fn main() {
+    let mut body = ["small", "prefixed", "plain"]
+        .map(|name| format!("{name}: {}", store_limits(name)))
+        .join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
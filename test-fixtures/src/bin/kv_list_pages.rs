@@ -0,0 +1,85 @@
+//! A guest program that pages through a KV listing, for testing what is captured of the list
+//! responses it receives.
+//!
+//! The guest lists the store `store` two keys at a time, following each page's `next_cursor`
+//! until there isn't one, and responds with the bodies of the pages it received, one per line.
+
+use {
+    crate::kv_abi::{
+        list, list_wait, open, ListConfig, ListHandle, KV_ERROR_OK, LIST_CONFIG_CURSOR,
+        LIST_CONFIG_LIMIT,
+    },
+    fastly::Response,
+    fastly_shared::FastlyStatus,
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+/// List a page of the store, starting after `cursor` if there is one.
+fn list_page(store: KVStoreHandle, cursor: Option<&str>) -> String {
+    let mut mask = LIST_CONFIG_LIMIT;
+    if cursor.is_some() {
+        mask |= LIST_CONFIG_CURSOR;
+    }
+    let cursor = cursor.unwrap_or_default();
+    let config = ListConfig {
+        mode: 0,
+        cursor: cursor.as_ptr(),
+        cursor_len: cursor.len() as u32,
+        limit: 2,
+        prefix: std::ptr::null(),
+        prefix_len: 0,
+    };
+    let mut pending: ListHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut kv_error = 0;
+    let mut page = vec![0u8; 4096];
+    let mut nread = 0;
+    unsafe {
+        assert_eq!(list(store, mask, &config, &mut pending), FastlyStatus::OK);
+        assert_eq!(
+            list_wait(pending, &mut body, &mut kv_error),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+        assert_eq!(
+            http_body::read(body, page.as_mut_ptr(), page.len(), &mut nread),
+            FastlyStatus::OK
+        );
+    }
+    page.truncate(nread);
+    String::from_utf8(page).unwrap()
+}
+
+/// The `next_cursor` of a page, if it has one.
+fn next_cursor(page: &str) -> Option<String> {
+    let start = page.find(r#""next_cursor":""#)? + r#""next_cursor":""#.len();
+    let len = page[start..].find('"')?;
+    Some(page[start..start + len].to_string())
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    let mut pages = vec![];
+    let mut cursor = None;
+    loop {
+        let page = list_page(store, cursor.as_deref());
+        cursor = next_cursor(&page);
+        pages.push(page);
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    let mut body = pages.join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
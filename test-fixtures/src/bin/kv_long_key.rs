@@ -0,0 +1,128 @@
+//! A guest program to test that KV keys over 1024 bytes are a buffer length error.
+
+use {
+    crate::kv_abi::{
+        delete, delete_wait, insert, insert_wait, lookup, lookup_wait, open, DeleteConfig,
+        DeleteHandle, InsertConfig, InsertHandle, LookupConfig, LookupHandle, KV_ERROR_OK,
+    },
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        body
+    }
+}
+
+fn try_insert(store: KVStoreHandle, key: &str) -> FastlyStatus {
+    let config = InsertConfig {
+        mode: 0,
+        if_generation_match: 0,
+        metadata: std::ptr::null(),
+        metadata_len: 0,
+        time_to_live_sec: 0,
+    };
+    let mut pending: InsertHandle = 0;
+    let status = unsafe {
+        insert(
+            store,
+            key.as_ptr(),
+            key.len(),
+            body("value"),
+            0,
+            &config,
+            &mut pending,
+        )
+    };
+    if status == FastlyStatus::OK {
+        let mut kv_error = 0;
+        assert_eq!(
+            unsafe { insert_wait(pending, &mut kv_error) },
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+    status
+}
+
+fn try_lookup(store: KVStoreHandle, key: &str) -> FastlyStatus {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let status = unsafe { lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending) };
+    if status == FastlyStatus::OK {
+        let mut body: BodyHandle = 0;
+        let mut buf = [0u8; 16];
+        let mut nwritten = 0;
+        let mut generation = 0;
+        let mut kv_error = 0;
+        assert_eq!(
+            unsafe {
+                lookup_wait(
+                    pending,
+                    &mut body,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut nwritten,
+                    &mut generation,
+                    &mut kv_error,
+                )
+            },
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+    status
+}
+
+fn try_delete(store: KVStoreHandle, key: &str) -> FastlyStatus {
+    let config = DeleteConfig { reserved: 0 };
+    let mut pending: DeleteHandle = 0;
+    let status = unsafe { delete(store, key.as_ptr(), key.len(), 0, &config, &mut pending) };
+    if status == FastlyStatus::OK {
+        let mut kv_error = 0;
+        assert_eq!(
+            unsafe { delete_wait(pending, &mut kv_error) },
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+    status
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    // The longest key allowed works.
+    let longest = "k".repeat(1024);
+    assert_eq!(try_insert(store, &longest), FastlyStatus::OK);
+    assert_eq!(try_lookup(store, &longest), FastlyStatus::OK);
+    assert_eq!(try_delete(store, &longest), FastlyStatus::OK);
+
+    // One byte more is a buffer length error.
+    let too_long = "k".repeat(1025);
+    assert_eq!(try_insert(store, &too_long), FastlyStatus::BUFLEN);
+    assert_eq!(try_lookup(store, &too_long), FastlyStatus::BUFLEN);
+    assert_eq!(try_delete(store, &too_long), FastlyStatus::BUFLEN);
+}
@@ -0,0 +1,70 @@
+//! A guest program to test that KV list prefixes and cursors far longer than any useful one are
+//! refused outright.
+
+use {
+    crate::kv_abi::{
+        list, list_wait, open, ListConfig, ListHandle, KV_ERROR_OK, LIST_CONFIG_CURSOR,
+        LIST_CONFIG_PREFIX,
+    },
+    fastly_shared::FastlyStatus,
+    fastly_sys::{BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+const TEN_MB: usize = 10 * 1024 * 1024;
+
+/// Start a list with the given cursor and prefix, waiting on it if it starts.
+fn try_list(store: KVStoreHandle, cursor: Option<&str>, prefix: Option<&str>) -> FastlyStatus {
+    let mut mask = 0;
+    if cursor.is_some() {
+        mask |= LIST_CONFIG_CURSOR;
+    }
+    if prefix.is_some() {
+        mask |= LIST_CONFIG_PREFIX;
+    }
+    let (cursor, prefix) = (cursor.unwrap_or_default(), prefix.unwrap_or_default());
+    let config = ListConfig {
+        mode: 0,
+        cursor: cursor.as_ptr(),
+        cursor_len: cursor.len() as u32,
+        limit: 0,
+        prefix: prefix.as_ptr(),
+        prefix_len: prefix.len() as u32,
+    };
+    let mut pending: ListHandle = 0;
+    let status = unsafe { list(store, mask, &config, &mut pending) };
+    if status == FastlyStatus::OK {
+        let mut body: BodyHandle = 0;
+        let mut kv_error = 0;
+        assert_eq!(
+            unsafe { list_wait(pending, &mut body, &mut kv_error) },
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+    }
+    status
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    // A prefix as long as the longest key works.
+    let longest = "k".repeat(1024);
+    assert_eq!(try_list(store, None, Some(&longest)), FastlyStatus::OK);
+
+    // Anything longer, up to ten megabytes, is an invalid argument.
+    let huge = "k".repeat(TEN_MB);
+    assert_eq!(
+        try_list(store, None, Some(&huge[..1025])),
+        FastlyStatus::INVAL
+    );
+    assert_eq!(try_list(store, None, Some(&huge)), FastlyStatus::INVAL);
+    assert_eq!(try_list(store, Some(&huge), None), FastlyStatus::INVAL);
+}
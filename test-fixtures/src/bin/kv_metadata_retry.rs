@@ -0,0 +1,117 @@
+//! A guest program to test the buffer-length protocol for KV lookup metadata.
+//!
+//! A value is written with metadata, then looked up with a metadata buffer that is too small. The
+//! lookup should report the size it needs, and the same handle should then succeed with a larger
+//! buffer. Once it has, the handle is spent.
+
+use {
+    crate::kv_abi::{
+        insert, insert_wait, lookup, lookup_wait, open, InsertConfig, InsertHandle, LookupConfig,
+        LookupHandle, INSERT_CONFIG_METADATA, KV_ERROR_OK,
+    },
+    fastly_shared::FastlyStatus,
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn main() {
+    let name = "store";
+    let key = "key";
+    let metadata = "some metadata that needs a big buffer";
+
+    unsafe {
+        let mut store: KVStoreHandle = 0;
+        assert_eq!(
+            open(name.as_ptr(), name.len(), &mut store),
+            FastlyStatus::OK
+        );
+
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let config = InsertConfig {
+            mode: 0,
+            if_generation_match: 0,
+            metadata: metadata.as_ptr(),
+            metadata_len: metadata.len() as u32,
+            time_to_live_sec: 0,
+        };
+        let mut pending_insert: InsertHandle = 0;
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                INSERT_CONFIG_METADATA,
+                &config,
+                &mut pending_insert
+            ),
+            FastlyStatus::OK
+        );
+        let mut kv_error = 0;
+        assert_eq!(insert_wait(pending_insert, &mut kv_error), FastlyStatus::OK);
+        assert_eq!(kv_error, KV_ERROR_OK);
+
+        let mut pending: LookupHandle = 0;
+        let config = LookupConfig { reserved: 0 };
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+
+        // too small: the needed size is reported, and the result is kept
+        let mut buf = vec![0u8; 4];
+        let mut nwritten = 0;
+        let mut body: BodyHandle = BodyHandle::MAX;
+        let mut generation = 0;
+        let mut kv_error = 0;
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::BUFLEN
+        );
+        assert_eq!(nwritten, metadata.len());
+        assert_eq!(body, BodyHandle::MAX);
+
+        // retrying with the reported size succeeds
+        let mut buf = vec![0u8; nwritten];
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+        assert_eq!(&buf[..nwritten], metadata.as_bytes());
+        assert_ne!(body, BodyHandle::MAX);
+
+        // and releases the result
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::BADF
+        );
+    }
+}
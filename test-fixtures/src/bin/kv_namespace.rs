@@ -0,0 +1,22 @@
+//! A guest program to test that KV store namespaces are isolated from each other.
+//!
+//! The value of the `x-value` header is stored under a fixed key unless a value is already
+//! present, and the stored value is sent back in the response.
+
+use fastly::{kv_store::KVStore, Request, Response};
+
+fn main() {
+    let req = Request::from_client();
+    let mut store = KVStore::open("store").unwrap().unwrap();
+
+    let value = match store.lookup_str("key").unwrap() {
+        Some(value) => value,
+        None => {
+            let value = req.get_header_str("x-value").unwrap().to_string();
+            store.insert("key", value.clone()).unwrap();
+            value
+        }
+    };
+
+    Response::from_body(value).send_to_client();
+}
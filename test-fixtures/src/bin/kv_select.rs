@@ -0,0 +1,112 @@
+//! A guest program that races pending KV lookups with `select`, for testing which handle wins and
+//! that the others can still be waited on.
+//!
+//! The store holds `k0` through `k3`, each with the value `v0` through `v3`. The guest first
+//! selects over lookups of all four without waiting on the winner, and checks that every lookup
+//! can still be waited on after the race. It then races four more lookups to completion, waiting
+//! on each winner as it is chosen. The response body reports both races:
+//!
+//! ```text
+//! first: k2
+//! order: k2 k0 k3 k1
+//! ```
+
+use {
+    crate::kv_abi::{lookup, lookup_wait, open, LookupConfig, LookupHandle, KV_ERROR_OK},
+    fastly::Response,
+    fastly_shared::FastlyStatus,
+    fastly_sys::{
+        fastly_async_io as async_io, fastly_http_body as http_body, BodyHandle, KVStoreHandle,
+    },
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+const KEYS: [&str; 4] = ["k0", "k1", "k2", "k3"];
+
+fn start_lookups(store: KVStoreHandle) -> Vec<LookupHandle> {
+    let config = LookupConfig { reserved: 0 };
+    KEYS.iter()
+        .map(|key| {
+            let mut pending: LookupHandle = 0;
+            assert_eq!(
+                unsafe { lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending) },
+                FastlyStatus::OK
+            );
+            pending
+        })
+        .collect()
+}
+
+/// The index of the handle that `select` picks from `handles`.
+fn select(handles: &[LookupHandle]) -> usize {
+    let mut done = u32::MAX;
+    assert_eq!(
+        unsafe { async_io::select(handles.as_ptr(), handles.len(), 0, &mut done) },
+        FastlyStatus::OK
+    );
+    done as usize
+}
+
+/// Wait on a lookup, and return the value it found.
+fn wait(pending: LookupHandle) -> String {
+    let mut body: BodyHandle = 0;
+    let mut metadata = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    let mut value = [0u8; 16];
+    let mut nread = 0;
+    unsafe {
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(kv_error, KV_ERROR_OK);
+        assert_eq!(
+            http_body::read(body, value.as_mut_ptr(), value.len(), &mut nread),
+            FastlyStatus::OK
+        );
+    }
+    String::from_utf8(value[..nread].to_vec()).unwrap()
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    // A race whose winner isn't taken: every lookup can still be waited on afterwards.
+    let pending = start_lookups(store);
+    let first = KEYS[select(&pending)];
+    for (i, handle) in pending.into_iter().enumerate() {
+        assert_eq!(wait(handle), format!("v{i}"));
+    }
+
+    // A race run to completion, taking each winner in turn.
+    let mut pending = start_lookups(store)
+        .into_iter()
+        .zip(KEYS)
+        .collect::<Vec<_>>();
+    let mut order = vec![];
+    while !pending.is_empty() {
+        let handles = pending.iter().map(|(h, _)| *h).collect::<Vec<_>>();
+        let (handle, key) = pending.remove(select(&handles));
+        assert_eq!(wait(handle), key.replace('k', "v"));
+        order.push(key);
+    }
+
+    Response::from_body(format!("first: {first}\norder: {}\n", order.join(" "))).send_to_client();
+}
@@ -0,0 +1,355 @@
+//! A guest program that brings about each condition in `test-fixtures/data/kv-status-matrix.txt`
+//! through the `fastly_kv_store` hostcalls, in the table's order, and reports the status it sees
+//! for each as a row of the table, one per line.
+
+use {
+    crate::kv_abi::{
+        delete, delete_wait, insert, insert_wait, list, list_wait, lookup, lookup_wait, open,
+        DeleteConfig, DeleteHandle, InsertConfig, InsertHandle, ListConfig, ListHandle,
+        LookupConfig, LookupHandle, INSERT_CONFIG_IF_GENERATION_MATCH, INSERT_CONFIG_METADATA,
+        INSERT_MODE_ADD, INSERT_MODE_OVERWRITE, LIST_CONFIG_CURSOR, LIST_CONFIG_PREFIX,
+    },
+    fastly::Response,
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+const INSERT_MODE_UNKNOWN: u32 = 42;
+
+/// The outcome of a row, as the table gives it.
+fn outcome(status: FastlyStatus, kv_error: u32) -> String {
+    if status != FastlyStatus::OK {
+        return format!("hostcall {}", status_name(status));
+    }
+    let kv_error = match kv_error {
+        0 => "UNINITIALIZED",
+        1 => "OK",
+        2 => "BAD_REQUEST",
+        3 => "NOT_FOUND",
+        4 => "PRECONDITION_FAILED",
+        5 => "PAYLOAD_TOO_LARGE",
+        6 => "INTERNAL_ERROR",
+        7 => "TOO_MANY_REQUESTS",
+        other => return format!("kv {other}"),
+    };
+    format!("kv {kv_error}")
+}
+
+fn status_name(status: FastlyStatus) -> String {
+    [
+        (FastlyStatus::OK, "OK"),
+        (FastlyStatus::ERROR, "ERROR"),
+        (FastlyStatus::INVAL, "INVAL"),
+        (FastlyStatus::BADF, "BADF"),
+        (FastlyStatus::BUFLEN, "BUFLEN"),
+        (FastlyStatus::UNSUPPORTED, "UNSUPPORTED"),
+        (FastlyStatus::NONE, "NONE"),
+        (FastlyStatus::LIMITEXCEEDED, "LIMITEXCEEDED"),
+    ]
+    .into_iter()
+    .find(|(known, _)| *known == status)
+    .map_or_else(|| format!("{status:?}"), |(_, name)| name.to_string())
+}
+
+fn body(contents: &str) -> BodyHandle {
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                contents.as_ptr(),
+                contents.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        body
+    }
+}
+
+fn open_store(name: &str, store: &mut KVStoreHandle) -> String {
+    let status = unsafe { open(name.as_ptr(), name.len(), store) };
+    format!("hostcall {}", status_name(status))
+}
+
+/// Start a lookup of `key`, returning the hostcall's status and the pending handle.
+fn start_lookup(store: KVStoreHandle, key: &[u8]) -> (FastlyStatus, LookupHandle) {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let status = unsafe { lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending) };
+    (status, pending)
+}
+
+fn wait_lookup(pending: LookupHandle) -> (FastlyStatus, u32) {
+    let mut body: BodyHandle = 0;
+    let mut buf = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    let status = unsafe {
+        lookup_wait(
+            pending,
+            &mut body,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut nwritten,
+            &mut generation,
+            &mut kv_error,
+        )
+    };
+    (status, kv_error)
+}
+
+fn lookup_key(store: KVStoreHandle, key: &[u8]) -> String {
+    let (status, pending) = start_lookup(store, key);
+    if status != FastlyStatus::OK {
+        return outcome(status, 0);
+    }
+    let (status, kv_error) = wait_lookup(pending);
+    outcome(status, kv_error)
+}
+
+fn insert_key(
+    store: KVStoreHandle,
+    key: &[u8],
+    value: &str,
+    mask: u32,
+    config: InsertConfig,
+) -> String {
+    let mut pending: InsertHandle = 0;
+    let status = unsafe {
+        insert(
+            store,
+            key.as_ptr(),
+            key.len(),
+            body(value),
+            mask,
+            &config,
+            &mut pending,
+        )
+    };
+    if status != FastlyStatus::OK {
+        return outcome(status, 0);
+    }
+    let mut kv_error = 0;
+    let status = unsafe { insert_wait(pending, &mut kv_error) };
+    outcome(status, kv_error)
+}
+
+fn insert_config(mode: u32) -> InsertConfig {
+    InsertConfig {
+        mode,
+        if_generation_match: 0,
+        metadata: std::ptr::null(),
+        metadata_len: 0,
+        time_to_live_sec: 0,
+    }
+}
+
+fn delete_key(store: KVStoreHandle, key: &[u8]) -> String {
+    let config = DeleteConfig { reserved: 0 };
+    let mut pending: DeleteHandle = 0;
+    let status = unsafe { delete(store, key.as_ptr(), key.len(), 0, &config, &mut pending) };
+    if status != FastlyStatus::OK {
+        return outcome(status, 0);
+    }
+    let mut kv_error = 0;
+    let status = unsafe { delete_wait(pending, &mut kv_error) };
+    outcome(status, kv_error)
+}
+
+/// List the store with the given cursor and prefix, each set in the config mask if given, even
+/// when empty.
+fn list_keys(store: KVStoreHandle, cursor: Option<&[u8]>, prefix: Option<&[u8]>) -> String {
+    let mut mask = 0;
+    if cursor.is_some() {
+        mask |= LIST_CONFIG_CURSOR;
+    }
+    if prefix.is_some() {
+        mask |= LIST_CONFIG_PREFIX;
+    }
+    let (cursor, prefix) = (cursor.unwrap_or_default(), prefix.unwrap_or_default());
+    let config = ListConfig {
+        mode: 0,
+        cursor: cursor.as_ptr(),
+        cursor_len: cursor.len() as u32,
+        limit: 0,
+        prefix: prefix.as_ptr(),
+        prefix_len: prefix.len() as u32,
+    };
+    let mut pending: ListHandle = 0;
+    let status = unsafe { list(store, mask, &config, &mut pending) };
+    if status != FastlyStatus::OK {
+        return outcome(status, 0);
+    }
+    let mut body: BodyHandle = 0;
+    let mut kv_error = 0;
+    let status = unsafe { list_wait(pending, &mut body, &mut kv_error) };
+    outcome(status, kv_error)
+}
+
+fn main() {
+    let long_key = "k".repeat(1025);
+    let long_key = long_key.as_bytes();
+    let mut rows = Vec::new();
+    let mut row = |operation: &str, condition: &str, outcome: String| {
+        rows.push(format!("{operation} | {condition} | {outcome}"));
+    };
+
+    let mut store: KVStoreHandle = 0;
+    let mut reference: KVStoreHandle = 0;
+    let mut unused: KVStoreHandle = 0;
+    row("open", "store exists", open_store("store", &mut store));
+    assert_eq!(open_store("reference", &mut reference), "hostcall OK");
+    row(
+        "open",
+        "store not configured",
+        open_store("missing", &mut unused),
+    );
+    row(
+        "open",
+        "name not a valid store name",
+        open_store("bad name", &mut unused),
+    );
+
+    row("lookup", "key exists", lookup_key(store, b"seed"));
+    row("lookup", "key missing", lookup_key(store, b"missing"));
+    row("lookup", "key invalid", lookup_key(store, b".."));
+    row("lookup", "key over 1024 bytes", lookup_key(store, long_key));
+    row(
+        "lookup",
+        "store handle invalid",
+        lookup_key(u32::MAX, b"seed"),
+    );
+    row("lookup", "store read-only", lookup_key(reference, b"ref"));
+    let (status, pending) = start_lookup(store, b"seed");
+    assert_eq!(status, FastlyStatus::OK);
+    assert_eq!(wait_lookup(pending).0, FastlyStatus::OK);
+    let (status, kv_error) = wait_lookup(pending);
+    row(
+        "lookup_wait",
+        "handle already waited on",
+        outcome(status, kv_error),
+    );
+
+    let overwrite = || insert_config(INSERT_MODE_OVERWRITE);
+    row(
+        "insert",
+        "key new",
+        insert_key(store, b"new", "v", 0, overwrite()),
+    );
+    row(
+        "insert",
+        "add mode, key exists",
+        insert_key(store, b"seed", "v", 0, insert_config(INSERT_MODE_ADD)),
+    );
+    row(
+        "insert",
+        "generation doesn't match",
+        insert_key(
+            store,
+            b"seed",
+            "v",
+            INSERT_CONFIG_IF_GENERATION_MATCH,
+            InsertConfig {
+                if_generation_match: 8,
+                ..overwrite()
+            },
+        ),
+    );
+    row(
+        "insert",
+        "value over max_value_size",
+        insert_key(store, b"seed", "123456789", 0, overwrite()),
+    );
+    row(
+        "insert",
+        "store holds max_keys",
+        insert_key(store, b"another", "v", 0, overwrite()),
+    );
+    row(
+        "insert",
+        "key invalid",
+        insert_key(store, b"..", "v", 0, overwrite()),
+    );
+    row(
+        "insert",
+        "key over 1024 bytes",
+        insert_key(store, long_key, "v", 0, overwrite()),
+    );
+    row(
+        "insert",
+        "mode unknown",
+        insert_key(store, b"seed", "v", 0, insert_config(INSERT_MODE_UNKNOWN)),
+    );
+    // the metadata pointer is valid, for the adapter, which reads it whatever its length
+    row(
+        "insert",
+        "metadata flag set, metadata empty",
+        insert_key(
+            store,
+            b"seed",
+            "v",
+            INSERT_CONFIG_METADATA,
+            InsertConfig {
+                metadata: b"".as_ptr(),
+                ..overwrite()
+            },
+        ),
+    );
+    row(
+        "insert",
+        "store read-only",
+        insert_key(reference, b"ref", "v", 0, overwrite()),
+    );
+
+    row("delete", "key exists", delete_key(store, b"new"));
+    row("delete", "key missing", delete_key(store, b"new"));
+    row("delete", "key invalid", delete_key(store, b".."));
+    row("delete", "key over 1024 bytes", delete_key(store, long_key));
+    row("delete", "store read-only", delete_key(reference, b"ref"));
+
+    let long_cursor = "c".repeat(2000);
+    row("list", "no options", list_keys(store, None, None));
+    row(
+        "list",
+        "prefix invalid",
+        list_keys(store, None, Some(b"a#")),
+    );
+    row(
+        "list",
+        "prefix over 1024 bytes",
+        list_keys(store, None, Some(long_key)),
+    );
+    row(
+        "list",
+        "prefix not UTF-8",
+        list_keys(store, None, Some(&[0xff, 0xfe])),
+    );
+    row(
+        "list",
+        "cursor not base64",
+        list_keys(store, Some(b"!!"), None),
+    );
+    row(
+        "list",
+        "cursor flag set, cursor empty",
+        list_keys(store, Some(b""), None),
+    );
+    row(
+        "list",
+        "cursor longer than any list gives",
+        list_keys(store, Some(long_cursor.as_bytes()), None),
+    );
+    row("list", "store read-only", list_keys(reference, None, None));
+
+    let mut body = rows.join("\n");
+    body.push('\n');
+    Response::from_body(body).send_to_client();
+}
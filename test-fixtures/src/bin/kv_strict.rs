@@ -0,0 +1,106 @@
+//! A guest program that receives KV errors, for testing strict KV mode.
+//!
+//! It looks up a missing key, and then adds a key that already exists.
+
+use {
+    crate::kv_abi::{
+        insert, insert_wait, lookup, lookup_wait, open, InsertConfig, InsertHandle, LookupConfig,
+        LookupHandle, INSERT_MODE_ADD, INSERT_MODE_OVERWRITE, KV_ERROR_NOT_FOUND, KV_ERROR_OK,
+        KV_ERROR_PRECONDITION_FAILED,
+    },
+    fastly_shared::{BodyWriteEnd, FastlyStatus},
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+fn lookup_error(store: KVStoreHandle, key: &str) -> u32 {
+    let config = LookupConfig { reserved: 0 };
+    let mut pending: LookupHandle = 0;
+    let mut body: BodyHandle = 0;
+    let mut metadata = [0u8; 16];
+    let mut nwritten = 0;
+    let mut generation = 0;
+    let mut kv_error = 0;
+    unsafe {
+        assert_eq!(
+            lookup(store, key.as_ptr(), key.len(), 0, &config, &mut pending),
+            FastlyStatus::OK
+        );
+        assert_eq!(
+            lookup_wait(
+                pending,
+                &mut body,
+                metadata.as_mut_ptr(),
+                metadata.len(),
+                &mut nwritten,
+                &mut generation,
+                &mut kv_error
+            ),
+            FastlyStatus::OK
+        );
+    }
+    kv_error
+}
+
+fn insert_error(store: KVStoreHandle, key: &str, mode: u32) -> u32 {
+    let config = InsertConfig {
+        mode,
+        if_generation_match: 0,
+        metadata: std::ptr::null(),
+        metadata_len: 0,
+        time_to_live_sec: 0,
+    };
+    let mut pending: InsertHandle = 0;
+    let mut kv_error = 0;
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let value = "value";
+        let mut nwritten = 0;
+        assert_eq!(
+            http_body::write(
+                body,
+                value.as_ptr(),
+                value.len(),
+                BodyWriteEnd::Back,
+                &mut nwritten
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                0,
+                &config,
+                &mut pending
+            ),
+            FastlyStatus::OK
+        );
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::OK);
+    }
+    kv_error
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    assert_eq!(lookup_error(store, "missing"), KV_ERROR_NOT_FOUND);
+    assert_eq!(
+        insert_error(store, "key", INSERT_MODE_OVERWRITE),
+        KV_ERROR_OK
+    );
+    assert_eq!(
+        insert_error(store, "key", INSERT_MODE_ADD),
+        KV_ERROR_PRECONDITION_FAILED
+    );
+}
@@ -0,0 +1,15 @@
+//! A guest program that traps after writing to a KV store.
+//!
+//! It inserts two keys, deletes one of them, and reads the other back before panicking.
+
+use fastly::kv_store::KVStore;
+
+fn main() {
+    let mut store = KVStore::open("store").unwrap().unwrap();
+    store.insert("kept", "kept value").unwrap();
+    store.insert("dropped", "dropped value").unwrap();
+    store.delete("dropped").unwrap();
+    assert_eq!(store.lookup_str("kept").unwrap().unwrap(), "kept value");
+
+    panic!("trapping after KV writes");
+}
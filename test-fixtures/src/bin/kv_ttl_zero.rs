@@ -0,0 +1,60 @@
+//! A guest program to test that KV inserts with a TTL of zero are rejected.
+//!
+//! The `fastly` crate doesn't expose insert TTLs yet, so the guest makes the `fastly_kv_store`
+//! hostcalls directly.
+
+use {
+    crate::kv_abi::{
+        insert, insert_wait, open, InsertConfig, InsertHandle, INSERT_CONFIG_TIME_TO_LIVE_SEC,
+        KV_ERROR_BAD_REQUEST, KV_ERROR_OK,
+    },
+    fastly_shared::FastlyStatus,
+    fastly_sys::{fastly_http_body as http_body, BodyHandle, KVStoreHandle},
+};
+
+#[path = "../kv_abi.rs"]
+pub(crate) mod kv_abi;
+
+/// Insert an empty value with the given TTL, returning the KV error.
+fn insert_with_ttl(store: KVStoreHandle, ttl: u32) -> u32 {
+    let key = "key";
+    unsafe {
+        let mut body: BodyHandle = 0;
+        assert_eq!(http_body::new(&mut body), FastlyStatus::OK);
+        let config = InsertConfig {
+            mode: 0,
+            if_generation_match: 0,
+            metadata: std::ptr::null(),
+            metadata_len: 0,
+            time_to_live_sec: ttl,
+        };
+        let mut pending: InsertHandle = 0;
+        assert_eq!(
+            insert(
+                store,
+                key.as_ptr(),
+                key.len(),
+                body,
+                INSERT_CONFIG_TIME_TO_LIVE_SEC,
+                &config,
+                &mut pending
+            ),
+            FastlyStatus::OK
+        );
+        let mut kv_error = 0;
+        assert_eq!(insert_wait(pending, &mut kv_error), FastlyStatus::OK);
+        kv_error
+    }
+}
+
+fn main() {
+    let name = "store";
+    let mut store: KVStoreHandle = 0;
+    assert_eq!(
+        unsafe { open(name.as_ptr(), name.len(), &mut store) },
+        FastlyStatus::OK
+    );
+
+    assert_eq!(insert_with_ttl(store, 0), KV_ERROR_BAD_REQUEST);
+    assert_eq!(insert_with_ttl(store, 60), KV_ERROR_OK);
+}
@@ -0,0 +1,164 @@
+// The `fastly_kv_store` hostcalls, and the types and constants they take, for the fixtures that
+// make them directly. The `fastly` crate doesn't use the module yet, so no SDK declares them, and
+// `limits` is Viceroy's own, so none ever will. Each fixture uses only some of them.
+#![allow(dead_code)]
+
+use {
+    fastly_shared::FastlyStatus,
+    fastly_sys::{BodyHandle, KVStoreHandle},
+};
+
+pub type LookupHandle = u32;
+pub type InsertHandle = u32;
+pub type DeleteHandle = u32;
+pub type ListHandle = u32;
+pub type HeadHandle = u32;
+
+#[repr(C)]
+pub struct LookupConfig {
+    pub reserved: u32,
+}
+
+#[repr(C)]
+pub struct InsertConfig {
+    pub mode: u32,
+    pub if_generation_match: u32,
+    pub metadata: *const u8,
+    pub metadata_len: u32,
+    pub time_to_live_sec: u32,
+}
+
+#[repr(C)]
+pub struct DeleteConfig {
+    pub reserved: u32,
+}
+
+#[repr(C)]
+pub struct ListConfig {
+    pub mode: u32,
+    pub cursor: *const u8,
+    pub cursor_len: u32,
+    pub limit: u32,
+    pub prefix: *const u8,
+    pub prefix_len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct Limits {
+    pub max_value_size: u64,
+    pub max_metadata_size: u64,
+    pub max_key_len: u64,
+}
+
+pub const INSERT_MODE_OVERWRITE: u32 = 0;
+pub const INSERT_MODE_ADD: u32 = 1;
+pub const INSERT_MODE_APPEND: u32 = 2;
+pub const INSERT_MODE_PREPEND: u32 = 3;
+
+pub const INSERT_CONFIG_IF_GENERATION_MATCH: u32 = 1 << 2;
+pub const INSERT_CONFIG_METADATA: u32 = 1 << 3;
+pub const INSERT_CONFIG_TIME_TO_LIVE_SEC: u32 = 1 << 4;
+
+pub const LIST_CONFIG_CURSOR: u32 = 1 << 1;
+pub const LIST_CONFIG_LIMIT: u32 = 1 << 2;
+pub const LIST_CONFIG_PREFIX: u32 = 1 << 3;
+
+pub const KV_ERROR_OK: u32 = 1;
+pub const KV_ERROR_BAD_REQUEST: u32 = 2;
+pub const KV_ERROR_NOT_FOUND: u32 = 3;
+pub const KV_ERROR_PRECONDITION_FAILED: u32 = 4;
+pub const KV_ERROR_PAYLOAD_TOO_LARGE: u32 = 5;
+pub const KV_ERROR_INTERNAL_ERROR: u32 = 6;
+pub const KV_ERROR_TOO_MANY_REQUESTS: u32 = 7;
+
+#[link(wasm_import_module = "fastly_kv_store")]
+extern "C" {
+    #[link_name = "open"]
+    pub fn open(name: *const u8, name_len: usize, handle_out: *mut KVStoreHandle) -> FastlyStatus;
+
+    #[link_name = "lookup"]
+    pub fn lookup(
+        store: KVStoreHandle,
+        key: *const u8,
+        key_len: usize,
+        lookup_config_mask: u32,
+        lookup_config: *const LookupConfig,
+        handle_out: *mut LookupHandle,
+    ) -> FastlyStatus;
+
+    #[link_name = "lookup_wait"]
+    pub fn lookup_wait(
+        handle: LookupHandle,
+        body_handle_out: *mut BodyHandle,
+        metadata_buf: *mut u8,
+        metadata_buf_len: usize,
+        nwritten_out: *mut usize,
+        generation_out: *mut u32,
+        kv_error_out: *mut u32,
+    ) -> FastlyStatus;
+
+    #[link_name = "insert"]
+    pub fn insert(
+        store: KVStoreHandle,
+        key: *const u8,
+        key_len: usize,
+        body_handle: BodyHandle,
+        insert_config_mask: u32,
+        insert_config: *const InsertConfig,
+        handle_out: *mut InsertHandle,
+    ) -> FastlyStatus;
+
+    #[link_name = "insert_wait"]
+    pub fn insert_wait(handle: InsertHandle, kv_error_out: *mut u32) -> FastlyStatus;
+
+    #[link_name = "delete"]
+    pub fn delete(
+        store: KVStoreHandle,
+        key: *const u8,
+        key_len: usize,
+        delete_config_mask: u32,
+        delete_config: *const DeleteConfig,
+        handle_out: *mut DeleteHandle,
+    ) -> FastlyStatus;
+
+    #[link_name = "delete_wait"]
+    pub fn delete_wait(handle: DeleteHandle, kv_error_out: *mut u32) -> FastlyStatus;
+
+    #[link_name = "list"]
+    pub fn list(
+        store: KVStoreHandle,
+        list_config_mask: u32,
+        list_config: *const ListConfig,
+        handle_out: *mut ListHandle,
+    ) -> FastlyStatus;
+
+    #[link_name = "list_wait"]
+    pub fn list_wait(
+        handle: ListHandle,
+        body_handle_out: *mut BodyHandle,
+        kv_error_out: *mut u32,
+    ) -> FastlyStatus;
+
+    #[link_name = "head"]
+    pub fn head(
+        store: KVStoreHandle,
+        key: *const u8,
+        key_len: usize,
+        handle_out: *mut HeadHandle,
+    ) -> FastlyStatus;
+
+    #[link_name = "head_wait"]
+    pub fn head_wait(
+        handle: HeadHandle,
+        metadata_buf: *mut u8,
+        metadata_buf_len: usize,
+        nwritten_out: *mut usize,
+        generation_out: *mut u32,
+        body_len_out: *mut u64,
+        kv_error_out: *mut u32,
+    ) -> FastlyStatus;
+
+    #[link_name = "limits"]
+    pub fn limits(store: KVStoreHandle, limits_out: *mut Limits) -> FastlyStatus;
+}